@@ -0,0 +1,40 @@
+//! Records what the library was built from, for `headway_version`
+
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+
+    let git_dir = manifest_dir.join("../../.git");
+    println!("cargo:rerun-if-changed={}", git_dir.join("HEAD").display());
+    println!("cargo:rerun-if-changed={}", git_dir.join("refs").display());
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .current_dir(manifest_dir)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+    if let Some(git_hash) = git_hash {
+        println!("cargo:rustc-env=HEADWAY_GIT_HASH={}", git_hash.trim());
+    }
+
+    // The resolved version, which may differ from the one requested in Cargo.toml
+    let lock_path = manifest_dir.join("../Cargo.lock");
+    println!("cargo:rerun-if-changed={}", lock_path.display());
+    let pmtiles_version = std::fs::read_to_string(&lock_path)
+        .ok()
+        .and_then(|lock| locked_version(&lock, "pmtiles"));
+    if let Some(pmtiles_version) = pmtiles_version {
+        println!("cargo:rustc-env=HEADWAY_PMTILES_VERSION={pmtiles_version}");
+    }
+}
+
+/// The version of `package` in the Cargo.lock `lock`
+fn locked_version(lock: &str, package: &str) -> Option<String> {
+    let mut lines = lock.lines();
+    lines.find(|line| *line == format!("name = \"{package}\""))?;
+    let version = lines.next()?.strip_prefix("version = ")?;
+    Some(version.trim_matches('"').to_string())
+}
@@ -0,0 +1,71 @@
+//! Verifies downloaded and extracted archives against an expected SHA-256, so a corrupted or
+//! truncated file is never served.
+
+use crate::{Error, Result};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::Path;
+
+/// A SHA-256 digest, parsed from hex as provided by the host app, e.g. from a catalog listing
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Sha256Digest([u8; 32]);
+
+impl Sha256Digest {
+    pub(crate) fn parse(hex: &str) -> Result<Self> {
+        let invalid = || Error::InvalidInput(format!("invalid SHA-256 hex digest: {hex:?}"));
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err(invalid());
+        }
+        let mut digest = [0; 32];
+        for (i, byte) in digest.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+        }
+        Ok(Self(digest))
+    }
+
+    /// Parses `hex` if present, so it's validated before any work is done
+    pub(crate) fn parse_optional(hex: Option<&str>) -> Result<Option<Self>> {
+        hex.map(Self::parse).transpose()
+    }
+
+    pub(crate) fn of_bytes(bytes: &[u8]) -> Self {
+        Self(Sha256::digest(bytes).into())
+    }
+
+    pub(crate) async fn of_file(path: &Path) -> Result<Self> {
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            let mut file = std::fs::File::open(path)?;
+            let mut hasher = Sha256::new();
+            let mut buf = vec![0; 64 * 1024];
+            loop {
+                let len = file.read(&mut buf)?;
+                if len == 0 {
+                    break;
+                }
+                hasher.update(&buf[..len]);
+            }
+            Ok(Self(hasher.finalize().into()))
+        })
+        .await
+        .map_err(|e| Error::Runtime(e.to_string()))?
+    }
+
+    /// Fails with [`Error::ChecksumMismatch`] unless the file at `path` has this digest
+    pub(crate) async fn verify_file(&self, path: &Path) -> Result<()> {
+        let actual = Self::of_file(path).await?;
+        if actual != *self {
+            return Err(Error::ChecksumMismatch {
+                expected: self.to_string(),
+                actual: actual.to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Sha256Digest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
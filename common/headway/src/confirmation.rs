@@ -0,0 +1,60 @@
+//! Asks the host app before starting large downloads, e.g. to ask the user before downloading
+//! over 100 MB on cellular, so the policy is enforced here rather than before every call.
+
+use crate::{Error, Result};
+use std::sync::{Arc, RwLock};
+
+#[uniffi::export(with_foreign)]
+pub trait DownloadConfirmation: Send + Sync {
+    /// Called before downloading `total_bytes` from `source_url`, when that's at least the
+    /// threshold the confirmation was set with, including for extracts.
+    ///
+    /// Return `true` to go ahead, e.g. after asking the user, or `false` to have it fail with
+    /// [`Error::DownloadDeclined`].
+    fn confirm_download(&self, source_url: String, total_bytes: u64) -> bool;
+}
+
+#[derive(Default)]
+pub(crate) struct Confirmation {
+    state: RwLock<ConfirmationState>,
+}
+
+#[derive(Default)]
+struct ConfirmationState {
+    threshold_bytes: u64,
+    confirmation: Option<Arc<dyn DownloadConfirmation>>,
+}
+
+impl Confirmation {
+    pub(crate) fn set(
+        &self,
+        threshold_bytes: u64,
+        confirmation: Option<Arc<dyn DownloadConfirmation>>,
+    ) {
+        *self.state.write().expect("poisoned lock") = ConfirmationState {
+            threshold_bytes,
+            confirmation,
+        };
+    }
+
+    /// Fails unless downloading `total_bytes` from `source_url` is below the threshold, or the
+    /// host app confirms it. Always succeeds if the host app hasn't set a confirmation.
+    pub(crate) fn check(&self, source_url: &str, total_bytes: u64) -> Result<()> {
+        let (threshold_bytes, confirmation) = {
+            let state = self.state.read().expect("poisoned lock");
+            (state.threshold_bytes, state.confirmation.clone())
+        };
+        let Some(confirmation) = confirmation else {
+            return Ok(());
+        };
+        if total_bytes < threshold_bytes {
+            return Ok(());
+        }
+        // Not holding the lock, since the host app might take its time, e.g. prompting the user
+        if !confirmation.confirm_download(source_url.to_string(), total_bytes) {
+            log::info!("Download of {total_bytes} bytes from {source_url} was declined");
+            return Err(Error::DownloadDeclined { total_bytes });
+        }
+        Ok(())
+    }
+}
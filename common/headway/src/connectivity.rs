@@ -0,0 +1,53 @@
+//! Asks the host app about the network before doing any network work, so offline and Wi-Fi-only
+//! behavior is enforced here rather than by every caller.
+
+use crate::{Error, Result};
+use std::sync::{Arc, RwLock};
+
+#[uniffi::export(with_foreign)]
+pub trait ConnectivityProvider: Send + Sync {
+    /// Whether there's any network connection, e.g. `false` in airplane mode
+    fn is_online(&self) -> bool;
+
+    /// Whether the connection is metered, e.g. cellular or a personal hotspot
+    fn is_metered(&self) -> bool;
+}
+
+#[derive(Default)]
+pub(crate) struct Connectivity {
+    state: RwLock<ConnectivityState>,
+}
+
+#[derive(Default)]
+struct ConnectivityState {
+    provider: Option<Arc<dyn ConnectivityProvider>>,
+    allow_metered: bool,
+}
+
+impl Connectivity {
+    pub(crate) fn set(&self, provider: Option<Arc<dyn ConnectivityProvider>>, allow_metered: bool) {
+        *self.state.write().expect("poisoned lock") = ConnectivityState {
+            provider,
+            allow_metered,
+        };
+    }
+
+    /// Fails with [`Error::Offline`] or [`Error::MeteredConnection`] unless network work may
+    /// start or continue. Always succeeds if the host app hasn't provided connectivity.
+    pub(crate) fn check(&self) -> Result<()> {
+        let (provider, allow_metered) = {
+            let state = self.state.read().expect("poisoned lock");
+            (state.provider.clone(), state.allow_metered)
+        };
+        let Some(provider) = provider else {
+            return Ok(());
+        };
+        if !provider.is_online() {
+            return Err(Error::Offline);
+        }
+        if !allow_metered && provider.is_metered() {
+            return Err(Error::MeteredConnection);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+//! A cap on how much data downloads and extracts may use, e.g. for a metered connection.
+//!
+//! Network work is checked against the remaining budget before it starts, using its expected
+//! size, and the bytes actually received are deducted as they arrive.
+
+use crate::{Error, Result};
+use std::sync::{Arc, Mutex};
+
+#[uniffi::export(with_foreign)]
+pub trait DataBudgetListener: Send + Sync {
+    /// Called before starting network work expected to use `requested_bytes`, which would exceed
+    /// the `remaining_bytes` of the budget.
+    ///
+    /// Return `true` to allow it anyway, e.g. after asking the user, or `false` to have it fail
+    /// with [`Error::DataBudgetExceeded`].
+    fn on_budget_exceeded(&self, requested_bytes: u64, remaining_bytes: u64) -> bool;
+}
+
+#[derive(Default)]
+pub(crate) struct DataBudget {
+    state: Mutex<BudgetState>,
+}
+
+#[derive(Default)]
+struct BudgetState {
+    /// `None` if there's no budget
+    remaining_bytes: Option<u64>,
+    listener: Option<Arc<dyn DataBudgetListener>>,
+}
+
+impl DataBudget {
+    pub(crate) fn set(
+        &self,
+        budget_bytes: Option<u64>,
+        listener: Option<Arc<dyn DataBudgetListener>>,
+    ) {
+        *self.state.lock().expect("poisoned lock") = BudgetState {
+            remaining_bytes: budget_bytes,
+            listener,
+        };
+    }
+
+    pub(crate) fn remaining_bytes(&self) -> Option<u64> {
+        self.state.lock().expect("poisoned lock").remaining_bytes
+    }
+
+    /// Fails unless `requested_bytes` fit within the remaining budget, or the listener allows
+    /// exceeding it.
+    pub(crate) fn check(&self, requested_bytes: u64) -> Result<()> {
+        let (remaining_bytes, listener) = {
+            let state = self.state.lock().expect("poisoned lock");
+            (state.remaining_bytes, state.listener.clone())
+        };
+        let Some(remaining_bytes) = remaining_bytes else {
+            return Ok(());
+        };
+        if requested_bytes <= remaining_bytes {
+            return Ok(());
+        }
+        // Not holding the lock, since the host app might take its time, e.g. prompting the user
+        let allowed = listener
+            .is_some_and(|listener| listener.on_budget_exceeded(requested_bytes, remaining_bytes));
+        if !allowed {
+            return Err(Error::DataBudgetExceeded {
+                requested_bytes,
+                remaining_bytes,
+            });
+        }
+        log::info!("Exceeding data budget of {remaining_bytes} bytes by request");
+        Ok(())
+    }
+
+    /// Deducts `bytes` received from the remaining budget, if any
+    pub(crate) fn spend(&self, bytes: u64) {
+        let mut state = self.state.lock().expect("poisoned lock");
+        if let Some(remaining_bytes) = &mut state.remaining_bytes {
+            *remaining_bytes = remaining_bytes.saturating_sub(bytes);
+        }
+    }
+}
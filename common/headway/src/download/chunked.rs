@@ -0,0 +1,231 @@
+//! Downloads a large file as several byte ranges at once, which is much faster than a single
+//! stream over high-latency connections, where one TCP connection rarely fills the link.
+//!
+//! The ranges are written straight into place in a file preallocated to the full size. Each
+//! range is retried independently, but a download interrupted partway starts over next time.
+
+use super::{is_retryable, DownloadProgress, Downloader, PROGRESS_INTERVAL_BYTES};
+use crate::{Error, Result};
+use reqwest::header;
+use reqwest::StatusCode;
+use std::io::SeekFrom;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::task::JoinSet;
+
+/// Files at least this large are downloaded in chunks, when the server supports range requests
+pub(super) const MIN_CHUNKED_BYTES: u64 = 32 * 1024 * 1024;
+
+const CHUNK_BYTES: u64 = 8 * 1024 * 1024;
+
+/// Tallies bytes received across every chunk, for reporting progress of the whole file
+struct Progress {
+    callback: Option<Arc<dyn DownloadProgress>>,
+    total_bytes: u64,
+    attempt: u32,
+    received: AtomicU64,
+    reported: AtomicU64,
+}
+
+impl Progress {
+    fn add(&self, bytes: u64) {
+        let received = self.received.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        let Some(callback) = &self.callback else {
+            return;
+        };
+        // Racing chunks may occasionally both report, which is harmless
+        if received.saturating_sub(self.reported.load(Ordering::Relaxed)) >= PROGRESS_INTERVAL_BYTES
+        {
+            self.reported.store(received, Ordering::Relaxed);
+            callback.on_progress(received, Some(self.total_bytes), self.attempt);
+        }
+    }
+
+    /// Un-counts the bytes of a failed chunk, which will be received again when it's retried
+    fn remove(&self, bytes: u64) {
+        self.received.fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    /// Reports that a chunk's `attempt` failed with `error`, and will be retried
+    fn retrying(&self, error: &Error, attempt: u32) {
+        if let Some(callback) = &self.callback {
+            callback.on_retryable_error(error.to_string(), attempt);
+        }
+    }
+
+    fn finish(&self) {
+        if let Some(callback) = &self.callback {
+            let received = self.received.load(Ordering::Relaxed);
+            callback.on_progress(received, Some(self.total_bytes), self.attempt);
+        }
+    }
+}
+
+/// Downloads the `total_bytes` of `source_url` to `path`, as `downloader.parallel_chunks` byte
+/// ranges at a time.
+///
+/// `validator` (the file's strong ETag or Last-Modified date) ensures every range comes from the
+/// same version of the file.
+pub(super) async fn download_chunked(
+    downloader: &Downloader,
+    source_url: &str,
+    path: &Path,
+    total_bytes: u64,
+    validator: &str,
+    progress_callback: Option<Arc<dyn DownloadProgress>>,
+    attempt: u32,
+) -> Result<()> {
+    let file = tokio::fs::File::create(path).await?;
+    file.set_len(total_bytes).await?;
+    drop(file);
+
+    let chunk_count = total_bytes.div_ceil(CHUNK_BYTES);
+    log::info!(
+        "Downloading {source_url} in {chunk_count} chunks, {} at a time",
+        downloader.parallel_chunks
+    );
+    let next_chunk = Arc::new(AtomicU64::new(0));
+    let progress = Arc::new(Progress {
+        callback: progress_callback,
+        total_bytes,
+        attempt,
+        received: AtomicU64::new(0),
+        reported: AtomicU64::new(0),
+    });
+
+    // Dropping the set aborts every worker, e.g. if the download is cancelled
+    let mut workers = JoinSet::new();
+    for _ in 0..u64::from(downloader.parallel_chunks).min(chunk_count) {
+        let downloader = downloader.clone();
+        let source_url = source_url.to_string();
+        let path = path.to_path_buf();
+        let validator = validator.to_string();
+        let next_chunk = next_chunk.clone();
+        let progress = progress.clone();
+        workers.spawn(async move {
+            loop {
+                let chunk = next_chunk.fetch_add(1, Ordering::Relaxed);
+                if chunk >= chunk_count {
+                    return Ok(());
+                }
+                let start = chunk * CHUNK_BYTES;
+                let end = (start + CHUNK_BYTES).min(total_bytes);
+                let range = ChunkRange {
+                    source_url: &source_url,
+                    path: &path,
+                    start,
+                    end,
+                    validator: &validator,
+                };
+                range.download_with_retries(&downloader, &progress).await?;
+            }
+        });
+    }
+    while let Some(result) = workers.join_next().await {
+        // Returning early drops the remaining workers
+        result.map_err(|e| Error::Runtime(format!("download worker failed: {e}")))??;
+    }
+
+    progress.finish();
+    let file = tokio::fs::OpenOptions::new().write(true).open(path).await?;
+    file.sync_all().await?;
+    Ok(())
+}
+
+struct ChunkRange<'a> {
+    source_url: &'a str,
+    path: &'a Path,
+    /// Inclusive
+    start: u64,
+    /// Exclusive
+    end: u64,
+    validator: &'a str,
+}
+
+impl ChunkRange<'_> {
+    async fn download_with_retries(
+        &self,
+        downloader: &Downloader,
+        progress: &Progress,
+    ) -> Result<()> {
+        let mut attempt = 1;
+        loop {
+            let mut received = 0;
+            match self.download(downloader, progress, &mut received).await {
+                Err(e) if attempt < downloader.retry_policy.max_attempts && is_retryable(&e) => {
+                    progress.remove(received);
+                    let backoff = downloader.retry_policy.backoff(attempt);
+                    log::warn!(
+                        "Download of bytes {}-{} of {} failed on attempt {attempt}, retrying in {backoff:?}: {e}",
+                        self.start,
+                        self.end,
+                        self.source_url
+                    );
+                    progress.retrying(&e, attempt);
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn download(
+        &self,
+        downloader: &Downloader,
+        progress: &Progress,
+        received: &mut u64,
+    ) -> Result<()> {
+        downloader.connectivity.check()?;
+        let mut response = downloader
+            .client
+            .get(self.source_url)
+            .header(
+                header::RANGE,
+                format!("bytes={}-{}", self.start, self.end - 1),
+            )
+            // Send the whole file instead if it's changed since we started, which we'll reject
+            .header(header::IF_RANGE, self.validator)
+            .send()
+            .await?
+            .error_for_status()?;
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            return Err(std::io::Error::other(format!(
+                "{} changed while it was being downloaded",
+                self.source_url
+            ))
+            .into());
+        }
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(self.path)
+            .await?;
+        file.seek(SeekFrom::Start(self.start)).await?;
+        while let Some(chunk) = response.chunk().await? {
+            if self.start + *received + chunk.len() as u64 > self.end {
+                return Err(std::io::Error::other(format!(
+                    "{} sent more than the requested range",
+                    self.source_url
+                ))
+                .into());
+            }
+            file.write_all(&chunk).await?;
+            *received += chunk.len() as u64;
+            downloader.data_budget.spend(chunk.len() as u64);
+            progress.add(chunk.len() as u64);
+        }
+        // Writes complete in the background, so may otherwise be lost when the file is dropped
+        file.flush().await?;
+        if self.start + *received != self.end {
+            // Most likely the connection dropped, so worth retrying
+            return Err(Error::Network(format!(
+                "incomplete range from {}",
+                self.source_url
+            )));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,634 @@
+//! Downloads whole files over HTTP, e.g. system pmtiles archives.
+
+mod chunked;
+
+use crate::checksum::Sha256Digest;
+use crate::confirmation::Confirmation;
+use crate::connectivity::Connectivity;
+use crate::data_budget::DataBudget;
+use crate::http::HttpOptions;
+use crate::mirrors::Mirrors;
+use crate::{Error, Result};
+use reqwest::header::{self, HeaderMap};
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use std::ffi::OsString;
+use std::future::Future;
+use std::hash::{BuildHasher, RandomState};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::io::AsyncWriteExt;
+use tokio_util::sync::CancellationToken;
+
+/// Report progress at most this often, rather than for every chunk received
+const PROGRESS_INTERVAL_BYTES: u64 = 256 * 1024;
+
+#[uniffi::export(with_foreign)]
+pub trait DownloadProgress: Send + Sync {
+    /// `total_bytes` is `None` if the server didn't say how large the file is. `attempt` counts
+    /// from 1, increasing each time the download is retried after a failure.
+    fn on_progress(&self, bytes_received: u64, total_bytes: Option<u64>, attempt: u32);
+    /// Called when `attempt` failed in a way that may well be transient, e.g. a timeout, before
+    /// it's retried, so the UI can say why progress has stalled
+    fn on_retryable_error(&self, message: String, attempt: u32);
+}
+
+/// How downloads are retried after failures that may well be transient, like timeouts, dropped
+/// connections, and 5xx responses. Retries resume from where the failed attempt left off.
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first, so 1 disables retries
+    pub max_attempts: u32,
+    /// The delay before the first retry, doubling for each one after
+    pub initial_backoff_ms: u64,
+    /// The longest delay between attempts
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            initial_backoff_ms: 1_000,
+            max_backoff_ms: 30_000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.max_attempts == 0 {
+            return Err(Error::InvalidInput(
+                "max_attempts must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// How long to wait before the attempt after `attempt`, with "full jitter": a random delay up
+    /// to the exponential backoff, so many clients failing at once don't retry in lockstep.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .initial_backoff_ms
+            .saturating_mul(1 << (attempt - 1).min(32))
+            .min(self.max_backoff_ms);
+        // A random number in [0, 1), without needing a dependency for this alone
+        let random = RandomState::new().hash_one(SystemTime::now()) >> 11;
+        let jitter = random as f64 / (1u64 << 53) as f64;
+        Duration::from_millis((exponential as f64 * jitter) as u64)
+    }
+}
+
+/// Downloads files with the configured HTTP client and retry policy
+#[derive(Clone)]
+pub(crate) struct Downloader {
+    client: Client,
+    http_options: HttpOptions,
+    pub(crate) data_budget: Arc<DataBudget>,
+    pub(crate) confirmation: Arc<Confirmation>,
+    pub(crate) mirrors: Arc<Mirrors>,
+    pub(crate) connectivity: Arc<Connectivity>,
+    pub(crate) retry_policy: RetryPolicy,
+    /// How many byte ranges of a large file to download at once, see [`chunked`]
+    pub(crate) parallel_chunks: u32,
+}
+
+impl Default for Downloader {
+    fn default() -> Self {
+        Self {
+            client: HttpOptions::default()
+                .client(Client::builder())
+                .expect("default HTTP options are valid"),
+            http_options: HttpOptions::default(),
+            data_budget: Arc::default(),
+            confirmation: Arc::default(),
+            mirrors: Arc::default(),
+            connectivity: Arc::default(),
+            retry_policy: RetryPolicy::default(),
+            parallel_chunks: 4,
+        }
+    }
+}
+
+impl Downloader {
+    /// For other requests that should share the configured proxy and timeouts
+    pub(crate) fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub(crate) fn http_options(&self) -> &HttpOptions {
+        &self.http_options
+    }
+
+    /// Rebuilds the client with `http_options`, for subsequent downloads
+    pub(crate) fn set_http_options(&mut self, http_options: HttpOptions) -> Result<()> {
+        self.client = http_options.client(Client::builder())?;
+        self.http_options = http_options;
+        Ok(())
+    }
+}
+
+/// Cancels the download it's passed to, e.g. when the user taps "cancel"
+#[derive(Debug, Default, uniffi::Object)]
+pub struct DownloadCancellation {
+    token: CancellationToken,
+    /// Set when pausing, to keep the partial download for resuming
+    keep_partial: AtomicBool,
+}
+
+#[uniffi::export]
+impl DownloadCancellation {
+    #[uniffi::constructor]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops the download, which then fails with [`Error::Cancelled`], and deletes whatever had
+    /// been downloaded so far. Has no effect once the download has finished.
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+}
+
+impl DownloadCancellation {
+    /// Stops the download like [`Self::cancel`], but keeps whatever had been downloaded so far,
+    /// for the next download to the same path to resume from
+    pub(crate) fn pause(&self) {
+        self.keep_partial.store(true, Ordering::Relaxed);
+        self.token.cancel();
+    }
+
+    /// Runs `future` to completion, unless it's cancelled first
+    pub(crate) async fn run<T>(&self, future: impl Future<Output = Result<T>>) -> Result<T> {
+        self.token
+            .run_until_cancelled(future)
+            .await
+            .unwrap_or(Err(Error::Cancelled))
+    }
+}
+
+/// Streams `source_url` to `destination_path`.
+///
+/// The response is written to a temporary file alongside `destination_path` as it arrives,
+/// rather than buffered in memory, and only renamed into place once complete. So an interrupted
+/// download never leaves a truncated file at `destination_path`.
+///
+/// If a previous download to `destination_path` was interrupted, it's resumed with a range
+/// request, provided the server identified the file with a validator (`ETag` or
+/// `Last-Modified`) we can use to check it hasn't since changed. Otherwise it starts over.
+///
+/// If `expected_sha256` is given, the download is discarded unless it matches.
+///
+/// If `current_version` is given, the file is only downloaded if the server has a different
+/// version. Returns the version downloaded, or `None` if the server had no newer version.
+///
+/// Unlike an interruption, cancelling with `cancellation` deletes the partial download.
+pub(crate) async fn download(
+    downloader: &Downloader,
+    source_url: &str,
+    destination_path: &Path,
+    current_version: Option<&RemoteVersion>,
+    expected_sha256: Option<&Sha256Digest>,
+    progress_callback: Option<Arc<dyn DownloadProgress>>,
+    cancellation: Option<&DownloadCancellation>,
+) -> Result<Option<RemoteVersion>> {
+    let partial_path = partial_path(destination_path);
+    let _discard_on_cancel = DiscardOnCancel::new(
+        cancellation,
+        vec![partial_path.clone(), validator_path(&partial_path)],
+    );
+    let candidate_urls = downloader.mirrors.candidates(source_url);
+    // Only asked once, rather than again for each retry or mirror
+    let confirmed = AtomicBool::new(false);
+    let download = async {
+        let mut candidate_urls = candidate_urls.iter().peekable();
+        loop {
+            let url = candidate_urls.next().expect("always has the source URL");
+            let mirror_url = candidate_urls.peek();
+            let result = download_with_retries(
+                downloader,
+                url,
+                &partial_path,
+                current_version,
+                progress_callback.clone(),
+                mirror_url.is_some(),
+                &confirmed,
+            )
+            .await;
+            match (result, mirror_url) {
+                (Err(e), Some(mirror_url)) if is_retryable(&e) => {
+                    log::warn!("Download of {url} failed, failing over to {mirror_url}: {e}");
+                }
+                (result, _) => return result,
+            }
+        }
+    };
+    let result = match cancellation {
+        Some(cancellation) => cancellation.token.run_until_cancelled(download).await,
+        None => Some(download.await),
+    };
+    let Some(result) = result else {
+        let paused = cancellation.is_some_and(|c| c.keep_partial.load(Ordering::Relaxed));
+        // Anything downloaded is deleted as the guard is dropped, unless paused
+        if paused {
+            log::info!("Paused download of {source_url}");
+        } else {
+            log::info!("Cancelled download of {source_url}");
+        }
+        return Err(Error::Cancelled);
+    };
+    let Some(version) = result? else {
+        return Ok(None);
+    };
+    if let Some(expected_sha256) = expected_sha256 {
+        if let Err(e) = expected_sha256.verify_file(&partial_path).await {
+            // Don't resume from a corrupt file next time
+            remove_if_exists(&partial_path).await?;
+            remove_if_exists(&validator_path(&partial_path)).await?;
+            return Err(e);
+        }
+    }
+    tokio::fs::rename(&partial_path, destination_path).await?;
+    remove_if_exists(&validator_path(&partial_path)).await?;
+    Ok(Some(version))
+}
+
+/// Downloads `source_url` to `path`, retrying according to the retry policy. If `has_mirror`,
+/// gives up straight away if the server is unreachable, rather than retrying.
+async fn download_with_retries(
+    downloader: &Downloader,
+    source_url: &str,
+    path: &Path,
+    current_version: Option<&RemoteVersion>,
+    progress_callback: Option<Arc<dyn DownloadProgress>>,
+    has_mirror: bool,
+    confirmed: &AtomicBool,
+) -> Result<Option<RemoteVersion>> {
+    let mut attempt = 1;
+    loop {
+        let result = download_to(
+            downloader,
+            source_url,
+            path,
+            current_version,
+            progress_callback.clone(),
+            attempt,
+            confirmed,
+        )
+        .await;
+        match result {
+            Err(e)
+                if attempt < downloader.retry_policy.max_attempts
+                    && is_retryable(&e)
+                    && !(has_mirror && is_unreachable(&e)) =>
+            {
+                let backoff = downloader.retry_policy.backoff(attempt);
+                log::warn!(
+                    "Download of {source_url} failed on attempt {attempt}, retrying in {backoff:?}: {e}"
+                );
+                if let Some(progress_callback) = &progress_callback {
+                    progress_callback.on_retryable_error(e.to_string(), attempt);
+                }
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn download_to(
+    downloader: &Downloader,
+    source_url: &str,
+    path: &Path,
+    current_version: Option<&RemoteVersion>,
+    progress_callback: Option<Arc<dyn DownloadProgress>>,
+    attempt: u32,
+    confirmed: &AtomicBool,
+) -> Result<Option<RemoteVersion>> {
+    downloader.connectivity.check()?;
+    let client = &downloader.client;
+    let resume_from = match tokio::fs::metadata(path).await {
+        Ok(metadata) => metadata.len(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+        Err(e) => return Err(e.into()),
+    };
+    let validator = match resume_from {
+        0 => None,
+        _ => read_validator(path).await?,
+    };
+
+    let mut request = client.get(source_url);
+    if let Some(validator) = &validator {
+        log::info!("Resuming download of {source_url} from byte {resume_from}");
+        request = request
+            .header(header::RANGE, format!("bytes={resume_from}-"))
+            // Send the whole file instead if it's changed since we started
+            .header(header::IF_RANGE, validator);
+    } else if let Some(current_version) = current_version {
+        if let Some(etag) = &current_version.etag {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &current_version.last_modified {
+            request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+    let mut response = request.send().await?;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        log::info!("{source_url} hasn't changed since it was downloaded");
+        return Ok(None);
+    }
+    if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        // Most likely the partial file is somehow longer than the whole, so start over
+        log::warn!("Can't resume download of {source_url} from byte {resume_from}");
+        response = client.get(source_url).send().await?;
+    }
+    let response_is_partial = response.status() == StatusCode::PARTIAL_CONTENT;
+    let mut response = response.error_for_status()?;
+    let version = RemoteVersion::from_headers(response.headers());
+
+    if let Some((total_bytes, validator)) = chunkable(downloader, &response) {
+        // Abandon this response in favor of requesting it in chunks
+        drop(response);
+        downloader.data_budget.check(total_bytes)?;
+        confirm(downloader, source_url, total_bytes, confirmed)?;
+        // Chunked downloads can't be resumed by appending to the partial file
+        remove_if_exists(&validator_path(path)).await?;
+        chunked::download_chunked(
+            downloader,
+            source_url,
+            path,
+            total_bytes,
+            &validator,
+            progress_callback,
+            attempt,
+        )
+        .await?;
+        return Ok(Some(version));
+    }
+
+    // Checked before touching the partial file, so one declined or over budget can still be
+    // resumed later. A download of unknown size can't be checked up front, but still counts
+    // against the budget
+    downloader
+        .data_budget
+        .check(response.content_length().unwrap_or(0))?;
+    if let Some(content_length) = response.content_length() {
+        confirm(downloader, source_url, content_length, confirmed)?;
+    }
+    let (mut file, mut bytes_received) = if validator.is_some() && response_is_partial {
+        let file = tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(path)
+            .await?;
+        (file, resume_from)
+    } else {
+        write_validator(path, response.headers()).await?;
+        (tokio::fs::File::create(path).await?, 0)
+    };
+    let total_bytes = response
+        .content_length()
+        .map(|content_length| bytes_received + content_length);
+    let mut reported_bytes = bytes_received;
+    while let Some(chunk) = response.chunk().await? {
+        file.write_all(&chunk).await?;
+        downloader.data_budget.spend(chunk.len() as u64);
+        bytes_received += chunk.len() as u64;
+        if bytes_received - reported_bytes >= PROGRESS_INTERVAL_BYTES {
+            // e.g. stop if the device has since switched from Wi-Fi to cellular
+            downloader.connectivity.check()?;
+            if let Some(progress_callback) = &progress_callback {
+                progress_callback.on_progress(bytes_received, total_bytes, attempt);
+            }
+            reported_bytes = bytes_received;
+        }
+    }
+    if let Some(progress_callback) = &progress_callback {
+        progress_callback.on_progress(bytes_received, total_bytes, attempt);
+    }
+    // Make sure the data is on disk before it's renamed into place
+    file.sync_all().await?;
+    Ok(Some(version))
+}
+
+/// Has the host app confirm downloading `total_bytes`, unless it already has for this download
+fn confirm(
+    downloader: &Downloader,
+    source_url: &str,
+    total_bytes: u64,
+    confirmed: &AtomicBool,
+) -> Result<()> {
+    if confirmed.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    downloader.confirmation.check(source_url, total_bytes)?;
+    confirmed.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Whether an attempt failed for reasons that might not recur, e.g. a timeout or server error,
+/// rather than something like a 404 or a full disk
+fn is_retryable(error: &Error) -> bool {
+    match error {
+        Error::Http { status, .. } => {
+            *status >= 500 || *status == StatusCode::TOO_MANY_REQUESTS.as_u16()
+        }
+        // e.g. a timeout, or a connection dropping partway through a chunk
+        Error::NetworkUnavailable(_) | Error::Network(_) => true,
+        _ => false,
+    }
+}
+
+/// Whether an attempt failed because we couldn't connect to the server at all
+fn is_unreachable(error: &Error) -> bool {
+    matches!(error, Error::NetworkUnavailable(_))
+}
+
+/// The size and validator of a fresh response worth downloading in parallel chunks: one large
+/// enough, from a server that supports range requests, with a validator to make sure every
+/// chunk comes from the same version of the file.
+fn chunkable(downloader: &Downloader, response: &reqwest::Response) -> Option<(u64, String)> {
+    if downloader.parallel_chunks < 2 || response.status() != StatusCode::OK {
+        return None;
+    }
+    let total_bytes = response.content_length()?;
+    let accepts_ranges = response
+        .headers()
+        .get(header::ACCEPT_RANGES)
+        .is_some_and(|value| value == "bytes");
+    if total_bytes < chunked::MIN_CHUNKED_BYTES || !accepts_ranges {
+        return None;
+    }
+    Some((
+        total_bytes,
+        resume_validator(response.headers())?.to_string(),
+    ))
+}
+
+/// Identifies the version of a downloaded file on its server, so it can be re-requested only if
+/// it's since changed
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RemoteVersion {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl RemoteVersion {
+    fn from_headers(response_headers: &HeaderMap) -> Self {
+        let header = |name| {
+            response_headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        Self {
+            etag: header(header::ETAG),
+            last_modified: header(header::LAST_MODIFIED),
+        }
+    }
+
+    fn sidecar_path(path: &Path) -> PathBuf {
+        path.with_extension("remote_version")
+    }
+
+    /// The version of the file downloaded to `path`, if known
+    pub(crate) fn load(path: &Path) -> Option<Self> {
+        let sidecar_path = Self::sidecar_path(path);
+        let contents = match std::fs::read_to_string(&sidecar_path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                log::warn!("Unable to read remote version file {sidecar_path:?}: {e}");
+                return None;
+            }
+        };
+        let Ok(json) = serde_json::from_str::<Value>(&contents) else {
+            log::warn!("Ignoring invalid remote version file {sidecar_path:?}");
+            return None;
+        };
+        let field = |name| json.get(name).and_then(Value::as_str).map(str::to_string);
+        Some(Self {
+            etag: field("etag"),
+            last_modified: field("last_modified"),
+        })
+    }
+
+    /// Records this as the version of the file downloaded to `path`
+    pub(crate) fn save(&self, path: &Path) -> Result<()> {
+        let json = json!({
+            "etag": self.etag,
+            "last_modified": self.last_modified,
+        });
+        std::fs::write(Self::sidecar_path(path), json.to_string())?;
+        Ok(())
+    }
+}
+
+/// Deletes `paths` when dropped if `cancellation` has been cancelled rather than paused, whether
+/// the download saw it or was dropped first, e.g. by [`DownloadCancellation::run`]
+pub(crate) struct DiscardOnCancel<'a> {
+    cancellation: Option<&'a DownloadCancellation>,
+    paths: Vec<PathBuf>,
+}
+
+impl<'a> DiscardOnCancel<'a> {
+    pub(crate) fn new(cancellation: Option<&'a DownloadCancellation>, paths: Vec<PathBuf>) -> Self {
+        Self {
+            cancellation,
+            paths,
+        }
+    }
+}
+
+impl Drop for DiscardOnCancel<'_> {
+    fn drop(&mut self) {
+        let Some(cancellation) = self.cancellation else {
+            return;
+        };
+        if !cancellation.is_cancelled() || cancellation.keep_partial.load(Ordering::Relaxed) {
+            return;
+        }
+        for path in &self.paths {
+            match std::fs::remove_file(path) {
+                Ok(()) => log::info!("Removed cancelled download {path:?}"),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => log::warn!("Unable to remove cancelled download {path:?}: {e}"),
+            }
+        }
+    }
+}
+
+/// Deletes whatever was downloaded by an unfinished download to `destination_path`, e.g. one that
+/// was paused and will never be resumed
+pub(crate) async fn discard_partial(destination_path: &Path) -> Result<()> {
+    let partial_path = partial_path(destination_path);
+    remove_if_exists(&partial_path).await?;
+    remove_if_exists(&validator_path(&partial_path)).await
+}
+
+/// Where a download to `destination_path` is written until complete. Mustn't end in .pmtiles,
+/// else we'd try to serve it upon restart.
+fn partial_path(destination_path: &Path) -> PathBuf {
+    let mut file_name = destination_path
+        .file_name()
+        .map(OsString::from)
+        .unwrap_or_default();
+    file_name.push(".partial");
+    destination_path.with_file_name(file_name)
+}
+
+/// Where we keep the validator identifying the version of the file being downloaded to
+/// `partial_path`, for resuming
+fn validator_path(partial_path: &Path) -> PathBuf {
+    let mut file_name = partial_path
+        .file_name()
+        .map(OsString::from)
+        .unwrap_or_default();
+    file_name.push(".validator");
+    partial_path.with_file_name(file_name)
+}
+
+async fn read_validator(partial_path: &Path) -> Result<Option<String>> {
+    match tokio::fs::read_to_string(validator_path(partial_path)).await {
+        Ok(validator) => Ok(Some(validator)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Saves the response's strong `ETag`, or else its `Last-Modified` date, for resuming the
+/// download with an `If-Range` request. Weak ETags aren't allowed in `If-Range`.
+async fn write_validator(partial_path: &Path, response_headers: &HeaderMap) -> Result<()> {
+    let path = validator_path(partial_path);
+    match resume_validator(response_headers) {
+        Some(validator) => tokio::fs::write(path, validator).await?,
+        None => remove_if_exists(&path).await?,
+    }
+    Ok(())
+}
+
+/// The response's strong `ETag`, or else its `Last-Modified` date
+fn resume_validator(response_headers: &HeaderMap) -> Option<&str> {
+    let etag = response_headers
+        .get(header::ETAG)
+        .and_then(|value| value.to_str().ok())
+        .filter(|etag| !etag.starts_with("W/"));
+    let last_modified = response_headers
+        .get(header::LAST_MODIFIED)
+        .and_then(|value| value.to_str().ok());
+    etag.or(last_modified)
+}
+
+async fn remove_if_exists(path: &Path) -> Result<()> {
+    match tokio::fs::remove_file(path).await {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,317 @@
+//! Geographic coordinates, and calculations on them
+
+/// A WGS84 coordinate in degrees
+#[derive(Clone, Copy, Debug, PartialEq, uniffi::Record)]
+pub struct LatLon {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+/// The mean radius of the earth, in meters
+pub(crate) const EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+impl LatLon {
+    /// The great-circle distance to `other` in meters, by the haversine formula
+    pub(crate) fn distance_to(&self, other: &LatLon) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let d_lat = lat2 - lat1;
+        let d_lon = (other.lon - self.lon).to_radians();
+        let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+    }
+
+    /// The point `fraction` of the way to `other`, interpolating linearly, which is accurate
+    /// enough for the short segments of a polyline
+    pub(crate) fn lerp(&self, other: &LatLon, fraction: f64) -> LatLon {
+        LatLon {
+            lat: self.lat + (other.lat - self.lat) * fraction,
+            lon: self.lon + (other.lon - self.lon) * fraction,
+        }
+    }
+}
+
+/// The WGS84 ellipsoid's semi-major axis, in meters
+const WGS84_A: f64 = 6_378_137.0;
+
+/// The WGS84 ellipsoid's flattening
+const WGS84_F: f64 = 1.0 / 298.257_223_563;
+
+/// The radius of the sphere with the same surface area as the WGS84 ellipsoid, in meters
+const AUTHALIC_RADIUS_M: f64 = 6_371_007.180_9;
+
+/// The distance in meters between `from` and `to` along the WGS84 ellipsoid, accurate to within
+/// a millimeter, unlike the haversine formula, which can be off by 0.5%
+#[uniffi::export]
+pub fn geodesic_distance(from: LatLon, to: LatLon) -> f64 {
+    match vincenty_inverse(from, to) {
+        Some((distance_m, _)) => distance_m,
+        // Nearly antipodal points, where the error of a sphere is at least consistent
+        None => from.distance_to(&to),
+    }
+}
+
+/// The bearing in degrees clockwise from north, from 0 to 360, to set out from `from` towards
+/// `to` along the shortest path
+#[uniffi::export]
+pub fn initial_bearing(from: LatLon, to: LatLon) -> f64 {
+    let azimuth = match vincenty_inverse(from, to) {
+        Some((_, azimuth)) => azimuth,
+        None => {
+            let (lat1, lat2) = (from.lat.to_radians(), to.lat.to_radians());
+            let d_lon = (to.lon - from.lon).to_radians();
+            (d_lon.sin() * lat2.cos())
+                .atan2(lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * d_lon.cos())
+        }
+    };
+    azimuth.to_degrees().rem_euclid(360.0)
+}
+
+/// The length in meters of the polyline through `points`
+#[uniffi::export]
+pub fn polyline_length(points: Vec<LatLon>) -> f64 {
+    points
+        .windows(2)
+        .map(|segment| geodesic_distance(segment[0], segment[1]))
+        .sum()
+}
+
+/// How far in meters along the polyline through `points` its closest point to `location` is,
+/// e.g. to show progress along a measured path
+#[uniffi::export]
+pub fn distance_along_polyline(points: Vec<LatLon>, location: LatLon) -> f64 {
+    let mut closest = (f64::MAX, 0.0);
+    let mut distance_m = 0.0;
+    for segment in points.windows(2) {
+        let (start, end) = (segment[0], segment[1]);
+        // Project to meters around `location`, which is accurate enough to find the closest
+        // point on a segment
+        let scale_x = location.lat.to_radians().cos();
+        let to_local = |point: LatLon| {
+            (
+                (point.lon - location.lon) * scale_x,
+                point.lat - location.lat,
+            )
+        };
+        let ((start_x, start_y), (end_x, end_y)) = (to_local(start), to_local(end));
+        let (d_x, d_y) = (end_x - start_x, end_y - start_y);
+        let length_squared = d_x * d_x + d_y * d_y;
+        let fraction = if length_squared > 0.0 {
+            (-(start_x * d_x + start_y * d_y) / length_squared).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let nearest = start.lerp(&end, fraction);
+        let offset_m = geodesic_distance(location, nearest);
+        if offset_m < closest.0 {
+            closest = (offset_m, distance_m + geodesic_distance(start, nearest));
+        }
+        distance_m += geodesic_distance(start, end);
+    }
+    closest.1
+}
+
+/// The area in square meters of the polygon with the ring `points`, in either winding order,
+/// and closed or not
+#[uniffi::export]
+pub fn polygon_area(points: Vec<LatLon>) -> f64 {
+    if points.len() < 3 {
+        return 0.0;
+    }
+    // The spherical excess of each edge, as summed by Chamberlain & Duquette's formula
+    let excess: f64 = points
+        .iter()
+        .zip(points.iter().cycle().skip(1))
+        .map(|(a, b)| {
+            // The short way round, for edges crossing the antimeridian
+            let mut d_lon = (b.lon - a.lon).rem_euclid(360.0);
+            if d_lon > 180.0 {
+                d_lon -= 360.0;
+            }
+            d_lon.to_radians() * (2.0 + a.lat.to_radians().sin() + b.lat.to_radians().sin())
+        })
+        .sum();
+    (excess * AUTHALIC_RADIUS_M * AUTHALIC_RADIUS_M / 2.0).abs()
+}
+
+/// Whether `point` is inside the polygon with the ring `polygon`, treating lat/lon as planar,
+/// as map rendering does
+#[uniffi::export]
+pub fn point_in_polygon(point: LatLon, polygon: Vec<LatLon>) -> bool {
+    let mut is_inside = false;
+    for (a, b) in polygon.iter().zip(polygon.iter().cycle().skip(1)) {
+        if (a.lat > point.lat) != (b.lat > point.lat)
+            && point.lon < a.lon + (point.lat - a.lat) / (b.lat - a.lat) * (b.lon - a.lon)
+        {
+            is_inside = !is_inside;
+        }
+    }
+    is_inside
+}
+
+/// The distance in meters and initial azimuth in radians from `from` to `to` by Vincenty's
+/// inverse formula, or `None` if it doesn't converge, as for nearly antipodal points
+fn vincenty_inverse(from: LatLon, to: LatLon) -> Option<(f64, f64)> {
+    let b = WGS84_A * (1.0 - WGS84_F);
+    let l = (to.lon - from.lon).to_radians();
+    let u1 = ((1.0 - WGS84_F) * from.lat.to_radians().tan()).atan();
+    let u2 = ((1.0 - WGS84_F) * to.lat.to_radians().tan()).atan();
+    let (sin_u1, cos_u1) = u1.sin_cos();
+    let (sin_u2, cos_u2) = u2.sin_cos();
+
+    let mut lambda = l;
+    for _ in 0..200 {
+        let (sin_lambda, cos_lambda) = lambda.sin_cos();
+        let sin_sigma = (cos_u2 * sin_lambda).hypot(cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_lambda);
+        if sin_sigma == 0.0 {
+            return Some((0.0, 0.0));
+        }
+        let cos_sigma = sin_u1 * sin_u2 + cos_u1 * cos_u2 * cos_lambda;
+        let sigma = sin_sigma.atan2(cos_sigma);
+        let sin_alpha = cos_u1 * cos_u2 * sin_lambda / sin_sigma;
+        let cos_sq_alpha = 1.0 - sin_alpha * sin_alpha;
+        // On the equator, cos_sq_alpha is 0
+        let cos_2_sigma_m = if cos_sq_alpha == 0.0 {
+            0.0
+        } else {
+            cos_sigma - 2.0 * sin_u1 * sin_u2 / cos_sq_alpha
+        };
+        let c = WGS84_F / 16.0 * cos_sq_alpha * (4.0 + WGS84_F * (4.0 - 3.0 * cos_sq_alpha));
+        let previous_lambda = lambda;
+        lambda = l
+            + (1.0 - c)
+                * WGS84_F
+                * sin_alpha
+                * (sigma
+                    + c * sin_sigma
+                        * (cos_2_sigma_m
+                            + c * cos_sigma * (-1.0 + 2.0 * cos_2_sigma_m * cos_2_sigma_m)));
+        if (lambda - previous_lambda).abs() > 1e-12 {
+            continue;
+        }
+
+        let u_sq = cos_sq_alpha * (WGS84_A * WGS84_A - b * b) / (b * b);
+        let big_a =
+            1.0 + u_sq / 16384.0 * (4096.0 + u_sq * (-768.0 + u_sq * (320.0 - 175.0 * u_sq)));
+        let big_b = u_sq / 1024.0 * (256.0 + u_sq * (-128.0 + u_sq * (74.0 - 47.0 * u_sq)));
+        let delta_sigma = big_b
+            * sin_sigma
+            * (cos_2_sigma_m
+                + big_b / 4.0
+                    * (cos_sigma * (-1.0 + 2.0 * cos_2_sigma_m * cos_2_sigma_m)
+                        - big_b / 6.0
+                            * cos_2_sigma_m
+                            * (-3.0 + 4.0 * sin_sigma * sin_sigma)
+                            * (-3.0 + 4.0 * cos_2_sigma_m * cos_2_sigma_m)));
+        let distance_m = b * big_a * (sigma - delta_sigma);
+        let (sin_lambda, cos_lambda) = lambda.sin_cos();
+        let azimuth = (cos_u2 * sin_lambda).atan2(cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_lambda);
+        return Some((distance_m, azimuth));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lat_lon(lat: f64, lon: f64) -> LatLon {
+        LatLon { lat, lon }
+    }
+
+    fn degrees(degrees: f64, minutes: f64, seconds: f64) -> f64 {
+        degrees.signum() * (degrees.abs() + minutes / 60.0 + seconds / 3600.0)
+    }
+
+    /// Vincenty's own example, from Flinders Peak to Buninyong
+    fn flinders_peak_to_buninyong() -> (LatLon, LatLon) {
+        (
+            lat_lon(
+                degrees(-37.0, 57.0, 3.72030),
+                degrees(144.0, 25.0, 29.52440),
+            ),
+            lat_lon(
+                degrees(-37.0, 39.0, 10.15610),
+                degrees(143.0, 55.0, 35.38390),
+            ),
+        )
+    }
+
+    /// The area of the 1° by 1° cell north east of `(lat, lon)` on the authalic sphere
+    fn cell_area(lat: f64) -> f64 {
+        let lat_extent = (lat + 1.0).to_radians().sin() - lat.to_radians().sin();
+        AUTHALIC_RADIUS_M * AUTHALIC_RADIUS_M * 1f64.to_radians() * lat_extent
+    }
+
+    #[test]
+    fn geodesic_distance_matches_vincentys_example() {
+        let (from, to) = flinders_peak_to_buninyong();
+        assert!((geodesic_distance(from, to) - 54_972.271).abs() < 0.001);
+    }
+
+    #[test]
+    fn initial_bearing_matches_vincentys_example() {
+        let (from, to) = flinders_peak_to_buninyong();
+        let expected = degrees(306.0, 52.0, 5.37);
+        assert!((initial_bearing(from, to) - expected).abs() < 1e-5);
+    }
+
+    #[test]
+    fn geodesic_distance_to_itself_is_zero() {
+        let (from, _) = flinders_peak_to_buninyong();
+        assert_eq!(geodesic_distance(from, from), 0.0);
+    }
+
+    #[test]
+    fn polygon_area_of_one_degree_cell() {
+        let cell = vec![
+            lat_lon(0.0, 0.0),
+            lat_lon(0.0, 1.0),
+            lat_lon(1.0, 1.0),
+            lat_lon(1.0, 0.0),
+        ];
+        assert!((polygon_area(cell.clone()) - cell_area(0.0)).abs() < 1.0);
+        // Either winding order
+        let reversed = cell.into_iter().rev().collect();
+        assert!((polygon_area(reversed) - cell_area(0.0)).abs() < 1.0);
+    }
+
+    #[test]
+    fn polygon_area_across_the_antimeridian() {
+        let cell = vec![
+            lat_lon(45.0, 179.5),
+            lat_lon(45.0, -179.5),
+            lat_lon(46.0, -179.5),
+            lat_lon(46.0, 179.5),
+        ];
+        assert!((polygon_area(cell) - cell_area(45.0)).abs() < 1.0);
+    }
+
+    #[test]
+    fn polygon_area_of_degenerate_rings() {
+        assert_eq!(polygon_area(vec![]), 0.0);
+        assert_eq!(
+            polygon_area(vec![lat_lon(0.0, 0.0), lat_lon(1.0, 1.0)]),
+            0.0
+        );
+    }
+
+    #[test]
+    fn distance_along_polyline_projects_onto_the_nearest_segment() {
+        let points = vec![lat_lon(0.0, 0.0), lat_lon(0.0, 1.0), lat_lon(0.0, 2.0)];
+        let distance_m = distance_along_polyline(points, lat_lon(0.001, 1.5));
+        let expected = geodesic_distance(lat_lon(0.0, 0.0), lat_lon(0.0, 1.5));
+        assert!((distance_m - expected).abs() < 1.0);
+    }
+
+    #[test]
+    fn distance_along_polyline_clamps_to_its_ends() {
+        let points = vec![lat_lon(0.0, 0.0), lat_lon(0.0, 1.0)];
+        assert_eq!(
+            distance_along_polyline(points.clone(), lat_lon(0.0, -1.0)),
+            0.0
+        );
+        let length_m = polyline_length(points.clone());
+        let distance_m = distance_along_polyline(points, lat_lon(0.0, 2.0));
+        assert!((distance_m - length_m).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,165 @@
+//! Glyph ranges for label rendering, in the SDF protobuf format MapLibre expects.
+//!
+//! For each font we serve, in order of preference:
+//!  1. pre-generated ranges from `{fonts_dir}/{font_name}/{start}-{end}.pbf`
+//!  2. ranges previously generated from a font file, cached in `cache_dir`
+//!  3. ranges generated on demand from a TTF/OTF font file, either registered by the host app or
+//!     found at `{fonts_dir}/{font_name}.ttf` (or `.otf`)
+
+mod pbf;
+mod sdf;
+
+use crate::{Error, ErrorContext, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+const FONT_FILE_EXTENSIONS: [&str; 2] = ["ttf", "otf"];
+
+#[derive(Debug)]
+pub(crate) struct GlyphStore {
+    dirs: RwLock<GlyphDirs>,
+    /// Font files provided by the host app, e.g. from its bundle, keyed by font name
+    registered_fonts: RwLock<HashMap<String, PathBuf>>,
+    /// Parsed font files, keyed by font name
+    loaded_fonts: RwLock<HashMap<String, Arc<fontdue::Font>>>,
+}
+
+#[derive(Debug)]
+struct GlyphDirs {
+    fonts_dir: PathBuf,
+    cache_dir: PathBuf,
+}
+
+impl GlyphStore {
+    pub(crate) fn new(fonts_dir: PathBuf, cache_dir: PathBuf) -> Self {
+        Self {
+            dirs: RwLock::new(GlyphDirs {
+                fonts_dir,
+                cache_dir,
+            }),
+            registered_fonts: RwLock::new(HashMap::new()),
+            loaded_fonts: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Makes the font file at `path` available as `font_name`, taking precedence over any font
+    /// file of the same name in `fonts_dir`.
+    pub(crate) async fn register_font(&self, font_name: String, path: PathBuf) -> Result<()> {
+        if !fs::exists(&path)? {
+            return Err(Error::InvalidInput(format!(
+                "font file not found: {path:?}"
+            )));
+        }
+        // Discard anything generated from whatever font previously had this name
+        self.loaded_fonts.write().await.remove(&font_name);
+        let cache_dir = self.dirs.read().await.cache_dir.join(&font_name);
+        if fs::exists(&cache_dir)? {
+            fs::remove_dir_all(&cache_dir)?;
+        }
+        self.registered_fonts.write().await.insert(font_name, path);
+        Ok(())
+    }
+
+    /// Serves fonts from `fonts_dir` instead, e.g. those of a newly installed asset bundle, with
+    /// ranges generated from them cached in `cache_dir`.
+    pub(crate) async fn set_dirs(&self, fonts_dir: PathBuf, cache_dir: PathBuf) {
+        let mut dirs = self.dirs.write().await;
+        *dirs = GlyphDirs {
+            fonts_dir,
+            cache_dir,
+        };
+        // Font files may differ in the new fonts dir
+        self.loaded_fonts.write().await.clear();
+    }
+
+    /// The encoded glyphs for codepoints `start..=end` of `font_name`, or `None` if we have no
+    /// such font.
+    ///
+    /// `font_name` must already have been validated as a safe path component.
+    pub(crate) async fn glyphs(
+        &self,
+        font_name: &str,
+        start: u32,
+        end: u32,
+    ) -> Result<Option<Vec<u8>>> {
+        let range_file_name = format!("{start}-{end}.pbf");
+        let (pregenerated_path, cached_path) = {
+            let dirs = self.dirs.read().await;
+            (
+                dirs.fonts_dir.join(font_name).join(&range_file_name),
+                dirs.cache_dir.join(font_name).join(&range_file_name),
+            )
+        };
+
+        if let Some(glyphs) = read_if_exists(&pregenerated_path)? {
+            return Ok(Some(glyphs));
+        }
+
+        if let Some(glyphs) = read_if_exists(&cached_path)? {
+            return Ok(Some(glyphs));
+        }
+
+        let Some(font) = self.font(font_name).await? else {
+            return Ok(None);
+        };
+        log::debug!("Generating glyphs {font_name}/{start}-{end}");
+        let owned_font_name = font_name.to_string();
+        let glyphs = tokio::task::spawn_blocking(move || {
+            sdf::render_range(&font, &owned_font_name, start, end)
+        })
+        .await
+        .map_err(|e| Error::Runtime(format!("glyph generation failed: {e}")))?;
+
+        if let Some(parent) = cached_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Write to a temp file first so a concurrent request never reads a partial range
+        let tmp_path = cached_path.with_extension("tmp");
+        fs::write(&tmp_path, &glyphs)?;
+        fs::rename(&tmp_path, &cached_path)?;
+
+        Ok(Some(glyphs))
+    }
+
+    async fn font(&self, font_name: &str) -> Result<Option<Arc<fontdue::Font>>> {
+        if let Some(font) = self.loaded_fonts.read().await.get(font_name) {
+            return Ok(Some(font.clone()));
+        }
+
+        let registered_path = self.registered_fonts.read().await.get(font_name).cloned();
+        let path = match registered_path {
+            Some(path) => path,
+            None => {
+                let fonts_dir = self.dirs.read().await.fonts_dir.clone();
+                let mut candidates = FONT_FILE_EXTENSIONS
+                    .iter()
+                    .map(|ext| fonts_dir.join(format!("{font_name}.{ext}")));
+                match candidates.find(|path| path.exists()) {
+                    Some(path) => path,
+                    None => return Ok(None),
+                }
+            }
+        };
+
+        let bytes = fs::read(&path).context(format!("reading font file {path:?}"))?;
+        let font = fontdue::Font::from_bytes(bytes, fontdue::FontSettings::default())
+            .map_err(|e| Error::InvalidInput(format!("invalid font file {path:?}: {e}")))?;
+        let font = Arc::new(font);
+        self.loaded_fonts
+            .write()
+            .await
+            .insert(font_name.to_string(), font.clone());
+        Ok(Some(font))
+    }
+}
+
+fn read_if_exists(path: &Path) -> Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).context(format!("reading glyphs {path:?}")),
+    }
+}
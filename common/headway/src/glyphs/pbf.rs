@@ -0,0 +1,59 @@
+//! A minimal protobuf encoder for the glyphs format, which is small enough that it's not worth
+//! pulling in a protobuf library:
+//!
+//! ```proto
+//! message glyph {
+//!   required uint32 id = 1;
+//!   optional bytes bitmap = 2;
+//!   required uint32 width = 3;
+//!   required uint32 height = 4;
+//!   required sint32 left = 5;
+//!   required sint32 top = 6;
+//!   required uint32 advance = 7;
+//! }
+//! message fontstack {
+//!   required string name = 1;
+//!   required string range = 2;
+//!   repeated glyph glyphs = 3;
+//! }
+//! message glyphs {
+//!   repeated fontstack stacks = 1;
+//! }
+//! ```
+
+use crate::pbf::{write_len_field, write_varint_field, zigzag};
+
+pub(crate) struct Glyph {
+    pub(crate) id: u32,
+    /// SDF values for a `(width + 2 * buffer) * (height + 2 * buffer)` bitmap
+    pub(crate) bitmap: Vec<u8>,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) left: i32,
+    pub(crate) top: i32,
+    pub(crate) advance: u32,
+}
+
+/// Encodes a `glyphs` message containing a single fontstack
+pub(crate) fn encode(font_name: &str, range: &str, glyphs: &[Glyph]) -> Vec<u8> {
+    let mut fontstack = vec![];
+    write_len_field(&mut fontstack, 1, font_name.as_bytes());
+    write_len_field(&mut fontstack, 2, range.as_bytes());
+    for glyph in glyphs {
+        let mut encoded = vec![];
+        write_varint_field(&mut encoded, 1, u64::from(glyph.id));
+        if !glyph.bitmap.is_empty() {
+            write_len_field(&mut encoded, 2, &glyph.bitmap);
+        }
+        write_varint_field(&mut encoded, 3, u64::from(glyph.width));
+        write_varint_field(&mut encoded, 4, u64::from(glyph.height));
+        write_varint_field(&mut encoded, 5, zigzag(glyph.left));
+        write_varint_field(&mut encoded, 6, zigzag(glyph.top));
+        write_varint_field(&mut encoded, 7, u64::from(glyph.advance));
+        write_len_field(&mut fontstack, 3, &encoded);
+    }
+
+    let mut message = vec![];
+    write_len_field(&mut message, 1, &fontstack);
+    message
+}
@@ -0,0 +1,152 @@
+//! Renders font glyphs as signed distance fields, following the same conventions as
+//! [TinySDF](https://github.com/mapbox/tiny-sdf) and fontnik so the results are interchangeable
+//! with pre-generated ranges.
+
+use super::pbf::{self, Glyph};
+
+/// The font size MapLibre expects glyphs to be rendered at
+const FONT_SIZE: f32 = 24.0;
+/// Padding around each glyph bitmap, so the distance field can extend beyond the glyph outline
+const BUFFER: usize = 3;
+/// How far, in pixels, the distance field extends from the glyph outline
+const RADIUS: f64 = 8.0;
+/// The fraction of the value range used for the inside of the glyph
+const CUTOFF: f64 = 0.25;
+/// Stands in for infinity, while avoiding NaNs when subtracting "infinities"
+const INF: f64 = 1e20;
+
+/// Renders all glyphs for codepoints `start..=end` which `font` contains, encoded as a glyphs
+/// protobuf.
+pub(crate) fn render_range(font: &fontdue::Font, font_name: &str, start: u32, end: u32) -> Vec<u8> {
+    let ascent = font
+        .horizontal_line_metrics(FONT_SIZE)
+        .map_or(FONT_SIZE, |metrics| metrics.ascent)
+        .round() as i32;
+
+    let glyphs: Vec<Glyph> = (start..=end)
+        .filter_map(char::from_u32)
+        .filter(|c| font.lookup_glyph_index(*c) != 0)
+        .map(|c| {
+            let (metrics, coverage) = font.rasterize(c, FONT_SIZE);
+            let bitmap = if metrics.width == 0 || metrics.height == 0 {
+                vec![]
+            } else {
+                signed_distance_field(&coverage, metrics.width, metrics.height)
+            };
+            Glyph {
+                id: u32::from(c),
+                bitmap,
+                width: metrics.width as u32,
+                height: metrics.height as u32,
+                left: metrics.xmin,
+                // fontdue's ymin is the bottom of the bitmap relative to the baseline
+                top: metrics.ymin + metrics.height as i32 - ascent,
+                advance: metrics.advance_width.round() as u32,
+            }
+        })
+        .collect();
+
+    pbf::encode(font_name, &format!("{start}-{end}"), &glyphs)
+}
+
+/// Converts a `width * height` coverage bitmap into a buffered signed distance field.
+fn signed_distance_field(coverage: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let buffered_width = width + 2 * BUFFER;
+    let buffered_height = height + 2 * BUFFER;
+    let len = buffered_width * buffered_height;
+
+    // Squared distances to the nearest pixel outside and inside the glyph respectively
+    let mut outer = vec![INF; len];
+    let mut inner = vec![0.0; len];
+    for y in 0..height {
+        for x in 0..width {
+            let alpha = f64::from(coverage[y * width + x]) / 255.0;
+            let i = (y + BUFFER) * buffered_width + x + BUFFER;
+            if alpha >= 1.0 {
+                outer[i] = 0.0;
+                inner[i] = INF;
+            } else if alpha > 0.0 {
+                // Approximate the sub-pixel position of the edge from the pixel's coverage
+                let d = 0.5 - alpha;
+                outer[i] = if d > 0.0 { d * d } else { 0.0 };
+                inner[i] = if d < 0.0 { d * d } else { 0.0 };
+            }
+        }
+    }
+
+    edt(&mut outer, buffered_width, buffered_height);
+    edt(&mut inner, buffered_width, buffered_height);
+
+    outer
+        .iter()
+        .zip(&inner)
+        .map(|(outer, inner)| {
+            let distance = outer.sqrt() - inner.sqrt();
+            (255.0 - 255.0 * (distance / RADIUS + CUTOFF))
+                .round()
+                .clamp(0.0, 255.0) as u8
+        })
+        .collect()
+}
+
+/// 2D squared Euclidean distance transform, as described by Felzenszwalb & Huttenlocher.
+fn edt(grid: &mut [f64], width: usize, height: usize) {
+    let max_len = width.max(height);
+    let mut f = vec![0.0; max_len];
+    let mut v = vec![0; max_len];
+    let mut z = vec![0.0; max_len + 1];
+    for x in 0..width {
+        edt_1d(grid, x, width, height, &mut f, &mut v, &mut z);
+    }
+    for y in 0..height {
+        edt_1d(grid, y * width, 1, width, &mut f, &mut v, &mut z);
+    }
+}
+
+fn edt_1d(
+    grid: &mut [f64],
+    offset: usize,
+    stride: usize,
+    len: usize,
+    f: &mut [f64],
+    v: &mut [usize],
+    z: &mut [f64],
+) {
+    v[0] = 0;
+    z[0] = -INF;
+    z[1] = INF;
+    f[0] = grid[offset];
+
+    let mut k = 0;
+    for q in 1..len {
+        f[q] = grid[offset + q * stride];
+        let q2 = (q * q) as f64;
+        let mut s;
+        loop {
+            let r = v[k];
+            s = (f[q] - f[r] + q2 - (r * r) as f64) / (q as f64 - r as f64) / 2.0;
+            if s > z[k] || k == 0 {
+                break;
+            }
+            k -= 1;
+        }
+        // The reference implementation lets k reach -1 before incrementing it, whereas a usize
+        // can't go negative, so only step forward if we stopped because of the boundary check
+        if s > z[k] {
+            k += 1;
+        }
+        v[k] = q;
+        z[k] = s;
+        z[k + 1] = INF;
+    }
+
+    let mut k = 0;
+    for q in 0..len {
+        while z[k + 1] < q as f64 {
+            k += 1;
+        }
+        let r = v[k];
+        let qr = q as f64 - r as f64;
+        grid[offset + q * stride] = f[r] + qr * qr;
+    }
+}
@@ -0,0 +1,123 @@
+//! Reads the tracks and routes of GPX files, e.g. a hike planned in another app.
+
+use crate::geo::LatLon;
+use crate::{Error, Result};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde_json::{json, Value};
+
+/// A `<trk>` or `<rte>` of a GPX file
+#[derive(Debug, Default)]
+pub(crate) struct GpxTrack {
+    pub(crate) name: Option<String>,
+    /// A route has a single segment, a track one per `<trkseg>`
+    pub(crate) segments: Vec<Vec<LatLon>>,
+}
+
+impl GpxTrack {
+    pub(crate) fn points(&self) -> impl Iterator<Item = &LatLon> {
+        self.segments.iter().flatten()
+    }
+
+    /// A GeoJSON feature with a MultiLineString of the track's segments
+    pub(crate) fn to_geojson(&self) -> Value {
+        let coordinates: Vec<Vec<[f64; 2]>> = self
+            .segments
+            .iter()
+            .map(|segment| segment.iter().map(|point| [point.lon, point.lat]).collect())
+            .collect();
+        json!({
+            "type": "Feature",
+            "geometry": { "type": "MultiLineString", "coordinates": coordinates },
+            "properties": { "name": self.name },
+        })
+    }
+}
+
+/// The tracks and routes in `xml`, in the order they appear, skipping any without points
+pub(crate) fn parse_gpx(xml: &str) -> Result<Vec<GpxTrack>> {
+    let invalid = |e: String| Error::InvalidInput(format!("invalid GPX: {e}"));
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+
+    let mut tracks = vec![];
+    // Local names of the enclosing elements
+    let mut path: Vec<Vec<u8>> = vec![];
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|e| invalid(format!("at byte {}: {e}", reader.buffer_position())))?;
+        match event {
+            Event::Start(element) => {
+                start_element(&element, &path, &mut tracks)?;
+                path.push(element.local_name().as_ref().to_vec());
+            }
+            Event::Empty(element) => start_element(&element, &path, &mut tracks)?,
+            Event::End(_) => {
+                path.pop();
+            }
+            Event::Text(text) => {
+                let in_track =
+                    path.len() >= 2 && matches!(path[path.len() - 2].as_slice(), b"trk" | b"rte");
+                if in_track && path.last().is_some_and(|name| name == b"name") {
+                    let name = text.unescape().map_err(|e| invalid(e.to_string()))?;
+                    if let Some(track) = tracks.last_mut() {
+                        track.name = Some(name.into_owned());
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    tracks.retain(|track| track.points().next().is_some());
+    Ok(tracks)
+}
+
+fn start_element(element: &BytesStart, path: &[Vec<u8>], tracks: &mut Vec<GpxTrack>) -> Result<()> {
+    let parent = path.last().map(Vec::as_slice);
+    match (parent, element.local_name().as_ref()) {
+        (Some(b"gpx"), b"trk") => tracks.push(GpxTrack::default()),
+        (Some(b"gpx"), b"rte") => tracks.push(GpxTrack {
+            segments: vec![vec![]],
+            ..GpxTrack::default()
+        }),
+        (Some(b"trk"), b"trkseg") => {
+            if let Some(track) = tracks.last_mut() {
+                track.segments.push(vec![]);
+            }
+        }
+        (Some(b"trkseg"), b"trkpt") | (Some(b"rte"), b"rtept") => {
+            let point = parse_point(element)?;
+            if let Some(segment) = tracks
+                .last_mut()
+                .and_then(|track| track.segments.last_mut())
+            {
+                segment.push(point);
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn parse_point(element: &BytesStart) -> Result<LatLon> {
+    let coordinate = |name: &str, max: f64| {
+        let value = element
+            .try_get_attribute(name)
+            .map_err(|e| Error::InvalidInput(format!("invalid GPX: {e}")))?
+            .ok_or_else(|| Error::InvalidInput(format!("invalid GPX: point without {name}")))?
+            .unescape_value()
+            .map_err(|e| Error::InvalidInput(format!("invalid GPX: {e}")))?;
+        value
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|value| value.abs() <= max)
+            .ok_or_else(|| Error::InvalidInput(format!("invalid GPX: {name} {value:?}")))
+    };
+    Ok(LatLon {
+        lat: coordinate("lat", 90.0)?,
+        lon: coordinate("lon", 180.0)?,
+    })
+}
@@ -1,91 +1,208 @@
+mod checksum;
+mod confirmation;
+mod connectivity;
+mod data_budget;
+mod download;
+mod geo;
+mod glyphs;
+mod gpx;
+mod http;
+mod logging;
+mod maneuvers;
 pub mod map_tiles;
+mod mirrors;
+mod pbf;
+mod runtime;
+mod search_history;
 pub mod server;
+mod snapshot;
+mod track_export;
+mod version;
 
-pub use server::HeadwayServer;
+pub use confirmation::DownloadConfirmation;
+pub use connectivity::ConnectivityProvider;
+pub use data_budget::DataBudgetListener;
+pub use download::{DownloadCancellation, DownloadProgress, RetryPolicy};
+pub use geo::{
+    distance_along_polyline, geodesic_distance, initial_bearing, point_in_polygon, polygon_area,
+    polyline_length, LatLon,
+};
+pub use http::HttpTimeouts;
+pub use logging::{enable_log_sink, enable_logging, set_log_level, LogLevel, LogRecord, LogSink};
+pub use maneuvers::{
+    maneuver_instructions, ManeuverInstruction, ManeuverModifier, ManeuverType, RouteManeuver,
+};
+pub use runtime::{configure_runtime, RuntimeConfig};
+pub use search_history::{AutocompleteResult, SearchHistory, SearchHistoryEntry};
+pub use server::{
+    Annotation, Annotations, BlockingHeadwayServer, CorsPolicy, DownloadJob, DownloadJobKind,
+    DownloadJobListener, DownloadJobRetry, DownloadJobState, DownloadManager, HeadwayServer,
+    HeadwayServerConfig, OperationHandle, OperationStatus, PlaceDetails, RasterOverlaySource,
+    RequestLimits, SavedPlace, SavedPlaces, ServerEvent, ServerEventListener,
+};
+pub use track_export::{export_track, TrackFormat, TrackPoint};
+pub use version::{headway_version, VersionInfo};
 
-#[cfg(target_os = "ios")]
-use oslog::OsLogger;
+use reqwest::StatusCode;
 
+/// Why an operation failed, for host apps to branch on, e.g. to suggest freeing up space on
+/// [`Error::InsufficientStorage`]
 #[derive(Debug, thiserror::Error, uniffi::Error)]
-#[uniffi(flat_error)]
 pub enum Error {
     #[error("Failed to create runtime: {0}")]
     Runtime(String),
     #[error("Invalid Input: {0}")]
     InvalidInput(String),
+    #[error("Invalid bounds: {0}")]
+    InvalidBounds(String),
     #[error("Server error: {0}")]
     Serve(String),
-    #[error(transparent)]
-    PmTiles(#[from] pmtiles::PmtError),
-    #[error(transparent)]
-    Reqwest(#[from] reqwest::Error),
-    #[error(transparent)]
-    Io(#[from] std::io::Error),
-    #[error("{1}: {0}")]
-    WithContext(Box<Error>, String),
+    #[error("Server is already running")]
+    AlreadyRunning,
+    #[error("Cancelled")]
+    Cancelled,
+    #[error("Checksum mismatch: expected SHA-256 {expected}, got {actual}")]
+    ChecksumMismatch { expected: String, actual: String },
+    #[error(
+        "Data budget exceeded: {requested_bytes} bytes requested, {remaining_bytes} remaining"
+    )]
+    DataBudgetExceeded {
+        requested_bytes: u64,
+        remaining_bytes: u64,
+    },
+    /// The [`DownloadConfirmation`] declined a download
+    #[error("Download of {total_bytes} bytes was declined")]
+    DownloadDeclined { total_bytes: u64 },
+    /// The [`ConnectivityProvider`] reported there's no network connection
+    #[error("No network connection")]
+    Offline,
+    #[error("Not allowed on a metered network connection")]
+    MeteredConnection,
+    /// A server couldn't be connected to, e.g. because the device is offline or the server is
+    /// down
+    #[error("Network unavailable: {0}")]
+    NetworkUnavailable(String),
+    /// A request failed partway, e.g. because it timed out or the connection dropped
+    #[error("Network error: {0}")]
+    Network(String),
+    /// A server responded with an error status other than 404 Not Found or 410 Gone
+    #[error("HTTP {status}: {message}")]
+    Http { status: u16, message: String },
+    /// A remote source or local file doesn't exist
+    #[error("Not found: {0}")]
+    SourceNotFound(String),
+    /// Something, e.g. a file or tileset, is already there
+    #[error("Already exists: {0}")]
+    AlreadyExists(String),
+    /// The device is out of space to write to
+    #[error("Insufficient storage: {0}")]
+    InsufficientStorage(String),
+    #[error("I/O error: {0}")]
+    Io(String),
+    /// An archive is invalid, or couldn't be read
+    #[error("PMTiles error: {0}")]
+    PmTiles(String),
+    /// The extract source has changed since the extract was planned, e.g. it's been rebuilt, so
+    /// it needs planning again
+    #[error("Extract source changed since the extract was planned")]
+    StalePlan,
 }
 
-pub type Result<T> = std::result::Result<T, Error>;
+impl Error {
+    /// Prefixes the message of `self` with `context`, for the variants that have one
+    fn with_context(self, context: &str) -> Self {
+        let prefix = |message: String| format!("{context}: {message}");
+        match self {
+            Self::Runtime(message) => Self::Runtime(prefix(message)),
+            Self::InvalidInput(message) => Self::InvalidInput(prefix(message)),
+            Self::InvalidBounds(message) => Self::InvalidBounds(prefix(message)),
+            Self::Serve(message) => Self::Serve(prefix(message)),
+            Self::NetworkUnavailable(message) => Self::NetworkUnavailable(prefix(message)),
+            Self::Network(message) => Self::Network(prefix(message)),
+            Self::Http { status, message } => Self::Http {
+                status,
+                message: prefix(message),
+            },
+            Self::SourceNotFound(message) => Self::SourceNotFound(prefix(message)),
+            Self::AlreadyExists(message) => Self::AlreadyExists(prefix(message)),
+            Self::InsufficientStorage(message) => Self::InsufficientStorage(prefix(message)),
+            Self::Io(message) => Self::Io(prefix(message)),
+            Self::PmTiles(message) => Self::PmTiles(prefix(message)),
+            error @ (Self::AlreadyRunning
+            | Self::Cancelled
+            | Self::ChecksumMismatch { .. }
+            | Self::DataBudgetExceeded { .. }
+            | Self::DownloadDeclined { .. }
+            | Self::Offline
+            | Self::MeteredConnection
+            | Self::StalePlan) => error,
+        }
+    }
 
-trait ErrorContext<T> {
-    fn context(self, context: impl ToString) -> Result<T>;
+    fn from_io(error: &std::io::Error) -> Self {
+        let message = error.to_string();
+        match error.kind() {
+            std::io::ErrorKind::NotFound => Self::SourceNotFound(message),
+            std::io::ErrorKind::AlreadyExists => Self::AlreadyExists(message),
+            std::io::ErrorKind::StorageFull => Self::InsufficientStorage(message),
+            _ => Self::Io(message),
+        }
+    }
+
+    fn from_reqwest(error: &reqwest::Error) -> Self {
+        let message = error.to_string();
+        match error.status() {
+            Some(StatusCode::NOT_FOUND | StatusCode::GONE) => Self::SourceNotFound(message),
+            Some(status) => Self::Http {
+                status: status.as_u16(),
+                message,
+            },
+            None if error.is_connect() => Self::NetworkUnavailable(message),
+            None if error.is_builder() => Self::InvalidInput(message),
+            None => Self::Network(message),
+        }
+    }
 }
 
-impl<T, IntoError: Into<Error>> ErrorContext<T> for std::result::Result<T, IntoError> {
-    fn context(self, context: impl ToString) -> Result<T> {
-        self.map_err(|e| Error::WithContext(Box::new(e.into()), context.to_string()))
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Self::from_io(&error)
     }
 }
 
-#[derive(uniffi::Enum)]
-pub enum LogLevel {
-    /// A level lower than all log levels.
-    Off,
-    /// Corresponds to the `Error` log level.
-    Error,
-    /// Corresponds to the `Warn` log level.
-    Warn,
-    /// Corresponds to the `Info` log level.
-    Info,
-    /// Corresponds to the `Debug` log level.
-    Debug,
-    /// Corresponds to the `Trace` log level.
-    Trace,
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Self {
+        Self::from_reqwest(&error)
+    }
 }
 
-impl From<LogLevel> for log::LevelFilter {
-    fn from(value: LogLevel) -> Self {
-        match value {
-            LogLevel::Off => Self::Off,
-            LogLevel::Error => Self::Error,
-            LogLevel::Warn => Self::Warn,
-            LogLevel::Info => Self::Info,
-            LogLevel::Debug => Self::Debug,
-            LogLevel::Trace => Self::Trace,
+impl From<pmtiles::PmtError> for Error {
+    fn from(error: pmtiles::PmtError) -> Self {
+        // Classify by the underlying cause where there is one, e.g. a failed request for an
+        // extract, keeping the archive's description of what it was doing
+        let mut source = std::error::Error::source(&error);
+        while let Some(cause) = source {
+            if let Some(cause) = cause.downcast_ref::<reqwest::Error>() {
+                return Self::from_reqwest(cause).with_context(&error.to_string());
+            }
+            if let Some(cause) = cause.downcast_ref::<std::io::Error>() {
+                return Self::from_io(cause).with_context(&error.to_string());
+            }
+            source = cause.source();
         }
+        Self::PmTiles(error.to_string())
     }
 }
-/// Initializes the logger for the headway library.
-/// This should be called once at application startup.
-/// On iOS, this will use OSLog with the specified subsystem and category.
-#[uniffi::export]
-pub fn enable_logging(subsystem: String, log_level: LogLevel) {
-    #[cfg(target_os = "ios")]
-    {
-        OsLogger::new(&subsystem)
-            .level_filter(log_level.into())
-            .init()
-            .ok(); // Ignore error if already initialized
-    }
 
-    #[cfg(not(target_os = "ios"))]
-    {
-        // Fallback for non-iOS platforms (e.g., simulator or tests)
-        let _ = subsystem; // Avoid unused variable warning
-        env_logger::Builder::from_default_env()
-            .filter_level(log_level.into())
-            .try_init()
-            .ok();
+pub type Result<T> = std::result::Result<T, Error>;
+
+trait ErrorContext<T> {
+    fn context(self, context: impl ToString) -> Result<T>;
+}
+
+impl<T, IntoError: Into<Error>> ErrorContext<T> for std::result::Result<T, IntoError> {
+    fn context(self, context: impl ToString) -> Result<T> {
+        self.map_err(|e| e.into().with_context(&context.to_string()))
     }
 }
 
@@ -0,0 +1,166 @@
+//! Routes the library's `log` records to the platform logger, e.g. OSLog or logcat, and to a
+//! [`LogSink`] provided by the host app, e.g. to attach recent logs to a bug report.
+
+use log::{Log, Metadata, Record};
+use std::sync::{Arc, Mutex, OnceLock};
+
+#[derive(uniffi::Enum)]
+pub enum LogLevel {
+    /// A level lower than all log levels.
+    Off,
+    /// Corresponds to the `Error` log level.
+    Error,
+    /// Corresponds to the `Warn` log level.
+    Warn,
+    /// Corresponds to the `Info` log level.
+    Info,
+    /// Corresponds to the `Debug` log level.
+    Debug,
+    /// Corresponds to the `Trace` log level.
+    Trace,
+}
+
+impl From<LogLevel> for log::LevelFilter {
+    fn from(value: LogLevel) -> Self {
+        match value {
+            LogLevel::Off => Self::Off,
+            LogLevel::Error => Self::Error,
+            LogLevel::Warn => Self::Warn,
+            LogLevel::Info => Self::Info,
+            LogLevel::Debug => Self::Debug,
+            LogLevel::Trace => Self::Trace,
+        }
+    }
+}
+
+impl From<log::Level> for LogLevel {
+    fn from(value: log::Level) -> Self {
+        match value {
+            log::Level::Error => Self::Error,
+            log::Level::Warn => Self::Warn,
+            log::Level::Info => Self::Info,
+            log::Level::Debug => Self::Debug,
+            log::Level::Trace => Self::Trace,
+        }
+    }
+}
+
+#[derive(uniffi::Record)]
+pub struct LogRecord {
+    /// Never [`LogLevel::Off`]
+    pub level: LogLevel,
+    /// The module the record was logged from, e.g. `headway::server`
+    pub target: String,
+    pub message: String,
+}
+
+#[uniffi::export(with_foreign)]
+pub trait LogSink: Send + Sync {
+    /// Called on whichever thread logged the record, so shouldn't block
+    fn log(&self, record: LogRecord);
+}
+
+/// Dispatches to the platform logger and sink, whichever are enabled.
+///
+/// The `log` crate only allows one logger to be installed, so both are installed through this,
+/// and filtered by the global max level rather than their own.
+struct Logger {
+    platform: OnceLock<Box<dyn Log>>,
+    sink: Mutex<Option<Arc<dyn LogSink>>>,
+}
+
+static LOGGER: Logger = Logger {
+    platform: OnceLock::new(),
+    sink: Mutex::new(None),
+};
+
+impl Logger {
+    fn install(&'static self, log_level: LogLevel) {
+        // Ignore error if already installed
+        log::set_logger(self).ok();
+        log::set_max_level(log_level.into());
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if let Some(platform) = self.platform.get() {
+            platform.log(record);
+        }
+        // Not holding the lock while calling out, in case the sink replaces itself
+        let sink = self.sink.lock().expect("poisoned lock").clone();
+        if let Some(sink) = sink {
+            sink.log(LogRecord {
+                level: record.level().into(),
+                target: record.target().to_string(),
+                message: record.args().to_string(),
+            });
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(platform) = self.platform.get() {
+            platform.flush();
+        }
+    }
+}
+
+/// Initializes the logger for the headway library.
+/// This should be called once at application startup.
+/// On iOS, this will use OSLog with the specified subsystem and category.
+/// On Android, this will log to logcat with `subsystem` as the tag.
+#[uniffi::export]
+pub fn enable_logging(subsystem: String, log_level: LogLevel) {
+    // Ignore error if already initialized
+    LOGGER.platform.set(platform_logger(&subsystem)).ok();
+    LOGGER.install(log_level);
+}
+
+/// Sends the library's log records at `log_level` and above to `sink`, in place of any previous
+/// sink, as well as to the platform logger if [`enable_logging`] was called.
+///
+/// The level applies to the platform logger too, whichever function was called last.
+#[uniffi::export]
+pub fn enable_log_sink(sink: Arc<dyn LogSink>, log_level: LogLevel) {
+    *LOGGER.sink.lock().expect("poisoned lock") = Some(sink);
+    LOGGER.install(log_level);
+}
+
+/// Changes which log records are sent to the platform logger and sink, e.g. to turn on debug
+/// logging from a settings screen without restarting the app
+#[uniffi::export]
+pub fn set_log_level(log_level: LogLevel) {
+    log::set_max_level(log_level.into());
+}
+
+#[cfg(target_os = "ios")]
+fn platform_logger(subsystem: &str) -> Box<dyn Log> {
+    Box::new(oslog::OsLogger::new(subsystem).level_filter(log::LevelFilter::Trace))
+}
+
+#[cfg(target_os = "android")]
+fn platform_logger(subsystem: &str) -> Box<dyn Log> {
+    Box::new(android_logger::AndroidLogger::new(
+        android_logger::Config::default()
+            .with_max_level(log::LevelFilter::Trace)
+            .with_tag(subsystem),
+    ))
+}
+
+#[cfg(not(any(target_os = "ios", target_os = "android")))]
+fn platform_logger(subsystem: &str) -> Box<dyn Log> {
+    // Fallback for non-mobile platforms (e.g., simulator or tests)
+    let _ = subsystem; // Avoid unused variable warning
+    Box::new(
+        env_logger::Builder::from_default_env()
+            .filter_level(log::LevelFilter::Trace)
+            .build(),
+    )
+}
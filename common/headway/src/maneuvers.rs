@@ -0,0 +1,466 @@
+//! Turns the maneuvers of a route leg, as returned by OSRM or Valhalla, into instructions in the
+//! user's language, for both display and text-to-speech, so each app doesn't have to template
+//! them itself.
+//!
+//! English, German, French and Spanish are supported, falling back to English for other
+//! languages. Distances are in miles in the US, UK, Liberia and Myanmar, and kilometers
+//! elsewhere.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum ManeuverType {
+    /// The start of the leg
+    Depart,
+    /// The end of the leg
+    Arrive,
+    Turn,
+    /// Continuing along the road, e.g. where it changes name
+    Continue,
+    Merge,
+    OnRamp,
+    OffRamp,
+    Fork,
+    /// Entering a roundabout, with [`RouteManeuver::roundabout_exit`] the exit to take
+    Roundabout,
+    ExitRoundabout,
+}
+
+/// The direction of a maneuver
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum ManeuverModifier {
+    UTurn,
+    SharpRight,
+    Right,
+    SlightRight,
+    Straight,
+    SlightLeft,
+    Left,
+    SharpLeft,
+}
+
+/// A maneuver of a route leg, e.g. an OSRM step's maneuver
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct RouteManeuver {
+    pub maneuver_type: ManeuverType,
+    pub modifier: Option<ManeuverModifier>,
+    /// The name of the road the maneuver leads onto, if it has one
+    pub street_name: Option<String>,
+    /// For [`ManeuverType::Roundabout`], which exit to take, counting from 1
+    pub roundabout_exit: Option<u32>,
+    /// How far to travel after the maneuver, until the next one, in meters
+    pub distance_m: f64,
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct ManeuverInstruction {
+    /// For display, e.g. "Turn left onto Main Street"
+    pub text: String,
+    /// To announce while approaching the maneuver, e.g. "In 200 meters, turn left onto Main
+    /// Street"
+    pub spoken_text: String,
+}
+
+/// The instructions for each of `maneuvers`, in the language of `locale`, a BCP 47 tag like
+/// "en-US" or "de_DE", usually the device's.
+#[uniffi::export]
+pub fn maneuver_instructions(
+    maneuvers: Vec<RouteManeuver>,
+    locale: String,
+) -> Vec<ManeuverInstruction> {
+    let (language, units) = parse_locale(&locale);
+    let phrases = language.phrases();
+    let mut previous_distance_m = None;
+    maneuvers
+        .iter()
+        .map(|maneuver| {
+            let text = instruction(phrases, maneuver);
+            let spoken_text = match previous_distance_m {
+                // Announced before reaching the maneuver
+                Some(distance_m) => {
+                    let instruction = if phrases.lowercase_instruction {
+                        lowercase_first(&text)
+                    } else {
+                        text.clone()
+                    };
+                    phrases
+                        .in_distance
+                        .replace("{distance}", &format_distance(language, units, distance_m))
+                        .replace("{instruction}", &instruction)
+                }
+                None => text.clone(),
+            };
+            previous_distance_m = Some(maneuver.distance_m);
+            ManeuverInstruction { text, spoken_text }
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Language {
+    English,
+    German,
+    French,
+    Spanish,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DistanceUnits {
+    Metric,
+    /// Miles, and feet for short distances
+    Imperial,
+    /// Miles, and yards for short distances
+    ImperialYards,
+}
+
+fn parse_locale(locale: &str) -> (Language, DistanceUnits) {
+    let mut parts = locale.split(['-', '_']);
+    let language = match parts.next().map(str::to_ascii_lowercase).as_deref() {
+        Some("de") => Language::German,
+        Some("fr") => Language::French,
+        Some("es") => Language::Spanish,
+        _ => Language::English,
+    };
+    // The region is the first two letter subtag, after any script subtag like "Latn"
+    let region = parts
+        .find(|part| part.len() == 2)
+        .map(str::to_ascii_uppercase);
+    let units = match region.as_deref() {
+        Some("US" | "LR" | "MM") => DistanceUnits::Imperial,
+        Some("GB") => DistanceUnits::ImperialYards,
+        _ => DistanceUnits::Metric,
+    };
+    (language, units)
+}
+
+/// Instruction templates, each without then with the street name, where `{name}` is replaced by
+/// it, and `{direction}` by a [`Phrases::directions`] entry.
+struct Phrases {
+    depart: (&'static str, &'static str),
+    arrive: &'static str,
+    turn: (&'static str, &'static str),
+    u_turn: (&'static str, &'static str),
+    continue_: (&'static str, &'static str),
+    merge: (&'static str, &'static str),
+    on_ramp: (&'static str, &'static str),
+    off_ramp: (&'static str, &'static str),
+    fork: (&'static str, &'static str),
+    /// `{exit}` is replaced by the ordinal of the exit
+    roundabout_exit: (&'static str, &'static str),
+    roundabout: (&'static str, &'static str),
+    exit_roundabout: (&'static str, &'static str),
+    /// In [`ManeuverModifier`] order, excluding [`ManeuverModifier::UTurn`]
+    directions: [&'static str; 7],
+    /// `{distance}` and `{instruction}` are replaced
+    in_distance: &'static str,
+    /// Whether the instruction's first letter is lowercased within `in_distance`, which it
+    /// mustn't be where it could be a noun, as in German
+    lowercase_instruction: bool,
+    ordinals: [&'static str; 10],
+    /// For exits beyond the tenth, where `{n}` is replaced by the number
+    numbered_ordinal: &'static str,
+    decimal_separator: char,
+    /// Each singular then plural
+    meters: (&'static str, &'static str),
+    kilometers: (&'static str, &'static str),
+    feet: (&'static str, &'static str),
+    yards: (&'static str, &'static str),
+    miles: (&'static str, &'static str),
+}
+
+const ENGLISH: Phrases = Phrases {
+    depart: ("Head out", "Head out on {name}"),
+    arrive: "Arrive at your destination",
+    turn: ("Turn {direction}", "Turn {direction} onto {name}"),
+    u_turn: ("Make a U-turn", "Make a U-turn onto {name}"),
+    continue_: ("Continue {direction}", "Continue {direction} on {name}"),
+    merge: ("Merge", "Merge onto {name}"),
+    on_ramp: ("Take the ramp", "Take the ramp onto {name}"),
+    off_ramp: ("Take the exit", "Take the exit onto {name}"),
+    fork: (
+        "Keep {direction} at the fork",
+        "Keep {direction} at the fork onto {name}",
+    ),
+    roundabout_exit: (
+        "At the roundabout, take the {exit} exit",
+        "At the roundabout, take the {exit} exit onto {name}",
+    ),
+    roundabout: ("Enter the roundabout", "Enter the roundabout toward {name}"),
+    exit_roundabout: ("Exit the roundabout", "Exit the roundabout onto {name}"),
+    directions: [
+        "sharp right",
+        "right",
+        "slightly right",
+        "straight",
+        "slightly left",
+        "left",
+        "sharp left",
+    ],
+    in_distance: "In {distance}, {instruction}",
+    lowercase_instruction: true,
+    ordinals: [
+        "first", "second", "third", "fourth", "fifth", "sixth", "seventh", "eighth", "ninth",
+        "tenth",
+    ],
+    numbered_ordinal: "{n}th",
+    decimal_separator: '.',
+    meters: ("meter", "meters"),
+    kilometers: ("kilometer", "kilometers"),
+    feet: ("foot", "feet"),
+    yards: ("yard", "yards"),
+    miles: ("mile", "miles"),
+};
+
+const GERMAN: Phrases = Phrases {
+    depart: ("Losfahren", "Auf {name} losfahren"),
+    arrive: "Ziel erreichen",
+    turn: ("{direction} abbiegen", "{direction} abbiegen auf {name}"),
+    u_turn: ("Wenden", "Wenden auf {name}"),
+    continue_: (
+        "{direction} weiterfahren",
+        "{direction} weiterfahren auf {name}",
+    ),
+    merge: ("Einfädeln", "Auf {name} einfädeln"),
+    on_ramp: ("Auffahrt nehmen", "Auffahrt auf {name} nehmen"),
+    off_ramp: ("Ausfahrt nehmen", "Ausfahrt auf {name} nehmen"),
+    fork: (
+        "An der Gabelung {direction} halten",
+        "An der Gabelung {direction} halten auf {name}",
+    ),
+    roundabout_exit: (
+        "Im Kreisverkehr die {exit} Ausfahrt nehmen",
+        "Im Kreisverkehr die {exit} Ausfahrt nehmen auf {name}",
+    ),
+    roundabout: (
+        "In den Kreisverkehr fahren",
+        "In den Kreisverkehr Richtung {name} fahren",
+    ),
+    exit_roundabout: (
+        "Kreisverkehr verlassen",
+        "Kreisverkehr verlassen auf {name}",
+    ),
+    directions: [
+        "Scharf rechts",
+        "Rechts",
+        "Leicht rechts",
+        "Geradeaus",
+        "Leicht links",
+        "Links",
+        "Scharf links",
+    ],
+    in_distance: "In {distance}: {instruction}",
+    lowercase_instruction: false,
+    ordinals: [
+        "erste", "zweite", "dritte", "vierte", "fünfte", "sechste", "siebte", "achte", "neunte",
+        "zehnte",
+    ],
+    numbered_ordinal: "{n}.",
+    decimal_separator: ',',
+    meters: ("Meter", "Metern"),
+    kilometers: ("Kilometer", "Kilometern"),
+    feet: ("Fuß", "Fuß"),
+    yards: ("Yard", "Yards"),
+    miles: ("Meile", "Meilen"),
+};
+
+const FRENCH: Phrases = Phrases {
+    depart: ("Partez", "Partez sur {name}"),
+    arrive: "Arrivée à destination",
+    turn: ("Tournez {direction}", "Tournez {direction} sur {name}"),
+    u_turn: ("Faites demi-tour", "Faites demi-tour sur {name}"),
+    continue_: ("Continuez {direction}", "Continuez {direction} sur {name}"),
+    merge: ("Insérez-vous", "Insérez-vous sur {name}"),
+    on_ramp: ("Prenez la bretelle", "Prenez la bretelle vers {name}"),
+    off_ramp: ("Prenez la sortie", "Prenez la sortie vers {name}"),
+    fork: (
+        "À l'embranchement, restez {direction}",
+        "À l'embranchement, restez {direction} sur {name}",
+    ),
+    roundabout_exit: (
+        "Au rond-point, prenez la {exit} sortie",
+        "Au rond-point, prenez la {exit} sortie sur {name}",
+    ),
+    roundabout: (
+        "Entrez dans le rond-point",
+        "Entrez dans le rond-point vers {name}",
+    ),
+    exit_roundabout: ("Sortez du rond-point", "Sortez du rond-point sur {name}"),
+    directions: [
+        "franchement à droite",
+        "à droite",
+        "légèrement à droite",
+        "tout droit",
+        "légèrement à gauche",
+        "à gauche",
+        "franchement à gauche",
+    ],
+    in_distance: "Dans {distance}, {instruction}",
+    lowercase_instruction: true,
+    ordinals: [
+        "première",
+        "deuxième",
+        "troisième",
+        "quatrième",
+        "cinquième",
+        "sixième",
+        "septième",
+        "huitième",
+        "neuvième",
+        "dixième",
+    ],
+    numbered_ordinal: "{n}e",
+    decimal_separator: ',',
+    meters: ("mètre", "mètres"),
+    kilometers: ("kilomètre", "kilomètres"),
+    feet: ("pied", "pieds"),
+    yards: ("yard", "yards"),
+    miles: ("mile", "miles"),
+};
+
+const SPANISH: Phrases = Phrases {
+    depart: ("Sal", "Sal por {name}"),
+    arrive: "Llega a tu destino",
+    turn: ("Gira {direction}", "Gira {direction} hacia {name}"),
+    u_turn: ("Da la vuelta", "Da la vuelta hacia {name}"),
+    continue_: ("Continúa {direction}", "Continúa {direction} por {name}"),
+    merge: ("Incorpórate", "Incorpórate a {name}"),
+    on_ramp: ("Toma la rampa", "Toma la rampa hacia {name}"),
+    off_ramp: ("Toma la salida", "Toma la salida hacia {name}"),
+    fork: (
+        "En la bifurcación, mantente {direction}",
+        "En la bifurcación, mantente {direction} hacia {name}",
+    ),
+    roundabout_exit: (
+        "En la rotonda, toma la {exit} salida",
+        "En la rotonda, toma la {exit} salida hacia {name}",
+    ),
+    roundabout: ("Entra en la rotonda", "Entra en la rotonda hacia {name}"),
+    exit_roundabout: ("Sal de la rotonda", "Sal de la rotonda hacia {name}"),
+    directions: [
+        "bruscamente a la derecha",
+        "a la derecha",
+        "ligeramente a la derecha",
+        "recto",
+        "ligeramente a la izquierda",
+        "a la izquierda",
+        "bruscamente a la izquierda",
+    ],
+    in_distance: "En {distance}, {instruction}",
+    lowercase_instruction: true,
+    ordinals: [
+        "primera", "segunda", "tercera", "cuarta", "quinta", "sexta", "séptima", "octava",
+        "novena", "décima",
+    ],
+    numbered_ordinal: "{n}.ª",
+    decimal_separator: ',',
+    meters: ("metro", "metros"),
+    kilometers: ("kilómetro", "kilómetros"),
+    feet: ("pie", "pies"),
+    yards: ("yarda", "yardas"),
+    miles: ("milla", "millas"),
+};
+
+impl Language {
+    fn phrases(self) -> &'static Phrases {
+        match self {
+            Self::English => &ENGLISH,
+            Self::German => &GERMAN,
+            Self::French => &FRENCH,
+            Self::Spanish => &SPANISH,
+        }
+    }
+}
+
+fn instruction(phrases: &Phrases, maneuver: &RouteManeuver) -> String {
+    let modifier = maneuver.modifier;
+    let template = match maneuver.maneuver_type {
+        ManeuverType::Depart => phrases.depart,
+        ManeuverType::Arrive => (phrases.arrive, phrases.arrive),
+        ManeuverType::Turn if modifier == Some(ManeuverModifier::UTurn) => phrases.u_turn,
+        ManeuverType::Turn => phrases.turn,
+        ManeuverType::Continue if modifier == Some(ManeuverModifier::UTurn) => phrases.u_turn,
+        ManeuverType::Continue => phrases.continue_,
+        ManeuverType::Merge => phrases.merge,
+        ManeuverType::OnRamp => phrases.on_ramp,
+        ManeuverType::OffRamp => phrases.off_ramp,
+        ManeuverType::Fork => phrases.fork,
+        ManeuverType::Roundabout if maneuver.roundabout_exit.is_some() => phrases.roundabout_exit,
+        ManeuverType::Roundabout => phrases.roundabout,
+        ManeuverType::ExitRoundabout => phrases.exit_roundabout,
+    };
+    let text = match maneuver
+        .street_name
+        .as_deref()
+        .filter(|name| !name.is_empty())
+    {
+        Some(name) => template.1.replace("{name}", name),
+        None => template.0.to_string(),
+    };
+    let direction = match modifier {
+        Some(ManeuverModifier::SharpRight) => phrases.directions[0],
+        Some(ManeuverModifier::Right) => phrases.directions[1],
+        Some(ManeuverModifier::SlightRight) => phrases.directions[2],
+        Some(ManeuverModifier::SlightLeft) => phrases.directions[4],
+        Some(ManeuverModifier::Left) => phrases.directions[5],
+        Some(ManeuverModifier::SharpLeft) => phrases.directions[6],
+        Some(ManeuverModifier::Straight | ManeuverModifier::UTurn) | None => phrases.directions[3],
+    };
+    let exit = match maneuver.roundabout_exit {
+        Some(exit @ 1..=10) => phrases.ordinals[exit as usize - 1].to_string(),
+        Some(exit) => phrases.numbered_ordinal.replace("{n}", &exit.to_string()),
+        None => String::new(),
+    };
+    text.replace("{direction}", direction)
+        .replace("{exit}", &exit)
+}
+
+/// A distance rounded as it would be spoken, e.g. "1.5 kilometers" or "200 feet"
+fn format_distance(language: Language, units: DistanceUnits, meters: f64) -> String {
+    const METERS_PER_MILE: f64 = 1_609.344;
+    const METERS_PER_FOOT: f64 = 0.3048;
+    const METERS_PER_YARD: f64 = 0.9144;
+
+    let phrases = language.phrases();
+    let (value, unit) = match units {
+        DistanceUnits::Metric if meters < 1_000.0 => (round_short(meters), phrases.meters),
+        DistanceUnits::Metric => (round_long(meters / 1_000.0), phrases.kilometers),
+        // Below a tenth of a mile
+        DistanceUnits::Imperial if meters < METERS_PER_MILE / 10.0 => {
+            (round_short(meters / METERS_PER_FOOT), phrases.feet)
+        }
+        DistanceUnits::ImperialYards if meters < METERS_PER_MILE / 10.0 => {
+            (round_short(meters / METERS_PER_YARD), phrases.yards)
+        }
+        DistanceUnits::Imperial | DistanceUnits::ImperialYards => {
+            (round_long(meters / METERS_PER_MILE), phrases.miles)
+        }
+    };
+    let number = if value.fract() == 0.0 {
+        format!("{value:.0}")
+    } else {
+        format!("{value:.1}").replace('.', &phrases.decimal_separator.to_string())
+    };
+    let unit = if value == 1.0 { unit.0 } else { unit.1 };
+    format!("{number} {unit}")
+}
+
+/// To the nearest 10 below 100, and the nearest 50 above
+fn round_short(value: f64) -> f64 {
+    let step = if value < 100.0 { 10.0 } else { 50.0 };
+    ((value / step).round() * step).max(step)
+}
+
+/// To one decimal place below 10, and whole numbers above
+fn round_long(value: f64) -> f64 {
+    if value < 10.0 {
+        (value * 10.0).round() / 10.0
+    } else {
+        value.round()
+    }
+}
+
+fn lowercase_first(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_lowercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
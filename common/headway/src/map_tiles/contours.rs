@@ -0,0 +1,324 @@
+//! Contour line vector tiles generated on the fly from a terrain-RGB tileset, so outdoor styles
+//! can show contours without downloading a contour tileset too.
+//!
+//! Elevations are sampled on a grid across each tile, from the terrain tile at the same zoom or
+//! the highest zoom below it, and traced with marching squares. Samples on a tile's edges are
+//! shared with its neighbors, so lines join up across tiles. Every line is in the
+//! [`CONTOUR_LAYER`] layer, with its elevation in meters as `ele`, and `level` 1 for the major
+//! lines every few intervals, or 0.
+
+use super::overlay_tiles::{encode_lines, LayerEncoder, PropertyValue, EXTENT};
+use super::terrain::{TerrainEncoding, TerrainTile};
+use super::tile_format::Tile;
+use super::TileCollection;
+use crate::{Error, Result};
+use pmtiles::TileType;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// The source id contour tiles are served as, in place of any tileset with the same id
+pub(crate) const CONTOUR_SOURCE_ID: &str = "contours";
+
+/// The layer of every contour tile
+const CONTOUR_LAYER: &str = "contours";
+
+/// How many zooms past the terrain tileset's highest contour tiles are generated for, as lines
+/// interpolated from its elevations are still smooth at a zoom or two beyond
+const CONTOUR_OVERZOOM: u8 = 2;
+
+/// How many cells elevations are sampled in across each tile
+const GRID_SIZE: usize = 256;
+
+/// The interval between contours at zoom `z` and how many intervals apart major contours are,
+/// which are denser the further in
+fn contour_interval(z: u8) -> (f64, u32) {
+    match z {
+        0..=10 => (100.0, 5),
+        11..=12 => (50.0, 5),
+        13 => (20.0, 5),
+        _ => (10.0, 5),
+    }
+}
+
+/// The terrain tiles needed for a contour tile, fetched so the contours can then be traced
+/// without holding the tile collection
+pub(crate) struct ContourSource {
+    z: u8,
+    x: u32,
+    y: u32,
+    terrain_z: u8,
+    encoding: TerrainEncoding,
+    terrain_tiles: HashMap<(u32, u32), Tile>,
+}
+
+/// Fetches the tiles of the terrain tileset `tileset_id` needed for the contour tile `z/x/y`, or
+/// returns `None` if it's outside the tileset's zooms
+pub(crate) async fn contour_source(
+    collection: &TileCollection,
+    tileset_id: &str,
+    z: u8,
+    x: u32,
+    y: u32,
+) -> Result<Option<ContourSource>> {
+    let coverage = collection
+        .coverage(tileset_id)
+        .ok_or_else(|| Error::InvalidInput(format!("no such tileset: {tileset_id}")))?;
+    if !matches!(
+        collection.tile_type(tileset_id),
+        Some(TileType::Png | TileType::Webp)
+    ) {
+        return Err(Error::InvalidInput(format!(
+            "{tileset_id} isn't a terrain-RGB tileset"
+        )));
+    }
+    if z < coverage.min_zoom() || z > coverage.max_zoom().saturating_add(CONTOUR_OVERZOOM) {
+        return Ok(None);
+    }
+    let terrain_z = z.min(coverage.max_zoom());
+    let (terrain_x, terrain_y) = (x >> (z - terrain_z), y >> (z - terrain_z));
+    // Samples on the right and bottom edges may be in the next tiles
+    let max = (1u32 << terrain_z) - 1;
+    let mut terrain_tiles = HashMap::new();
+    for tile_x in terrain_x..=(terrain_x + 1).min(max) {
+        for tile_y in terrain_y..=(terrain_y + 1).min(max) {
+            if let Some(tile) = collection
+                .get_tile(tileset_id, terrain_z, tile_x, tile_y)
+                .await?
+            {
+                terrain_tiles.insert((tile_x, tile_y), tile);
+            }
+        }
+    }
+    Ok(Some(ContourSource {
+        z,
+        x,
+        y,
+        terrain_z,
+        encoding: TerrainEncoding::from_metadata(collection.metadata(tileset_id)),
+        terrain_tiles,
+    }))
+}
+
+/// A [TileJSON](https://github.com/mapbox/tilejson-spec) document describing the contour tiles
+/// generated from the terrain tileset `tileset_id`, or `None` if it has no sources.
+///
+/// `source_url` is the URL under which `{z}/{x}/{y}` tiles are served.
+pub(crate) fn contour_tile_json(
+    collection: &TileCollection,
+    tileset_id: &str,
+    source_url: &str,
+) -> Option<Value> {
+    let coverage = collection.coverage(tileset_id)?;
+    let bounds = coverage.bounds();
+    let [max_lat, max_lon, min_lat, min_lon] = bounds.as_nesw();
+    Some(json!({
+        "tilejson": "3.0.0",
+        "name": CONTOUR_SOURCE_ID,
+        "tiles": [format!("{source_url}/{{z}}/{{x}}/{{y}}.pbf")],
+        "format": "pbf",
+        "minzoom": coverage.min_zoom(),
+        "maxzoom": coverage.max_zoom().saturating_add(CONTOUR_OVERZOOM),
+        "bounds": [min_lon, min_lat, max_lon, max_lat],
+        "center": [
+            (min_lon + max_lon) / 2.0,
+            (min_lat + max_lat) / 2.0,
+            coverage.min_zoom()
+        ],
+        "vector_layers": [{
+            "id": CONTOUR_LAYER,
+            "fields": {
+                "ele": "Number",
+                "level": "Number",
+            },
+            "minzoom": coverage.min_zoom(),
+            "maxzoom": coverage.max_zoom().saturating_add(CONTOUR_OVERZOOM),
+        }],
+    }))
+}
+
+/// Identifies the point where a contour crosses a grid edge: the column and row of the edge's
+/// top or left end, and whether the edge is vertical
+type EdgeKey = (usize, usize, bool);
+
+type Segment = [(EdgeKey, (f64, f64)); 2];
+
+impl ContourSource {
+    /// The encoded contour tile, or `None` if it has no contours
+    pub(crate) fn tile(self) -> Result<Option<Vec<u8>>> {
+        let terrain_tiles = self
+            .terrain_tiles
+            .into_iter()
+            .map(|(coords, tile)| Ok((coords, TerrainTile::decode(tile)?)))
+            .collect::<Result<HashMap<_, _>>>()?;
+        let Some(size) = terrain_tiles.values().next().map(|tile| tile.size as f64) else {
+            return Ok(None);
+        };
+
+        // Elevations at each grid point, row by row, or NaN where there's no terrain tile
+        let scale = f64::from(1u32 << (self.z - self.terrain_z));
+        let max_tile = (1u32 << self.terrain_z) - 1;
+        let mut grid = Vec::with_capacity((GRID_SIZE + 1) * (GRID_SIZE + 1));
+        for row in 0..=GRID_SIZE {
+            // In terrain tiles, at the terrain zoom
+            let terrain_y = (f64::from(self.y) + row as f64 / GRID_SIZE as f64) / scale;
+            for column in 0..=GRID_SIZE {
+                let terrain_x = (f64::from(self.x) + column as f64 / GRID_SIZE as f64) / scale;
+                let mut tile_coords = (
+                    (terrain_x.floor() as u32).min(max_tile),
+                    (terrain_y.floor() as u32).min(max_tile),
+                );
+                // A point on the far edge of the world, or of the tileset, is in the tile before
+                if !terrain_tiles.contains_key(&tile_coords) && terrain_x.fract() == 0.0 {
+                    tile_coords.0 = tile_coords.0.saturating_sub(1);
+                }
+                if !terrain_tiles.contains_key(&tile_coords) && terrain_y.fract() == 0.0 {
+                    tile_coords.1 = tile_coords.1.saturating_sub(1);
+                }
+                let elevation = terrain_tiles.get(&tile_coords).map_or(f64::NAN, |tile| {
+                    tile.elevation_at(
+                        self.encoding,
+                        (terrain_x - f64::from(tile_coords.0)) * size,
+                        (terrain_y - f64::from(tile_coords.1)) * size,
+                    )
+                });
+                grid.push(elevation);
+            }
+        }
+
+        let (interval, major_every) = contour_interval(self.z);
+        let mut layer = LayerEncoder::default();
+        let mut levels: Vec<(i64, Vec<Segment>)> = trace(&grid, interval).into_iter().collect();
+        levels.sort_by_key(|(level, _)| *level);
+        for (level, segments) in levels {
+            let lines: Vec<Vec<(f64, f64)>> = join(&segments);
+            let Some((geometry_type, geometry)) = encode_lines(&lines, |point| point) else {
+                continue;
+            };
+            let properties = [
+                (
+                    "ele".to_string(),
+                    PropertyValue::Number(level as f64 * interval),
+                ),
+                (
+                    "level".to_string(),
+                    PropertyValue::Number(if level % i64::from(major_every) == 0 {
+                        1.0
+                    } else {
+                        0.0
+                    }),
+                ),
+            ];
+            layer.add_feature(None, &properties, geometry_type, &geometry);
+        }
+        Ok(layer.finish(CONTOUR_LAYER))
+    }
+}
+
+/// The segments of each contour crossing `grid`, keyed by the contour's multiple of `interval`,
+/// by marching squares
+fn trace(grid: &[f64], interval: f64) -> HashMap<i64, Vec<Segment>> {
+    let value = |column: usize, row: usize| grid[row * (GRID_SIZE + 1) + column];
+    let to_tile = |column: f64, row: f64| {
+        (
+            column / GRID_SIZE as f64 * EXTENT,
+            row / GRID_SIZE as f64 * EXTENT,
+        )
+    };
+    let mut levels: HashMap<i64, Vec<Segment>> = HashMap::new();
+    for row in 0..GRID_SIZE {
+        for column in 0..GRID_SIZE {
+            let corners = [
+                value(column, row),
+                value(column + 1, row),
+                value(column + 1, row + 1),
+                value(column, row + 1),
+            ];
+            if corners.iter().any(|elevation| elevation.is_nan()) {
+                continue;
+            }
+            let [top_left, top_right, bottom_right, bottom_left] = corners;
+            let min = corners.iter().copied().fold(f64::INFINITY, f64::min);
+            let max = corners.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            let first_level = (min / interval).ceil() as i64;
+            let last_level = (max / interval).floor() as i64;
+            for level in first_level..=last_level {
+                let elevation = level as f64 * interval;
+                // Where the contour crosses each edge, if it does
+                let crossing = |a: f64, b: f64| {
+                    ((a >= elevation) != (b >= elevation)).then(|| (elevation - a) / (b - a))
+                };
+                let (c, r) = (column as f64, row as f64);
+                let top = crossing(top_left, top_right)
+                    .map(|t| ((column, row, false), to_tile(c + t, r)));
+                let bottom = crossing(bottom_left, bottom_right)
+                    .map(|t| ((column, row + 1, false), to_tile(c + t, r + 1.0)));
+                let left = crossing(top_left, bottom_left)
+                    .map(|t| ((column, row, true), to_tile(c, r + t)));
+                let right = crossing(top_right, bottom_right)
+                    .map(|t| ((column + 1, row, true), to_tile(c + 1.0, r + t)));
+                let segments = levels.entry(level).or_default();
+                let crossings = [top, right, bottom, left];
+                if let [Some(top), Some(right), Some(bottom), Some(left)] = crossings {
+                    // A saddle, resolved by the elevation at the cell's center: if it's on the
+                    // same side as the top left and bottom right corners, they're connected and
+                    // the contours cut off the other two
+                    let center = (top_left + top_right + bottom_right + bottom_left) / 4.0;
+                    if (center >= elevation) == (top_left >= elevation) {
+                        segments.push([top, right]);
+                        segments.push([bottom, left]);
+                    } else {
+                        segments.push([left, top]);
+                        segments.push([right, bottom]);
+                    }
+                } else {
+                    let mut ends = crossings.into_iter().flatten();
+                    if let (Some(first), Some(second)) = (ends.next(), ends.next()) {
+                        segments.push([first, second]);
+                    }
+                }
+            }
+        }
+    }
+    levels
+}
+
+/// Joins `segments` which share an end into lines
+fn join(segments: &[Segment]) -> Vec<Vec<(f64, f64)>> {
+    let mut segments_by_end: HashMap<EdgeKey, Vec<usize>> = HashMap::new();
+    for (index, segment) in segments.iter().enumerate() {
+        for (key, _) in segment {
+            segments_by_end.entry(*key).or_default().push(index);
+        }
+    }
+    let mut is_joined = vec![false; segments.len()];
+    // The unjoined segment ending at `key`, and its other end
+    let next = |key: EdgeKey, is_joined: &mut Vec<bool>| {
+        let index = *segments_by_end[&key]
+            .iter()
+            .find(|index| !is_joined[**index])?;
+        is_joined[index] = true;
+        let [a, b] = segments[index];
+        Some(if a.0 == key { b } else { a })
+    };
+    let mut lines = vec![];
+    for (index, &[start, end]) in segments.iter().enumerate() {
+        if is_joined[index] {
+            continue;
+        }
+        is_joined[index] = true;
+        let mut forwards = vec![start, end];
+        while let Some(point) = next(forwards.last().expect("not empty").0, &mut is_joined) {
+            forwards.push(point);
+        }
+        let mut backwards = vec![];
+        let mut key = start.0;
+        while let Some(point) = next(key, &mut is_joined) {
+            key = point.0;
+            backwards.push(point);
+        }
+        backwards.reverse();
+        backwards.extend(forwards);
+        lines.push(backwards.into_iter().map(|(_, point)| point).collect());
+    }
+    lines
+}
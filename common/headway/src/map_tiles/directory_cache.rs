@@ -0,0 +1,144 @@
+//! Keeps the extract source's leaf directories on disk, so planning an extract in a later
+//! session doesn't fetch every one of them again.
+//!
+//! pmtiles only hands a `DirectoryCache` parsed directories, which can't be written back out,
+//! so the directories are cached as the bytes read from the source instead, beneath the
+//! in-memory cache of parsed directories.
+
+use crate::checksum::Sha256Digest;
+use bytes::Bytes;
+use pmtiles::{AsyncBackend, HttpBackend, PmtResult};
+use std::io::ErrorKind;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use uuid::Uuid;
+
+/// The length of a v3 header, which every archive starts with
+const HEADER_LEN: usize = 127;
+
+pub(crate) struct DirectoryCachingBackend {
+    backend: HttpBackend,
+    cache_dir: PathBuf,
+    /// Set once the header has been read
+    archive: Mutex<Option<CachedArchive>>,
+    /// The digest of the header, once read, which identifies the build of the archive
+    header_digest: Arc<OnceLock<Sha256Digest>>,
+}
+
+#[derive(Clone)]
+struct CachedArchive {
+    /// Where this build of the archive has its directories cached, named for its header, which
+    /// changes with every build
+    dir: PathBuf,
+    leaf_directories: Range<usize>,
+}
+
+impl DirectoryCachingBackend {
+    pub(crate) fn new(backend: HttpBackend, cache_dir: PathBuf) -> Self {
+        Self {
+            backend,
+            cache_dir,
+            archive: Mutex::new(None),
+            header_digest: Arc::default(),
+        }
+    }
+
+    /// Set to the digest of the archive's header once it's been read, e.g. by a reader the
+    /// backend has been handed to
+    pub(crate) fn header_digest(&self) -> Arc<OnceLock<Sha256Digest>> {
+        self.header_digest.clone()
+    }
+
+    /// The cached archive, if `offset..offset + length` is within its leaf directories
+    fn archive_containing(&self, offset: usize, length: usize) -> Option<CachedArchive> {
+        let archive = self.archive.lock().expect("poisoned lock").clone()?;
+        let is_leaf_directory = archive.leaf_directories.start <= offset
+            && offset + length <= archive.leaf_directories.end;
+        is_leaf_directory.then_some(archive)
+    }
+
+    /// Notes where the archive starting with `header` keeps its leaf directories, discarding any
+    /// cached for another build of it
+    async fn read_header(&self, header: &[u8]) {
+        if header.len() < HEADER_LEN || !header.starts_with(b"PMTiles") || header[7] != 3 {
+            return;
+        }
+        let u64_at = |offset: usize| {
+            u64::from_le_bytes(header[offset..offset + 8].try_into().expect("8 bytes")) as usize
+        };
+        let (leaf_offset, leaf_length) = (u64_at(40), u64_at(48));
+        let digest = Sha256Digest::of_bytes(&header[..HEADER_LEN]);
+        let dir = self.cache_dir.join(digest.to_string());
+        let _ = self.header_digest.set(digest);
+        discard_other_builds(&self.cache_dir, &dir).await;
+        *self.archive.lock().expect("poisoned lock") = Some(CachedArchive {
+            dir,
+            leaf_directories: leaf_offset..leaf_offset + leaf_length,
+        });
+    }
+}
+
+impl AsyncBackend for DirectoryCachingBackend {
+    async fn read_exact(&self, offset: usize, length: usize) -> PmtResult<Bytes> {
+        let Some(archive) = self.archive_containing(offset, length) else {
+            return self.backend.read_exact(offset, length).await;
+        };
+        let path = archive.dir.join(format!("{offset}-{length}"));
+        match tokio::fs::read(&path).await {
+            Ok(directory) if directory.len() == length => return Ok(directory.into()),
+            Ok(_) => log::warn!("Ignoring truncated cached directory {path:?}"),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => log::warn!("Unable to read cached directory {path:?}: {e}"),
+        }
+        let directory = self.backend.read_exact(offset, length).await?;
+        store(&path, &directory).await;
+        Ok(directory)
+    }
+
+    async fn read(&self, offset: usize, length: usize) -> PmtResult<Bytes> {
+        // The reader starts with the header, which is always fetched, to tell whether the
+        // cached directories are still those of the source's build
+        let bytes = self.backend.read(offset, length).await?;
+        if offset == 0 {
+            self.read_header(&bytes).await;
+        }
+        Ok(bytes)
+    }
+}
+
+/// Caches `directory` at `path`. Failures are only logged, since it can be fetched again.
+async fn store(path: &Path, directory: &[u8]) {
+    let result = async {
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        // Write then rename, so a concurrent read never sees a partial directory. Concurrent
+        // jobs may be caching the same one, so each writes its own temporary file.
+        let tmp_path = path.with_extension(format!("{}.tmp", Uuid::new_v4()));
+        tokio::fs::write(&tmp_path, directory).await?;
+        tokio::fs::rename(&tmp_path, path).await
+    }
+    .await;
+    if let Err(e) = result {
+        log::warn!("Unable to cache directory at {path:?}: {e}");
+    }
+}
+
+/// Removes every directory in `cache_dir` other than `current`, each the cache of an outdated
+/// build of the source
+async fn discard_other_builds(cache_dir: &Path, current: &Path) {
+    let Ok(mut entries) = tokio::fs::read_dir(cache_dir).await else {
+        return;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        if path == current {
+            continue;
+        }
+        log::info!("Discarding directories cached for an outdated extract source: {path:?}");
+        if let Err(e) = tokio::fs::remove_dir_all(&path).await {
+            log::warn!("Unable to remove outdated directory cache {path:?}: {e}");
+        }
+    }
+}
@@ -1,59 +1,151 @@
-use crate::Result;
+use super::directory_cache::DirectoryCachingBackend;
+use crate::checksum::Sha256Digest;
+use crate::confirmation::Confirmation;
+use crate::connectivity::Connectivity;
+use crate::data_budget::DataBudget;
+use crate::download::DownloadCancellation;
+use crate::http::HttpOptions;
+use crate::mirrors::Mirrors;
+use crate::{Error, Result};
 use pmtiles::extract::{BoundingBox, ExtractionPlan};
 use pmtiles::{AsyncPmTilesReader, HashMapCache, HttpBackend};
 use reqwest::Client;
 use std::fs::File;
+use std::future::Future;
 use std::io::BufWriter;
-use std::path::Path;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// How often a running extract checks it may continue, e.g. is still on Wi-Fi
+const CONNECTIVITY_CHECK_INTERVAL: Duration = Duration::from_secs(5);
 
 #[uniffi::export(with_foreign)]
 pub trait ExtractProgress: Send + Sync {
     fn on_progress(&self, progress: f64);
+    /// Called when `attempt` failed in a way that may well be transient, e.g. the extract source
+    /// being unreachable, before it's retried, e.g. with a mirror
+    fn on_retryable_error(&self, message: String, attempt: u32);
+}
+
+/// A plan to extract a region, along with the build of the extract source it was prepared from,
+/// since its offsets only apply to that build
+pub(crate) struct PlannedExtract {
+    plan: ExtractionPlan,
+    /// The digest of the source's header, which changes with every build
+    source_header: Sha256Digest,
+}
+
+impl PlannedExtract {
+    pub(crate) fn tile_data_length(&self) -> u64 {
+        self.plan.tile_data_length()
+    }
 }
 
+/// Plans and runs extracts from the extract source. Each one connects on its own, so planning
+/// one region doesn't hold up extracting another.
 pub struct Extractor {
     source_url: String,
-    reader: Option<AsyncPmTilesReader<HttpBackend, HashMapCache>>,
+    data_budget: Arc<DataBudget>,
+    confirmation: Arc<Confirmation>,
+    mirrors: Arc<Mirrors>,
+    connectivity: Arc<Connectivity>,
+    http_options: RwLock<HttpOptions>,
+    /// Where the source's leaf directories are cached, across jobs as well as sessions
+    directory_cache_dir: PathBuf,
 }
 
 impl Extractor {
-    pub(crate) async fn new(source_url: &str) -> Result<Self> {
+    pub(crate) async fn new(
+        source_url: &str,
+        data_budget: Arc<DataBudget>,
+        confirmation: Arc<Confirmation>,
+        mirrors: Arc<Mirrors>,
+        connectivity: Arc<Connectivity>,
+        directory_cache_dir: PathBuf,
+    ) -> Result<Self> {
         Ok(Self {
             source_url: source_url.into(),
-            reader: None,
+            data_budget,
+            confirmation,
+            mirrors,
+            connectivity,
+            http_options: RwLock::default(),
+            directory_cache_dir,
         })
     }
 
-    pub(crate) async fn reader(
-        &mut self,
-    ) -> Result<&mut AsyncPmTilesReader<HttpBackend, HashMapCache>> {
-        if self.reader.is_none() {
-            let client = Client::builder()
-                .user_agent("maps.earth-ios/0.1.0")
-                .build()
-                .expect("nothing invalid in client builder");
-            let backend = HttpBackend::try_from(client, &self.source_url)?;
-            let reader =
-                AsyncPmTilesReader::try_from_cached_source(backend, HashMapCache::default())
-                    .await?;
-            self.reader = Some(reader);
+    /// Connects to the first reachable of the source URL and its mirrors, for one job, along
+    /// with the digest of its header. Only the header is fetched, since the directories are
+    /// cached.
+    async fn reader(
+        &self,
+        progress_callback: Option<&dyn ExtractProgress>,
+    ) -> Result<(
+        AsyncPmTilesReader<DirectoryCachingBackend, HashMapCache>,
+        Sha256Digest,
+    )> {
+        let client = self
+            .http_options
+            .read()
+            .expect("poisoned lock")
+            .client(Client::builder().user_agent("maps.earth-ios/0.1.0"))?;
+        let mut candidate_urls = self.mirrors.candidates(&self.source_url).into_iter();
+        let mut attempt = 1;
+        loop {
+            let url = candidate_urls.next().expect("always has the source URL");
+            let backend = DirectoryCachingBackend::new(
+                HttpBackend::try_from(client.clone(), &url)?,
+                self.directory_cache_dir.clone(),
+            );
+            let header_digest = backend.header_digest();
+            match AsyncPmTilesReader::try_from_cached_source(backend, HashMapCache::default()).await
+            {
+                Ok(reader) => {
+                    let Some(header_digest) = header_digest.get().cloned() else {
+                        return Err(Error::PmTiles(format!(
+                            "unsupported extract source header: {url}"
+                        )));
+                    };
+                    return Ok((reader, header_digest));
+                }
+                Err(e) if !candidate_urls.as_slice().is_empty() => {
+                    log::warn!("Unable to read extract source {url}, failing over: {e}");
+                    if let Some(progress_callback) = progress_callback {
+                        progress_callback.on_retryable_error(e.to_string(), attempt);
+                    }
+                    attempt += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
         }
-        Ok(self.reader.as_mut().expect("ensured initialized just now"))
+    }
+
+    /// Caches the source's leaf directories in `directory_cache_dir` from the next job on, e.g.
+    /// once the storage they're in has been migrated
+    pub(crate) fn set_directory_cache_dir(&mut self, directory_cache_dir: PathBuf) {
+        self.directory_cache_dir = directory_cache_dir;
+    }
+
+    /// Connects to the extract source with `http_options` from the next job on
+    pub(crate) fn set_http_options(&self, http_options: HttpOptions) {
+        *self.http_options.write().expect("poisoned lock") = http_options;
     }
 
     pub async fn prepare_pmtiles_extract(
-        &mut self,
+        &self,
         bbox: BoundingBox,
         progress_callback: Option<Arc<dyn ExtractProgress>>,
-    ) -> Result<ExtractionPlan> {
+    ) -> Result<PlannedExtract> {
         log::info!("Preparing extraction");
+        self.connectivity.check()?;
+        let (reader, source_header) = &mut self.reader(progress_callback.as_deref()).await?;
         let callback = move |ratio| {
             if let Some(progress_callback) = &progress_callback {
                 progress_callback.on_progress(ratio)
             }
         };
-        let extractor = pmtiles::extract::Extractor::new(self.reader().await?).progress(&callback);
+        let extractor = pmtiles::extract::Extractor::new(reader).progress(&callback);
         let plan = extractor.prepare(bbox).await?;
         let size_bytes = plan.tile_data_length();
         log::info!(
@@ -62,40 +154,65 @@ impl Extractor {
             size_bytes as f64 / 1_048_576.0
         );
 
-        Ok(plan)
+        Ok(PlannedExtract {
+            plan,
+            source_header: source_header.clone(),
+        })
     }
 
     pub async fn extract_pmtiles_region(
-        &mut self,
+        &self,
         output_path: &Path,
-        plan: &ExtractionPlan,
+        plan: &PlannedExtract,
+        expected_sha256: Option<&Sha256Digest>,
         progress_callback: Option<Arc<dyn ExtractProgress>>,
     ) -> Result<()> {
         log::info!("Starting PMTiles extraction");
         log::info!("Output path: {}", output_path.display());
+        self.connectivity.check()?;
+        self.data_budget.check(plan.tile_data_length())?;
+        self.confirmation
+            .check(&self.source_url, plan.tile_data_length())?;
 
-        let callback = move |ratio| {
+        let (reader, source_header) = &mut self.reader(progress_callback.as_deref()).await?;
+        if *source_header != plan.source_header {
+            // The plan's offsets are for another build, e.g. the source has since been rebuilt,
+            // or the mirror read serves an older one, so would extract the wrong data
+            return Err(Error::StalePlan);
+        }
+        // Progress is reported from within the extract, which can't fail it, so it's stopped
+        // like a cancellation when connectivity is lost, and fails with why
+        let connectivity_lost = ConnectivityLost::new(&self.connectivity);
+        let callback = |ratio| {
+            connectivity_lost.check();
             if let Some(progress_callback) = &progress_callback {
                 progress_callback.on_progress(ratio)
             }
         };
-        let extractor = pmtiles::extract::Extractor::new(self.reader().await?).progress(&callback);
+        let extractor = pmtiles::extract::Extractor::new(reader).progress(&callback);
 
         // Extract to a temporary file first to avoid partial files on failure
         let tmp_path = output_path.with_extension("tmp");
 
+        let partial = PartialExtract::new(&tmp_path);
         let mut output_file = BufWriter::new(File::create(&tmp_path)?);
 
         // TODO: Pass in owned and remove this clone? Could be annoying with mobile client code.
-        extractor
-            .extract_to_writer(plan.clone(), &mut output_file)
+        connectivity_lost
+            .run(extractor.extract_to_writer(plan.plan.clone(), &mut output_file))
             .await?;
+        self.data_budget.spend(plan.tile_data_length());
 
         // Close the file before moving it
         drop(output_file);
 
+        if let Some(expected_sha256) = expected_sha256 {
+            expected_sha256.verify_file(&tmp_path).await?;
+        }
+
         let size = std::fs::metadata(&tmp_path)?.len();
         std::fs::rename(&tmp_path, output_path)?;
+        partial.completed();
 
         log::info!(
             "Successfully extracted PMTiles region to {}",
@@ -111,3 +228,93 @@ impl Extractor {
         Ok(())
     }
 }
+
+/// Stops an extract once connectivity is lost, e.g. if the device has since switched from Wi-Fi
+/// to cellular
+struct ConnectivityLost<'a> {
+    connectivity: &'a Connectivity,
+    last_checked: Mutex<Instant>,
+    error: Mutex<Option<Error>>,
+    stop: DownloadCancellation,
+}
+
+impl<'a> ConnectivityLost<'a> {
+    fn new(connectivity: &'a Connectivity) -> Self {
+        Self {
+            connectivity,
+            last_checked: Mutex::new(Instant::now()),
+            error: Mutex::new(None),
+            stop: DownloadCancellation::new(),
+        }
+    }
+
+    /// Stops the extract if connectivity has been lost, checking at most every
+    /// [`CONNECTIVITY_CHECK_INTERVAL`], since progress is reported far more often
+    fn check(&self) {
+        {
+            let mut last_checked = self.last_checked.lock().expect("poisoned lock");
+            if last_checked.elapsed() < CONNECTIVITY_CHECK_INTERVAL {
+                return;
+            }
+            *last_checked = Instant::now();
+        }
+        if let Err(e) = self.connectivity.check() {
+            log::warn!("Stopping extract: {e}");
+            *self.error.lock().expect("poisoned lock") = Some(e);
+            self.stop.cancel();
+        }
+    }
+
+    /// Runs `extract`, unless connectivity is lost first
+    async fn run<T, E: Into<Error>>(
+        &self,
+        extract: impl Future<Output = std::result::Result<T, E>>,
+    ) -> Result<T> {
+        let result = self
+            .stop
+            .run(async { extract.await.map_err(Into::into) })
+            .await;
+        match result {
+            Err(Error::Cancelled) => Err(self
+                .error
+                .lock()
+                .expect("poisoned lock")
+                .take()
+                .unwrap_or(Error::Cancelled)),
+            result => result,
+        }
+    }
+}
+
+/// Deletes a partially written extract when dropped, whether the extract failed or was
+/// cancelled by dropping its future, unless it was completed and moved into place
+struct PartialExtract<'a> {
+    path: &'a Path,
+    is_completed: bool,
+}
+
+impl<'a> PartialExtract<'a> {
+    fn new(path: &'a Path) -> Self {
+        Self {
+            path,
+            is_completed: false,
+        }
+    }
+
+    fn completed(mut self) {
+        self.is_completed = true;
+    }
+}
+
+impl Drop for PartialExtract<'_> {
+    fn drop(&mut self) {
+        if self.is_completed {
+            return;
+        }
+        match std::fs::remove_file(self.path) {
+            Ok(()) => log::info!("Removed partial extract {:?}", self.path),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => log::warn!("Unable to remove partial extract {:?}: {e}", self.path),
+        }
+    }
+}
@@ -0,0 +1,542 @@
+//! Features decoded from the vector tiles of a tileset, so "what's here" and POI taps can be
+//! answered from the downloaded basemap without a network connection.
+//!
+//! See the [vector tile spec](https://github.com/mapbox/vector-tile-spec/tree/master/2.1), and
+//! [`super::gap_tile`] for the messages involved.
+
+use super::tile_format::Tile;
+use super::{Bounds, TileCollection, TilesetCoverage};
+use crate::geo::{LatLon, EARTH_RADIUS_M};
+use crate::pbf::{read_fields, read_packed_varints, unzigzag, FieldValue};
+use crate::{Error, Result};
+use pmtiles::TileType;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::f64::consts::PI;
+
+/// How near a tap a feature must be to have been tapped, in pixels, allowing for the size of a
+/// finger
+const HIT_TOLERANCE_PX: f64 = 12.0;
+
+/// How many distinct values of each attribute [`inspect_tile`] lists
+const MAX_SAMPLE_VALUES: usize = 10;
+
+/// Enough for a radius of a few kilometers at the usual max zoom of 14
+const MAX_TILES: usize = 25;
+
+const DEFAULT_EXTENT: u32 = 4096;
+
+const GEOM_TYPE_POINT: u64 = 1;
+const GEOM_TYPE_LINESTRING: u64 = 2;
+const GEOM_TYPE_POLYGON: u64 = 3;
+const COMMAND_MOVE_TO: u64 = 1;
+const COMMAND_LINE_TO: u64 = 2;
+const COMMAND_CLOSE_PATH: u64 = 7;
+
+/// An attribute value of a vector tile feature
+#[derive(Clone, Debug, PartialEq, uniffi::Enum)]
+pub enum FeatureValue {
+    String { value: String },
+    Double { value: f64 },
+    Int { value: i64 },
+    Bool { value: bool },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum FeatureGeometryType {
+    Point,
+    LineString,
+    Polygon,
+}
+
+/// A feature of a vector tileset near a location, see [`features_near`]
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct NearbyFeature {
+    /// The vector tile layer the feature is in, e.g. `"poi"`
+    pub layer: String,
+    /// The feature's id, if the tileset assigns them, which for OpenMapTiles-style tilesets is
+    /// derived from its OSM id
+    pub id: Option<u64>,
+    pub geometry_type: FeatureGeometryType,
+    pub properties: HashMap<String, FeatureValue>,
+    /// The point of the feature nearest the queried location, which is the location itself
+    /// when it's within a polygon
+    pub nearest_location: LatLon,
+    /// How far `nearest_location` is from the queried location, in meters
+    pub distance_m: f64,
+}
+
+/// The features of `tileset_id` within `radius_m` of `location`, from its highest zoom, nearest
+/// first. Only features in one of `layers` are included, if given.
+pub(crate) async fn features_near(
+    collection: &TileCollection,
+    tileset_id: &str,
+    location: LatLon,
+    radius_m: f64,
+    layers: Option<&[String]>,
+) -> Result<Vec<NearbyFeature>> {
+    if !(radius_m > 0.0 && radius_m.is_finite()) {
+        return Err(Error::InvalidInput(format!(
+            "radius must be positive, got {radius_m}"
+        )));
+    }
+    let coverage = vector_coverage(collection, tileset_id)?;
+    features_in_tiles(
+        collection,
+        tileset_id,
+        coverage.max_zoom(),
+        location,
+        radius_m,
+        layers,
+    )
+    .await
+}
+
+/// The features of `tileset_id` within [`HIT_TOLERANCE_PX`] of `location` on a map at `zoom`,
+/// nearest first, from the tiles the map would draw there. So they're the features the user
+/// could have tapped, simplified and filtered as drawn.
+pub(crate) async fn rendered_features_at(
+    collection: &TileCollection,
+    tileset_id: &str,
+    location: LatLon,
+    zoom: f64,
+    layers: Option<&[String]>,
+) -> Result<Vec<NearbyFeature>> {
+    if !(zoom >= 0.0 && zoom.is_finite()) {
+        return Err(Error::InvalidInput(format!(
+            "zoom must be non-negative, got {zoom}"
+        )));
+    }
+    let coverage = vector_coverage(collection, tileset_id)?;
+    // Vector tiles are drawn 512 pixels wide, so a map at zoom 14.6 draws tiles from zoom 14
+    let z = (zoom.floor().min(f64::from(u8::MAX)) as u8)
+        .clamp(coverage.min_zoom(), coverage.max_zoom());
+    let meters_per_pixel =
+        2.0 * PI * EARTH_RADIUS_M * location.lat.to_radians().cos() / (512.0 * zoom.exp2());
+    let radius_m = HIT_TOLERANCE_PX * meters_per_pixel;
+    features_in_tiles(collection, tileset_id, z, location, radius_m, layers).await
+}
+
+fn vector_coverage(collection: &TileCollection, tileset_id: &str) -> Result<TilesetCoverage> {
+    let coverage = collection
+        .coverage(tileset_id)
+        .ok_or_else(|| Error::InvalidInput(format!("no such tileset: {tileset_id}")))?;
+    if collection.tile_type(tileset_id) != Some(TileType::Mvt) {
+        return Err(Error::InvalidInput(format!(
+            "{tileset_id} isn't a vector tileset"
+        )));
+    }
+    Ok(coverage)
+}
+
+/// The features within `radius_m` of `location` in the tiles of `tileset_id` at zoom `z`
+async fn features_in_tiles(
+    collection: &TileCollection,
+    tileset_id: &str,
+    z: u8,
+    location: LatLon,
+    radius_m: f64,
+    layers: Option<&[String]>,
+) -> Result<Vec<NearbyFeature>> {
+    let search_bounds = Bounds::around(&[location], radius_m).expect("one location");
+    let [max_lat, max_lon, min_lat, min_lon] = search_bounds.as_nesw();
+    let (min_x, min_y) = tile_containing(z, max_lat, min_lon);
+    let (max_x, max_y) = tile_containing(z, min_lat, max_lon);
+    let tile_count = (max_x - min_x + 1) as usize * (max_y - min_y + 1) as usize;
+    if tile_count > MAX_TILES {
+        return Err(Error::InvalidInput(format!(
+            "radius {radius_m}m spans too many tiles at zoom {z}"
+        )));
+    }
+
+    let projection = LocalProjection::new(location);
+    // Features crossing tile boundaries appear in each tile, so are kept once by id
+    let mut features_by_id: HashMap<(String, u64), NearbyFeature> = HashMap::new();
+    let mut features = vec![];
+    for x in min_x..=max_x {
+        for y in min_y..=max_y {
+            let Some(tile) = collection.get_tile(tileset_id, z, x, y).await? else {
+                continue;
+            };
+            for feature in decode_tile(tile, z, x, y, layers)? {
+                let Some(nearby) = feature.nearby(&projection, radius_m) else {
+                    continue;
+                };
+                match nearby.id {
+                    Some(id) => {
+                        let key = (nearby.layer.clone(), id);
+                        if features_by_id
+                            .get(&key)
+                            .is_none_or(|existing| nearby.distance_m < existing.distance_m)
+                        {
+                            features_by_id.insert(key, nearby);
+                        }
+                    }
+                    None => features.push(nearby),
+                }
+            }
+        }
+    }
+    features.extend(features_by_id.into_values());
+    features.sort_by(|a, b| a.distance_m.total_cmp(&b.distance_m));
+    Ok(features)
+}
+
+/// The tile at zoom `z` containing a point, clamped to the tile grid
+fn tile_containing(z: u8, lat: f64, lon: f64) -> (u32, u32) {
+    let tiles_per_side = f64::from(1u32 << z);
+    let lat = lat.clamp(-85.051_128, 85.051_128).to_radians();
+    let x = (lon + 180.0) / 360.0 * tiles_per_side;
+    let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0 * tiles_per_side;
+    let max = (1u32 << z) - 1;
+    (
+        (x.max(0.0).floor() as u32).min(max),
+        (y.max(0.0).floor() as u32).min(max),
+    )
+}
+
+/// A feature decoded from a tile, with its geometry in lat/lon
+struct DecodedFeature {
+    layer: String,
+    id: Option<u64>,
+    geometry_type: FeatureGeometryType,
+    properties: HashMap<String, FeatureValue>,
+    /// Points, line strings, or polygon rings, by `geometry_type`
+    parts: Vec<Vec<LatLon>>,
+}
+
+impl DecodedFeature {
+    /// The feature as a [`NearbyFeature`], if it's within `radius_m` of the projection's origin
+    fn nearby(self, projection: &LocalProjection, radius_m: f64) -> Option<NearbyFeature> {
+        let parts: Vec<Vec<(f64, f64)>> = self
+            .parts
+            .iter()
+            .map(|part| {
+                part.iter()
+                    .map(|point| projection.project(*point))
+                    .collect()
+            })
+            .collect();
+        let nearest = if self.geometry_type == FeatureGeometryType::Polygon && contains(&parts) {
+            (0.0, 0.0)
+        } else {
+            let is_closed = self.geometry_type == FeatureGeometryType::Polygon;
+            parts
+                .iter()
+                .filter_map(|part| nearest_on_part(part, is_closed))
+                .min_by(|a, b| length(*a).total_cmp(&length(*b)))?
+        };
+        let distance_m = length(nearest);
+        (distance_m <= radius_m).then(|| NearbyFeature {
+            layer: self.layer,
+            id: self.id,
+            geometry_type: self.geometry_type,
+            properties: self.properties,
+            nearest_location: projection.unproject(nearest),
+            distance_m,
+        })
+    }
+}
+
+/// An equirectangular projection to meters east and north of an origin, which is accurate
+/// enough within the few kilometers of a query
+struct LocalProjection {
+    origin: LatLon,
+    meters_per_lon_radian: f64,
+}
+
+impl LocalProjection {
+    fn new(origin: LatLon) -> Self {
+        Self {
+            origin,
+            meters_per_lon_radian: EARTH_RADIUS_M * origin.lat.to_radians().cos(),
+        }
+    }
+
+    fn project(&self, point: LatLon) -> (f64, f64) {
+        (
+            (point.lon - self.origin.lon).to_radians() * self.meters_per_lon_radian,
+            (point.lat - self.origin.lat).to_radians() * EARTH_RADIUS_M,
+        )
+    }
+
+    fn unproject(&self, (x, y): (f64, f64)) -> LatLon {
+        LatLon {
+            lat: self.origin.lat + (y / EARTH_RADIUS_M).to_degrees(),
+            lon: self.origin.lon + (x / self.meters_per_lon_radian).to_degrees(),
+        }
+    }
+}
+
+fn length((x, y): (f64, f64)) -> f64 {
+    x.hypot(y)
+}
+
+/// The point of `part` nearest the origin: the nearest of its points, or the nearest point on
+/// its segments if it has more than one, including the closing segment of a ring
+fn nearest_on_part(part: &[(f64, f64)], is_closed: bool) -> Option<(f64, f64)> {
+    let mut nearest = *part.first()?;
+    let closing_segment = is_closed.then(|| [part[part.len() - 1], part[0]]);
+    let segments = part
+        .windows(2)
+        .map(|segment| [segment[0], segment[1]])
+        .chain(closing_segment);
+    for [(x0, y0), (x1, y1)] in segments {
+        let (dx, dy) = (x1 - x0, y1 - y0);
+        let length_squared = dx * dx + dy * dy;
+        let t = if length_squared > 0.0 {
+            (-(x0 * dx + y0 * dy) / length_squared).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let candidate = (x0 + t * dx, y0 + t * dy);
+        if length(candidate) < length(nearest) {
+            nearest = candidate;
+        }
+    }
+    Some(nearest)
+}
+
+/// Whether the origin is within the polygon formed by `rings`, by the even-odd rule, so holes
+/// are excluded
+fn contains(rings: &[Vec<(f64, f64)>]) -> bool {
+    let mut inside = false;
+    for ring in rings {
+        let Some(&last) = ring.last() else {
+            continue;
+        };
+        let mut previous = last;
+        for &(x, y) in ring {
+            let (previous_x, previous_y) = previous;
+            if (y > 0.0) != (previous_y > 0.0)
+                && 0.0 < previous_x + (x - previous_x) * -previous_y / (y - previous_y)
+            {
+                inside = !inside;
+            }
+            previous = (x, y);
+        }
+    }
+    inside
+}
+
+/// A JSON summary of each layer of a vector tile: how many features of each geometry type it
+/// has, and which attributes, with some of their values. For diagnosing why a style doesn't
+/// render a tile as expected.
+pub(crate) fn inspect_tile(tile: Tile, z: u8, x: u32, y: u32) -> Result<Vec<Value>> {
+    #[derive(Default)]
+    struct LayerSummary {
+        feature_count: usize,
+        geometry_types: BTreeMap<&'static str, usize>,
+        attributes: BTreeMap<String, (usize, Vec<Value>)>,
+    }
+    let mut layers: BTreeMap<String, LayerSummary> = BTreeMap::new();
+    for feature in decode_tile(tile, z, x, y, None)? {
+        let layer = layers.entry(feature.layer).or_default();
+        layer.feature_count += 1;
+        let geometry_type = match feature.geometry_type {
+            FeatureGeometryType::Point => "Point",
+            FeatureGeometryType::LineString => "LineString",
+            FeatureGeometryType::Polygon => "Polygon",
+        };
+        *layer.geometry_types.entry(geometry_type).or_default() += 1;
+        for (key, value) in feature.properties {
+            let (count, values) = layer.attributes.entry(key).or_default();
+            *count += 1;
+            let value = match value {
+                FeatureValue::String { value } => json!(value),
+                FeatureValue::Double { value } => json!(value),
+                FeatureValue::Int { value } => json!(value),
+                FeatureValue::Bool { value } => json!(value),
+            };
+            if values.len() < MAX_SAMPLE_VALUES && !values.contains(&value) {
+                values.push(value);
+            }
+        }
+    }
+    Ok(layers
+        .into_iter()
+        .map(|(name, layer)| {
+            let attributes: Map<String, Value> = layer
+                .attributes
+                .into_iter()
+                .map(|(key, (count, values))| (key, json!({ "count": count, "values": values })))
+                .collect();
+            json!({
+                "name": name,
+                "feature_count": layer.feature_count,
+                "geometry_types": layer.geometry_types,
+                "attributes": attributes,
+            })
+        })
+        .collect())
+}
+
+fn decode_tile(
+    tile: Tile,
+    z: u8,
+    x: u32,
+    y: u32,
+    layers: Option<&[String]>,
+) -> Result<Vec<DecodedFeature>> {
+    let tile = tile.decompressed()?;
+    let invalid = || Error::InvalidInput(format!("invalid vector tile {z}/{x}/{y}"));
+    let mut features = vec![];
+    for (field_number, value) in read_fields(&tile.data).ok_or_else(invalid)? {
+        let (3, FieldValue::Len(layer)) = (field_number, value) else {
+            continue;
+        };
+        decode_layer(layer, z, x, y, layers, &mut features).ok_or_else(invalid)?;
+    }
+    Ok(features)
+}
+
+/// Appends the features of a `Layer` message to `features`, unless it's excluded by `layers`,
+/// or returns `None` if it's malformed
+fn decode_layer(
+    layer: &[u8],
+    z: u8,
+    x: u32,
+    y: u32,
+    layers: Option<&[String]>,
+    features: &mut Vec<DecodedFeature>,
+) -> Option<()> {
+    let mut name = None;
+    let mut encoded_features = vec![];
+    let mut keys = vec![];
+    let mut values = vec![];
+    let mut extent = DEFAULT_EXTENT;
+    for (field_number, value) in read_fields(layer)? {
+        match (field_number, value) {
+            (1, FieldValue::Len(bytes)) => name = Some(String::from_utf8_lossy(bytes).into_owned()),
+            (2, FieldValue::Len(bytes)) => encoded_features.push(bytes),
+            (3, FieldValue::Len(bytes)) => keys.push(String::from_utf8_lossy(bytes).into_owned()),
+            (4, FieldValue::Len(bytes)) => values.push(decode_value(bytes)?),
+            (5, FieldValue::Varint(value)) => extent = u32::try_from(value).ok()?,
+            _ => {}
+        }
+    }
+    let name = name?;
+    if layers.is_some_and(|layers| !layers.contains(&name)) || extent == 0 {
+        return Some(());
+    }
+    let tile_origin = TileOrigin { z, x, y, extent };
+    for feature in encoded_features {
+        let mut id = None;
+        let mut tags = vec![];
+        let mut geometry_type = None;
+        let mut geometry = vec![];
+        for (field_number, value) in read_fields(feature)? {
+            match (field_number, value) {
+                (1, FieldValue::Varint(value)) => id = Some(value),
+                (2, FieldValue::Len(bytes)) => tags = read_packed_varints(bytes)?,
+                (3, FieldValue::Varint(value)) => geometry_type = Some(value),
+                (4, FieldValue::Len(bytes)) => geometry = read_packed_varints(bytes)?,
+                _ => {}
+            }
+        }
+        let geometry_type = match geometry_type {
+            Some(GEOM_TYPE_POINT) => FeatureGeometryType::Point,
+            Some(GEOM_TYPE_LINESTRING) => FeatureGeometryType::LineString,
+            Some(GEOM_TYPE_POLYGON) => FeatureGeometryType::Polygon,
+            // Features of unknown type are ignored, per the spec
+            _ => continue,
+        };
+        let properties = tags
+            .chunks_exact(2)
+            .map(|tag| {
+                let key = keys.get(usize::try_from(tag[0]).ok()?)?;
+                let value = values.get(usize::try_from(tag[1]).ok()?)?;
+                Some((key.clone(), value.clone()))
+            })
+            .collect::<Option<_>>()?;
+        features.push(DecodedFeature {
+            layer: name.clone(),
+            id,
+            geometry_type,
+            properties,
+            parts: decode_geometry(&geometry, &tile_origin)?,
+        });
+    }
+    Some(())
+}
+
+fn decode_value(value: &[u8]) -> Option<FeatureValue> {
+    let mut decoded = None;
+    for (field_number, field) in read_fields(value)? {
+        decoded = match (field_number, field) {
+            (1, FieldValue::Len(bytes)) => Some(FeatureValue::String {
+                value: String::from_utf8_lossy(bytes).into_owned(),
+            }),
+            (2, FieldValue::Fixed32(bits)) => Some(FeatureValue::Double {
+                value: f64::from(f32::from_bits(bits)),
+            }),
+            (3, FieldValue::Fixed64(bits)) => Some(FeatureValue::Double {
+                value: f64::from_bits(bits),
+            }),
+            (4, FieldValue::Varint(value)) => Some(FeatureValue::Int {
+                // Negative values are encoded as their two's complement
+                value: i64::from_le_bytes(value.to_le_bytes()),
+            }),
+            (5, FieldValue::Varint(value)) => Some(FeatureValue::Int {
+                value: i64::try_from(value).ok()?,
+            }),
+            (6, FieldValue::Varint(value)) => Some(FeatureValue::Int {
+                value: unzigzag(value),
+            }),
+            (7, FieldValue::Varint(value)) => Some(FeatureValue::Bool { value: value != 0 }),
+            _ => continue,
+        };
+    }
+    decoded
+}
+
+/// Where a layer's tile coordinates are, to convert them to lat/lon
+struct TileOrigin {
+    z: u8,
+    x: u32,
+    y: u32,
+    extent: u32,
+}
+
+impl TileOrigin {
+    fn to_lat_lon(&self, tile_x: i64, tile_y: i64) -> LatLon {
+        let tiles_per_side = f64::from(1u32 << self.z);
+        let extent = f64::from(self.extent);
+        let world_x = (f64::from(self.x) + tile_x as f64 / extent) / tiles_per_side;
+        let world_y = (f64::from(self.y) + tile_y as f64 / extent) / tiles_per_side;
+        LatLon {
+            lat: (PI * (1.0 - 2.0 * world_y)).sinh().atan().to_degrees(),
+            lon: world_x * 360.0 - 180.0,
+        }
+    }
+}
+
+/// Decodes packed geometry commands into points, line strings, or rings, each starting at a
+/// `MoveTo`, or returns `None` if they're malformed
+fn decode_geometry(commands: &[u64], tile_origin: &TileOrigin) -> Option<Vec<Vec<LatLon>>> {
+    let mut parts: Vec<Vec<LatLon>> = vec![];
+    let (mut cursor_x, mut cursor_y) = (0i64, 0i64);
+    let mut commands = commands.iter();
+    while let Some(&command) = commands.next() {
+        let (id, count) = (command & 0x7, command >> 3);
+        match id {
+            COMMAND_MOVE_TO | COMMAND_LINE_TO => {
+                for _ in 0..count {
+                    cursor_x += unzigzag(*commands.next()?);
+                    cursor_y += unzigzag(*commands.next()?);
+                    let point = tile_origin.to_lat_lon(cursor_x, cursor_y);
+                    // Each point of a multipoint is a part of its own
+                    if id == COMMAND_MOVE_TO {
+                        parts.push(vec![point]);
+                    } else {
+                        parts.last_mut()?.push(point);
+                    }
+                }
+            }
+            // Rings are treated as closed anyway
+            COMMAND_CLOSE_PATH => {}
+            _ => return None,
+        }
+    }
+    Some(parts)
+}
@@ -0,0 +1,109 @@
+//! Stand-in vector tiles for gaps in a tileset's data, e.g. at the ragged edges of extracts, so
+//! the map renders a neutral background there rather than holes.
+//!
+//! See the [vector tile spec](https://github.com/mapbox/vector-tile-spec/tree/master/2.1):
+//!
+//! ```proto
+//! message Tile {
+//!   message Value { optional string string_value = 1; ... }
+//!   message Feature {
+//!     repeated uint32 tags = 2 [ packed = true ];
+//!     optional GeomType type = 3;
+//!     repeated uint32 geometry = 4 [ packed = true ];
+//!   }
+//!   message Layer {
+//!     required uint32 version = 15;
+//!     required string name = 1;
+//!     repeated Feature features = 2;
+//!     repeated string keys = 3;
+//!     repeated Value values = 4;
+//!     optional uint32 extent = 5;
+//!   }
+//!   repeated Layer layers = 3;
+//! }
+//! ```
+
+use crate::pbf::{write_len_field, write_varint, write_varint_field, zigzag};
+
+const EXTENT: u32 = 4096;
+
+/// How far the fill polygon extends past the tile's edges, so no seams show between tiles
+const BUFFER: i32 = 64;
+
+const GEOM_TYPE_POLYGON: u64 = 3;
+const COMMAND_MOVE_TO: u32 = 1;
+const COMMAND_LINE_TO: u32 = 2;
+const COMMAND_CLOSE_PATH: u32 = 7;
+
+/// What to serve for a vector tile that's missing from a tileset, at a location and zoom within
+/// the tileset's overall coverage.
+#[derive(Clone, Debug, uniffi::Enum)]
+pub enum GapTile {
+    /// Respond 204 No Content within a source's coverage, and 404 Not Found elsewhere (the
+    /// default)
+    NoContent,
+    /// A vector tile without any features
+    Empty,
+    /// A vector tile entirely covered by a single polygon in `layer`, e.g. `"water"` so gaps
+    /// render as sea, optionally with a `class` attribute, e.g. `"ocean"`
+    Fill {
+        layer: String,
+        class: Option<String>,
+    },
+}
+
+impl GapTile {
+    /// The encoded vector tile, or `None` for [`GapTile::NoContent`]
+    pub(crate) fn encode(&self) -> Option<Vec<u8>> {
+        match self {
+            Self::NoContent => None,
+            // A tile without layers is an empty protobuf message
+            Self::Empty => Some(vec![]),
+            Self::Fill { layer, class } => Some(encode_fill(layer, class.as_deref())),
+        }
+    }
+}
+
+fn encode_fill(layer_name: &str, class: Option<&str>) -> Vec<u8> {
+    let mut feature = vec![];
+    if class.is_some() {
+        // key 0 ("class") = value 0
+        write_len_field(&mut feature, 2, &[0, 0]);
+    }
+    write_varint_field(&mut feature, 3, GEOM_TYPE_POLYGON);
+    write_len_field(&mut feature, 4, &fill_geometry());
+
+    let mut layer = vec![];
+    write_varint_field(&mut layer, 15, 2);
+    write_len_field(&mut layer, 1, layer_name.as_bytes());
+    write_len_field(&mut layer, 2, &feature);
+    if let Some(class) = class {
+        write_len_field(&mut layer, 3, b"class");
+        let mut value = vec![];
+        write_len_field(&mut value, 1, class.as_bytes());
+        write_len_field(&mut layer, 4, &value);
+    }
+    write_varint_field(&mut layer, 5, u64::from(EXTENT));
+
+    let mut tile = vec![];
+    write_len_field(&mut tile, 3, &layer);
+    tile
+}
+
+/// A square covering the tile and its buffer, wound clockwise (in tile coordinates, where y
+/// points down) as required of exterior rings, encoded as packed geometry commands.
+fn fill_geometry() -> Vec<u8> {
+    let side = EXTENT as i32 + 2 * BUFFER;
+    let mut geometry = vec![];
+    let command = |id: u32, count: u32| u64::from((id & 0x7) | (count << 3));
+    write_varint(&mut geometry, command(COMMAND_MOVE_TO, 1));
+    write_varint(&mut geometry, zigzag(-BUFFER));
+    write_varint(&mut geometry, zigzag(-BUFFER));
+    write_varint(&mut geometry, command(COMMAND_LINE_TO, 3));
+    for (dx, dy) in [(side, 0), (0, side), (-side, 0)] {
+        write_varint(&mut geometry, zigzag(dx));
+        write_varint(&mut geometry, zigzag(dy));
+    }
+    write_varint(&mut geometry, command(COMMAND_CLOSE_PATH, 1));
+    geometry
+}
@@ -1,9 +1,42 @@
 mod tile_collection;
 
-pub(crate) use tile_collection::TileCollection;
+pub(crate) use tile_collection::{
+    validate_archive, validate_tileset_id, TileCollection, DEFAULT_TILESET_ID,
+};
 
+use crate::geo::{LatLon, EARTH_RADIUS_M};
+use crate::{Error, Result};
+use std::time::SystemTime;
+
+mod directory_cache;
 mod extract;
-pub(crate) use extract::{ExtractProgress, Extractor};
+pub(crate) use extract::{ExtractProgress, Extractor, PlannedExtract};
+
+pub(crate) mod tile_format;
+pub use tile_format::TileFormat;
+
+mod tile_coord;
+pub(crate) use tile_coord::is_valid_tile;
+pub use tile_coord::{tile_at, tile_bounds, tile_count, tiles_in_bounds, TileCoord, MAX_TILE_ZOOM};
+
+mod gap_tile;
+pub use gap_tile::GapTile;
+
+mod overlay_tiles;
+pub(crate) use overlay_tiles::{
+    OverlayTiler, CLUSTER_PROPERTY, OVERLAY_LAYER, OVERLAY_MAX_ZOOM, TRACK_PROPERTY,
+};
+
+mod features;
+pub(crate) use features::{features_near, inspect_tile, rendered_features_at};
+pub use features::{FeatureGeometryType, FeatureValue, NearbyFeature};
+
+mod terrain;
+pub use terrain::ElevationSample;
+pub(crate) use terrain::{elevation_profile, elevations};
+
+mod contours;
+pub(crate) use contours::{contour_source, contour_tile_json, CONTOUR_SOURCE_ID};
 
 #[derive(Clone, Debug, uniffi::Object)]
 pub struct Bounds {
@@ -15,13 +48,146 @@ pub struct Bounds {
 
 #[uniffi::export]
 impl Bounds {
+    /// Fails with [`Error::InvalidBounds`] unless latitudes are within ±90 and longitudes within
+    /// ±180, with each minimum at most its maximum
     #[uniffi::constructor]
-    pub fn nesw(max_lat: f64, max_lon: f64, min_lat: f64, min_lon: f64) -> Self {
-        Self {
+    pub fn nesw(max_lat: f64, max_lon: f64, min_lat: f64, min_lon: f64) -> Result<Self> {
+        let bounds = Self {
             max_lat,
             max_lon,
             min_lat,
             min_lon,
+        };
+        bounds.validate()?;
+        Ok(bounds)
+    }
+
+    /// The bounds extending `radius_m` meters north, east, south and west of `center`, e.g. to
+    /// extract the area around the user
+    #[uniffi::constructor]
+    pub fn from_center_radius(center: LatLon, radius_m: f64) -> Result<Self> {
+        if !(radius_m.is_finite() && radius_m >= 0.0) {
+            return Err(Error::InvalidInput(format!(
+                "radius must be a non-negative number of meters, got {radius_m}"
+            )));
+        }
+        Ok(Self::around(&[center], radius_m).expect("one location"))
+    }
+
+    /// Whether the point at `lat`, `lon` is within the bounds, including on their edges
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        (self.min_lat..=self.max_lat).contains(&lat) && (self.min_lon..=self.max_lon).contains(&lon)
+    }
+
+    /// Whether `self` and `other` overlap by more than a shared edge
+    pub fn intersects(&self, other: &Bounds) -> bool {
+        self.min_lon < other.max_lon
+            && other.min_lon < self.max_lon
+            && self.min_lat < other.max_lat
+            && other.min_lat < self.max_lat
+    }
+
+    /// The smallest bounds containing both `self` and `other`
+    pub fn union(&self, other: &Bounds) -> Bounds {
+        Self {
+            max_lat: self.max_lat.max(other.max_lat),
+            max_lon: self.max_lon.max(other.max_lon),
+            min_lat: self.min_lat.min(other.min_lat),
+            min_lon: self.min_lon.min(other.min_lon),
+        }
+    }
+
+    /// The midpoint of the bounds' latitudes and longitudes
+    pub fn center(&self) -> LatLon {
+        LatLon {
+            lat: (self.min_lat + self.max_lat) / 2.0,
+            lon: (self.min_lon + self.max_lon) / 2.0,
+        }
+    }
+
+    /// The area on the Earth's surface within the bounds, in square kilometers
+    pub fn area_km2(&self) -> f64 {
+        let radius_km = EARTH_RADIUS_M / 1000.0;
+        let lat_extent = self.max_lat.to_radians().sin() - self.min_lat.to_radians().sin();
+        let lon_extent = (self.max_lon - self.min_lon).to_radians();
+        radius_km * radius_km * (lat_extent * lon_extent).abs()
+    }
+}
+
+impl Bounds {
+    fn validate(&self) -> Result<()> {
+        let invalid = |reason: String| Err(Error::InvalidBounds(reason));
+        for (name, lat) in [("max_lat", self.max_lat), ("min_lat", self.min_lat)] {
+            if !(-90.0..=90.0).contains(&lat) {
+                return invalid(format!("{name} must be within ±90, got {lat}"));
+            }
+        }
+        for (name, lon) in [("max_lon", self.max_lon), ("min_lon", self.min_lon)] {
+            if !(-180.0..=180.0).contains(&lon) {
+                return invalid(format!("{name} must be within ±180, got {lon}"));
+            }
+        }
+        if self.min_lat > self.max_lat {
+            return invalid(format!(
+                "min_lat {} is greater than max_lat {}",
+                self.min_lat, self.max_lat
+            ));
+        }
+        if self.min_lon > self.max_lon {
+            return invalid(format!(
+                "min_lon {} is greater than max_lon {}",
+                self.min_lon, self.max_lon
+            ));
+        }
+        Ok(())
+    }
+
+    /// `[max_lat, max_lon, min_lat, min_lon]`, the order of [`Self::nesw`]
+    pub(crate) fn as_nesw(&self) -> [f64; 4] {
+        [self.max_lat, self.max_lon, self.min_lat, self.min_lon]
+    }
+
+    /// The smallest bounds containing every one of `locations`, padded by `padding_m` meters on
+    /// each side, or `None` if there are no locations
+    pub(crate) fn around(locations: &[LatLon], padding_m: f64) -> Option<Bounds> {
+        let first = locations.first()?;
+        let mut bounds = Self {
+            max_lat: first.lat,
+            max_lon: first.lon,
+            min_lat: first.lat,
+            min_lon: first.lon,
+        };
+        for location in locations {
+            bounds.max_lat = bounds.max_lat.max(location.lat);
+            bounds.max_lon = bounds.max_lon.max(location.lon);
+            bounds.min_lat = bounds.min_lat.min(location.lat);
+            bounds.min_lon = bounds.min_lon.min(location.lon);
+        }
+        let lat_padding = (padding_m / EARTH_RADIUS_M).to_degrees();
+        // Degrees of longitude shrink towards the poles, so pad by the most at the widest
+        let max_abs_lat = bounds.max_lat.abs().max(bounds.min_lat.abs()) + lat_padding;
+        let lon_padding = lat_padding / max_abs_lat.min(89.0).to_radians().cos();
+        Some(Self {
+            max_lat: (bounds.max_lat + lat_padding).min(90.0),
+            max_lon: (bounds.max_lon + lon_padding).min(180.0),
+            min_lat: (bounds.min_lat - lat_padding).max(-90.0),
+            min_lon: (bounds.min_lon - lon_padding).max(-180.0),
+        })
+    }
+
+    /// The extent of the web mercator tile at `z/x/y`
+    pub(crate) fn for_tile(z: u8, x: u32, y: u32) -> Bounds {
+        let tiles_per_side = f64::from(1u32 << z);
+        let lon = |x: u32| f64::from(x) / tiles_per_side * 360.0 - 180.0;
+        let lat = |y: u32| {
+            let n = std::f64::consts::PI * (1.0 - 2.0 * f64::from(y) / tiles_per_side);
+            n.sinh().atan().to_degrees()
+        };
+        Self {
+            max_lat: lat(y),
+            max_lon: lon(x + 1),
+            min_lat: lat(y + 1),
+            min_lon: lon(x),
         }
     }
 }
@@ -37,15 +203,50 @@ impl From<&Bounds> for pmtiles::extract::BoundingBox {
     }
 }
 
+/// The combined extent of all the sources in a tileset
+#[derive(Debug, Clone, uniffi::Object)]
+pub struct TilesetCoverage {
+    bounds: Bounds,
+    min_zoom: u8,
+    max_zoom: u8,
+}
+
+#[uniffi::export]
+impl TilesetCoverage {
+    /// The union of the bounds of every source in the tileset
+    pub fn bounds(&self) -> Bounds {
+        self.bounds.clone()
+    }
+    /// The lowest zoom available from any source in the tileset
+    pub fn min_zoom(&self) -> u8 {
+        self.min_zoom
+    }
+    /// The highest zoom available from any source in the tileset
+    pub fn max_zoom(&self) -> u8 {
+        self.max_zoom
+    }
+}
+
 #[derive(Debug, Clone, uniffi::Object)]
 pub struct RegionRecord {
+    tileset_id: String,
     bounds: Bounds,
     file_name: String,
     file_size: u64,
+    min_zoom: u8,
+    max_zoom: u8,
+    tile_format: TileFormat,
+    created_at: Option<SystemTime>,
+    is_system: bool,
+    last_accessed: Option<SystemTime>,
 }
 
 #[uniffi::export]
 impl RegionRecord {
+    /// The tileset this region belongs to, e.g. the basemap or terrain
+    pub fn tileset_id(&self) -> String {
+        self.tileset_id.clone()
+    }
     pub fn bounds(&self) -> Bounds {
         self.bounds.clone()
     }
@@ -55,4 +256,30 @@ impl RegionRecord {
     pub fn file_size(&self) -> u64 {
         self.file_size
     }
+    /// The lowest zoom with tiles in this region
+    pub fn min_zoom(&self) -> u8 {
+        self.min_zoom
+    }
+    /// The highest zoom with tiles in this region, beyond which maps overzoom them
+    pub fn max_zoom(&self) -> u8 {
+        self.max_zoom
+    }
+    pub fn tile_format(&self) -> TileFormat {
+        self.tile_format
+    }
+    /// When this region was downloaded or extracted, if known
+    pub fn created_at(&self) -> Option<SystemTime> {
+        self.created_at
+    }
+    /// Whether this region was downloaded as part of a tileset, e.g. the basemap, rather than
+    /// extracted by the user. Only user extracts can be removed.
+    pub fn is_system(&self) -> bool {
+        self.is_system
+    }
+    /// When this region last served a tile, if ever.
+    ///
+    /// Only persisted with hourly precision, so may be slightly earlier after a restart.
+    pub fn last_accessed(&self) -> Option<SystemTime> {
+        self.last_accessed
+    }
 }
@@ -0,0 +1,1154 @@
+//! Vector tiles cut on the fly from a GeoJSON overlay, so it can be added to a style as a
+//! `vector` source and rendered like the basemap, rather than every client loading all of it.
+//!
+//! Features are projected to web mercator once, when the overlay is added, and then each tile
+//! is clipped from those that intersect it and encoded into a single layer, [`OVERLAY_LAYER`].
+//! See [`super::gap_tile`] for the vector tile messages involved.
+//!
+//! Up to [`CLUSTER_MAX_ZOOM`], points close enough to overlap are drawn as a cluster instead,
+//! merged greedily at each zoom from the clusters of the zoom above, as in [supercluster]. So
+//! an overlay of thousands of markers stays light at low zooms.
+//!
+//! A recorded track is split into features of [`TRACK_CHUNK_POINTS`] points, so appending to it
+//! only rebuilds the last of them, and tiles the track hasn't reached are unchanged.
+//!
+//! [supercluster]: https://github.com/mapbox/supercluster
+
+use crate::geo::LatLon;
+use crate::pbf::{write_double_field, write_len_field, write_varint, write_varint_field, zigzag};
+use crate::{Error, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::sync::{Arc, OnceLock};
+
+/// The layer of every overlay tile
+pub(crate) const OVERLAY_LAYER: &str = "overlay";
+
+/// Clients overzoom beyond this, which is detailed enough for tracks and shapes
+pub(crate) const OVERLAY_MAX_ZOOM: u8 = 16;
+
+/// Set on the features of a recorded track, to `"line"` for the track itself and `"position"`
+/// for its latest point, so styles can draw tracks apart from other overlays
+pub(crate) const TRACK_PROPERTY: &str = "headway:track";
+
+/// Points are clustered in tiles up to this zoom, and drawn individually beyond it
+const CLUSTER_MAX_ZOOM: u8 = 14;
+
+/// Points closer than this, in tile coordinates, are clustered
+const CLUSTER_RADIUS: f64 = 320.0;
+
+/// Set on a cluster of points, along with its `point_count` and `point_count_abbreviated`, as
+/// supercluster does
+pub(crate) const CLUSTER_PROPERTY: &str = "cluster";
+
+/// The most points in each line feature of a recorded track
+const TRACK_CHUNK_POINTS: usize = 256;
+
+pub(super) const EXTENT: f64 = 4096.0;
+
+/// How far features are kept past the tile's edges, so lines and outlines join up seamlessly
+const BUFFER: f64 = 64.0;
+
+const GEOM_TYPE_POINT: u64 = 1;
+const GEOM_TYPE_LINESTRING: u64 = 2;
+const GEOM_TYPE_POLYGON: u64 = 3;
+const COMMAND_MOVE_TO: u32 = 1;
+const COMMAND_LINE_TO: u32 = 2;
+const COMMAND_CLOSE_PATH: u32 = 7;
+
+/// A position in web mercator, from `(0, 0)` at the top left of the world to `(1, 1)` at the
+/// bottom right
+type WorldPoint = (f64, f64);
+
+/// A position in a tile's coordinates, from `(0, 0)` to `(EXTENT, EXTENT)`
+type TilePoint = (f64, f64);
+
+enum Geometry {
+    Points(Vec<WorldPoint>),
+    Lines(Vec<Vec<WorldPoint>>),
+    /// Each polygon is its exterior ring followed by any holes, without the closing points
+    Polygons(Vec<Vec<Vec<WorldPoint>>>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum PropertyValue {
+    String(String),
+    Number(f64),
+    Bool(bool),
+}
+
+struct Feature {
+    id: Option<u64>,
+    properties: Vec<(String, PropertyValue)>,
+    geometry: Geometry,
+    /// `(min_x, min_y, max_x, max_y)` of the geometry
+    bbox: (f64, f64, f64, f64),
+}
+
+/// The features of an overlay, ready to be cut into tiles
+pub(crate) struct OverlayTiler {
+    /// Shared, so a track's tiler can be rebuilt without copying all of it
+    features: Vec<Arc<Feature>>,
+    /// The clusters of the single point features at each zoom up to [`CLUSTER_MAX_ZOOM`],
+    /// made when first needed
+    clusters: OnceLock<Vec<Vec<Cluster>>>,
+}
+
+#[derive(Clone, Copy)]
+struct Cluster {
+    point: WorldPoint,
+    count: usize,
+    /// The index of the feature, if the cluster is just its point
+    feature: Option<usize>,
+}
+
+impl OverlayTiler {
+    /// Accepts a `FeatureCollection`, a `Feature`, or a bare geometry
+    pub(crate) fn new(geojson: &Value) -> Result<Self> {
+        let invalid = |reason: &str| Error::InvalidInput(format!("invalid GeoJSON: {reason}"));
+        let mut tiler = Self {
+            features: vec![],
+            clusters: OnceLock::new(),
+        };
+        let features: Vec<&Value> = match geojson.get("type").and_then(Value::as_str) {
+            Some("FeatureCollection") => geojson
+                .get("features")
+                .and_then(Value::as_array)
+                .ok_or_else(|| invalid("FeatureCollection without features"))?
+                .iter()
+                .collect(),
+            Some("Feature") => vec![geojson],
+            Some(_) => {
+                tiler.add_feature(None, &[], geojson)?;
+                vec![]
+            }
+            None => return Err(invalid("missing type")),
+        };
+        for feature in features {
+            let id = feature.get("id").and_then(Value::as_u64);
+            let properties: Vec<(String, PropertyValue)> = feature
+                .get("properties")
+                .and_then(Value::as_object)
+                .map(|properties| {
+                    properties
+                        .iter()
+                        .filter_map(|(key, value)| {
+                            let value = match value {
+                                Value::Null => return None,
+                                Value::String(value) => PropertyValue::String(value.clone()),
+                                Value::Number(value) => PropertyValue::Number(value.as_f64()?),
+                                Value::Bool(value) => PropertyValue::Bool(*value),
+                                // Nested values can only be strings in a vector tile
+                                value => PropertyValue::String(value.to_string()),
+                            };
+                            Some((key.clone(), value))
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            match feature.get("geometry") {
+                Some(Value::Null) | None => {}
+                Some(geometry) => tiler.add_feature(id, &properties, geometry)?,
+            }
+        }
+        Ok(tiler)
+    }
+
+    /// Adds a feature for each type of geometry in `geometry`, as vector tile features can only
+    /// have one
+    fn add_feature(
+        &mut self,
+        id: Option<u64>,
+        properties: &[(String, PropertyValue)],
+        geometry: &Value,
+    ) -> Result<()> {
+        let mut points = vec![];
+        let mut lines = vec![];
+        let mut polygons = vec![];
+        collect_geometry(geometry, &mut points, &mut lines, &mut polygons)
+            .ok_or_else(|| Error::InvalidInput(format!("invalid GeoJSON geometry: {geometry}")))?;
+        let geometries = [
+            (!points.is_empty()).then_some(Geometry::Points(points)),
+            (!lines.is_empty()).then_some(Geometry::Lines(lines)),
+            (!polygons.is_empty()).then_some(Geometry::Polygons(polygons)),
+        ];
+        for geometry in geometries.into_iter().flatten() {
+            let bbox = geometry.bbox();
+            self.features.push(Arc::new(Feature {
+                id,
+                properties: properties.to_vec(),
+                geometry,
+                bbox,
+            }));
+        }
+        Ok(())
+    }
+
+    /// The tiler for a recorded track through `points`
+    pub(crate) fn track(points: &[LatLon]) -> Self {
+        Self {
+            features: vec![],
+            clusters: OnceLock::new(),
+        }
+        .with_track_points(points)
+    }
+
+    /// A copy of this track's tiler with `points` appended to the track. Only its last line
+    /// feature and its position are rebuilt, the rest are shared.
+    pub(crate) fn with_track_points(&self, points: &[LatLon]) -> Self {
+        let mut features = self.features.clone();
+        // The position is always last, and is where the track continues from
+        let mut line = match features.pop().as_deref().map(|feature| &feature.geometry) {
+            Some(Geometry::Points(position)) => position.clone(),
+            _ => vec![],
+        };
+        if let Some(Geometry::Lines(lines)) = features.last().map(|feature| &feature.geometry) {
+            if lines[0].len() < TRACK_CHUNK_POINTS {
+                line.clone_from(&lines[0]);
+                features.pop();
+            }
+        }
+        line.extend(
+            points
+                .iter()
+                .filter_map(|point| project(point.lon, point.lat)),
+        );
+        let Some(&latest) = line.last() else {
+            return Self {
+                features,
+                clusters: OnceLock::new(),
+            };
+        };
+        // Each line starts where the previous one ended, so they join up
+        let mut start = 0;
+        while start + 1 < line.len() {
+            let end = (start + TRACK_CHUNK_POINTS).min(line.len());
+            features.push(track_feature(
+                "line",
+                Geometry::Lines(vec![line[start..end].to_vec()]),
+            ));
+            start = end - 1;
+        }
+        features.push(track_feature("position", Geometry::Points(vec![latest])));
+        Self {
+            features,
+            clusters: OnceLock::new(),
+        }
+    }
+
+    /// The encoded tile at `z/x/y`, or `None` if no features intersect it
+    pub(crate) fn tile(&self, z: u8, x: u32, y: u32) -> Option<Vec<u8>> {
+        let tiles_per_side = f64::from(1u32 << z);
+        let buffer = BUFFER / EXTENT / tiles_per_side;
+        let tile_bbox = (
+            f64::from(x) / tiles_per_side - buffer,
+            f64::from(y) / tiles_per_side - buffer,
+            f64::from(x + 1) / tiles_per_side + buffer,
+            f64::from(y + 1) / tiles_per_side + buffer,
+        );
+        let to_tile = |(world_x, world_y): WorldPoint| -> TilePoint {
+            (
+                (world_x * tiles_per_side - f64::from(x)) * EXTENT,
+                (world_y * tiles_per_side - f64::from(y)) * EXTENT,
+            )
+        };
+        let clusters = (z <= CLUSTER_MAX_ZOOM).then(|| self.clusters(z));
+        let mut layer = LayerEncoder::default();
+        for feature in &self.features {
+            if clusters.is_some() && feature.single_point().is_some() {
+                continue;
+            }
+            let (min_x, min_y, max_x, max_y) = feature.bbox;
+            if min_x > tile_bbox.2
+                || max_x < tile_bbox.0
+                || min_y > tile_bbox.3
+                || max_y < tile_bbox.1
+            {
+                continue;
+            }
+            let encoded = match &feature.geometry {
+                Geometry::Points(points) => encode_points(points, to_tile),
+                Geometry::Lines(lines) => encode_lines(lines, to_tile),
+                Geometry::Polygons(polygons) => encode_polygons(polygons, to_tile),
+            };
+            if let Some((geometry_type, geometry)) = encoded {
+                layer.add_feature(feature.id, &feature.properties, geometry_type, &geometry);
+            }
+        }
+        for cluster in clusters.into_iter().flatten() {
+            let (world_x, world_y) = cluster.point;
+            if world_x < tile_bbox.0
+                || world_x > tile_bbox.2
+                || world_y < tile_bbox.1
+                || world_y > tile_bbox.3
+            {
+                continue;
+            }
+            let Some((geometry_type, geometry)) = encode_points(&[cluster.point], to_tile) else {
+                continue;
+            };
+            match cluster.feature {
+                Some(index) => {
+                    let feature = &self.features[index];
+                    layer.add_feature(feature.id, &feature.properties, geometry_type, &geometry);
+                }
+                None => layer.add_feature(
+                    None,
+                    &cluster_properties(cluster.count),
+                    geometry_type,
+                    &geometry,
+                ),
+            }
+        }
+        layer.finish(OVERLAY_LAYER)
+    }
+
+    /// The clusters at `z`, which must be at most [`CLUSTER_MAX_ZOOM`]
+    fn clusters(&self, z: u8) -> &[Cluster] {
+        let levels = self.clusters.get_or_init(|| {
+            let points: Vec<Cluster> = self
+                .features
+                .iter()
+                .enumerate()
+                .filter_map(|(index, feature)| {
+                    Some(Cluster {
+                        point: feature.single_point()?,
+                        count: 1,
+                        feature: Some(index),
+                    })
+                })
+                .collect();
+            let mut levels: Vec<Vec<Cluster>> = vec![];
+            for z in (0..=CLUSTER_MAX_ZOOM).rev() {
+                let clusters = cluster(levels.last().unwrap_or(&points), z);
+                levels.push(clusters);
+            }
+            levels.reverse();
+            levels
+        });
+        &levels[usize::from(z)]
+    }
+}
+
+impl Feature {
+    /// The feature's point, if it's a single point, which can be clustered
+    fn single_point(&self) -> Option<WorldPoint> {
+        match &self.geometry {
+            Geometry::Points(points) if points.len() == 1 => Some(points[0]),
+            _ => None,
+        }
+    }
+}
+
+/// Merges the clusters of the zoom above `z` which are within [`CLUSTER_RADIUS`] of each other
+/// at `z`, each into the first of them, at their weighted centroid
+fn cluster(clusters: &[Cluster], z: u8) -> Vec<Cluster> {
+    let radius = CLUSTER_RADIUS / EXTENT / f64::from(1u32 << z);
+    let cell = |(x, y): WorldPoint| ((x / radius).floor() as i64, (y / radius).floor() as i64);
+    let mut grid: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+    for (index, cluster) in clusters.iter().enumerate() {
+        grid.entry(cell(cluster.point)).or_default().push(index);
+    }
+    let mut is_merged = vec![false; clusters.len()];
+    let mut merged = vec![];
+    for (index, cluster) in clusters.iter().enumerate() {
+        if is_merged[index] {
+            continue;
+        }
+        is_merged[index] = true;
+        let (x, y) = cluster.point;
+        let (cell_x, cell_y) = cell(cluster.point);
+        let mut count = cluster.count;
+        let mut sum = (x * count as f64, y * count as f64);
+        for neighbor_x in cell_x - 1..=cell_x + 1 {
+            for neighbor_y in cell_y - 1..=cell_y + 1 {
+                let Some(neighbors) = grid.get(&(neighbor_x, neighbor_y)) else {
+                    continue;
+                };
+                for &neighbor in neighbors {
+                    let other = clusters[neighbor];
+                    let (other_x, other_y) = other.point;
+                    if is_merged[neighbor] || (other_x - x).hypot(other_y - y) > radius {
+                        continue;
+                    }
+                    is_merged[neighbor] = true;
+                    count += other.count;
+                    sum.0 += other_x * other.count as f64;
+                    sum.1 += other_y * other.count as f64;
+                }
+            }
+        }
+        merged.push(if count == cluster.count {
+            *cluster
+        } else {
+            Cluster {
+                point: (sum.0 / count as f64, sum.1 / count as f64),
+                count,
+                feature: None,
+            }
+        });
+    }
+    merged
+}
+
+fn cluster_properties(count: usize) -> Vec<(String, PropertyValue)> {
+    let abbreviated = if count >= 10_000 {
+        format!("{}k", (count as f64 / 1000.0).round())
+    } else if count >= 1000 {
+        format!("{}k", (count as f64 / 100.0).round() / 10.0)
+    } else {
+        count.to_string()
+    };
+    vec![
+        (CLUSTER_PROPERTY.to_string(), PropertyValue::Bool(true)),
+        (
+            "point_count".to_string(),
+            PropertyValue::Number(count as f64),
+        ),
+        (
+            "point_count_abbreviated".to_string(),
+            PropertyValue::String(abbreviated),
+        ),
+    ]
+}
+
+impl Geometry {
+    fn bbox(&self) -> (f64, f64, f64, f64) {
+        let points: Box<dyn Iterator<Item = &WorldPoint>> = match self {
+            Self::Points(points) => Box::new(points.iter()),
+            Self::Lines(lines) => Box::new(lines.iter().flatten()),
+            Self::Polygons(polygons) => Box::new(polygons.iter().flatten().flatten()),
+        };
+        points.fold(
+            (f64::MAX, f64::MAX, f64::MIN, f64::MIN),
+            |(min_x, min_y, max_x, max_y), &(x, y)| {
+                (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y))
+            },
+        )
+    }
+}
+
+fn track_feature(part: &str, geometry: Geometry) -> Arc<Feature> {
+    Arc::new(Feature {
+        id: None,
+        properties: vec![(
+            TRACK_PROPERTY.to_string(),
+            PropertyValue::String(part.to_string()),
+        )],
+        bbox: geometry.bbox(),
+        geometry,
+    })
+}
+
+/// Appends the parts of a GeoJSON geometry to the lists for each type, or returns `None` if it's
+/// malformed
+fn collect_geometry(
+    geometry: &Value,
+    points: &mut Vec<WorldPoint>,
+    lines: &mut Vec<Vec<WorldPoint>>,
+    polygons: &mut Vec<Vec<Vec<WorldPoint>>>,
+) -> Option<()> {
+    let coordinates = || geometry.get("coordinates");
+    match geometry.get("type")?.as_str()? {
+        "Point" => points.push(position(coordinates()?)?),
+        "MultiPoint" => {
+            for point in coordinates()?.as_array()? {
+                points.push(position(point)?);
+            }
+        }
+        "LineString" => lines.push(line(coordinates()?)?),
+        "MultiLineString" => {
+            for coordinates in coordinates()?.as_array()? {
+                lines.push(line(coordinates)?);
+            }
+        }
+        "Polygon" => polygons.push(polygon(coordinates()?)?),
+        "MultiPolygon" => {
+            for coordinates in coordinates()?.as_array()? {
+                polygons.push(polygon(coordinates)?);
+            }
+        }
+        "GeometryCollection" => {
+            for geometry in geometry.get("geometries")?.as_array()? {
+                collect_geometry(geometry, points, lines, polygons)?;
+            }
+        }
+        _ => return None,
+    }
+    Some(())
+}
+
+/// A `[lon, lat]` position, projected to web mercator
+fn position(coordinates: &Value) -> Option<WorldPoint> {
+    let coordinates = coordinates.as_array()?;
+    project(
+        coordinates.first()?.as_f64()?,
+        coordinates.get(1)?.as_f64()?,
+    )
+}
+
+/// Projects a position to web mercator, or returns `None` if it isn't on the globe
+fn project(lon: f64, lat: f64) -> Option<WorldPoint> {
+    if !(lat.abs() <= 90.0 && lon.abs() <= 180.0) {
+        return None;
+    }
+    let lat = lat.clamp(-85.051_128, 85.051_128).to_radians();
+    Some((
+        (lon + 180.0) / 360.0,
+        (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0,
+    ))
+}
+
+fn line(coordinates: &Value) -> Option<Vec<WorldPoint>> {
+    coordinates.as_array()?.iter().map(position).collect()
+}
+
+fn polygon(coordinates: &Value) -> Option<Vec<Vec<WorldPoint>>> {
+    coordinates
+        .as_array()?
+        .iter()
+        .map(|ring| {
+            let mut ring = line(ring)?;
+            if ring.len() > 1 && ring.first() == ring.last() {
+                ring.pop();
+            }
+            Some(ring)
+        })
+        .collect()
+}
+
+fn command(id: u32, count: usize) -> u64 {
+    u64::from((id & 0x7) | ((count as u32) << 3))
+}
+
+/// Rounds `points` to integer tile coordinates, dropping consecutive duplicates
+fn snap(points: impl IntoIterator<Item = TilePoint>) -> Vec<(i32, i32)> {
+    let mut snapped: Vec<(i32, i32)> = vec![];
+    for (x, y) in points {
+        let point = (x.round() as i32, y.round() as i32);
+        if snapped.last() != Some(&point) {
+            snapped.push(point);
+        }
+    }
+    snapped
+}
+
+/// Appends commands to draw `points` to `geometry`, from `cursor`, which is left at the last of
+/// them
+fn write_path(geometry: &mut Vec<u8>, cursor: &mut (i32, i32), points: &[(i32, i32)]) {
+    for &(x, y) in points {
+        write_varint(geometry, zigzag(x - cursor.0));
+        write_varint(geometry, zigzag(y - cursor.1));
+        *cursor = (x, y);
+    }
+}
+
+fn encode_points(
+    points: &[WorldPoint],
+    to_tile: impl Fn(WorldPoint) -> TilePoint,
+) -> Option<(u64, Vec<u8>)> {
+    let points: Vec<(i32, i32)> = points
+        .iter()
+        .map(|point| to_tile(*point))
+        .filter(|&(x, y)| is_within_buffer(x) && is_within_buffer(y))
+        .map(|(x, y)| (x.round() as i32, y.round() as i32))
+        .collect();
+    if points.is_empty() {
+        return None;
+    }
+    let mut geometry = vec![];
+    write_varint(&mut geometry, command(COMMAND_MOVE_TO, points.len()));
+    write_path(&mut geometry, &mut (0, 0), &points);
+    Some((GEOM_TYPE_POINT, geometry))
+}
+
+pub(super) fn encode_lines(
+    lines: &[Vec<WorldPoint>],
+    to_tile: impl Fn(WorldPoint) -> TilePoint,
+) -> Option<(u64, Vec<u8>)> {
+    let mut geometry = vec![];
+    let mut cursor = (0, 0);
+    for line in lines {
+        let line: Vec<TilePoint> = line.iter().map(|point| to_tile(*point)).collect();
+        for clipped in clip_line(&line) {
+            let points = snap(clipped);
+            if points.len() < 2 {
+                continue;
+            }
+            write_varint(&mut geometry, command(COMMAND_MOVE_TO, 1));
+            write_path(&mut geometry, &mut cursor, &points[..1]);
+            write_varint(&mut geometry, command(COMMAND_LINE_TO, points.len() - 1));
+            write_path(&mut geometry, &mut cursor, &points[1..]);
+        }
+    }
+    (!geometry.is_empty()).then_some((GEOM_TYPE_LINESTRING, geometry))
+}
+
+fn encode_polygons(
+    polygons: &[Vec<Vec<WorldPoint>>],
+    to_tile: impl Fn(WorldPoint) -> TilePoint,
+) -> Option<(u64, Vec<u8>)> {
+    let mut geometry = vec![];
+    let mut cursor = (0, 0);
+    for polygon in polygons {
+        for (i, ring) in polygon.iter().enumerate() {
+            let ring: Vec<TilePoint> = ring.iter().map(|point| to_tile(*point)).collect();
+            let mut points = snap(clip_ring(&ring));
+            if points.len() > 1 && points.first() == points.last() {
+                points.pop();
+            }
+            if points.len() < 3 {
+                if i == 0 {
+                    // Without its exterior ring, the polygon's holes mean nothing
+                    break;
+                }
+                continue;
+            }
+            // Exterior rings must have a positive area, in tile coordinates where y points down,
+            // and holes a negative one
+            let is_exterior = i == 0;
+            if (signed_area(&points) > 0) != is_exterior {
+                points.reverse();
+            }
+            write_varint(&mut geometry, command(COMMAND_MOVE_TO, 1));
+            write_path(&mut geometry, &mut cursor, &points[..1]);
+            write_varint(&mut geometry, command(COMMAND_LINE_TO, points.len() - 1));
+            write_path(&mut geometry, &mut cursor, &points[1..]);
+            write_varint(&mut geometry, command(COMMAND_CLOSE_PATH, 1));
+        }
+    }
+    (!geometry.is_empty()).then_some((GEOM_TYPE_POLYGON, geometry))
+}
+
+/// Twice the area of `ring`, by the surveyor's formula
+fn signed_area(ring: &[(i32, i32)]) -> i64 {
+    let mut area = 0;
+    let mut previous = ring[ring.len() - 1];
+    for &point in ring {
+        area +=
+            i64::from(previous.0) * i64::from(point.1) - i64::from(point.0) * i64::from(previous.1);
+        previous = point;
+    }
+    area
+}
+
+fn is_within_buffer(coordinate: f64) -> bool {
+    (-BUFFER..=EXTENT + BUFFER).contains(&coordinate)
+}
+
+/// The parts of `line` within the tile's buffer
+fn clip_line(line: &[TilePoint]) -> Vec<Vec<TilePoint>> {
+    let mut clipped = vec![];
+    let mut current: Vec<TilePoint> = vec![];
+    for segment in line.windows(2) {
+        match clip_segment(segment[0], segment[1]) {
+            Some((start, end)) => {
+                // The line left the tile since the last segment, so this is a new part
+                if current.last() != Some(&start) {
+                    clipped.push(std::mem::take(&mut current));
+                    current.push(start);
+                }
+                current.push(end);
+            }
+            None => clipped.push(std::mem::take(&mut current)),
+        }
+    }
+    clipped.push(current);
+    clipped.retain(|part| part.len() > 1);
+    clipped
+}
+
+/// The part of the segment from `start` to `end` within the tile's buffer, if any, by the
+/// Liang–Barsky algorithm. Unclipped ends are returned exactly, so consecutive segments join up.
+fn clip_segment(start: TilePoint, end: TilePoint) -> Option<(TilePoint, TilePoint)> {
+    let (min, max) = (-BUFFER, EXTENT + BUFFER);
+    let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+    let (mut t0, mut t1) = (0.0, 1.0);
+    for (p, q) in [
+        (-dx, start.0 - min),
+        (dx, max - start.0),
+        (-dy, start.1 - min),
+        (dy, max - start.1),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else if p < 0.0 {
+            t0 = f64::max(t0, q / p);
+        } else {
+            t1 = f64::min(t1, q / p);
+        }
+    }
+    if t0 > t1 {
+        return None;
+    }
+    let at = |t: f64| (start.0 + t * dx, start.1 + t * dy);
+    Some((
+        if t0 > 0.0 { at(t0) } else { start },
+        if t1 < 1.0 { at(t1) } else { end },
+    ))
+}
+
+/// The part of `ring` within the tile's buffer, by the Sutherland–Hodgman algorithm
+fn clip_ring(ring: &[TilePoint]) -> Vec<TilePoint> {
+    let (min, max) = (-BUFFER, EXTENT + BUFFER);
+    let mut output = ring.to_vec();
+    // Each edge as the axis it bounds (0 for x, 1 for y), its position, and whether points
+    // inside are below it
+    for (axis, bound, inside_is_below) in [
+        (0, min, false),
+        (0, max, true),
+        (1, min, false),
+        (1, max, true),
+    ] {
+        let input = std::mem::take(&mut output);
+        let Some(&last) = input.last() else {
+            break;
+        };
+        let coordinate = |point: TilePoint| if axis == 0 { point.0 } else { point.1 };
+        let is_inside = |point: TilePoint| (coordinate(point) <= bound) == inside_is_below;
+        let intersection = |a: TilePoint, b: TilePoint| {
+            let t = (bound - coordinate(a)) / (coordinate(b) - coordinate(a));
+            (a.0 + t * (b.0 - a.0), a.1 + t * (b.1 - a.1))
+        };
+        let mut previous = last;
+        for point in input {
+            if is_inside(point) {
+                if !is_inside(previous) {
+                    output.push(intersection(previous, point));
+                }
+                output.push(point);
+            } else if is_inside(previous) {
+                output.push(intersection(previous, point));
+            }
+            previous = point;
+        }
+    }
+    output
+}
+
+/// Builds a vector tile layer, sharing the keys and values of its features' properties
+#[derive(Default)]
+pub(super) struct LayerEncoder {
+    features: Vec<Vec<u8>>,
+    keys: Vec<String>,
+    key_indices: HashMap<String, u64>,
+    /// Encoded `Value` messages
+    values: Vec<Vec<u8>>,
+    value_indices: HashMap<Vec<u8>, u64>,
+}
+
+impl LayerEncoder {
+    pub(super) fn add_feature(
+        &mut self,
+        id: Option<u64>,
+        properties: &[(String, PropertyValue)],
+        geometry_type: u64,
+        geometry: &[u8],
+    ) {
+        let mut tags = vec![];
+        for (key, value) in properties {
+            let key_index = *self.key_indices.entry(key.clone()).or_insert_with(|| {
+                self.keys.push(key.clone());
+                self.keys.len() as u64 - 1
+            });
+            let mut encoded_value = vec![];
+            match value {
+                PropertyValue::String(value) => {
+                    write_len_field(&mut encoded_value, 1, value.as_bytes());
+                }
+                PropertyValue::Number(value) => write_double_field(&mut encoded_value, 3, *value),
+                PropertyValue::Bool(value) => {
+                    write_varint_field(&mut encoded_value, 7, u64::from(*value));
+                }
+            }
+            let value_index = *self
+                .value_indices
+                .entry(encoded_value.clone())
+                .or_insert_with(|| {
+                    self.values.push(encoded_value);
+                    self.values.len() as u64 - 1
+                });
+            write_varint(&mut tags, key_index);
+            write_varint(&mut tags, value_index);
+        }
+
+        let mut feature = vec![];
+        if let Some(id) = id {
+            write_varint_field(&mut feature, 1, id);
+        }
+        if !tags.is_empty() {
+            write_len_field(&mut feature, 2, &tags);
+        }
+        write_varint_field(&mut feature, 3, geometry_type);
+        write_len_field(&mut feature, 4, geometry);
+        self.features.push(feature);
+    }
+
+    /// The encoded tile containing the layer, or `None` if it has no features
+    pub(super) fn finish(self, name: &str) -> Option<Vec<u8>> {
+        if self.features.is_empty() {
+            return None;
+        }
+        let mut layer = vec![];
+        write_varint_field(&mut layer, 15, 2);
+        write_len_field(&mut layer, 1, name.as_bytes());
+        for feature in &self.features {
+            write_len_field(&mut layer, 2, feature);
+        }
+        for key in &self.keys {
+            write_len_field(&mut layer, 3, key.as_bytes());
+        }
+        for value in &self.values {
+            write_len_field(&mut layer, 4, value);
+        }
+        write_varint_field(&mut layer, 5, EXTENT as u64);
+
+        let mut tile = vec![];
+        write_len_field(&mut tile, 3, &layer);
+        Some(tile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pbf::{read_fields, read_packed_varints, unzigzag, FieldValue};
+    use serde_json::json;
+
+    #[derive(Debug)]
+    struct DecodedFeature {
+        id: Option<u64>,
+        properties: Vec<(String, PropertyValue)>,
+        geometry_type: u64,
+        /// Each part in tile coordinates, with rings closed by repeating their first point
+        parts: Vec<Vec<(i32, i32)>>,
+    }
+
+    /// The features of an overlay tile, checking its layer along the way
+    fn decode(tile: &[u8]) -> Vec<DecodedFeature> {
+        let fields = read_fields(tile).expect("valid tile");
+        let [(3, FieldValue::Len(layer))] = fields.as_slice() else {
+            panic!("expected a single layer");
+        };
+        let mut features = vec![];
+        let mut keys = vec![];
+        let mut values = vec![];
+        for (field_number, value) in read_fields(layer).expect("valid layer") {
+            match (field_number, value) {
+                (1, FieldValue::Len(name)) => assert_eq!(name, OVERLAY_LAYER.as_bytes()),
+                (2, FieldValue::Len(feature)) => features.push(feature),
+                (3, FieldValue::Len(key)) => keys.push(String::from_utf8(key.to_vec()).unwrap()),
+                (4, FieldValue::Len(value)) => values.push(decode_value(value)),
+                (5, FieldValue::Varint(extent)) => assert_eq!(extent, EXTENT as u64),
+                (15, FieldValue::Varint(version)) => assert_eq!(version, 2),
+                (field_number, _) => panic!("unexpected layer field {field_number}"),
+            }
+        }
+        features
+            .into_iter()
+            .map(|feature| {
+                let mut decoded = DecodedFeature {
+                    id: None,
+                    properties: vec![],
+                    geometry_type: 0,
+                    parts: vec![],
+                };
+                for (field_number, value) in read_fields(feature).expect("valid feature") {
+                    match (field_number, value) {
+                        (1, FieldValue::Varint(id)) => decoded.id = Some(id),
+                        (2, FieldValue::Len(tags)) => {
+                            let tags = read_packed_varints(tags).expect("valid tags");
+                            for tag in tags.chunks(2) {
+                                decoded.properties.push((
+                                    keys[tag[0] as usize].clone(),
+                                    values[tag[1] as usize].clone(),
+                                ));
+                            }
+                        }
+                        (3, FieldValue::Varint(geometry_type)) => {
+                            decoded.geometry_type = geometry_type;
+                        }
+                        (4, FieldValue::Len(geometry)) => {
+                            let commands = read_packed_varints(geometry).expect("valid geometry");
+                            decoded.parts = decode_geometry(&commands);
+                        }
+                        (field_number, _) => panic!("unexpected feature field {field_number}"),
+                    }
+                }
+                decoded
+            })
+            .collect()
+    }
+
+    fn decode_value(value: &[u8]) -> PropertyValue {
+        match read_fields(value).expect("valid value").as_slice() {
+            [(1, FieldValue::Len(value))] => {
+                PropertyValue::String(String::from_utf8(value.to_vec()).unwrap())
+            }
+            [(3, FieldValue::Fixed64(value))] => PropertyValue::Number(f64::from_bits(*value)),
+            [(7, FieldValue::Varint(value))] => PropertyValue::Bool(*value != 0),
+            _ => panic!("unexpected value"),
+        }
+    }
+
+    fn decode_geometry(commands: &[u64]) -> Vec<Vec<(i32, i32)>> {
+        let mut parts: Vec<Vec<(i32, i32)>> = vec![];
+        let mut cursor = (0, 0);
+        let mut commands = commands.iter().copied();
+        while let Some(command) = commands.next() {
+            let id = (command & 0x7) as u32;
+            for _ in 0..command >> 3 {
+                if id == COMMAND_CLOSE_PATH {
+                    let ring = parts.last_mut().expect("a ring to close");
+                    ring.push(ring[0]);
+                    continue;
+                }
+                let mut delta = || unzigzag(commands.next().expect("a parameter")) as i32;
+                cursor = (cursor.0 + delta(), cursor.1 + delta());
+                match id {
+                    COMMAND_MOVE_TO => parts.push(vec![cursor]),
+                    COMMAND_LINE_TO => parts.last_mut().expect("a part to extend").push(cursor),
+                    _ => panic!("unexpected command {id}"),
+                }
+            }
+        }
+        parts
+    }
+
+    fn point(lon: f64, lat: f64) -> Value {
+        json!({
+            "type": "Feature",
+            "properties": {},
+            "geometry": { "type": "Point", "coordinates": [lon, lat] },
+        })
+    }
+
+    #[test]
+    fn encodes_features_with_their_properties() {
+        let tiler = OverlayTiler::new(&json!({
+            "type": "Feature",
+            "id": 7,
+            "properties": { "name": "Cafe", "seats": 12, "open": true, "closed": null },
+            "geometry": { "type": "Point", "coordinates": [0.0, 0.0] },
+        }))
+        .unwrap();
+        let features = decode(&tiler.tile(0, 0, 0).unwrap());
+        assert_eq!(features.len(), 1);
+        let mut feature = features.into_iter().next().unwrap();
+        assert_eq!(feature.id, Some(7));
+        assert_eq!(feature.geometry_type, GEOM_TYPE_POINT);
+        assert_eq!(feature.parts, [[(2048, 2048)]]);
+        feature.properties.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            feature.properties,
+            [
+                (
+                    "name".to_string(),
+                    PropertyValue::String("Cafe".to_string())
+                ),
+                ("open".to_string(), PropertyValue::Bool(true)),
+                ("seats".to_string(), PropertyValue::Number(12.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn tiles_without_features_are_empty() {
+        let tiler = OverlayTiler::new(&point(0.0, 0.0)).unwrap();
+        assert!(tiler.tile(2, 0, 0).is_none());
+    }
+
+    #[test]
+    fn clips_lines_at_the_tile_buffer() {
+        let tiler = OverlayTiler::new(&json!({
+            "type": "LineString",
+            "coordinates": [[-90.0, 0.0], [90.0, 0.0]],
+        }))
+        .unwrap();
+        let west = decode(&tiler.tile(1, 0, 0).unwrap());
+        assert_eq!(west[0].geometry_type, GEOM_TYPE_LINESTRING);
+        assert_eq!(west[0].parts, [[(2048, 4096), (4160, 4096)]]);
+        let east = decode(&tiler.tile(1, 1, 1).unwrap());
+        assert_eq!(east[0].parts, [[(-64, 0), (2048, 0)]]);
+    }
+
+    #[test]
+    fn splits_lines_that_leave_the_tile() {
+        let line = [
+            (100.0, 100.0),
+            (100.0, -1948.0),
+            (200.0, -1948.0),
+            (200.0, 100.0),
+        ];
+        assert_eq!(
+            clip_line(&line),
+            [
+                [(100.0, 100.0), (100.0, -64.0)],
+                [(200.0, -64.0), (200.0, 100.0)],
+            ]
+        );
+    }
+
+    #[test]
+    fn clips_rings_at_the_tile_buffer() {
+        let ring = [
+            (-1000.0, -1000.0),
+            (5000.0, -1000.0),
+            (5000.0, 5000.0),
+            (-1000.0, 5000.0),
+        ];
+        let mut clipped = snap(clip_ring(&ring));
+        clipped.sort_unstable();
+        assert_eq!(
+            clipped,
+            [(-64, -64), (-64, 4160), (4160, -64), (4160, 4160)]
+        );
+        // A ring entirely outside the tile is clipped away
+        let outside = [(5000.0, 0.0), (6000.0, 0.0), (6000.0, 1000.0)];
+        assert!(clip_ring(&outside).is_empty());
+    }
+
+    #[test]
+    fn winds_exterior_rings_clockwise_and_holes_counterclockwise() {
+        let exterior = json!([
+            [-90.0, -45.0],
+            [90.0, -45.0],
+            [90.0, 45.0],
+            [-90.0, 45.0],
+            [-90.0, -45.0]
+        ]);
+        let hole = json!([
+            [-45.0, -20.0],
+            [45.0, -20.0],
+            [45.0, 20.0],
+            [-45.0, 20.0],
+            [-45.0, -20.0]
+        ]);
+        let mut reversed_exterior = exterior.clone();
+        reversed_exterior.as_array_mut().unwrap().reverse();
+        let mut reversed_hole = hole.clone();
+        reversed_hole.as_array_mut().unwrap().reverse();
+        for coordinates in [
+            json!([exterior, hole]),
+            json!([reversed_exterior, reversed_hole]),
+        ] {
+            let tiler =
+                OverlayTiler::new(&json!({ "type": "Polygon", "coordinates": coordinates }))
+                    .unwrap();
+            let features = decode(&tiler.tile(0, 0, 0).unwrap());
+            assert_eq!(features[0].geometry_type, GEOM_TYPE_POLYGON);
+            let [exterior, hole] = features[0].parts.as_slice() else {
+                panic!("expected an exterior ring and a hole");
+            };
+            // Each ring is closed by the close path command, not a repeated point
+            assert_eq!(exterior.len(), 5);
+            assert_eq!(hole.len(), 5);
+            assert!(signed_area(exterior) > 0);
+            assert!(signed_area(hole) < 0);
+        }
+    }
+
+    #[test]
+    fn signed_area_is_positive_clockwise_in_tile_coordinates() {
+        let clockwise = [(0, 0), (10, 0), (10, 10), (0, 10)];
+        assert_eq!(signed_area(&clockwise), 200);
+        let counterclockwise: Vec<_> = clockwise.iter().rev().copied().collect();
+        assert_eq!(signed_area(&counterclockwise), -200);
+    }
+
+    #[test]
+    fn clips_polygons_covering_the_tile_to_its_buffer() {
+        let tiler = OverlayTiler::new(&json!({
+            "type": "Polygon",
+            "coordinates": [[[-170.0, -80.0], [170.0, -80.0], [170.0, 80.0], [-170.0, 80.0]]],
+        }))
+        .unwrap();
+        let features = decode(&tiler.tile(2, 1, 1).unwrap());
+        let [ring] = features[0].parts.as_slice() else {
+            panic!("expected a single ring");
+        };
+        assert!(signed_area(ring) > 0);
+        let mut corners = ring[..ring.len() - 1].to_vec();
+        corners.sort_unstable();
+        assert_eq!(
+            corners,
+            [(-64, -64), (-64, 4160), (4160, -64), (4160, 4160)]
+        );
+    }
+
+    #[test]
+    fn clusters_nearby_points_up_to_the_max_zoom() {
+        let (near, nearby) = (point(0.0, 0.0), point(0.001, 0.0));
+        let far = json!({
+            "type": "Feature",
+            "id": 3,
+            "geometry": { "type": "Point", "coordinates": [-100.0, -40.0] },
+        });
+        let tiler = OverlayTiler::new(&json!({
+            "type": "FeatureCollection",
+            "features": [near, nearby, far],
+        }))
+        .unwrap();
+
+        let features = decode(&tiler.tile(0, 0, 0).unwrap());
+        assert_eq!(features.len(), 2);
+        let (clusters, points): (Vec<_>, Vec<_>) = features.iter().partition(|feature| {
+            feature
+                .properties
+                .contains(&(CLUSTER_PROPERTY.to_string(), PropertyValue::Bool(true)))
+        });
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(clusters[0].parts, [[(2048, 2048)]]);
+        assert!(clusters[0]
+            .properties
+            .contains(&("point_count".to_string(), PropertyValue::Number(2.0))));
+        assert_eq!(points[0].id, Some(3));
+
+        let z = CLUSTER_MAX_ZOOM + 1;
+        let origin = 1 << (z - 1);
+        let features = decode(&tiler.tile(z, origin, origin).unwrap());
+        assert_eq!(features.len(), 2);
+        assert!(features.iter().all(|feature| feature.properties.is_empty()));
+    }
+
+    #[test]
+    fn clusters_merge_at_their_weighted_centroid() {
+        let clusters = [
+            Cluster {
+                point: (0.5, 0.5),
+                count: 3,
+                feature: None,
+            },
+            Cluster {
+                point: (0.5 + 1e-6, 0.5),
+                count: 1,
+                feature: Some(0),
+            },
+        ];
+        let merged = cluster(&clusters, CLUSTER_MAX_ZOOM);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].count, 4);
+        assert_eq!(merged[0].feature, None);
+        assert!((merged[0].point.0 - (0.5 + 1e-6 / 4.0)).abs() < 1e-12);
+        assert_eq!(merged[0].point.1, 0.5);
+        // Beyond the radius, they're left apart
+        assert_eq!(cluster(&clusters, 20).len(), 2);
+    }
+
+    #[test]
+    fn abbreviates_cluster_point_counts() {
+        for (count, abbreviated) in [(999, "999"), (1000, "1k"), (1500, "1.5k"), (12_345, "12k")] {
+            let properties = cluster_properties(count);
+            assert_eq!(
+                properties[2],
+                (
+                    "point_count_abbreviated".to_string(),
+                    PropertyValue::String(abbreviated.to_string())
+                )
+            );
+        }
+    }
+}
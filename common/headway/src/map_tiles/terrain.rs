@@ -0,0 +1,250 @@
+//! Elevations decoded from the terrain-RGB tiles of a terrain tileset, e.g. for the elevation
+//! profile of a hiking route.
+//!
+//! Both the Mapbox and Terrarium encodings are supported, per the archive's `encoding`
+//! metadata, defaulting to Mapbox.
+
+use super::tile_format::Tile;
+use super::TileCollection;
+use crate::geo::LatLon;
+use crate::{Error, Result};
+use pmtiles::TileType;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::io::Cursor;
+
+/// Enough to sample a 1000km route every 10m
+const MAX_PROFILE_SAMPLES: usize = 100_000;
+
+/// How elevations are encoded in the red, green and blue channels of each pixel
+#[derive(Clone, Copy, Debug)]
+pub(super) enum TerrainEncoding {
+    Mapbox,
+    Terrarium,
+}
+
+impl TerrainEncoding {
+    pub(super) fn from_metadata(metadata: Option<&serde_json::Map<String, Value>>) -> Self {
+        match metadata
+            .and_then(|metadata| metadata.get("encoding"))
+            .and_then(Value::as_str)
+        {
+            Some("terrarium") => Self::Terrarium,
+            _ => Self::Mapbox,
+        }
+    }
+
+    /// The elevation in meters of a pixel
+    fn elevation(self, [r, g, b]: [u8; 3]) -> f64 {
+        let (r, g, b) = (f64::from(r), f64::from(g), f64::from(b));
+        match self {
+            Self::Mapbox => -10_000.0 + (r * 65_536.0 + g * 256.0 + b) * 0.1,
+            Self::Terrarium => r * 256.0 + g + b / 256.0 - 32_768.0,
+        }
+    }
+}
+
+/// The RGB pixels of a square terrain tile
+pub(super) struct TerrainTile {
+    pub(super) size: usize,
+    pixels: Vec<[u8; 3]>,
+}
+
+impl TerrainTile {
+    pub(super) fn decode(tile: Tile) -> Result<Self> {
+        let tile = tile.decompressed()?;
+        let invalid = |e: String| Error::InvalidInput(format!("invalid terrain tile: {e}"));
+        let (width, height, channels, bytes) = match tile.tile_type {
+            TileType::Png => {
+                let mut decoder = png::Decoder::new(Cursor::new(&tile.data));
+                // Expand palettes to RGB, and 16-bit channels to 8
+                decoder.set_transformations(
+                    png::Transformations::EXPAND | png::Transformations::STRIP_16,
+                );
+                let mut reader = decoder.read_info().map_err(|e| invalid(e.to_string()))?;
+                let mut bytes = vec![0; reader.output_buffer_size()];
+                let info = reader
+                    .next_frame(&mut bytes)
+                    .map_err(|e| invalid(e.to_string()))?;
+                let channels = match info.color_type {
+                    png::ColorType::Rgb => 3,
+                    png::ColorType::Rgba => 4,
+                    color_type => {
+                        return Err(invalid(format!("unsupported color type {color_type:?}")))
+                    }
+                };
+                (info.width, info.height, channels, bytes)
+            }
+            TileType::Webp => {
+                let mut decoder = image_webp::WebPDecoder::new(Cursor::new(&tile.data))
+                    .map_err(|e| invalid(e.to_string()))?;
+                let (width, height) = decoder.dimensions();
+                let channels = if decoder.has_alpha() { 4 } else { 3 };
+                let mut bytes = vec![
+                    0;
+                    decoder
+                        .output_buffer_size()
+                        .ok_or_else(|| invalid("too large".to_string()))?
+                ];
+                decoder
+                    .read_image(&mut bytes)
+                    .map_err(|e| invalid(e.to_string()))?;
+                (width, height, channels, bytes)
+            }
+            tile_type => {
+                return Err(Error::InvalidInput(format!(
+                    "terrain tiles must be PNG or WebP, not {tile_type:?}"
+                )))
+            }
+        };
+        if width != height || width == 0 {
+            return Err(invalid(format!(
+                "expected a square tile, got {width}x{height}"
+            )));
+        }
+        let pixels = bytes
+            .chunks_exact(channels)
+            .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+            .collect();
+        Ok(Self {
+            size: width as usize,
+            pixels,
+        })
+    }
+
+    /// The elevation at fractional pixel coordinates, interpolated bilinearly between the
+    /// nearest pixel centers
+    pub(super) fn elevation_at(&self, encoding: TerrainEncoding, x: f64, y: f64) -> f64 {
+        let max = (self.size - 1) as f64;
+        let x = (x - 0.5).clamp(0.0, max);
+        let y = (y - 0.5).clamp(0.0, max);
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(self.size - 1), (y0 + 1).min(self.size - 1));
+        let (fx, fy) = (x - x0 as f64, y - y0 as f64);
+        let elevation = |x: usize, y: usize| encoding.elevation(self.pixels[y * self.size + x]);
+        let top = elevation(x0, y0) * (1.0 - fx) + elevation(x1, y0) * fx;
+        let bottom = elevation(x0, y1) * (1.0 - fx) + elevation(x1, y1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+/// A point along a polyline, see [`elevation_profile`]
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct ElevationSample {
+    /// How far along the polyline the sample is, in meters
+    pub distance_m: f64,
+    pub location: LatLon,
+    /// `None` if the terrain tileset doesn't cover `location`
+    pub elevation_m: Option<f64>,
+}
+
+/// Samples the elevation every `interval_m` along `polyline`, and at its end
+pub(crate) async fn elevation_profile(
+    collection: &TileCollection,
+    tileset_id: &str,
+    polyline: &[LatLon],
+    interval_m: f64,
+) -> Result<Vec<ElevationSample>> {
+    if interval_m.is_nan() || interval_m <= 0.0 {
+        return Err(Error::InvalidInput(format!(
+            "sample interval must be positive, got {interval_m}"
+        )));
+    }
+    let samples = resample(polyline, interval_m);
+    if samples.len() > MAX_PROFILE_SAMPLES {
+        return Err(Error::InvalidInput(format!(
+            "sample interval {interval_m}m is too short for a polyline this long"
+        )));
+    }
+    let locations: Vec<LatLon> = samples.iter().map(|(_, location)| *location).collect();
+    let elevations = elevations(collection, tileset_id, &locations).await?;
+    Ok(samples
+        .into_iter()
+        .zip(elevations)
+        .map(|((distance_m, location), elevation_m)| ElevationSample {
+            distance_m,
+            location,
+            elevation_m,
+        })
+        .collect())
+}
+
+/// Points every `interval_m` along `polyline`, starting at its first point and ending with its
+/// last, with their distance along it
+fn resample(polyline: &[LatLon], interval_m: f64) -> Vec<(f64, LatLon)> {
+    let Some(first) = polyline.first() else {
+        return vec![];
+    };
+    let mut samples = vec![(0.0, *first)];
+    // Distance along the polyline of the start of the current segment
+    let mut segment_start_m = 0.0;
+    let mut next_sample_m = interval_m;
+    for segment in polyline.windows(2) {
+        let length_m = segment[0].distance_to(&segment[1]);
+        while next_sample_m < segment_start_m + length_m {
+            let fraction = (next_sample_m - segment_start_m) / length_m;
+            samples.push((next_sample_m, segment[0].lerp(&segment[1], fraction)));
+            next_sample_m += interval_m;
+        }
+        segment_start_m += length_m;
+    }
+    if polyline.len() > 1 {
+        samples.push((segment_start_m, *polyline.last().expect("not empty")));
+    }
+    samples
+}
+
+/// The elevation in meters at each of `locations`, from the highest zoom of `tileset_id`, or
+/// `None` where it has no tile.
+pub(crate) async fn elevations(
+    collection: &TileCollection,
+    tileset_id: &str,
+    locations: &[LatLon],
+) -> Result<Vec<Option<f64>>> {
+    let coverage = collection
+        .coverage(tileset_id)
+        .ok_or_else(|| Error::InvalidInput(format!("no such tileset: {tileset_id}")))?;
+    if !matches!(
+        collection.tile_type(tileset_id),
+        Some(TileType::Png | TileType::Webp)
+    ) {
+        return Err(Error::InvalidInput(format!(
+            "{tileset_id} isn't a terrain-RGB tileset"
+        )));
+    }
+    let encoding = TerrainEncoding::from_metadata(collection.metadata(tileset_id));
+    let z = coverage.max_zoom();
+    let tiles_per_side = f64::from(1u32 << z);
+
+    // Consecutive locations are usually in the same tile
+    let mut tiles: HashMap<(u32, u32), Option<TerrainTile>> = HashMap::new();
+    let mut elevations = Vec::with_capacity(locations.len());
+    for location in locations {
+        // Fractional tile coordinates in web mercator
+        let lat = location.lat.clamp(-85.051_128, 85.051_128).to_radians();
+        let x = (location.lon + 180.0) / 360.0 * tiles_per_side;
+        let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0 * tiles_per_side;
+        let (tile_x, tile_y) = (
+            (x.floor() as u32).min((1 << z) - 1),
+            (y.floor() as u32).min((1 << z) - 1),
+        );
+        if !tiles.contains_key(&(tile_x, tile_y)) {
+            let tile = match collection.get_tile(tileset_id, z, tile_x, tile_y).await? {
+                Some(tile) => Some(TerrainTile::decode(tile)?),
+                None => None,
+            };
+            tiles.insert((tile_x, tile_y), tile);
+        }
+        let elevation = tiles[&(tile_x, tile_y)].as_ref().map(|tile| {
+            let size = tile.size as f64;
+            tile.elevation_at(
+                encoding,
+                (x - f64::from(tile_x)) * size,
+                (y - f64::from(tile_y)) * size,
+            )
+        });
+        elevations.push(elevation);
+    }
+    Ok(elevations)
+}
@@ -4,19 +4,134 @@
 // - Have the webserver state reference this new entity
 // - have this entity call the extract logic to mutate its own state (so we don't need to restart service)
 
-use super::{Bounds, RegionRecord};
+use super::tile_format::{self, Tile};
+use super::{is_valid_tile, Bounds, RegionRecord, TilesetCoverage};
 use crate::{Error, ErrorContext, Result};
-use bytes::Bytes;
-use pmtiles::{AsyncPmTilesReader, MmapBackend, TileCoord};
+use pmtiles::{AsyncPmTilesReader, MmapBackend, TileCoord, TileType};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
 use std::fmt::Formatter;
 use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
+/// How stale the persisted last-accessed time may get before we rewrite it.
+///
+/// Writing on every tile request would be wasteful, and nobody needs better than
+/// "last used 3 months ago" precision.
+const LAST_ACCESSED_PERSIST_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Tracks when a source last served a tile, persisted in a sidecar file next to the archive.
+#[derive(Debug)]
+struct LastAccessed {
+    sidecar_path: PathBuf,
+    /// Seconds since the unix epoch, 0 if never accessed
+    in_memory: AtomicU64,
+    /// Seconds since the unix epoch of the value last written to `sidecar_path`
+    persisted: AtomicU64,
+}
+
+impl LastAccessed {
+    fn load(pmtiles_path: &Path) -> Self {
+        let sidecar_path = Self::sidecar_path(pmtiles_path);
+        let persisted = match fs::read_to_string(&sidecar_path) {
+            Ok(contents) => contents.trim().parse().unwrap_or_else(|e| {
+                log::warn!("Ignoring invalid last accessed file {sidecar_path:?}: {e}");
+                0
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => {
+                log::warn!("Unable to read last accessed file {sidecar_path:?}: {e}");
+                0
+            }
+        };
+        Self {
+            sidecar_path,
+            in_memory: AtomicU64::new(persisted),
+            persisted: AtomicU64::new(persisted),
+        }
+    }
+
+    fn sidecar_path(pmtiles_path: &Path) -> PathBuf {
+        pmtiles_path.with_extension("last_accessed")
+    }
+
+    fn get(&self) -> Option<SystemTime> {
+        match self.in_memory.load(Ordering::Relaxed) {
+            0 => None,
+            secs => Some(UNIX_EPOCH + Duration::from_secs(secs)),
+        }
+    }
+
+    fn touch(&self) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system clock is after the unix epoch")
+            .as_secs();
+        self.in_memory.store(now, Ordering::Relaxed);
+
+        let persisted = self.persisted.load(Ordering::Relaxed);
+        if now.saturating_sub(persisted) < LAST_ACCESSED_PERSIST_INTERVAL.as_secs() {
+            return;
+        }
+        // Only one concurrent request needs to do the write
+        if self
+            .persisted
+            .compare_exchange(persisted, now, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            return;
+        }
+        if let Err(e) = fs::write(&self.sidecar_path, now.to_string()) {
+            log::warn!(
+                "Unable to persist last accessed time to {:?}: {e}",
+                self.sidecar_path
+            );
+        }
+    }
+}
+
+/// When the archive at `pmtiles_path` was downloaded or extracted, persisted in a sidecar file
+/// next to it since copying the archive to new storage resets its modification time.
+///
+/// Archives from before this was recorded fall back to their modification time, which is then
+/// persisted.
+fn load_created_at(pmtiles_path: &Path, file_metadata: &fs::Metadata) -> Option<SystemTime> {
+    let sidecar_path = created_at_sidecar_path(pmtiles_path);
+    match fs::read_to_string(&sidecar_path) {
+        Ok(contents) => match contents.trim().parse() {
+            Ok(secs) => return Some(UNIX_EPOCH + Duration::from_secs(secs)),
+            Err(e) => log::warn!("Ignoring invalid created at file {sidecar_path:?}: {e}"),
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => log::warn!("Unable to read created at file {sidecar_path:?}: {e}"),
+    }
+    let created_at = file_metadata.modified().ok()?;
+    let secs = created_at.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    if let Err(e) = fs::write(&sidecar_path, secs.to_string()) {
+        log::warn!("Unable to persist created at time to {sidecar_path:?}: {e}");
+    }
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+fn created_at_sidecar_path(pmtiles_path: &Path) -> PathBuf {
+    pmtiles_path.with_extension("created_at")
+}
+
 struct PmTilesSource {
     reader: AsyncPmTilesReader<MmapBackend>,
+    /// The archive's JSON metadata, e.g. `vector_layers` and `attribution`
+    metadata: serde_json::Map<String, Value>,
+    /// Identifies this particular build of the archive, e.g. for tile ETags
+    source_hash: u64,
     record: RegionRecord,
     path: PathBuf,
+    last_accessed: LastAccessed,
 }
 
 impl std::fmt::Debug for PmTilesSource {
@@ -29,158 +144,649 @@ impl std::fmt::Debug for PmTilesSource {
 }
 
 impl PmTilesSource {
-    async fn get_tile(&self, z: u8, x: u32, y: u32) -> Result<Option<Bytes>> {
+    async fn load(tileset_id: &str, path: &Path) -> Result<Self> {
+        let Some(path_display) = path.file_name().and_then(|name| {
+            path.parent()
+                .and_then(|p| p.file_name())
+                .map(|parent| Path::new(parent).join(name))
+        }) else {
+            return Err(Error::Runtime(format!("invalid source path: {path:?}")));
+        };
+        log::debug!("Adding PMTiles file: {}", path_display.display());
+        let reader = AsyncPmTilesReader::new_with_path(&path)
+            .await
+            .context(format!("pmtiles archive: {path:?}"))?;
+
+        let metadata = match reader.get_metadata().await {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                log::warn!("Ignoring invalid metadata in {path:?}: {e}");
+                serde_json::Map::new()
+            }),
+            Err(e) => {
+                log::warn!("Unable to read metadata from {path:?}: {e}");
+                serde_json::Map::new()
+            }
+        };
+
+        let header = reader.get_header();
+        let bounds = Bounds {
+            min_lon: header.min_longitude,
+            min_lat: header.min_latitude,
+            max_lon: header.max_longitude,
+            max_lat: header.max_latitude,
+        };
+
+        log::info!(
+            "  Loaded {} - bbox: {bounds:?}, zoom: {}-{}",
+            path_display.display(),
+            header.min_zoom,
+            header.max_zoom
+        );
+
+        let file_name = path
+            .file_name()
+            .expect("file name must be present")
+            .to_str()
+            .expect("names are valid by construction")
+            .to_string();
+        let file_metadata = fs::metadata(path)?;
+        let file_size = file_metadata.len();
+        let source_hash = {
+            let mut hasher = DefaultHasher::new();
+            file_name.hash(&mut hasher);
+            file_size.hash(&mut hasher);
+            file_metadata.modified().ok().hash(&mut hasher);
+            hasher.finish()
+        };
+        let last_accessed = LastAccessed::load(path);
+        let record = RegionRecord {
+            tileset_id: tileset_id.to_string(),
+            file_name,
+            file_size,
+            bounds,
+            min_zoom: header.min_zoom,
+            max_zoom: header.max_zoom,
+            tile_format: header.tile_type.into(),
+            created_at: load_created_at(path, &file_metadata),
+            is_system: path.parent().and_then(Path::file_name) == Some(OsStr::new("system")),
+            last_accessed: last_accessed.get(),
+        };
+
+        Ok(Self {
+            reader,
+            metadata,
+            source_hash,
+            path: path.to_path_buf(),
+            record,
+            last_accessed,
+        })
+    }
+
+    async fn get_tile(&self, z: u8, x: u32, y: u32) -> Result<Option<Tile>> {
+        // Skips the directory lookup for tiles the archive can't have, which is most of them
+        // when there are several regions
+        if !self.covers(z, x, y) {
+            return Ok(None);
+        }
         let tile_coord = TileCoord::new(z, x, y)?;
-        Ok(self.reader.get_tile(tile_coord).await?)
+        let Some(data) = self.reader.get_tile(tile_coord).await? else {
+            return Ok(None);
+        };
+        self.last_accessed.touch();
+        let header = self.reader.get_header();
+        Ok(Some(Tile {
+            data,
+            tile_type: header.tile_type,
+            compression: header.tile_compression,
+            source_hash: self.source_hash,
+        }))
+    }
+
+    /// Whether `z/x/y` is within the zoom range and bounds of this source, regardless of whether
+    /// there's any data there.
+    fn covers(&self, z: u8, x: u32, y: u32) -> bool {
+        let header = self.reader.get_header();
+        is_valid_tile(z, x, y)
+            && (header.min_zoom..=header.max_zoom).contains(&z)
+            && Bounds::for_tile(z, x, y).intersects(&self.record.bounds)
+    }
+
+    fn record(&self) -> RegionRecord {
+        RegionRecord {
+            last_accessed: self.last_accessed.get(),
+            ..self.record.clone()
+        }
     }
 }
 
-#[derive(Debug)]
+/// The tileset served when a request doesn't specify one, typically the basemap.
+pub(crate) const DEFAULT_TILESET_ID: &str = "default";
+
+/// A group of sources which together make up one logical layer of map data, e.g. the basemap or
+/// terrain. Each tileset has its own storage directory, TileJSON, and serving route.
+#[derive(Clone, Debug)]
+struct Tileset {
+    root: PathBuf,
+    pmtiles_sources: Vec<Arc<PmTilesSource>>,
+}
+
+impl Tileset {
+    fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            pmtiles_sources: vec![],
+        }
+    }
+
+    fn user_extracts_root(&self) -> PathBuf {
+        self.root.join("user")
+    }
+
+    fn system_root(&self) -> PathBuf {
+        self.root.join("system")
+    }
+
+    async fn load_from_storage(&mut self, tileset_id: &str) -> Result<()> {
+        fs::create_dir_all(self.system_root())?;
+        fs::create_dir_all(self.user_extracts_root())?;
+
+        // Scan directory for .pmtiles files
+        for entry in
+            fs::read_dir(self.system_root())?.chain(fs::read_dir(self.user_extracts_root())?)
+        {
+            let path = entry?.path();
+
+            // Only process .pmtiles files
+            if path.extension().and_then(|s| s.to_str()) != Some("pmtiles") {
+                continue;
+            }
+            match PmTilesSource::load(tileset_id, &path).await {
+                Ok(source) => self.pmtiles_sources.push(Arc::new(source)),
+                Err(e) => {
+                    log::error!("Skipping pmtiles source: {path:?} due to error: {e}")
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn coverage(sources: &[Arc<PmTilesSource>]) -> Option<TilesetCoverage> {
+    sources
+        .iter()
+        .map(|source| {
+            let header = source.reader.get_header();
+            TilesetCoverage {
+                bounds: source.record.bounds.clone(),
+                min_zoom: header.min_zoom,
+                max_zoom: header.max_zoom,
+            }
+        })
+        .reduce(|acc, next| TilesetCoverage {
+            bounds: acc.bounds.union(&next.bounds),
+            min_zoom: acc.min_zoom.min(next.min_zoom),
+            max_zoom: acc.max_zoom.max(next.max_zoom),
+        })
+}
+
+/// Builds a [TileJSON](https://github.com/mapbox/tilejson-spec) document describing the
+/// live contents of `sources`, or `None` if there are no sources.
+///
+/// `source_url` is the URL under which `{z}/{x}/{y}` tiles are served.
+fn tile_json(sources: &[Arc<PmTilesSource>], source_id: &str, source_url: &str) -> Option<Value> {
+    let coverage = coverage(sources)?;
+    let bounds = &coverage.bounds;
+    let tile_type = tile_type(sources)?;
+    let Some(extension) = tile_format::extension(tile_type) else {
+        log::warn!("Unsupported tile type {tile_type:?} in source {source_id}");
+        return None;
+    };
+
+    // Sources are consulted in order, so the first one to provide a value wins
+    let first_metadata_str = |key: &str| {
+        sources
+            .iter()
+            .find_map(|source| source.metadata.get(key)?.as_str())
+    };
+
+    let mut tile_json = json!({
+        "tilejson": "3.0.0",
+        "name": first_metadata_str("name").unwrap_or(source_id),
+        "tiles": [format!("{source_url}/{{z}}/{{x}}/{{y}}.{extension}")],
+        "format": extension,
+        "minzoom": coverage.min_zoom,
+        "maxzoom": coverage.max_zoom,
+        "bounds": [bounds.min_lon, bounds.min_lat, bounds.max_lon, bounds.max_lat],
+        "center": [
+            (bounds.min_lon + bounds.max_lon) / 2.0,
+            (bounds.min_lat + bounds.max_lat) / 2.0,
+            coverage.min_zoom
+        ],
+    });
+    for key in ["attribution", "description", "version"] {
+        if let Some(value) = first_metadata_str(key) {
+            tile_json[key] = value.into();
+        }
+    }
+    let vector_layers = vector_layers(sources);
+    if !vector_layers.is_empty() {
+        tile_json["vector_layers"] = vector_layers.into();
+    }
+    Some(tile_json)
+}
+
+/// The union of each source's `vector_layers`, merging layers which share an id.
+fn vector_layers(sources: &[Arc<PmTilesSource>]) -> Vec<Value> {
+    let mut layers_by_id: BTreeMap<String, serde_json::Map<String, Value>> = BTreeMap::new();
+    let source_layers = sources
+        .iter()
+        .filter_map(|source| source.metadata.get("vector_layers")?.as_array())
+        .flatten()
+        .filter_map(Value::as_object);
+    for layer in source_layers {
+        let Some(id) = layer.get("id").and_then(Value::as_str) else {
+            continue;
+        };
+        let Some(merged) = layers_by_id.get_mut(id) else {
+            layers_by_id.insert(id.to_string(), layer.clone());
+            continue;
+        };
+        let zoom = |layer: &serde_json::Map<String, Value>, key: &str| {
+            layer.get(key).and_then(Value::as_u64)
+        };
+        if let Some(min_zoom) = zoom(layer, "minzoom") {
+            let min_zoom = zoom(&*merged, "minzoom").map_or(min_zoom, |z| z.min(min_zoom));
+            merged.insert("minzoom".to_string(), min_zoom.into());
+        }
+        if let Some(max_zoom) = zoom(layer, "maxzoom") {
+            let max_zoom = zoom(&*merged, "maxzoom").map_or(max_zoom, |z| z.max(max_zoom));
+            merged.insert("maxzoom".to_string(), max_zoom.into());
+        }
+        if let (Some(Value::Object(merged_fields)), Some(Value::Object(fields))) =
+            (merged.get_mut("fields"), layer.get("fields"))
+        {
+            for (name, field_type) in fields {
+                merged_fields
+                    .entry(name.clone())
+                    .or_insert_with(|| field_type.clone());
+            }
+        }
+    }
+    layers_by_id.into_values().map(Value::Object).collect()
+}
+
+/// The type of tiles in `sources`, taken from the first one
+fn tile_type(sources: &[Arc<PmTilesSource>]) -> Option<TileType> {
+    let source = sources.first()?;
+    Some(source.reader.get_header().tile_type)
+}
+
+/// The tile from the first of `sources` which has one, and that source
+async fn get_tile(
+    sources: &[Arc<PmTilesSource>],
+    z: u8,
+    x: u32,
+    y: u32,
+) -> Result<Option<(&Arc<PmTilesSource>, Tile)>> {
+    for source in sources {
+        if let Some(tile) = source.get_tile(z, x, y).await? {
+            log::debug!(
+                "Found tile {z}/{x}/{y} in source: {:?}",
+                source.path.file_name().expect("filename must be set")
+            );
+            return Ok(Some((source, tile)));
+        }
+    }
+    Ok(None)
+}
+
+/// Cheap to clone, since the sources are shared, e.g. to change a copy while the original is
+/// still being served
+#[derive(Clone, Debug)]
 pub struct TileCollection {
-    pmtiles_sources: Vec<PmTilesSource>,
+    tilesets: BTreeMap<String, Tileset>,
     pub(crate) file_root: PathBuf,
 }
 
 impl TileCollection {
     pub fn new(file_root: PathBuf) -> Self {
         Self {
-            pmtiles_sources: vec![],
+            tilesets: BTreeMap::new(),
             file_root,
         }
     }
 
-    pub(crate) fn user_extracts_root(&self) -> PathBuf {
-        let mut path = self.file_root.clone();
-        path.push("user");
-        path
+    fn tileset_root(&self, tileset_id: &str) -> PathBuf {
+        self.file_root.join(tileset_id)
+    }
+
+    pub(crate) fn user_extracts_root(&self, tileset_id: &str) -> PathBuf {
+        self.tileset_root(tileset_id).join("user")
     }
 
-    pub(crate) fn system_root(&self) -> PathBuf {
-        let mut path = self.file_root.clone();
-        path.push("system");
-        path
+    pub(crate) fn system_root(&self, tileset_id: &str) -> PathBuf {
+        self.tileset_root(tileset_id).join("system")
     }
 
-    pub(crate) fn generate_user_pmtiles_path(&self) -> PathBuf {
-        let mut path = self.user_extracts_root();
+    pub(crate) fn generate_user_pmtiles_path(&self, tileset_id: &str) -> PathBuf {
+        let mut path = self.user_extracts_root(tileset_id);
         path.push(Uuid::new_v4().to_string());
         path.with_extension("pmtiles")
     }
 
-    pub fn remove_extract(&mut self, file_name: &str) -> Result<()> {
-        let Some(pos) = self
-            .pmtiles_sources
+    /// The ids of all tilesets with at least one source
+    pub(crate) fn tileset_ids(&self) -> impl Iterator<Item = &str> {
+        self.tilesets
             .iter()
-            .position(|x| x.record.file_name == file_name)
+            .filter(|(_, tileset)| !tileset.pmtiles_sources.is_empty())
+            .map(|(id, _)| id.as_str())
+    }
+
+    /// The sources served as `source_id`: every source in the tileset with that id, or else the
+    /// single archive with that file stem, e.g. `planet-overview` for `planet-overview.pmtiles`.
+    fn sources(&self, source_id: &str) -> Option<&[Arc<PmTilesSource>]> {
+        if let Some(tileset) = self.tilesets.get(source_id) {
+            if !tileset.pmtiles_sources.is_empty() {
+                return Some(&tileset.pmtiles_sources);
+            }
+        }
+        self.tilesets
+            .values()
+            .flat_map(|tileset| &tileset.pmtiles_sources)
+            .find(|source| source.path.file_stem() == Some(OsStr::new(source_id)))
+            .map(std::slice::from_ref)
+    }
+
+    /// The union bounds and zoom range of all sources in the tileset, or `None` if the tileset
+    /// has no sources.
+    pub(crate) fn coverage(&self, tileset_id: &str) -> Option<TilesetCoverage> {
+        coverage(&self.tilesets.get(tileset_id)?.pmtiles_sources)
+    }
+
+    /// A TileJSON document describing `source_id` (see [`Self::get_tile`]), whose tiles are
+    /// available at `source_url`, or `None` if there's no such source.
+    pub(crate) fn tile_json(&self, source_id: &str, source_url: &str) -> Option<Value> {
+        tile_json(self.sources(source_id)?, source_id, source_url)
+    }
+
+    /// The tile type of `source_id` (see [`Self::get_tile`]), or `None` if there's no such source
+    pub(crate) fn tile_type(&self, source_id: &str) -> Option<TileType> {
+        tile_type(self.sources(source_id)?)
+    }
+
+    /// The JSON metadata of `source_id`'s first archive, e.g. a terrain tileset's `encoding`
+    pub(crate) fn metadata(&self, source_id: &str) -> Option<&serde_json::Map<String, Value>> {
+        Some(&self.sources(source_id)?.first()?.metadata)
+    }
+
+    /// The on-disk location of the archive with `file_name`, in any tileset
+    pub(crate) fn archive_path(&self, file_name: &str) -> Option<&Path> {
+        self.tilesets
+            .values()
+            .flat_map(|tileset| &tileset.pmtiles_sources)
+            .find(|source| source.record.file_name == file_name)
+            .map(|source| source.path.as_path())
+    }
+
+    pub fn remove_extract(&mut self, file_name: &str) -> Result<()> {
+        let Some((tileset_id, tileset, pos)) =
+            self.tilesets.iter_mut().find_map(|(tileset_id, tileset)| {
+                tileset
+                    .pmtiles_sources
+                    .iter()
+                    .position(|x| x.record.file_name == file_name)
+                    .map(|pos| (tileset_id, tileset, pos))
+            })
         else {
             return Err(Error::Runtime(format!(
                 "no pmtiles source exists with file_name: {file_name}"
             )));
         };
-        let path = &self.pmtiles_sources[pos].path;
+        let path = &tileset.pmtiles_sources[pos].path;
         assert!(fs::exists(path)?);
-        if !is_path_within_dir(path, &self.user_extracts_root())? {
+        if !is_path_within_dir(path, &tileset.user_extracts_root())? {
             return Err(Error::Runtime(format!(
                 "Can only remove extracts within user tile dir: {path:?}"
             )));
         }
         fs::remove_file(path)?;
-        self.pmtiles_sources.remove(pos);
+        let source = tileset.pmtiles_sources.remove(pos);
+        for sidecar_path in [
+            &source.last_accessed.sidecar_path,
+            &created_at_sidecar_path(&source.path),
+        ] {
+            if let Err(e) = fs::remove_file(sidecar_path) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    return Err(e.into());
+                }
+            }
+        }
+        log::debug!("Removed {file_name} from tileset {tileset_id}");
         Ok(())
     }
 
-    pub(crate) async fn load_tiles_from_storage(&mut self) -> Result<()> {
-        fs::create_dir_all(self.system_root())?;
-        fs::create_dir_all(self.user_extracts_root())?;
+    pub(crate) fn region_records(&self) -> Vec<RegionRecord> {
+        self.tilesets
+            .values()
+            .flat_map(|tileset| &tileset.pmtiles_sources)
+            .map(|source| source.record())
+            .collect()
+    }
 
-        // Scan directory for .pmtiles files
-        for entry in
-            fs::read_dir(self.system_root())?.chain(fs::read_dir(self.user_extracts_root())?)
-        {
-            let path = entry?.path();
+    /// A JSON summary of every loaded source, for the status endpoint
+    pub(crate) fn sources_status(&self) -> Vec<Value> {
+        self.tilesets
+            .iter()
+            .flat_map(|(tileset_id, tileset)| {
+                tileset.pmtiles_sources.iter().map(move |source| {
+                    let header = source.reader.get_header();
+                    let bounds = &source.record.bounds;
+                    json!({
+                        "tileset_id": tileset_id,
+                        "file_name": source.record.file_name,
+                        "bounds": [bounds.min_lon, bounds.min_lat, bounds.max_lon, bounds.max_lat],
+                        "min_zoom": header.min_zoom,
+                        "max_zoom": header.max_zoom,
+                    })
+                })
+            })
+            .collect()
+    }
 
-            // Only process .pmtiles files
-            if path.extension().and_then(|s| s.to_str()) != Some("pmtiles") {
+    pub(crate) async fn load_tiles_from_storage(&mut self) -> Result<()> {
+        fs::create_dir_all(&self.file_root)?;
+        self.migrate_legacy_layout()?;
+        fs::create_dir_all(self.tileset_root(DEFAULT_TILESET_ID))?;
+
+        for entry in fs::read_dir(&self.file_root)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
                 continue;
             }
-            match self.add_source(&path).await {
-                Ok(_) => {}
-                Err(e) => {
-                    log::error!("Skipping pmtiles source: {path:?} due to error: {e}")
-                }
+            let Some(tileset_id) = entry.file_name().to_str().map(str::to_string) else {
+                log::warn!("Skipping tileset with invalid name: {:?}", entry.path());
+                continue;
+            };
+            if let Err(e) = validate_tileset_id(&tileset_id) {
+                log::warn!("Skipping tileset directory: {e}");
+                continue;
             }
+            let mut tileset = Tileset::new(entry.path());
+            tileset.load_from_storage(&tileset_id).await?;
+            self.tilesets.insert(tileset_id, tileset);
         }
 
-        if self.pmtiles_sources.is_empty() {
+        let source_count: usize = self
+            .tilesets
+            .values()
+            .map(|tileset| tileset.pmtiles_sources.len())
+            .sum();
+        if source_count == 0 {
             log::warn!("No PMTiles files found in directory: {:?}", self.file_root);
         } else {
-            log::info!("Loaded {} PMTiles source(s)", self.pmtiles_sources.len());
+            log::info!(
+                "Loaded {source_count} PMTiles source(s) across {} tileset(s)",
+                self.tileset_ids().count()
+            );
         }
 
         Ok(())
     }
 
-    pub(crate) async fn get_tile(&self, z: u8, x: u32, y: u32) -> Result<Option<Bytes>> {
-        for source in &self.pmtiles_sources {
-            if let Some(tile) = source.get_tile(z, x, y).await? {
-                log::debug!(
-                    "Found tile {z}/{x}/{y} in source: {:?}",
-                    source.path.file_name().expect("filename must be set")
-                );
-                return Ok(Some(tile));
+    /// Before tilesets were introduced, all sources lived directly in `system` and `user`
+    /// directories under `file_root`. Those now belong to the default tileset.
+    fn migrate_legacy_layout(&self) -> Result<()> {
+        for dir_name in ["system", "user"] {
+            let legacy_dir = self.file_root.join(dir_name);
+            if !fs::exists(&legacy_dir)? {
+                continue;
+            }
+            let new_dir = self.tileset_root(DEFAULT_TILESET_ID).join(dir_name);
+            if fs::exists(&new_dir)? {
+                log::warn!("Not migrating {legacy_dir:?} since {new_dir:?} already exists");
+                continue;
             }
+            log::info!("Migrating legacy tile directory {legacy_dir:?} to {new_dir:?}");
+            fs::create_dir_all(self.tileset_root(DEFAULT_TILESET_ID))?;
+            fs::rename(&legacy_dir, &new_dir)?;
         }
-        Ok(None)
+        Ok(())
     }
 
-    pub async fn add_source(&mut self, path: &Path) -> Result<RegionRecord> {
-        let Some(path_display) = path.file_name().and_then(|name| {
-            path.parent()
-                .and_then(|p| p.file_name())
-                .map(|parent| Path::new(parent).join(name))
-        }) else {
-            return Err(Error::Runtime(format!("invalid source path: {path:?}")));
-        };
-        log::debug!("Adding PMTiles file: {}", path_display.display());
-        let reader = AsyncPmTilesReader::new_with_path(&path)
-            .await
-            .context(format!("pmtiles archive: {path:?}"))?;
+    /// Gets a tile from `source_id`, which is either a tileset id or an individual archive's file
+    /// stem.
+    pub(crate) async fn get_tile(
+        &self,
+        source_id: &str,
+        z: u8,
+        x: u32,
+        y: u32,
+    ) -> Result<Option<Tile>> {
+        Ok(self
+            .get_tile_with_file_name(source_id, z, x, y)
+            .await?
+            .map(|(_, tile)| tile))
+    }
 
-        let header = reader.get_header();
-        let bounds = Bounds {
-            min_lon: header.min_longitude,
-            min_lat: header.min_latitude,
-            max_lon: header.max_longitude,
-            max_lat: header.max_latitude,
+    /// Like [`Self::get_tile`], along with the file name of the archive the tile came from
+    pub(crate) async fn get_tile_with_file_name(
+        &self,
+        source_id: &str,
+        z: u8,
+        x: u32,
+        y: u32,
+    ) -> Result<Option<(String, Tile)>> {
+        let Some(sources) = self.sources(source_id) else {
+            return Ok(None);
         };
+        let tile = get_tile(sources, z, x, y).await?;
+        Ok(tile.map(|(source, tile)| (source.record.file_name.clone(), tile)))
+    }
 
-        log::info!(
-            "  Loaded {} - bbox: {bounds:?}, zoom: {}-{}",
-            path_display.display(),
-            header.min_zoom,
-            header.max_zoom
-        );
+    /// Whether any source for `source_id` covers `z/x/y`, meaning a missing tile there is empty
+    /// rather than unavailable.
+    pub(crate) fn covers(&self, source_id: &str, z: u8, x: u32, y: u32) -> bool {
+        self.sources(source_id)
+            .is_some_and(|sources| sources.iter().any(|source| source.covers(z, x, y)))
+    }
 
-        let file_name = path
-            .file_name()
-            .expect("file name must be present")
-            .to_str()
-            .expect("names are valid by construction")
-            .to_string();
-        let file_size = fs::metadata(path)?.len();
-        let pmt_record = RegionRecord {
-            file_name,
-            file_size,
-            bounds,
+    /// Whether `z/x/y` is within the combined bounds and zoom range of `source_id`'s sources, and
+    /// they're vector tiles, such that a [`super::GapTile`] can stand in for a missing tile.
+    pub(crate) fn within_vector_coverage(&self, source_id: &str, z: u8, x: u32, y: u32) -> bool {
+        let Some(sources) = self.sources(source_id) else {
+            return false;
         };
+        if tile_type(sources) != Some(TileType::Mvt) {
+            return false;
+        }
+        coverage(sources).is_some_and(|coverage| {
+            is_valid_tile(z, x, y)
+                && (coverage.min_zoom..=coverage.max_zoom).contains(&z)
+                && Bounds::for_tile(z, x, y).intersects(&coverage.bounds)
+        })
+    }
 
-        self.pmtiles_sources.push(PmTilesSource {
-            reader,
-            path: path.to_path_buf(),
-            record: pmt_record.clone(),
-        });
-        Ok(pmt_record)
+    pub async fn add_source(&mut self, tileset_id: &str, path: &Path) -> Result<RegionRecord> {
+        validate_tileset_id(tileset_id)?;
+        let source = PmTilesSource::load(tileset_id, path).await?;
+        let record = source.record();
+        self.tileset_mut(tileset_id)
+            .pmtiles_sources
+            .push(Arc::new(source));
+        Ok(record)
+    }
+
+    fn tileset_mut(&mut self, tileset_id: &str) -> &mut Tileset {
+        let root = self.tileset_root(tileset_id);
+        self.tilesets
+            .entry(tileset_id.to_string())
+            .or_insert_with(|| Tileset::new(root))
+    }
+
+    /// Atomically replaces the system tileset at `file_name` with the archive at `new_path`.
+    ///
+    /// The new archive is moved into place over the old one, so there's no moment at which
+    /// neither is being served. If no system tileset with `file_name` exists yet, the new
+    /// archive is simply added.
+    pub(crate) async fn replace_system_source(
+        &mut self,
+        tileset_id: &str,
+        file_name: &str,
+        new_path: &Path,
+    ) -> Result<RegionRecord> {
+        validate_tileset_id(tileset_id)?;
+        let destination_path = self.system_root(tileset_id).join(file_name);
+        // On unix, the existing reader's mmap remains valid after its file is replaced
+        fs::rename(new_path, &destination_path)?;
+        // So the new build's creation time is recorded, rather than the one it replaced
+        if let Err(e) = fs::remove_file(created_at_sidecar_path(&destination_path)) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                return Err(e.into());
+            }
+        }
+        let source = PmTilesSource::load(tileset_id, &destination_path).await?;
+        let record = source.record();
+        let tileset = self.tileset_mut(tileset_id);
+        match tileset
+            .pmtiles_sources
+            .iter()
+            .position(|x| x.path == destination_path)
+        {
+            Some(pos) => tileset.pmtiles_sources[pos] = Arc::new(source),
+            None => tileset.pmtiles_sources.push(Arc::new(source)),
+        }
+        Ok(record)
+    }
+}
+
+/// Tileset ids are used as directory names and URL path segments, so are restricted to a
+/// conservative set of characters.
+pub(crate) fn validate_tileset_id(tileset_id: &str) -> Result<()> {
+    let is_valid = !tileset_id.is_empty()
+        && tileset_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !is_valid {
+        return Err(Error::InvalidInput(format!(
+            "tileset id must be non-empty and contain only ascii letters, digits, '-' or '_' - got: {tileset_id:?}"
+        )));
+    }
+    Ok(())
+}
+
+/// Checks that `path` is a readable pmtiles archive before we start serving it.
+pub(crate) async fn validate_archive(path: &Path) -> Result<()> {
+    let reader = AsyncPmTilesReader::new_with_path(path)
+        .await
+        .context(format!("validating pmtiles archive: {path:?}"))?;
+    let header = reader.get_header();
+    if header.min_zoom > header.max_zoom {
+        return Err(Error::InvalidInput(format!(
+            "invalid zoom range {}-{} in pmtiles archive: {path:?}",
+            header.min_zoom, header.max_zoom
+        )));
     }
+    Ok(())
 }
 
 fn is_path_within_dir(path: &Path, dir: &Path) -> std::io::Result<bool> {
@@ -0,0 +1,136 @@
+//! Web mercator tile math, so apps prefetching tiles or drawing coverage don't each need their
+//! own.
+
+use super::Bounds;
+use crate::geo::LatLon;
+use crate::{Error, Result};
+use std::f64::consts::PI;
+use std::ops::RangeInclusive;
+
+/// The deepest zoom tiles can be addressed at, where x and y still fit in a `u32`
+pub const MAX_TILE_ZOOM: u8 = 30;
+
+/// The most tiles [`tiles_in_bounds`] lists, beyond which [`tile_count`] should be used instead
+const MAX_LISTED_TILES: u64 = 100_000;
+
+/// The latitude web mercator's square world is cut off at
+const MAX_MERCATOR_LAT: f64 = 85.051_128_779_806_59;
+
+/// A web mercator tile, in XYZ order, with y increasing southwards
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, uniffi::Record)]
+pub struct TileCoord {
+    pub z: u8,
+    pub x: u32,
+    pub y: u32,
+}
+
+/// The tile at `zoom` containing `location`. Latitudes beyond web mercator's ±85.0511 are in the
+/// northernmost or southernmost row.
+#[uniffi::export]
+pub fn tile_at(location: LatLon, zoom: u8) -> Result<TileCoord> {
+    validate_zoom(zoom)?;
+    if !((-90.0..=90.0).contains(&location.lat) && (-180.0..=180.0).contains(&location.lon)) {
+        return Err(Error::InvalidInput(format!(
+            "location must be within ±90 latitude and ±180 longitude, got {location:?}"
+        )));
+    }
+    Ok(TileCoord {
+        z: zoom,
+        x: tile_x(location.lon, zoom),
+        y: tile_y(location.lat, zoom),
+    })
+}
+
+/// The extent of `tile`. Fails with [`Error::InvalidInput`] if its x or y is beyond its zoom's
+/// `2^z` tiles per side.
+#[uniffi::export]
+pub fn tile_bounds(tile: TileCoord) -> Result<Bounds> {
+    validate_zoom(tile.z)?;
+    let tiles_per_side = 1u32 << tile.z;
+    if tile.x >= tiles_per_side || tile.y >= tiles_per_side {
+        return Err(Error::InvalidInput(format!(
+            "x and y must be less than {tiles_per_side} at zoom {}, got {tile:?}",
+            tile.z
+        )));
+    }
+    Ok(Bounds::for_tile(tile.z, tile.x, tile.y))
+}
+
+/// Every tile from `min_zoom` to `max_zoom` intersecting `bounds`, by zoom, then row, then
+/// column. Fails with [`Error::InvalidInput`] for more than 100,000 tiles, which should be
+/// counted with [`tile_count`] rather than listed.
+#[uniffi::export]
+pub fn tiles_in_bounds(bounds: &Bounds, min_zoom: u8, max_zoom: u8) -> Result<Vec<TileCoord>> {
+    let count = tile_count(bounds, min_zoom, max_zoom)?;
+    if count > MAX_LISTED_TILES {
+        return Err(Error::InvalidInput(format!(
+            "{count} tiles are too many to list, at most {MAX_LISTED_TILES} can be"
+        )));
+    }
+    let mut tiles = Vec::with_capacity(count as usize);
+    for z in min_zoom..=max_zoom {
+        let (xs, ys) = tile_ranges(bounds, z);
+        for y in ys {
+            tiles.extend(xs.clone().map(|x| TileCoord { z, x, y }));
+        }
+    }
+    Ok(tiles)
+}
+
+/// How many tiles from `min_zoom` to `max_zoom` intersect `bounds`, e.g. to estimate the size of
+/// a prefetch before starting it
+#[uniffi::export]
+pub fn tile_count(bounds: &Bounds, min_zoom: u8, max_zoom: u8) -> Result<u64> {
+    validate_zoom(max_zoom)?;
+    if min_zoom > max_zoom {
+        return Err(Error::InvalidInput(format!(
+            "min_zoom {min_zoom} is greater than max_zoom {max_zoom}"
+        )));
+    }
+    Ok((min_zoom..=max_zoom)
+        .map(|z| {
+            let (xs, ys) = tile_ranges(bounds, z);
+            let len = |range: RangeInclusive<u32>| u64::from(range.end() - range.start()) + 1;
+            len(xs) * len(ys)
+        })
+        .sum())
+}
+
+/// Whether `x` and `y` are within the `2^z` tiles per side at zoom `z`, without overflowing
+/// for any `z/x/y`, e.g. one straight from a URL
+pub(crate) fn is_valid_tile(z: u8, x: u32, y: u32) -> bool {
+    let tiles_per_side = 1u32.checked_shl(z.into());
+    tiles_per_side.is_some_and(|tiles_per_side| x < tiles_per_side && y < tiles_per_side)
+}
+
+fn validate_zoom(zoom: u8) -> Result<()> {
+    if zoom > MAX_TILE_ZOOM {
+        return Err(Error::InvalidInput(format!(
+            "zoom must be at most {MAX_TILE_ZOOM}, got {zoom}"
+        )));
+    }
+    Ok(())
+}
+
+/// The columns and rows of the tiles at `zoom` intersecting `bounds`
+fn tile_ranges(bounds: &Bounds, zoom: u8) -> (RangeInclusive<u32>, RangeInclusive<u32>) {
+    (
+        tile_x(bounds.min_lon, zoom)..=tile_x(bounds.max_lon, zoom),
+        // y increases southwards, so the north edge is in the first row
+        tile_y(bounds.max_lat, zoom)..=tile_y(bounds.min_lat, zoom),
+    )
+}
+
+fn tile_x(lon: f64, zoom: u8) -> u32 {
+    let tiles_per_side = f64::from(1u32 << zoom);
+    let x = ((lon + 180.0) / 360.0 * tiles_per_side).floor();
+    // 180° is the east edge of the last column rather than a column of its own
+    x.clamp(0.0, tiles_per_side - 1.0) as u32
+}
+
+fn tile_y(lat: f64, zoom: u8) -> u32 {
+    let tiles_per_side = f64::from(1u32 << zoom);
+    let lat = lat.clamp(-MAX_MERCATOR_LAT, MAX_MERCATOR_LAT).to_radians();
+    let y = ((1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0 * tiles_per_side).floor();
+    y.clamp(0.0, tiles_per_side - 1.0) as u32
+}
@@ -0,0 +1,111 @@
+use bytes::Bytes;
+use pmtiles::{Compression, TileType};
+use std::io::Read;
+
+/// Tile data as stored in a pmtiles archive, along with what's needed to serve it.
+#[derive(Debug)]
+pub(crate) struct Tile {
+    pub(crate) data: Bytes,
+    pub(crate) tile_type: TileType,
+    /// How `data` is compressed within the archive
+    pub(crate) compression: Compression,
+    /// Identifies the archive the tile came from, changing whenever the archive does
+    pub(crate) source_hash: u64,
+}
+
+impl Tile {
+    /// Returns the tile with its data decompressed, for clients which don't support the
+    /// archive's compression.
+    pub(crate) fn decompressed(self) -> std::io::Result<Tile> {
+        let mut data = vec![];
+        match self.compression {
+            Compression::Gzip => {
+                flate2::read::GzDecoder::new(self.data.as_ref()).read_to_end(&mut data)?;
+            }
+            Compression::Brotli => {
+                brotli_decompressor::Decompressor::new(self.data.as_ref(), 4096)
+                    .read_to_end(&mut data)?;
+            }
+            Compression::Zstd => {
+                ruzstd::decoding::StreamingDecoder::new(self.data.as_ref())
+                    .map_err(std::io::Error::other)?
+                    .read_to_end(&mut data)?;
+            }
+            _ => return Ok(self),
+        }
+        Ok(Tile {
+            data: data.into(),
+            compression: Compression::None,
+            ..self
+        })
+    }
+}
+
+/// What kind of tiles an archive contains
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum TileFormat {
+    /// Mapbox vector tiles
+    Vector,
+    Png,
+    Jpeg,
+    Webp,
+    Avif,
+    Unknown,
+}
+
+impl From<TileType> for TileFormat {
+    fn from(tile_type: TileType) -> Self {
+        match tile_type {
+            TileType::Mvt => Self::Vector,
+            TileType::Png => Self::Png,
+            TileType::Jpeg => Self::Jpeg,
+            TileType::Webp => Self::Webp,
+            TileType::Avif => Self::Avif,
+            TileType::Unknown => Self::Unknown,
+        }
+    }
+}
+
+/// The file extension conventionally used in tile URLs for `tile_type`
+pub(crate) fn extension(tile_type: TileType) -> Option<&'static str> {
+    match tile_type {
+        TileType::Mvt => Some("pbf"),
+        TileType::Png => Some("png"),
+        TileType::Jpeg => Some("jpg"),
+        TileType::Webp => Some("webp"),
+        _ => None,
+    }
+}
+
+/// The HTTP Content-Type for tiles of `tile_type`
+pub(crate) fn content_type(tile_type: TileType) -> Option<&'static str> {
+    match tile_type {
+        TileType::Mvt => Some("application/x-protobuf"),
+        TileType::Png => Some("image/png"),
+        TileType::Jpeg => Some("image/jpeg"),
+        TileType::Webp => Some("image/webp"),
+        _ => None,
+    }
+}
+
+/// Parses the extension of a tile request like `{y}.pbf` or `{y}.png`
+pub(crate) fn tile_type_for_extension(extension: &str) -> Option<TileType> {
+    match extension {
+        "pbf" | "mvt" => Some(TileType::Mvt),
+        "png" => Some(TileType::Png),
+        "jpg" | "jpeg" => Some(TileType::Jpeg),
+        "webp" => Some(TileType::Webp),
+        _ => None,
+    }
+}
+
+/// The HTTP Content-Encoding for tiles stored with `compression`, or `None` if they are served
+/// as-is.
+pub(crate) fn content_encoding(compression: Compression) -> Option<&'static str> {
+    match compression {
+        Compression::Gzip => Some("gzip"),
+        Compression::Brotli => Some("br"),
+        Compression::Zstd => Some("zstd"),
+        _ => None,
+    }
+}
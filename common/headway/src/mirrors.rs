@@ -0,0 +1,43 @@
+//! Alternative URLs for the same file, e.g. community mirrors of a planet build, to fail over to
+//! when the primary is unreachable.
+
+use crate::{Error, Result};
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+#[derive(Debug, Default)]
+pub(crate) struct Mirrors {
+    /// Ordered mirrors of each primary URL
+    mirror_urls: RwLock<HashMap<String, Vec<String>>>,
+}
+
+impl Mirrors {
+    /// Replaces the mirrors of `source_url`. Empty `mirror_urls` removes them.
+    pub(crate) fn set(&self, source_url: String, mirror_urls: Vec<String>) -> Result<()> {
+        for url in std::iter::once(&source_url).chain(&mirror_urls) {
+            reqwest::Url::parse(url)
+                .map_err(|e| Error::InvalidInput(format!("invalid URL {url:?}: {e}")))?;
+        }
+        let mut all_mirror_urls = self.mirror_urls.write().expect("poisoned lock");
+        if mirror_urls.is_empty() {
+            all_mirror_urls.remove(&source_url);
+        } else {
+            all_mirror_urls.insert(source_url, mirror_urls);
+        }
+        Ok(())
+    }
+
+    /// `source_url` followed by its mirrors, in the order to try them
+    pub(crate) fn candidates(&self, source_url: &str) -> Vec<String> {
+        let all_mirror_urls = self.mirror_urls.read().expect("poisoned lock");
+        std::iter::once(source_url.to_string())
+            .chain(
+                all_mirror_urls
+                    .get(source_url)
+                    .into_iter()
+                    .flatten()
+                    .cloned(),
+            )
+            .collect()
+    }
+}
@@ -0,0 +1,114 @@
+//! Minimal protobuf helpers, for the few small messages we produce (glyphs, fallback and overlay
+//! vector tiles) or read (vector tile features) where it's not worth pulling in a protobuf
+//! library.
+
+const WIRE_TYPE_VARINT: u8 = 0;
+const WIRE_TYPE_FIXED64: u8 = 1;
+const WIRE_TYPE_LEN: u8 = 2;
+const WIRE_TYPE_FIXED32: u8 = 5;
+
+pub(crate) fn zigzag(value: i32) -> u64 {
+    u64::from(((value << 1) ^ (value >> 31)) as u32)
+}
+
+pub(crate) fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn write_tag(buf: &mut Vec<u8>, field_number: u32, wire_type: u8) {
+    write_varint(buf, (u64::from(field_number) << 3) | u64::from(wire_type));
+}
+
+pub(crate) fn write_varint_field(buf: &mut Vec<u8>, field_number: u32, value: u64) {
+    write_tag(buf, field_number, WIRE_TYPE_VARINT);
+    write_varint(buf, value);
+}
+
+pub(crate) fn write_double_field(buf: &mut Vec<u8>, field_number: u32, value: f64) {
+    write_tag(buf, field_number, WIRE_TYPE_FIXED64);
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn write_len_field(buf: &mut Vec<u8>, field_number: u32, bytes: &[u8]) {
+    write_tag(buf, field_number, WIRE_TYPE_LEN);
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+pub(crate) fn unzigzag(value: u64) -> i64 {
+    let magnitude = i64::try_from(value >> 1).expect("fits once shifted");
+    if value & 1 == 0 {
+        magnitude
+    } else {
+        -magnitude - 1
+    }
+}
+
+/// Reads a varint from the start of `buf`, advancing past it, or `None` if it's truncated
+pub(crate) fn read_varint(buf: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = buf.split_first()?;
+        *buf = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte < 0x80 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// The value of a field, by its wire type
+pub(crate) enum FieldValue<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Len(&'a [u8]),
+    Fixed32(u32),
+}
+
+/// The `(field_number, value)` of each field of `message`, in order, or `None` if it's malformed
+pub(crate) fn read_fields(mut message: &[u8]) -> Option<Vec<(u32, FieldValue<'_>)>> {
+    let mut fields = vec![];
+    while !message.is_empty() {
+        let tag = read_varint(&mut message)?;
+        let field_number = u32::try_from(tag >> 3).ok()?;
+        let value = match (tag & 0x7) as u8 {
+            WIRE_TYPE_VARINT => FieldValue::Varint(read_varint(&mut message)?),
+            WIRE_TYPE_FIXED64 => {
+                let (bytes, rest) = message.split_first_chunk::<8>()?;
+                message = rest;
+                FieldValue::Fixed64(u64::from_le_bytes(*bytes))
+            }
+            WIRE_TYPE_LEN => {
+                let len = usize::try_from(read_varint(&mut message)?).ok()?;
+                if len > message.len() {
+                    return None;
+                }
+                let (bytes, rest) = message.split_at(len);
+                message = rest;
+                FieldValue::Len(bytes)
+            }
+            WIRE_TYPE_FIXED32 => {
+                let (bytes, rest) = message.split_first_chunk::<4>()?;
+                message = rest;
+                FieldValue::Fixed32(u32::from_le_bytes(*bytes))
+            }
+            _ => return None,
+        };
+        fields.push((field_number, value));
+    }
+    Some(fields)
+}
+
+/// The values of a packed repeated varint field, or `None` if it's malformed
+pub(crate) fn read_packed_varints(mut bytes: &[u8]) -> Option<Vec<u64>> {
+    let mut values = vec![];
+    while !bytes.is_empty() {
+        values.push(read_varint(&mut bytes)?);
+    }
+    Some(values)
+}
@@ -0,0 +1,86 @@
+//! The tokio runtime the library's own work runs on: serving requests, queued downloads, and
+//! every [`BlockingHeadwayServer`](crate::BlockingHeadwayServer) call.
+//!
+//! It's started on first use, with tokio's defaults unless [`configure_runtime`] was called
+//! beforehand, e.g. to use fewer threads on a watch.
+
+use crate::{Error, Result};
+use std::future::Future;
+use std::sync::{Mutex, OnceLock};
+use tokio::runtime::Runtime;
+use tokio::task::JoinHandle;
+
+#[derive(Clone, Debug, Default, uniffi::Record)]
+pub struct RuntimeConfig {
+    /// Threads running async work, one per CPU core by default
+    pub worker_threads: Option<u32>,
+    /// The most threads running blocking work at once, e.g. decoding tiles and hashing files,
+    /// 512 by default. Idle ones exit after a while.
+    pub max_blocking_threads: Option<u32>,
+}
+
+static CONFIG: Mutex<Option<RuntimeConfig>> = Mutex::new(None);
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+/// Configures the runtime the library runs its work on. Should be called before creating a
+/// server, since it fails with [`Error::Runtime`] once the runtime has started.
+#[uniffi::export]
+pub fn configure_runtime(config: RuntimeConfig) -> Result<()> {
+    if config.worker_threads == Some(0) || config.max_blocking_threads == Some(0) {
+        return Err(Error::InvalidInput(format!(
+            "runtime threads must be at least 1 - got: {config:?}"
+        )));
+    }
+    let mut configured = CONFIG.lock().expect("poisoned lock");
+    if RUNTIME.get().is_some() {
+        return Err(Error::Runtime(
+            "runtime must be configured before it's started".to_string(),
+        ));
+    }
+    *configured = Some(config);
+    Ok(())
+}
+
+/// The shared runtime, started by the first caller
+pub(crate) fn runtime() -> Result<&'static Runtime> {
+    if let Some(runtime) = RUNTIME.get() {
+        return Ok(runtime);
+    }
+    // Held until the runtime is stored, so it can't be configured after being read
+    let config = CONFIG.lock().expect("poisoned lock");
+    if let Some(runtime) = RUNTIME.get() {
+        return Ok(runtime);
+    }
+    let config = config.clone().unwrap_or_default();
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.thread_name("headway").enable_all();
+    if let Some(worker_threads) = config.worker_threads {
+        builder.worker_threads(worker_threads as usize);
+    }
+    if let Some(max_blocking_threads) = config.max_blocking_threads {
+        builder.max_blocking_threads(max_blocking_threads as usize);
+    }
+    let runtime = builder.build().map_err(|e| Error::Runtime(e.to_string()))?;
+    Ok(RUNTIME.get_or_init(|| runtime))
+}
+
+/// Runs `future` to completion on the shared runtime, rather than whichever one is polling the
+/// returned future. Dropping the returned future cancels `future` too.
+pub(crate) async fn run_on_runtime<F>(future: F) -> Result<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let mut task = AbortOnDrop(runtime()?.spawn(future));
+    (&mut task.0)
+        .await
+        .map_err(|e| Error::Runtime(e.to_string()))
+}
+
+struct AbortOnDrop<T>(JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
@@ -0,0 +1,289 @@
+//! The user's search and visit history, persisted and ranked by frecency (how frequently and
+//! recently each place was chosen), so both apps suggest the same places first.
+
+use crate::geo::LatLon;
+use crate::{Error, Result};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Beyond this, the entries with the lowest frecency are forgotten
+const MAX_ENTRIES: usize = 1_000;
+
+/// How many of an entry's most recent visits are kept to score it
+const MAX_SAMPLED_VISITS: usize = 10;
+
+/// How many history entries [`SearchHistory::blend`] puts ahead of autocomplete results
+const MAX_BLENDED_ENTRIES: usize = 3;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct SearchHistoryEntry {
+    /// Identifies the place or query, e.g. an OSM id like `node/123` or a Pelias gid
+    pub key: String,
+    pub label: String,
+    pub location: Option<LatLon>,
+    pub visit_count: u32,
+    pub last_visited: SystemTime,
+    /// Higher for entries visited more often and more recently
+    pub frecency: f64,
+}
+
+/// An autocomplete result, e.g. from Pelias, to blend with history
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct AutocompleteResult {
+    /// As for [`SearchHistoryEntry::key`], so results already in the history aren't repeated
+    pub key: String,
+    pub label: String,
+    pub location: Option<LatLon>,
+    pub from_history: bool,
+}
+
+#[derive(uniffi::Object)]
+pub struct SearchHistory {
+    state_path: PathBuf,
+    entries: Mutex<Vec<Entry>>,
+}
+
+struct Entry {
+    key: String,
+    label: String,
+    location: Option<LatLon>,
+    visit_count: u32,
+    /// Most recent last, at most [`MAX_SAMPLED_VISITS`]
+    visits: Vec<SystemTime>,
+}
+
+#[uniffi::export]
+impl SearchHistory {
+    /// Restores the history persisted at `state_path`, e.g. `{storage_dir}/search_history.json`
+    #[uniffi::constructor]
+    pub fn new(state_path: String) -> Result<Arc<Self>> {
+        let state_path = PathBuf::from(state_path);
+        let entries = load(&state_path)?;
+        Ok(Arc::new(Self {
+            state_path,
+            entries: Mutex::new(entries),
+        }))
+    }
+
+    /// Records that the user searched for or visited `key`, e.g. chose it from search results
+    pub fn record(&self, key: String, label: String, location: Option<LatLon>) -> Result<()> {
+        if key.is_empty() {
+            return Err(Error::InvalidInput(
+                "history key must not be empty".to_string(),
+            ));
+        }
+        let now = SystemTime::now();
+        let mut entries = self.lock_entries();
+        match entries.iter_mut().find(|entry| entry.key == key) {
+            Some(entry) => {
+                entry.label = label;
+                entry.location = location.or(entry.location);
+                entry.visit_count += 1;
+                entry.visits.push(now);
+                if entry.visits.len() > MAX_SAMPLED_VISITS {
+                    entry.visits.remove(0);
+                }
+            }
+            None => entries.push(Entry {
+                key,
+                label,
+                location,
+                visit_count: 1,
+                visits: vec![now],
+            }),
+        }
+        if entries.len() > MAX_ENTRIES {
+            entries.sort_by(|a, b| b.frecency(now).total_cmp(&a.frecency(now)));
+            entries.truncate(MAX_ENTRIES);
+        }
+        self.save(&entries);
+        Ok(())
+    }
+
+    /// Entries whose label has a word starting with `query`, ignoring case, or every entry if
+    /// `query` is empty, highest frecency first
+    pub fn search(&self, query: String, limit: u32) -> Vec<SearchHistoryEntry> {
+        let now = SystemTime::now();
+        let query = query.trim().to_lowercase();
+        let entries = self.lock_entries();
+        let mut matches: Vec<SearchHistoryEntry> = entries
+            .iter()
+            .filter(|entry| query.is_empty() || entry.matches(&query))
+            .map(|entry| entry.to_record(now))
+            .collect();
+        matches.sort_by(|a, b| b.frecency.total_cmp(&a.frecency));
+        matches.truncate(limit as usize);
+        matches
+    }
+
+    /// Puts up to three history entries matching `query` ahead of `results`, e.g. from an
+    /// autocomplete request for `query`, dropping results already among them, and returns at
+    /// most `limit`
+    pub fn blend(
+        &self,
+        query: String,
+        results: Vec<AutocompleteResult>,
+        limit: u32,
+    ) -> Vec<AutocompleteResult> {
+        let history = self.search(query, MAX_BLENDED_ENTRIES as u32);
+        let mut blended: Vec<AutocompleteResult> = history
+            .into_iter()
+            .map(|entry| AutocompleteResult {
+                key: entry.key,
+                label: entry.label,
+                location: entry.location,
+                from_history: true,
+            })
+            .collect();
+        for result in results {
+            if !blended.iter().any(|blended| blended.key == result.key) {
+                blended.push(result);
+            }
+        }
+        blended.truncate(limit as usize);
+        blended
+    }
+
+    pub fn remove(&self, key: String) {
+        let mut entries = self.lock_entries();
+        entries.retain(|entry| entry.key != key);
+        self.save(&entries);
+    }
+
+    pub fn clear(&self) {
+        let mut entries = self.lock_entries();
+        entries.clear();
+        self.save(&entries);
+    }
+}
+
+impl SearchHistory {
+    fn lock_entries(&self) -> MutexGuard<'_, Vec<Entry>> {
+        self.entries.lock().expect("poisoned lock")
+    }
+
+    /// Persists the history. Failures are only logged, since losing history is harmless.
+    fn save(&self, entries: &[Entry]) {
+        let json = json!({ "entries": entries.iter().map(Entry::to_json).collect::<Vec<_>>() });
+        if let Err(e) = std::fs::write(&self.state_path, json.to_string()) {
+            log::warn!(
+                "Unable to save search history to {}: {e}",
+                self.state_path.display()
+            );
+        }
+    }
+}
+
+impl Entry {
+    /// Like Firefox's: each sampled visit scores by its age, and their average is scaled by
+    /// the total number of visits
+    fn frecency(&self, now: SystemTime) -> f64 {
+        if self.visits.is_empty() {
+            return 0.0;
+        }
+        let total: f64 = self
+            .visits
+            .iter()
+            .map(|visit| {
+                let age = now.duration_since(*visit).unwrap_or_default();
+                if age < 4 * DAY {
+                    100.0
+                } else if age < 14 * DAY {
+                    70.0
+                } else if age < 31 * DAY {
+                    50.0
+                } else if age < 90 * DAY {
+                    30.0
+                } else {
+                    10.0
+                }
+            })
+            .sum();
+        f64::from(self.visit_count) * total / self.visits.len() as f64
+    }
+
+    fn matches(&self, query: &str) -> bool {
+        let label = self.label.to_lowercase();
+        // Only at the start of a word
+        label
+            .match_indices(query)
+            .any(|(i, _)| !label[..i].ends_with(char::is_alphanumeric))
+    }
+
+    fn to_record(&self, now: SystemTime) -> SearchHistoryEntry {
+        SearchHistoryEntry {
+            key: self.key.clone(),
+            label: self.label.clone(),
+            location: self.location,
+            visit_count: self.visit_count,
+            last_visited: self.visits.last().copied().unwrap_or(UNIX_EPOCH),
+            frecency: self.frecency(now),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let visits_ms: Vec<u64> = self
+            .visits
+            .iter()
+            .map(|visit| {
+                visit
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as u64
+            })
+            .collect();
+        json!({
+            "key": self.key,
+            "label": self.label,
+            "lat": self.location.map(|location| location.lat),
+            "lon": self.location.map(|location| location.lon),
+            "visit_count": self.visit_count,
+            "visits_ms": visits_ms,
+        })
+    }
+
+    fn from_json(json: &Value) -> Option<Self> {
+        let location = match (
+            json.get("lat").and_then(Value::as_f64),
+            json.get("lon").and_then(Value::as_f64),
+        ) {
+            (Some(lat), Some(lon)) => Some(LatLon { lat, lon }),
+            _ => None,
+        };
+        let visits: Vec<SystemTime> = json
+            .get("visits_ms")?
+            .as_array()?
+            .iter()
+            .filter_map(Value::as_u64)
+            .map(|ms| UNIX_EPOCH + Duration::from_millis(ms))
+            .collect();
+        Some(Self {
+            key: json.get("key")?.as_str()?.to_string(),
+            label: json.get("label")?.as_str()?.to_string(),
+            location,
+            visit_count: u32::try_from(json.get("visit_count")?.as_u64()?).ok()?,
+            visits,
+        })
+    }
+}
+
+fn load(path: &Path) -> Result<Vec<Entry>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+    let Ok(json) = serde_json::from_str::<Value>(&contents) else {
+        log::warn!("Ignoring invalid search history {}", path.display());
+        return Ok(vec![]);
+    };
+    Ok(json
+        .get("entries")
+        .and_then(Value::as_array)
+        .map(|entries| entries.iter().filter_map(Entry::from_json).collect())
+        .unwrap_or_default())
+}
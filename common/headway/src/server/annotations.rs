@@ -0,0 +1,271 @@
+//! The user's map annotations ("my pins"), each with a title and optionally an icon and color,
+//! persisted so both apps share one implementation.
+//!
+//! Annotations are always served as an overlay, so they're tiled and added to every served
+//! style like any other: pins are drawn in their color, with their icon from the style's sprite
+//! and their title beneath.
+
+use super::HeadwayServer;
+use crate::geo::LatLon;
+use crate::{Error, Result};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+#[derive(Clone, Debug, PartialEq, uniffi::Record)]
+pub struct Annotation {
+    pub id: u64,
+    pub title: String,
+    pub location: LatLon,
+    /// The name of an image in the style's sprite, e.g. `star`
+    pub icon: Option<String>,
+    /// A hex color, e.g. `#e5484d`
+    pub color: Option<String>,
+    pub created_at: SystemTime,
+}
+
+#[derive(uniffi::Object)]
+pub struct Annotations {
+    server: Arc<HeadwayServer>,
+    state_path: PathBuf,
+    overlay_id: String,
+    store: Mutex<Store>,
+}
+
+#[derive(Default)]
+struct Store {
+    annotations: Vec<Annotation>,
+    next_id: u64,
+}
+
+#[uniffi::export(async_runtime = "tokio")]
+impl Annotations {
+    /// Restores the annotations persisted at `state_path`, e.g. `{profile_dir}/annotations.json`,
+    /// and serves them as the overlay `overlay_id`, kept up to date as they change.
+    ///
+    /// Clients need to reload the style to show the overlay the first time it's added.
+    #[uniffi::constructor]
+    pub async fn new(
+        server: Arc<HeadwayServer>,
+        state_path: String,
+        overlay_id: String,
+    ) -> Result<Arc<Self>> {
+        let state_path = PathBuf::from(state_path);
+        let store = Store::load(&state_path)?;
+        let annotations = Self {
+            server,
+            state_path,
+            overlay_id,
+            store: Mutex::new(store),
+        };
+        annotations
+            .update_overlay(&annotations.store.lock().await)
+            .await?;
+        Ok(Arc::new(annotations))
+    }
+
+    /// Every annotation, in the order they were added
+    pub async fn annotations(&self) -> Vec<Annotation> {
+        self.store.lock().await.annotations.clone()
+    }
+
+    pub async fn annotation(&self, id: u64) -> Option<Annotation> {
+        let store = self.store.lock().await;
+        store
+            .annotations
+            .iter()
+            .find(|annotation| annotation.id == id)
+            .cloned()
+    }
+
+    /// Adds an annotation, returning it with its id
+    pub async fn add(
+        &self,
+        title: String,
+        location: LatLon,
+        icon: Option<String>,
+        color: Option<String>,
+    ) -> Result<Annotation> {
+        let mut store = self.store.lock().await;
+        let annotation = Annotation {
+            id: store.next_id,
+            title,
+            location,
+            icon,
+            color,
+            created_at: SystemTime::now(),
+        };
+        validate(&annotation)?;
+        store.next_id += 1;
+        store.annotations.push(annotation.clone());
+        self.save(&store).await?;
+        Ok(annotation)
+    }
+
+    /// Replaces the annotation with `annotation.id`, e.g. to move it or change its color
+    pub async fn update(&self, annotation: Annotation) -> Result<()> {
+        validate(&annotation)?;
+        let mut store = self.store.lock().await;
+        *store.annotation_mut(annotation.id)? = annotation;
+        self.save(&store).await
+    }
+
+    pub async fn remove(&self, id: u64) -> Result<()> {
+        let mut store = self.store.lock().await;
+        store.annotation_mut(id)?;
+        store.annotations.retain(|annotation| annotation.id != id);
+        self.save(&store).await
+    }
+}
+
+impl Annotations {
+    async fn save(&self, store: &Store) -> Result<()> {
+        // Write then rename, so the annotations are never lost to a partial write
+        let tmp_path = self.state_path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, store.to_json().to_string())?;
+        std::fs::rename(&tmp_path, &self.state_path)?;
+        self.update_overlay(store).await
+    }
+
+    /// Serves the annotations as simplestyle points, which the overlay layers draw
+    async fn update_overlay(&self, store: &Store) -> Result<()> {
+        let features: Vec<Value> = store
+            .annotations
+            .iter()
+            .map(|annotation| {
+                let mut properties = json!({ "id": annotation.id, "title": annotation.title });
+                if let Some(icon) = &annotation.icon {
+                    properties["marker-symbol"] = icon.as_str().into();
+                }
+                if let Some(color) = &annotation.color {
+                    properties["marker-color"] = color.as_str().into();
+                }
+                json!({
+                    "type": "Feature",
+                    "id": annotation.id,
+                    "geometry": {
+                        "type": "Point",
+                        "coordinates": [annotation.location.lon, annotation.location.lat],
+                    },
+                    "properties": properties,
+                })
+            })
+            .collect();
+        let geojson = json!({ "type": "FeatureCollection", "features": features });
+        self.server
+            .overlays
+            .insert(&self.overlay_id, &geojson)
+            .await
+    }
+}
+
+fn validate(annotation: &Annotation) -> Result<()> {
+    let location = annotation.location;
+    if !(location.lat.abs() <= 90.0 && location.lon.abs() <= 180.0) {
+        return Err(Error::InvalidInput(format!(
+            "invalid location {location:?}"
+        )));
+    }
+    if annotation.icon.as_ref().is_some_and(String::is_empty) {
+        return Err(Error::InvalidInput(
+            "annotation icon must not be empty".to_string(),
+        ));
+    }
+    if let Some(color) = &annotation.color {
+        let is_hex = color.strip_prefix('#').is_some_and(|hex| {
+            matches!(hex.len(), 3 | 4 | 6 | 8) && hex.chars().all(|c| c.is_ascii_hexdigit())
+        });
+        if !is_hex {
+            return Err(Error::InvalidInput(format!(
+                "annotation color must be a hex color like #e5484d - got: {color:?}"
+            )));
+        }
+    }
+    Ok(())
+}
+
+impl Store {
+    fn annotation_mut(&mut self, id: u64) -> Result<&mut Annotation> {
+        self.annotations
+            .iter_mut()
+            .find(|annotation| annotation.id == id)
+            .ok_or_else(|| Error::InvalidInput(format!("no annotation with id {id}")))
+    }
+
+    fn load(path: &Path) -> Result<Self> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let json: Value = serde_json::from_str(&contents).map_err(|e| {
+            Error::InvalidInput(format!("invalid annotations {}: {e}", path.display()))
+        })?;
+        let annotations: Vec<Annotation> = json
+            .get("annotations")
+            .and_then(Value::as_array)
+            .map(|annotations| {
+                annotations
+                    .iter()
+                    .filter_map(annotation_from_json)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let next_id = json
+            .get("next_id")
+            .and_then(Value::as_u64)
+            .unwrap_or_default()
+            .max(
+                annotations
+                    .iter()
+                    .map(|annotation| annotation.id + 1)
+                    .max()
+                    .unwrap_or_default(),
+            );
+        Ok(Self {
+            annotations,
+            next_id,
+        })
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "next_id": self.next_id,
+            "annotations": self.annotations.iter().map(annotation_to_json).collect::<Vec<_>>(),
+        })
+    }
+}
+
+fn annotation_to_json(annotation: &Annotation) -> Value {
+    let created_at_ms = annotation
+        .created_at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    json!({
+        "id": annotation.id,
+        "title": annotation.title,
+        "lat": annotation.location.lat,
+        "lon": annotation.location.lon,
+        "icon": annotation.icon,
+        "color": annotation.color,
+        "created_at_ms": created_at_ms,
+    })
+}
+
+fn annotation_from_json(json: &Value) -> Option<Annotation> {
+    let string = |name: &str| json.get(name).and_then(Value::as_str).map(str::to_string);
+    Some(Annotation {
+        id: json.get("id")?.as_u64()?,
+        title: string("title")?,
+        location: LatLon {
+            lat: json.get("lat")?.as_f64()?,
+            lon: json.get("lon")?.as_f64()?,
+        },
+        icon: string("icon"),
+        color: string("color"),
+        created_at: UNIX_EPOCH + Duration::from_millis(json.get("created_at_ms")?.as_u64()?),
+    })
+}
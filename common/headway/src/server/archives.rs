@@ -0,0 +1,114 @@
+//! Serves the underlying .pmtiles archives directly, for clients which read them with a
+//! `pmtiles://` protocol handler rather than requesting individual tiles.
+
+use crate::server::AppState;
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use std::io::SeekFrom;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+pub(crate) async fn get_archive(
+    State(state): State<AppState>,
+    Path(file_name): Path<String>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let path = {
+        let collection = state.tile_collection.load();
+        match collection.archive_path(&file_name) {
+            Some(path) => path.to_path_buf(),
+            None => return StatusCode::NOT_FOUND.into_response(),
+        }
+    };
+
+    let mut file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(e) => {
+            log::error!("Error opening archive {path:?}, error: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let file_len = match file.metadata().await {
+        Ok(metadata) => metadata.len(),
+        Err(e) => {
+            log::error!("Error reading metadata for archive {path:?}, error: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let response = Response::builder()
+        .header(header::CONTENT_TYPE, "application/octet-stream")
+        .header(header::ACCEPT_RANGES, "bytes");
+
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| parse_range(value, file_len));
+    match range {
+        None => response
+            .status(StatusCode::OK)
+            .header(header::CONTENT_LENGTH, file_len)
+            .body(Body::from_stream(ReaderStream::new(file)))
+            .unwrap(),
+        Some(Err(())) => response
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{file_len}"))
+            .body(Body::empty())
+            .unwrap(),
+        Some(Ok((start, end))) => {
+            if let Err(e) = file.seek(SeekFrom::Start(start)).await {
+                log::error!("Error seeking archive {path:?}, error: {e}");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+            let len = end - start + 1;
+            response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_LENGTH, len)
+                .header(
+                    header::CONTENT_RANGE,
+                    format!("bytes {start}-{end}/{file_len}"),
+                )
+                .body(Body::from_stream(ReaderStream::new(file.take(len))))
+                .unwrap()
+        }
+    }
+}
+
+/// Parses a `Range` header value into an inclusive `(start, end)` byte range.
+///
+/// Returns `None` if the header should be ignored, in which case the whole file is served. This
+/// includes multipart ranges, which pmtiles clients never request. Returns `Some(Err(()))` if the
+/// range can't be satisfied.
+fn parse_range(range: &str, file_len: u64) -> Option<std::result::Result<(u64, u64), ()>> {
+    let spec = range.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        // e.g. "bytes=-500": the final 500 bytes
+        ("", suffix_len) => {
+            let suffix_len: u64 = suffix_len.parse().ok()?;
+            if suffix_len == 0 {
+                return Some(Err(()));
+            }
+            (
+                file_len.saturating_sub(suffix_len),
+                file_len.checked_sub(1)?,
+            )
+        }
+        // e.g. "bytes=500-": everything from byte 500 on
+        (start, "") => (start.parse().ok()?, file_len.checked_sub(1)?),
+        (start, end) => {
+            let start: u64 = start.parse().ok()?;
+            let end: u64 = end.parse().ok()?;
+            (start, end.min(file_len.saturating_sub(1)))
+        }
+    };
+    if start > end || start >= file_len {
+        return Some(Err(()));
+    }
+    Some(Ok((start, end)))
+}
@@ -0,0 +1,262 @@
+//! Versioned bundles of styling assets (fonts, sprites and styles), installed from a manifest so
+//! they can be updated without an app release.
+//!
+//! A manifest lists every file in the bundle, laid out like the storage dir:
+//!
+//! ```json
+//! {
+//!   "version": "2025-06-01",
+//!   "files": [
+//!     { "path": "styles/basic/style.json", "url": "styles/basic/style.json", "sha256": "…" }
+//!   ]
+//! }
+//! ```
+//!
+//! File URLs may be relative to the manifest's. Each bundle is installed to
+//! `{storage_dir}/asset_bundles/{version}/`, and only activated once every file has been
+//! downloaded and verified. The active bundle then takes the place of whichever of
+//! `{storage_dir}/fonts`, `sprites` and `styles` it includes.
+
+use crate::checksum::Sha256Digest;
+use crate::download::{download, Downloader};
+use crate::server::storage::ProfileStorage;
+use crate::{Error, ErrorContext, Result};
+use reqwest::Url;
+use serde_json::Value;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+/// The directories of the storage dir a bundle may provide
+const ASSET_DIRS: [&str; 3] = ["fonts", "sprites", "styles"];
+
+/// Records the version of the active bundle
+const ACTIVE_FILE_NAME: &str = "active";
+
+#[derive(Debug)]
+pub(crate) struct AssetBundles {
+    storage: Arc<ProfileStorage>,
+}
+
+impl AssetBundles {
+    pub(crate) fn new(storage: Arc<ProfileStorage>) -> Self {
+        Self { storage }
+    }
+
+    /// `{storage_dir}/asset_bundles`
+    fn root(&self) -> PathBuf {
+        self.storage.join_shared("asset_bundles")
+    }
+
+    pub(crate) fn active_version(&self) -> Option<String> {
+        let path = self.root().join(ACTIVE_FILE_NAME);
+        let version = match fs::read_to_string(&path) {
+            Ok(version) => version,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                log::warn!("Unable to read active asset bundle from {path:?}: {e}");
+                return None;
+            }
+        };
+        if let Err(e) = validate_version(&version) {
+            log::warn!("Ignoring active asset bundle: {e}");
+            return None;
+        }
+        Some(version)
+    }
+
+    /// Where `asset_dir`, e.g. "fonts", is served from: the active bundle's, if it has one,
+    /// otherwise the storage dir's.
+    pub(crate) fn dir(&self, asset_dir: &str) -> PathBuf {
+        if let Some(version) = self.active_version() {
+            let bundle_dir = self.root().join(version).join(asset_dir);
+            if bundle_dir.is_dir() {
+                return bundle_dir;
+            }
+        }
+        self.storage.join_shared(asset_dir)
+    }
+
+    /// Where glyphs generated from the fonts in [`Self::dir`] are cached
+    pub(crate) fn glyph_cache_dir(&self) -> PathBuf {
+        self.dir("fonts")
+            .parent()
+            .expect("fonts dir is within a bundle or the storage dir")
+            .join("glyph_cache")
+    }
+
+    /// Downloads and verifies every file of the bundle described at `manifest_url`, returning
+    /// its version, without activating it.
+    ///
+    /// Files already downloaded by an earlier, interrupted install of the same version are kept.
+    pub(crate) async fn install(
+        &self,
+        downloader: &Downloader,
+        manifest_url: &str,
+    ) -> Result<String> {
+        let base_url = Url::parse(manifest_url).map_err(|e| {
+            Error::InvalidInput(format!("invalid manifest URL {manifest_url:?}: {e}"))
+        })?;
+        let root = self.root();
+        fs::create_dir_all(&root)?;
+        let manifest_path = root.join(".manifest.json");
+        download(
+            downloader,
+            manifest_url,
+            &manifest_path,
+            None,
+            None,
+            None,
+            None,
+        )
+        .await
+        .context("downloading asset bundle manifest")?;
+        let manifest_json = fs::read_to_string(&manifest_path)?;
+        fs::remove_file(&manifest_path)?;
+        let manifest = Manifest::parse(&manifest_json, &base_url)?;
+
+        if self.active_version().as_ref() == Some(&manifest.version) {
+            log::info!("Asset bundle {} is already active", manifest.version);
+            return Ok(manifest.version);
+        }
+        log::info!(
+            "Installing asset bundle {} ({} files)",
+            manifest.version,
+            manifest.files.len()
+        );
+        let staging_dir = root.join(format!(".{}", manifest.version));
+        for file in &manifest.files {
+            let path = staging_dir.join(&file.path);
+            if path.exists() && file.sha256.verify_file(&path).await.is_ok() {
+                continue;
+            }
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            download(
+                downloader,
+                file.url.as_str(),
+                &path,
+                None,
+                Some(&file.sha256),
+                None,
+                None,
+            )
+            .await
+            .context(format!("downloading asset {}", file.path.display()))?;
+        }
+
+        let bundle_dir = root.join(&manifest.version);
+        if bundle_dir.exists() {
+            fs::remove_dir_all(&bundle_dir)?;
+        }
+        fs::rename(&staging_dir, &bundle_dir)?;
+        Ok(manifest.version)
+    }
+
+    /// Makes the installed bundle `version` the active one, and deletes every other bundle
+    pub(crate) fn activate(&self, version: &str) -> Result<()> {
+        validate_version(version)?;
+        // Write then rename, so the active version is never a partially written file
+        let root = self.root();
+        let tmp_path = root.join(format!(".{ACTIVE_FILE_NAME}.tmp"));
+        fs::write(&tmp_path, version)?;
+        fs::rename(&tmp_path, root.join(ACTIVE_FILE_NAME))?;
+        log::info!("Activated asset bundle {version}");
+
+        for entry in fs::read_dir(&root)?.flatten() {
+            let name = entry.file_name();
+            let is_other_bundle = name.to_str().is_some_and(|name| {
+                name != version && name != ACTIVE_FILE_NAME && !name.starts_with('.')
+            });
+            if is_other_bundle && entry.path().is_dir() {
+                if let Err(e) = fs::remove_dir_all(entry.path()) {
+                    log::warn!("Unable to remove old asset bundle {name:?}: {e}");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Manifest {
+    version: String,
+    files: Vec<ManifestFile>,
+}
+
+struct ManifestFile {
+    /// Relative to the bundle dir, e.g. `styles/basic/style.json`
+    path: PathBuf,
+    url: Url,
+    sha256: Sha256Digest,
+}
+
+impl Manifest {
+    fn parse(json: &str, base_url: &Url) -> Result<Self> {
+        let invalid = |reason: String| {
+            Error::InvalidInput(format!("invalid asset bundle manifest: {reason}"))
+        };
+        let json: Value = serde_json::from_str(json).map_err(|e| invalid(e.to_string()))?;
+        let version = json
+            .get("version")
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid("missing version".to_string()))?
+            .to_string();
+        validate_version(&version)?;
+        let files = json
+            .get("files")
+            .and_then(Value::as_array)
+            .ok_or_else(|| invalid("missing files".to_string()))?
+            .iter()
+            .map(|file| {
+                let field = |name: &str| {
+                    file.get(name)
+                        .and_then(Value::as_str)
+                        .ok_or_else(|| invalid(format!("file missing {name}")))
+                };
+                let path = PathBuf::from(field("path")?);
+                if !is_valid_asset_path(&path) {
+                    return Err(invalid(format!("invalid file path {path:?}")));
+                }
+                let url = base_url
+                    .join(field("url")?)
+                    .map_err(|e| invalid(format!("invalid URL for {path:?}: {e}")))?;
+                let sha256 = Sha256Digest::parse(field("sha256")?)?;
+                Ok(ManifestFile { path, url, sha256 })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { version, files })
+    }
+}
+
+/// Versions become directory names, so mustn't be able to escape the bundles dir or collide
+/// with our own files there
+fn validate_version(version: &str) -> Result<()> {
+    let is_valid = version != ACTIVE_FILE_NAME
+        && version
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphanumeric())
+        && version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'));
+    if !is_valid {
+        return Err(Error::InvalidInput(format!(
+            "invalid asset bundle version: {version:?}"
+        )));
+    }
+    Ok(())
+}
+
+/// Whether `path` is a file within one of the [`ASSET_DIRS`], without escaping it
+fn is_valid_asset_path(path: &Path) -> bool {
+    let mut components = path.components();
+    let in_asset_dir = components.next().is_some_and(|component| {
+        ASSET_DIRS
+            .iter()
+            .any(|asset_dir| component == Component::Normal(asset_dir.as_ref()))
+    });
+    in_asset_dir
+        && path.components().count() > 1
+        && components.all(|component| matches!(component, Component::Normal(_)))
+}
@@ -0,0 +1,58 @@
+//! Requires clients to present a shared secret, so other apps on the device can't use the
+//! server.
+//!
+//! The token may be sent as an `Authorization: Bearer {token}` header, or for clients which
+//! can't set headers on every request, as an `access_token={token}` query parameter.
+
+use crate::{Error, Result};
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::sync::Arc;
+
+const QUERY_PARAM: &str = "access_token";
+
+/// Tokens are restricted to URL-safe characters so they can be sent in a query string as-is
+pub(crate) fn validate_auth_token(token: &str) -> Result<()> {
+    let is_url_safe = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_' | '~');
+    if token.is_empty() || !token.chars().all(is_url_safe) {
+        return Err(Error::InvalidInput(
+            "auth token must be non-empty and contain only ASCII letters, digits, '-', '.', '_', or '~'"
+                .to_string(),
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) async fn require_auth_token(
+    State(token): State<Arc<str>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let bearer = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let query_param = request.uri().query().and_then(|query| {
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix(QUERY_PARAM)?.strip_prefix('='))
+    });
+    let authorized = bearer
+        .into_iter()
+        .chain(query_param)
+        .any(|candidate| constant_time_eq(candidate.as_bytes(), token.as_bytes()));
+    if !authorized {
+        log::warn!("Rejected unauthorized request for {}", request.uri().path());
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    next.run(request).await
+}
+
+/// Compares without short-circuiting, so response timing doesn't reveal how much of a guess
+/// was correct
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
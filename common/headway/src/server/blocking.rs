@@ -0,0 +1,481 @@
+//! A synchronous facade over [`HeadwayServer`], for hosts that can't easily consume uniffi's
+//! async methods, e.g. some Kotlin Multiplatform and C consumers.
+//!
+//! Each method runs its async counterpart on the library's runtime, see
+//! [`configure_runtime`](crate::configure_runtime), blocking the calling thread until it
+//! completes. So they mustn't be called from within an async runtime, and long running ones, like
+//! [`BlockingHeadwayServer::start`] or downloads, should be called from a thread of their own.
+//! Methods which are already synchronous are called on [`BlockingHeadwayServer::server`].
+
+use super::{
+    CorsPolicy, ExtractionPlan, GpxImport, HeadwayServer, HeadwayServerConfig, PlaceDetails,
+    RequestLimits,
+};
+use crate::download::{DownloadCancellation, DownloadProgress, RetryPolicy};
+use crate::geo::LatLon;
+use crate::http::HttpTimeouts;
+use crate::map_tiles::{
+    Bounds, ElevationSample, ExtractProgress, GapTile, NearbyFeature, RegionRecord, TilesetCoverage,
+};
+use crate::runtime::runtime;
+use crate::Result;
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+
+#[derive(uniffi::Object)]
+pub struct BlockingHeadwayServer {
+    server: Arc<HeadwayServer>,
+    runtime: &'static Runtime,
+}
+
+#[uniffi::export]
+impl BlockingHeadwayServer {
+    /// Blocks on [`HeadwayServer::new`]
+    #[uniffi::constructor(default(profile = None))]
+    pub fn new(
+        storage_dir: &str,
+        extract_source_url: &str,
+        profile: Option<String>,
+    ) -> Result<Self> {
+        let runtime = runtime()?;
+        let server =
+            runtime.block_on(HeadwayServer::new(storage_dir, extract_source_url, profile))?;
+        Ok(Self {
+            server: Arc::new(server),
+            runtime,
+        })
+    }
+
+    /// Blocks on [`HeadwayServer::with_config`]
+    #[uniffi::constructor]
+    pub fn with_config(config: Arc<HeadwayServerConfig>) -> Result<Self> {
+        let runtime = runtime()?;
+        let server = runtime.block_on(HeadwayServer::with_config(config))?;
+        Ok(Self {
+            server: Arc::new(server),
+            runtime,
+        })
+    }
+
+    /// Wraps an existing `server`, e.g. one shared with code that does use async methods
+    #[uniffi::constructor]
+    pub fn wrap(server: Arc<HeadwayServer>) -> Result<Self> {
+        Ok(Self {
+            server,
+            runtime: runtime()?,
+        })
+    }
+
+    /// The wrapped server, for its synchronous methods
+    pub fn server(&self) -> Arc<HeadwayServer> {
+        self.server.clone()
+    }
+
+    /// Blocks on [`HeadwayServer::install_asset_bundle`]
+    pub fn install_asset_bundle(&self, manifest_url: String) -> Result<String> {
+        self.runtime
+            .block_on(self.server.install_asset_bundle(manifest_url))
+    }
+
+    /// Blocks on [`HeadwayServer::set_transit_endpoint`]
+    pub fn set_transit_endpoint(&self, endpoint: Option<String>) -> Result<()> {
+        self.runtime
+            .block_on(self.server.set_transit_endpoint(endpoint))
+    }
+
+    /// Blocks on [`HeadwayServer::set_routing_endpoint`]
+    pub fn set_routing_endpoint(&self, endpoint: Option<String>) -> Result<()> {
+        self.runtime
+            .block_on(self.server.set_routing_endpoint(endpoint))
+    }
+
+    /// Blocks on [`HeadwayServer::set_sprites_dir`]
+    pub fn set_sprites_dir(&self, sprites_dir: String) {
+        self.runtime
+            .block_on(self.server.set_sprites_dir(sprites_dir))
+    }
+
+    /// Blocks on [`HeadwayServer::register_font`]
+    pub fn register_font(&self, font_name: String, path: String) -> Result<()> {
+        self.runtime
+            .block_on(self.server.register_font(font_name, path))
+    }
+
+    /// Blocks on [`HeadwayServer::set_cors_policy`]
+    pub fn set_cors_policy(&self, cors_policy: Option<CorsPolicy>) -> Result<()> {
+        self.runtime
+            .block_on(self.server.set_cors_policy(cors_policy))
+    }
+
+    /// Blocks on [`HeadwayServer::set_download_retry_policy`]
+    pub fn set_download_retry_policy(&self, retry_policy: RetryPolicy) -> Result<()> {
+        self.runtime
+            .block_on(self.server.set_download_retry_policy(retry_policy))
+    }
+
+    /// Blocks on [`HeadwayServer::set_http_proxy`]
+    pub fn set_http_proxy(&self, proxy_url: Option<String>) -> Result<()> {
+        self.runtime.block_on(self.server.set_http_proxy(proxy_url))
+    }
+
+    /// Blocks on [`HeadwayServer::set_http_timeouts`]
+    pub fn set_http_timeouts(&self, timeouts: HttpTimeouts) -> Result<()> {
+        self.runtime
+            .block_on(self.server.set_http_timeouts(timeouts))
+    }
+
+    /// Blocks on [`HeadwayServer::set_download_concurrency`]
+    pub fn set_download_concurrency(&self, parallel_chunks: u32) -> Result<()> {
+        self.runtime
+            .block_on(self.server.set_download_concurrency(parallel_chunks))
+    }
+
+    /// Blocks on [`HeadwayServer::set_base_path`]
+    pub fn set_base_path(&self, base_path: Option<String>) -> Result<()> {
+        self.runtime.block_on(self.server.set_base_path(base_path))
+    }
+
+    /// Blocks on [`HeadwayServer::set_request_limits`]
+    pub fn set_request_limits(&self, request_limits: RequestLimits) -> Result<()> {
+        self.runtime
+            .block_on(self.server.set_request_limits(request_limits))
+    }
+
+    /// Blocks on [`HeadwayServer::set_tls_enabled`]
+    pub fn set_tls_enabled(&self, enabled: bool) -> Result<()> {
+        self.runtime.block_on(self.server.set_tls_enabled(enabled))
+    }
+
+    /// Blocks on [`HeadwayServer::tls_certificate`]
+    pub fn tls_certificate(&self) -> Option<Vec<u8>> {
+        self.runtime.block_on(self.server.tls_certificate())
+    }
+
+    /// Blocks on [`HeadwayServer::set_auth_token`]
+    pub fn set_auth_token(&self, auth_token: Option<String>) -> Result<()> {
+        self.runtime
+            .block_on(self.server.set_auth_token(auth_token))
+    }
+
+    /// Blocks on [`HeadwayServer::set_base_url`]
+    pub fn set_base_url(&self, base_url: Option<String>) -> Result<()> {
+        self.runtime.block_on(self.server.set_base_url(base_url))
+    }
+
+    /// Blocks on [`HeadwayServer::set_tile_cache_control`]
+    pub fn set_tile_cache_control(&self, cache_control: String) -> Result<()> {
+        self.runtime
+            .block_on(self.server.set_tile_cache_control(cache_control))
+    }
+
+    /// Blocks on [`HeadwayServer::set_gap_tile`]
+    pub fn set_gap_tile(&self, gap_tile: GapTile) {
+        self.runtime.block_on(self.server.set_gap_tile(gap_tile))
+    }
+
+    /// Blocks on [`HeadwayServer::start`]
+    pub fn start(&self, bind_addr: &str) -> Result<()> {
+        self.runtime.block_on(self.server.start(bind_addr))
+    }
+
+    /// Blocks on [`HeadwayServer::start_configured`]
+    pub fn start_configured(&self) -> Result<()> {
+        self.runtime.block_on(self.server.start_configured())
+    }
+
+    /// Blocks on [`HeadwayServer::start_on`]
+    pub fn start_on(&self, bind_addrs: Vec<String>) -> Result<()> {
+        self.runtime.block_on(self.server.start_on(bind_addrs))
+    }
+
+    /// Blocks on [`HeadwayServer::start_unix`]
+    pub fn start_unix(&self, socket_path: &str) -> Result<()> {
+        self.runtime.block_on(self.server.start_unix(socket_path))
+    }
+
+    /// Blocks on [`HeadwayServer::import_gpx`]
+    pub fn import_gpx(
+        &self,
+        path: String,
+        corridor_m: Option<f64>,
+        progress_callback: Option<Arc<dyn ExtractProgress>>,
+    ) -> Result<GpxImport> {
+        self.runtime
+            .block_on(self.server.import_gpx(path, corridor_m, progress_callback))
+    }
+
+    /// Blocks on [`HeadwayServer::add_overlay`]
+    pub fn add_overlay(&self, overlay_id: String, geojson: String) -> Result<()> {
+        self.runtime
+            .block_on(self.server.add_overlay(overlay_id, geojson))
+    }
+
+    /// Blocks on [`HeadwayServer::append_to_track`]
+    pub fn append_to_track(&self, overlay_id: String, points: Vec<LatLon>) -> Result<()> {
+        self.runtime
+            .block_on(self.server.append_to_track(overlay_id, points))
+    }
+
+    /// Blocks on [`HeadwayServer::remove_overlay`]
+    pub fn remove_overlay(&self, overlay_id: String) -> Result<()> {
+        self.runtime
+            .block_on(self.server.remove_overlay(overlay_id))
+    }
+
+    /// Blocks on [`HeadwayServer::download_place_details_if_necessary`]
+    pub fn download_place_details_if_necessary(
+        &self,
+        source_url: &str,
+        destination_filename: &str,
+        expected_sha256: Option<String>,
+        progress_callback: Option<Arc<dyn DownloadProgress>>,
+        cancellation: Option<Arc<DownloadCancellation>>,
+    ) -> Result<bool> {
+        self.runtime
+            .block_on(self.server.download_place_details_if_necessary(
+                source_url,
+                destination_filename,
+                expected_sha256,
+                progress_callback,
+                cancellation,
+            ))
+    }
+
+    /// Blocks on [`HeadwayServer::place_details`]
+    pub fn place_details(&self, osm_id: String) -> Option<PlaceDetails> {
+        self.runtime.block_on(self.server.place_details(osm_id))
+    }
+
+    /// Blocks on [`HeadwayServer::place_details_datasets`]
+    pub fn place_details_datasets(&self) -> Vec<String> {
+        self.runtime.block_on(self.server.place_details_datasets())
+    }
+
+    /// Blocks on [`HeadwayServer::remove_place_details`]
+    pub fn remove_place_details(&self, file_name: &str) -> Result<()> {
+        self.runtime
+            .block_on(self.server.remove_place_details(file_name))
+    }
+
+    /// Blocks on [`HeadwayServer::prepare_pmtiles_extract`]
+    pub fn prepare_pmtiles_extract(
+        &self,
+        bounds: Arc<Bounds>,
+        progress_callback: Option<Arc<dyn ExtractProgress>>,
+    ) -> Result<ExtractionPlan> {
+        self.runtime.block_on(
+            self.server
+                .prepare_pmtiles_extract(bounds, progress_callback),
+        )
+    }
+
+    /// Blocks on [`HeadwayServer::extract_pmtiles_region`]
+    pub fn extract_pmtiles_region(
+        &self,
+        plan: Arc<ExtractionPlan>,
+        expected_sha256: Option<String>,
+        progress_callback: Option<Arc<dyn ExtractProgress>>,
+    ) -> Result<RegionRecord> {
+        self.runtime.block_on(self.server.extract_pmtiles_region(
+            plan,
+            expected_sha256,
+            progress_callback,
+        ))
+    }
+
+    /// Blocks on [`HeadwayServer::regions`]
+    pub fn regions(&self) -> Vec<Arc<RegionRecord>> {
+        self.runtime.block_on(self.server.regions())
+    }
+
+    /// Blocks on [`HeadwayServer::tileset_coverage`]
+    pub fn tileset_coverage(&self, tileset_id: &str) -> Option<Arc<TilesetCoverage>> {
+        self.runtime
+            .block_on(self.server.tileset_coverage(tileset_id))
+    }
+
+    /// Blocks on [`HeadwayServer::elevation`]
+    pub fn elevation(&self, tileset_id: &str, location: LatLon) -> Result<Option<f64>> {
+        self.runtime
+            .block_on(self.server.elevation(tileset_id, location))
+    }
+
+    /// Blocks on [`HeadwayServer::elevation_profile`]
+    pub fn elevation_profile(
+        &self,
+        tileset_id: &str,
+        polyline: Vec<LatLon>,
+        interval_m: f64,
+    ) -> Result<Vec<ElevationSample>> {
+        self.runtime.block_on(
+            self.server
+                .elevation_profile(tileset_id, polyline, interval_m),
+        )
+    }
+
+    /// Blocks on [`HeadwayServer::set_contour_tileset`]
+    pub fn set_contour_tileset(&self, tileset_id: Option<String>) -> Result<()> {
+        self.runtime
+            .block_on(self.server.set_contour_tileset(tileset_id))
+    }
+
+    /// Blocks on [`HeadwayServer::features_near`]
+    pub fn features_near(
+        &self,
+        tileset_id: &str,
+        location: LatLon,
+        radius_m: f64,
+        layers: Option<Vec<String>>,
+    ) -> Result<Vec<NearbyFeature>> {
+        self.runtime.block_on(
+            self.server
+                .features_near(tileset_id, location, radius_m, layers),
+        )
+    }
+
+    /// Blocks on [`HeadwayServer::query_rendered_source_features`]
+    pub fn query_rendered_source_features(
+        &self,
+        tileset_id: &str,
+        location: LatLon,
+        zoom: f64,
+        layers: Option<Vec<String>>,
+    ) -> Result<Vec<NearbyFeature>> {
+        self.runtime.block_on(
+            self.server
+                .query_rendered_source_features(tileset_id, location, zoom, layers),
+        )
+    }
+
+    /// Blocks on [`HeadwayServer::remove_pmtiles_extract`]
+    pub fn remove_pmtiles_extract(&self, file_name: &str) -> Result<()> {
+        self.runtime
+            .block_on(self.server.remove_pmtiles_extract(file_name))
+    }
+
+    /// Blocks on [`HeadwayServer::migrate_storage`]
+    pub fn migrate_storage(
+        &self,
+        new_storage_dir: &str,
+        progress_callback: Option<Arc<dyn ExtractProgress>>,
+    ) -> Result<()> {
+        self.runtime.block_on(
+            self.server
+                .migrate_storage(new_storage_dir, progress_callback),
+        )
+    }
+
+    /// Blocks on [`HeadwayServer::download_system_pmtiles_if_necessary`]
+    pub fn download_system_pmtiles_if_necessary(
+        &self,
+        source_url: &str,
+        destination_filename: &str,
+        expected_sha256: Option<String>,
+        progress_callback: Option<Arc<dyn DownloadProgress>>,
+        cancellation: Option<Arc<DownloadCancellation>>,
+    ) -> Result<bool> {
+        self.runtime
+            .block_on(self.server.download_system_pmtiles_if_necessary(
+                source_url,
+                destination_filename,
+                expected_sha256,
+                progress_callback,
+                cancellation,
+            ))
+    }
+
+    /// Blocks on [`HeadwayServer::download_tileset_pmtiles_if_necessary`]
+    pub fn download_tileset_pmtiles_if_necessary(
+        &self,
+        tileset_id: &str,
+        source_url: &str,
+        destination_filename: &str,
+        expected_sha256: Option<String>,
+        progress_callback: Option<Arc<dyn DownloadProgress>>,
+        cancellation: Option<Arc<DownloadCancellation>>,
+    ) -> Result<bool> {
+        self.runtime
+            .block_on(self.server.download_tileset_pmtiles_if_necessary(
+                tileset_id,
+                source_url,
+                destination_filename,
+                expected_sha256,
+                progress_callback,
+                cancellation,
+            ))
+    }
+
+    /// Blocks on [`HeadwayServer::upgrade_system_pmtiles`]
+    pub fn upgrade_system_pmtiles(
+        &self,
+        source_url: &str,
+        destination_filename: &str,
+        expected_sha256: Option<String>,
+        progress_callback: Option<Arc<dyn DownloadProgress>>,
+    ) -> Result<RegionRecord> {
+        self.runtime.block_on(self.server.upgrade_system_pmtiles(
+            source_url,
+            destination_filename,
+            expected_sha256,
+            progress_callback,
+        ))
+    }
+
+    /// Blocks on [`HeadwayServer::upgrade_tileset_pmtiles`]
+    pub fn upgrade_tileset_pmtiles(
+        &self,
+        tileset_id: &str,
+        source_url: &str,
+        destination_filename: &str,
+        expected_sha256: Option<String>,
+        progress_callback: Option<Arc<dyn DownloadProgress>>,
+    ) -> Result<RegionRecord> {
+        self.runtime.block_on(self.server.upgrade_tileset_pmtiles(
+            tileset_id,
+            source_url,
+            destination_filename,
+            expected_sha256,
+            progress_callback,
+        ))
+    }
+
+    /// Blocks on [`HeadwayServer::update_system_pmtiles_if_newer`]
+    pub fn update_system_pmtiles_if_newer(
+        &self,
+        source_url: &str,
+        destination_filename: &str,
+        expected_sha256: Option<String>,
+        progress_callback: Option<Arc<dyn DownloadProgress>>,
+        cancellation: Option<Arc<DownloadCancellation>>,
+    ) -> Result<Option<Arc<RegionRecord>>> {
+        self.runtime
+            .block_on(self.server.update_system_pmtiles_if_newer(
+                source_url,
+                destination_filename,
+                expected_sha256,
+                progress_callback,
+                cancellation,
+            ))
+    }
+
+    /// Blocks on [`HeadwayServer::update_tileset_pmtiles_if_newer`]
+    pub fn update_tileset_pmtiles_if_newer(
+        &self,
+        tileset_id: &str,
+        source_url: &str,
+        destination_filename: &str,
+        expected_sha256: Option<String>,
+        progress_callback: Option<Arc<dyn DownloadProgress>>,
+        cancellation: Option<Arc<DownloadCancellation>>,
+    ) -> Result<Option<Arc<RegionRecord>>> {
+        self.runtime
+            .block_on(self.server.update_tileset_pmtiles_if_newer(
+                tileset_id,
+                source_url,
+                destination_filename,
+                expected_sha256,
+                progress_callback,
+                cancellation,
+            ))
+    }
+}
@@ -0,0 +1,84 @@
+//! Conditional GET for assets whose content rarely changes: styles, sprites, glyphs, and TileJSON.
+//!
+//! Responses carry an `ETag` derived from their content, and a `Last-Modified` time when they're
+//! read from a file, along with `Cache-Control: no-cache`. Clients, e.g. a webview's HTTP cache,
+//! keep their copy but check it's still current with `If-None-Match` or `If-Modified-Since`,
+//! which we answer with an empty 304 when it is.
+
+use axum::body::Body;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::Response;
+use bytes::Bytes;
+use std::hash::{DefaultHasher, Hasher};
+use std::time::SystemTime;
+
+/// Responds with `body`, or 304 Not Modified if the request shows the client already has it.
+///
+/// `last_modified` is the modification time of the file `body` was read from, if any. Responses
+/// which are rewritten before being served, e.g. styles, should pass `None`, as their content
+/// can change without the file changing.
+pub(crate) fn conditional_response(
+    request_headers: &HeaderMap,
+    content_type: &str,
+    body: impl Into<Bytes>,
+    last_modified: Option<SystemTime>,
+) -> Response {
+    let body = body.into();
+    let etag = content_etag(&body);
+    let last_modified = last_modified.map(httpdate::fmt_http_date);
+
+    let not_modified = is_not_modified(request_headers, &etag, last_modified.as_deref());
+
+    let mut response = if not_modified {
+        Response::builder().status(StatusCode::NOT_MODIFIED)
+    } else {
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, content_type)
+    };
+    response = response
+        .header(header::ETAG, etag)
+        .header(header::CACHE_CONTROL, "no-cache");
+    if let Some(last_modified) = last_modified {
+        response = response.header(header::LAST_MODIFIED, last_modified);
+    }
+    if not_modified {
+        return response.body(Body::empty()).unwrap();
+    }
+    response.body(Body::from(body)).unwrap()
+}
+
+fn content_etag(body: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(body);
+    format!("\"{:016x}-{:x}\"", hasher.finish(), body.len())
+}
+
+/// Whether the client's cached copy is current, per the precedence in RFC 9110 section 13.2.2:
+/// `If-Modified-Since` is only considered when there's no `If-None-Match`.
+fn is_not_modified(request_headers: &HeaderMap, etag: &str, last_modified: Option<&str>) -> bool {
+    if let Some(if_none_match) = request_headers.get(header::IF_NONE_MATCH) {
+        return if_none_match
+            .to_str()
+            .is_ok_and(|if_none_match| etag_matches(if_none_match, etag));
+    }
+    let Some(last_modified) = last_modified.and_then(|date| httpdate::parse_http_date(date).ok())
+    else {
+        return false;
+    };
+    request_headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| httpdate::parse_http_date(value).ok())
+        .is_some_and(|if_modified_since| last_modified <= if_modified_since)
+}
+
+/// Whether an `If-None-Match` header value matches `etag`, using the weak comparison required
+/// for `If-None-Match` by RFC 9110.
+pub(crate) fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    if_none_match.split(',').any(|candidate| {
+        let candidate = candidate.trim();
+        candidate == "*" || candidate.trim_start_matches("W/") == etag
+    })
+}
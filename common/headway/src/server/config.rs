@@ -0,0 +1,207 @@
+//! Everything a [`HeadwayServer`](super::HeadwayServer) can be configured with up front, so new
+//! options don't change the signature of its constructor.
+//!
+//! Each `with_*` method returns a copy of the config with that option changed, e.g.
+//! `HeadwayServerConfig(storage_dir, extract_source_url).with_auth_token(token)` in Swift or
+//! Kotlin. Options are validated when the server is created, by the same setters that change
+//! them afterwards.
+
+use super::raster_overlays::DEFAULT_MAX_CACHE_BYTES;
+use super::transit::DEFAULT_MAX_CACHED_RESPONSES;
+use super::{CorsPolicy, RequestLimits};
+use crate::download::RetryPolicy;
+use crate::http::HttpTimeouts;
+use std::sync::Arc;
+
+#[derive(Clone, Debug, uniffi::Object)]
+pub struct HeadwayServerConfig {
+    pub(crate) storage_dir: String,
+    pub(crate) extract_source_url: String,
+    pub(crate) profile: Option<String>,
+    pub(crate) bind_addrs: Vec<String>,
+    pub(crate) auth_token: Option<String>,
+    pub(crate) cors_policy: Option<CorsPolicy>,
+    pub(crate) request_limits: RequestLimits,
+    pub(crate) tile_cache_control: Option<String>,
+    pub(crate) base_url: Option<String>,
+    pub(crate) base_path: Option<String>,
+    pub(crate) tls_enabled: bool,
+    pub(crate) http_proxy: Option<String>,
+    pub(crate) http_timeouts: Option<HttpTimeouts>,
+    pub(crate) download_retry_policy: Option<RetryPolicy>,
+    pub(crate) download_concurrency: Option<u32>,
+    pub(crate) transit_endpoint: Option<String>,
+    pub(crate) routing_endpoint: Option<String>,
+    pub(crate) raster_overlay_cache_bytes: u64,
+    pub(crate) transit_cache_responses: u32,
+}
+
+#[uniffi::export]
+impl HeadwayServerConfig {
+    /// The required options, see [`HeadwayServer::new`](super::HeadwayServer::new). Everything
+    /// else has the same default as when it's not set on the server.
+    #[uniffi::constructor]
+    pub fn new(storage_dir: String, extract_source_url: String) -> Self {
+        Self {
+            storage_dir,
+            extract_source_url,
+            profile: None,
+            bind_addrs: vec![],
+            auth_token: None,
+            cors_policy: None,
+            request_limits: RequestLimits::default(),
+            tile_cache_control: None,
+            base_url: None,
+            base_path: None,
+            tls_enabled: false,
+            http_proxy: None,
+            http_timeouts: None,
+            download_retry_policy: None,
+            download_concurrency: None,
+            transit_endpoint: None,
+            routing_endpoint: None,
+            raster_overlay_cache_bytes: DEFAULT_MAX_CACHE_BYTES,
+            transit_cache_responses: DEFAULT_MAX_CACHED_RESPONSES,
+        }
+    }
+
+    /// Keeps tiles, overlays, place details and caches in `{storage_dir}/profiles/{profile}`,
+    /// apart from other profiles', e.g. for apps with multiple accounts. Fonts, sprites, styles
+    /// and the TLS certificate are shared. `profile` may only contain ascii letters, digits, '-'
+    /// or '_'.
+    pub fn with_profile(&self, profile: Option<String>) -> Arc<Self> {
+        Arc::new(Self {
+            profile,
+            ..self.clone()
+        })
+    }
+
+    /// The addresses [`HeadwayServer::start_configured`](super::HeadwayServer::start_configured)
+    /// listens on, see [`HeadwayServer::start_on`](super::HeadwayServer::start_on)
+    pub fn with_bind_addrs(&self, bind_addrs: Vec<String>) -> Arc<Self> {
+        Arc::new(Self {
+            bind_addrs,
+            ..self.clone()
+        })
+    }
+
+    /// See [`HeadwayServer::set_auth_token`](super::HeadwayServer::set_auth_token)
+    pub fn with_auth_token(&self, auth_token: Option<String>) -> Arc<Self> {
+        Arc::new(Self {
+            auth_token,
+            ..self.clone()
+        })
+    }
+
+    /// See [`HeadwayServer::set_cors_policy`](super::HeadwayServer::set_cors_policy)
+    pub fn with_cors_policy(&self, cors_policy: Option<CorsPolicy>) -> Arc<Self> {
+        Arc::new(Self {
+            cors_policy,
+            ..self.clone()
+        })
+    }
+
+    /// See [`HeadwayServer::set_request_limits`](super::HeadwayServer::set_request_limits)
+    pub fn with_request_limits(&self, request_limits: RequestLimits) -> Arc<Self> {
+        Arc::new(Self {
+            request_limits,
+            ..self.clone()
+        })
+    }
+
+    /// See [`HeadwayServer::set_tile_cache_control`](super::HeadwayServer::set_tile_cache_control)
+    pub fn with_tile_cache_control(&self, cache_control: String) -> Arc<Self> {
+        Arc::new(Self {
+            tile_cache_control: Some(cache_control),
+            ..self.clone()
+        })
+    }
+
+    /// See [`HeadwayServer::set_base_url`](super::HeadwayServer::set_base_url)
+    pub fn with_base_url(&self, base_url: Option<String>) -> Arc<Self> {
+        Arc::new(Self {
+            base_url,
+            ..self.clone()
+        })
+    }
+
+    /// See [`HeadwayServer::set_base_path`](super::HeadwayServer::set_base_path)
+    pub fn with_base_path(&self, base_path: Option<String>) -> Arc<Self> {
+        Arc::new(Self {
+            base_path,
+            ..self.clone()
+        })
+    }
+
+    /// See [`HeadwayServer::set_tls_enabled`](super::HeadwayServer::set_tls_enabled)
+    pub fn with_tls_enabled(&self, enabled: bool) -> Arc<Self> {
+        Arc::new(Self {
+            tls_enabled: enabled,
+            ..self.clone()
+        })
+    }
+
+    /// See [`HeadwayServer::set_http_proxy`](super::HeadwayServer::set_http_proxy)
+    pub fn with_http_proxy(&self, proxy_url: Option<String>) -> Arc<Self> {
+        Arc::new(Self {
+            http_proxy: proxy_url,
+            ..self.clone()
+        })
+    }
+
+    /// See [`HeadwayServer::set_http_timeouts`](super::HeadwayServer::set_http_timeouts)
+    pub fn with_http_timeouts(&self, timeouts: HttpTimeouts) -> Arc<Self> {
+        Arc::new(Self {
+            http_timeouts: Some(timeouts),
+            ..self.clone()
+        })
+    }
+
+    /// See [`HeadwayServer::set_download_retry_policy`](super::HeadwayServer::set_download_retry_policy)
+    pub fn with_download_retry_policy(&self, retry_policy: RetryPolicy) -> Arc<Self> {
+        Arc::new(Self {
+            download_retry_policy: Some(retry_policy),
+            ..self.clone()
+        })
+    }
+
+    /// See [`HeadwayServer::set_download_concurrency`](super::HeadwayServer::set_download_concurrency)
+    pub fn with_download_concurrency(&self, parallel_chunks: u32) -> Arc<Self> {
+        Arc::new(Self {
+            download_concurrency: Some(parallel_chunks),
+            ..self.clone()
+        })
+    }
+
+    /// See [`HeadwayServer::set_transit_endpoint`](super::HeadwayServer::set_transit_endpoint)
+    pub fn with_transit_endpoint(&self, endpoint: Option<String>) -> Arc<Self> {
+        Arc::new(Self {
+            transit_endpoint: endpoint,
+            ..self.clone()
+        })
+    }
+
+    /// See [`HeadwayServer::set_routing_endpoint`](super::HeadwayServer::set_routing_endpoint)
+    pub fn with_routing_endpoint(&self, endpoint: Option<String>) -> Arc<Self> {
+        Arc::new(Self {
+            routing_endpoint: endpoint,
+            ..self.clone()
+        })
+    }
+
+    /// How many bytes of raster overlay tiles are cached, 200 MiB by default
+    pub fn with_raster_overlay_cache_bytes(&self, max_bytes: u64) -> Arc<Self> {
+        Arc::new(Self {
+            raster_overlay_cache_bytes: max_bytes,
+            ..self.clone()
+        })
+    }
+
+    /// How many transit responses are cached, 200 by default
+    pub fn with_transit_cache_responses(&self, max_responses: u32) -> Arc<Self> {
+        Arc::new(Self {
+            transit_cache_responses: max_responses,
+            ..self.clone()
+        })
+    }
+}
@@ -0,0 +1,54 @@
+use crate::{Error, Result};
+use axum::http::{header, HeaderName, HeaderValue, Method};
+use tower_http::cors::{AllowHeaders, AllowOrigin, CorsLayer};
+
+/// Which cross-origin requests the server permits, e.g. from a web-based or hybrid
+/// (Capacitor/Tauri) client whose page isn't served from the tileserver itself.
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct CorsPolicy {
+    /// Origins like `"capacitor://localhost"`, or `"*"` to allow any origin
+    pub allowed_origins: Vec<String>,
+    /// Request headers clients may send, or `"*"` to allow any header
+    pub allowed_headers: Vec<String>,
+}
+
+impl CorsPolicy {
+    pub(crate) fn layer(&self) -> Result<CorsLayer> {
+        let allow_origin = if self.allowed_origins.iter().any(|origin| origin == "*") {
+            AllowOrigin::any()
+        } else {
+            let origins = self
+                .allowed_origins
+                .iter()
+                .map(|origin| {
+                    HeaderValue::from_str(origin).map_err(|_| {
+                        Error::InvalidInput(format!("invalid CORS origin: {origin:?}"))
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            AllowOrigin::list(origins)
+        };
+
+        let allow_headers = if self.allowed_headers.iter().any(|name| name == "*") {
+            AllowHeaders::any()
+        } else {
+            let names = self
+                .allowed_headers
+                .iter()
+                .map(|name| {
+                    HeaderName::try_from(name.as_str()).map_err(|_| {
+                        Error::InvalidInput(format!("invalid CORS header name: {name:?}"))
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            AllowHeaders::list(names)
+        };
+
+        Ok(CorsLayer::new()
+            .allow_methods([Method::GET, Method::HEAD])
+            .allow_origin(allow_origin)
+            .allow_headers(allow_headers)
+            // Needed by pmtiles clients making range requests against /archives
+            .expose_headers([header::CONTENT_RANGE, header::CONTENT_LENGTH, header::ETAG]))
+    }
+}
@@ -0,0 +1,590 @@
+//! Queues downloads and extracts as jobs the host app can list, pause, resume and cancel, rather
+//! than each being an opaque call it has to await. The queue is persisted, so it survives the app
+//! being killed.
+//!
+//! Jobs run one at a time, in the order they were queued.
+
+use super::HeadwayServer;
+use crate::checksum::Sha256Digest;
+use crate::download::{DownloadCancellation, DownloadProgress};
+use crate::map_tiles::{validate_tileset_id, Bounds, ExtractProgress};
+use crate::runtime::runtime;
+use crate::{Error, Result};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+
+#[derive(Clone, Debug, uniffi::Enum)]
+pub enum DownloadJobKind {
+    /// Downloads a system tileset archive, see
+    /// [`HeadwayServer::download_tileset_pmtiles_if_necessary`]
+    TilesetDownload {
+        tileset_id: String,
+        source_url: String,
+        destination_filename: String,
+        expected_sha256: Option<String>,
+    },
+    /// Prepares and performs an extract of `bounds`, see [`HeadwayServer::prepare_pmtiles_extract`]
+    Extract {
+        bounds: Arc<Bounds>,
+        expected_sha256: Option<String>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, uniffi::Enum)]
+pub enum DownloadJobState {
+    Queued,
+    Running,
+    Paused,
+    Completed,
+    Failed { message: String },
+    Cancelled,
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct DownloadJob {
+    pub id: u64,
+    pub kind: DownloadJobKind,
+    pub state: DownloadJobState,
+    pub bytes_received: u64,
+    /// `None` until known
+    pub total_bytes: Option<u64>,
+    /// The failure a running job is retrying after, e.g. to explain a stall, until it makes
+    /// progress again
+    pub retrying: Option<DownloadJobRetry>,
+}
+
+#[derive(Clone, Debug, PartialEq, uniffi::Record)]
+pub struct DownloadJobRetry {
+    pub message: String,
+    /// The attempt that failed, from 1
+    pub attempt: u32,
+}
+
+#[uniffi::export(with_foreign)]
+pub trait DownloadJobListener: Send + Sync {
+    /// Called whenever a job is queued, or its state or progress changes, or it's retrying
+    fn on_job_changed(&self, job: DownloadJob);
+}
+
+#[derive(uniffi::Object)]
+pub struct DownloadManager {
+    server: Arc<HeadwayServer>,
+    state_path: PathBuf,
+    listener: Option<Arc<dyn DownloadJobListener>>,
+    queue: Mutex<Queue>,
+}
+
+#[derive(Default)]
+struct Queue {
+    jobs: Vec<DownloadJob>,
+    next_id: u64,
+    /// The running job, and how to stop it
+    running: Option<(u64, Arc<DownloadCancellation>)>,
+}
+
+#[uniffi::export(async_runtime = "tokio")]
+impl DownloadManager {
+    /// Restores the queue persisted at `state_path`, e.g. `{profile_dir}/downloads.json`.
+    ///
+    /// Jobs that were queued or running when the app last exited are restored paused, to be
+    /// resumed with [`Self::resume`] when appropriate, e.g. once back on Wi-Fi.
+    #[uniffi::constructor]
+    pub async fn new(
+        server: Arc<HeadwayServer>,
+        state_path: String,
+        listener: Option<Arc<dyn DownloadJobListener>>,
+    ) -> Result<Arc<Self>> {
+        let state_path = PathBuf::from(state_path);
+        let queue = Queue::load(&state_path)?;
+        Ok(Arc::new(Self {
+            server,
+            state_path,
+            listener,
+            queue: Mutex::new(queue),
+        }))
+    }
+
+    /// Adds a job to the end of the queue, returning its id
+    pub async fn enqueue(self: Arc<Self>, kind: DownloadJobKind) -> Result<u64> {
+        match &kind {
+            DownloadJobKind::TilesetDownload {
+                tileset_id,
+                expected_sha256,
+                ..
+            } => {
+                validate_tileset_id(tileset_id)?;
+                Sha256Digest::parse_optional(expected_sha256.as_deref())?;
+            }
+            DownloadJobKind::Extract {
+                expected_sha256, ..
+            } => {
+                Sha256Digest::parse_optional(expected_sha256.as_deref())?;
+            }
+        }
+        let job = {
+            let mut queue = self.lock_queue();
+            let job = DownloadJob {
+                id: queue.next_id,
+                kind,
+                state: DownloadJobState::Queued,
+                bytes_received: 0,
+                total_bytes: None,
+                retrying: None,
+            };
+            queue.next_id += 1;
+            queue.jobs.push(job.clone());
+            self.save(&queue);
+            job
+        };
+        let id = job.id;
+        self.notify(job);
+        self.schedule();
+        Ok(id)
+    }
+
+    /// Every job, in the order they were queued, including finished ones until
+    /// [`Self::remove_finished`] is called
+    pub fn jobs(&self) -> Vec<DownloadJob> {
+        self.lock_queue().jobs.clone()
+    }
+
+    pub fn job(&self, id: u64) -> Option<DownloadJob> {
+        self.lock_queue()
+            .jobs
+            .iter()
+            .find(|job| job.id == id)
+            .cloned()
+    }
+
+    /// Stops a queued or running job until it's resumed. A paused download resumes from where it
+    /// left off, whereas a paused extract starts over.
+    pub fn pause(&self, id: u64) -> Result<()> {
+        let job = {
+            let mut queue = self.lock_queue();
+            let job = queue.job_mut(id)?;
+            match job.state {
+                DownloadJobState::Queued | DownloadJobState::Running => {}
+                _ => return Ok(()),
+            }
+            job.state = DownloadJobState::Paused;
+            let job = job.clone();
+            if let Some(cancellation) = queue.cancellation(id) {
+                cancellation.pause();
+            }
+            self.save(&queue);
+            job
+        };
+        self.notify(job);
+        Ok(())
+    }
+
+    /// Queues a paused or failed job again
+    pub async fn resume(self: Arc<Self>, id: u64) -> Result<()> {
+        let job = {
+            let mut queue = self.lock_queue();
+            let job = queue.job_mut(id)?;
+            match job.state {
+                DownloadJobState::Paused | DownloadJobState::Failed { .. } => {}
+                _ => return Ok(()),
+            }
+            job.state = DownloadJobState::Queued;
+            let job = job.clone();
+            self.save(&queue);
+            job
+        };
+        self.notify(job);
+        self.schedule();
+        Ok(())
+    }
+
+    /// Stops a job for good, deleting anything it had downloaded so far
+    pub async fn cancel(&self, id: u64) -> Result<()> {
+        let (job, was_running) = {
+            let mut queue = self.lock_queue();
+            let job = queue.job_mut(id)?;
+            let was_running = match job.state {
+                DownloadJobState::Completed | DownloadJobState::Cancelled => return Ok(()),
+                DownloadJobState::Running => true,
+                _ => false,
+            };
+            job.state = DownloadJobState::Cancelled;
+            let job = job.clone();
+            // A running download deletes its partial file itself once cancelled
+            if let Some(cancellation) = queue.cancellation(id) {
+                cancellation.cancel();
+            }
+            self.save(&queue);
+            (job, was_running)
+        };
+        // e.g. left behind by pausing it earlier
+        if let DownloadJobKind::TilesetDownload {
+            tileset_id,
+            destination_filename,
+            ..
+        } = &job.kind
+        {
+            if !was_running {
+                self.server
+                    .discard_partial_tileset_download(tileset_id, destination_filename)
+                    .await?;
+            }
+        }
+        self.notify(job);
+        Ok(())
+    }
+
+    /// Forgets completed, failed and cancelled jobs
+    pub fn remove_finished(&self) {
+        let mut queue = self.lock_queue();
+        queue.jobs.retain(|job| {
+            matches!(
+                job.state,
+                DownloadJobState::Queued | DownloadJobState::Running | DownloadJobState::Paused
+            )
+        });
+        self.save(&queue);
+    }
+}
+
+impl DownloadManager {
+    fn lock_queue(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().expect("poisoned lock")
+    }
+
+    /// Starts the next queued job, unless one is already running
+    fn schedule(self: &Arc<Self>) {
+        let (job, cancellation) = {
+            let mut queue = self.lock_queue();
+            if queue.running.is_some() {
+                return;
+            }
+            let Some(job) = queue
+                .jobs
+                .iter_mut()
+                .find(|job| job.state == DownloadJobState::Queued)
+            else {
+                return;
+            };
+            job.state = DownloadJobState::Running;
+            job.retrying = None;
+            let job = job.clone();
+            let cancellation = Arc::new(DownloadCancellation::new());
+            queue.running = Some((job.id, cancellation.clone()));
+            self.save(&queue);
+            (job, cancellation)
+        };
+        let id = job.id;
+        let kind = job.kind.clone();
+        self.notify(job);
+        let runtime = match runtime() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                self.finish(id, Err(e));
+                return;
+            }
+        };
+        let manager = self.clone();
+        runtime.spawn(async move {
+            let result = manager.run(id, kind, &cancellation).await;
+            manager.finish(id, result);
+            manager.schedule();
+        });
+    }
+
+    async fn run(
+        self: &Arc<Self>,
+        id: u64,
+        kind: DownloadJobKind,
+        cancellation: &Arc<DownloadCancellation>,
+    ) -> Result<()> {
+        let progress = Arc::new(JobProgress {
+            manager: Arc::downgrade(self),
+            id,
+        });
+        match kind {
+            DownloadJobKind::TilesetDownload {
+                tileset_id,
+                source_url,
+                destination_filename,
+                expected_sha256,
+            } => {
+                self.server
+                    .download_tileset_pmtiles_if_necessary(
+                        &tileset_id,
+                        &source_url,
+                        &destination_filename,
+                        expected_sha256,
+                        Some(progress),
+                        Some(cancellation.clone()),
+                    )
+                    .await?;
+            }
+            DownloadJobKind::Extract {
+                bounds,
+                expected_sha256,
+            } => {
+                // Extracts can't be resumed, so a paused one is simply abandoned
+                cancellation
+                    .run(async {
+                        let plan = self.server.prepare_pmtiles_extract(bounds, None).await?;
+                        let total_bytes = plan.0.tile_data_length();
+                        self.update(id, |job| job.total_bytes = Some(total_bytes));
+                        self.server
+                            .extract_pmtiles_region(Arc::new(plan), expected_sha256, Some(progress))
+                            .await
+                    })
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&self, id: u64, result: Result<()>) {
+        let job = {
+            let mut queue = self.lock_queue();
+            queue.running = None;
+            let Ok(job) = queue.job_mut(id) else {
+                return;
+            };
+            job.retrying = None;
+            match result {
+                // Even if it was paused or cancelled too late to stop it
+                Ok(()) => job.state = DownloadJobState::Completed,
+                Err(e) => {
+                    log::warn!("Download job {id} stopped: {e}");
+                    // Otherwise it was paused or cancelled, which is already recorded
+                    if job.state == DownloadJobState::Running {
+                        job.state = DownloadJobState::Failed {
+                            message: e.to_string(),
+                        };
+                    }
+                }
+            }
+            let job = job.clone();
+            self.save(&queue);
+            job
+        };
+        self.notify(job);
+    }
+
+    /// Updates the progress of a job, which isn't worth persisting
+    fn update(&self, id: u64, update: impl FnOnce(&mut DownloadJob)) {
+        let job = {
+            let mut queue = self.lock_queue();
+            let Ok(job) = queue.job_mut(id) else {
+                return;
+            };
+            update(job);
+            job.clone()
+        };
+        self.notify(job);
+    }
+
+    fn notify(&self, job: DownloadJob) {
+        if let Some(listener) = &self.listener {
+            listener.on_job_changed(job);
+        }
+    }
+
+    /// Persists the queue. Failures are only logged, since the jobs themselves are unaffected.
+    fn save(&self, queue: &Queue) {
+        if let Err(e) = std::fs::write(&self.state_path, queue.to_json().to_string()) {
+            log::warn!(
+                "Unable to save download queue to {}: {e}",
+                self.state_path.display()
+            );
+        }
+    }
+}
+
+impl Queue {
+    fn job_mut(&mut self, id: u64) -> Result<&mut DownloadJob> {
+        self.jobs
+            .iter_mut()
+            .find(|job| job.id == id)
+            .ok_or_else(|| Error::InvalidInput(format!("no download job with id {id}")))
+    }
+
+    fn cancellation(&self, id: u64) -> Option<&DownloadCancellation> {
+        match &self.running {
+            Some((running_id, cancellation)) if *running_id == id => Some(cancellation.as_ref()),
+            _ => None,
+        }
+    }
+
+    fn load(path: &Path) -> Result<Self> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let Ok(json) = serde_json::from_str::<Value>(&contents) else {
+            log::warn!("Ignoring invalid download queue {}", path.display());
+            return Ok(Self::default());
+        };
+        let jobs: Vec<DownloadJob> = json
+            .get("jobs")
+            .and_then(Value::as_array)
+            .map(|jobs| jobs.iter().filter_map(job_from_json).collect())
+            .unwrap_or_default();
+        let next_id = json
+            .get("next_id")
+            .and_then(Value::as_u64)
+            .unwrap_or_default()
+            .max(jobs.iter().map(|job| job.id + 1).max().unwrap_or_default());
+        Ok(Self {
+            jobs,
+            next_id,
+            running: None,
+        })
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "next_id": self.next_id,
+            "jobs": self.jobs.iter().map(job_to_json).collect::<Vec<_>>(),
+        })
+    }
+}
+
+fn job_to_json(job: &DownloadJob) -> Value {
+    let (state, message) = match &job.state {
+        DownloadJobState::Queued => ("queued", None),
+        DownloadJobState::Running => ("running", None),
+        DownloadJobState::Paused => ("paused", None),
+        DownloadJobState::Completed => ("completed", None),
+        DownloadJobState::Failed { message } => ("failed", Some(message)),
+        DownloadJobState::Cancelled => ("cancelled", None),
+    };
+    let mut json = json!({
+        "id": job.id,
+        "state": state,
+        "message": message,
+        "bytes_received": job.bytes_received,
+        "total_bytes": job.total_bytes,
+    });
+    let kind = match &job.kind {
+        DownloadJobKind::TilesetDownload {
+            tileset_id,
+            source_url,
+            destination_filename,
+            expected_sha256,
+        } => json!({
+            "type": "tileset_download",
+            "tileset_id": tileset_id,
+            "source_url": source_url,
+            "destination_filename": destination_filename,
+            "expected_sha256": expected_sha256,
+        }),
+        DownloadJobKind::Extract {
+            bounds,
+            expected_sha256,
+        } => json!({
+            "type": "extract",
+            "bounds": bounds.as_nesw(),
+            "expected_sha256": expected_sha256,
+        }),
+    };
+    json["kind"] = kind;
+    json
+}
+
+fn job_from_json(json: &Value) -> Option<DownloadJob> {
+    let string =
+        |json: &Value, name: &str| json.get(name).and_then(Value::as_str).map(str::to_string);
+    let kind_json = json.get("kind")?;
+    let kind = match kind_json.get("type")?.as_str()? {
+        "tileset_download" => DownloadJobKind::TilesetDownload {
+            tileset_id: string(kind_json, "tileset_id")?,
+            source_url: string(kind_json, "source_url")?,
+            destination_filename: string(kind_json, "destination_filename")?,
+            expected_sha256: string(kind_json, "expected_sha256"),
+        },
+        "extract" => {
+            let nesw: Vec<f64> = kind_json
+                .get("bounds")?
+                .as_array()?
+                .iter()
+                .map(Value::as_f64)
+                .collect::<Option<_>>()?;
+            let [max_lat, max_lon, min_lat, min_lon] = nesw[..] else {
+                return None;
+            };
+            DownloadJobKind::Extract {
+                bounds: Arc::new(Bounds::nesw(max_lat, max_lon, min_lat, min_lon).ok()?),
+                expected_sha256: string(kind_json, "expected_sha256"),
+            }
+        }
+        _ => return None,
+    };
+    let state = match json.get("state")?.as_str()? {
+        // Interrupted by the app exiting
+        "queued" | "running" | "paused" => DownloadJobState::Paused,
+        "completed" => DownloadJobState::Completed,
+        "failed" => DownloadJobState::Failed {
+            message: string(json, "message").unwrap_or_default(),
+        },
+        "cancelled" => DownloadJobState::Cancelled,
+        _ => return None,
+    };
+    Some(DownloadJob {
+        id: json.get("id")?.as_u64()?,
+        kind,
+        state,
+        bytes_received: json
+            .get("bytes_received")
+            .and_then(Value::as_u64)
+            .unwrap_or_default(),
+        total_bytes: json.get("total_bytes").and_then(Value::as_u64),
+        retrying: None,
+    })
+}
+
+/// Reports a job's progress to the manager
+struct JobProgress {
+    manager: Weak<DownloadManager>,
+    id: u64,
+}
+
+impl JobProgress {
+    fn retrying(&self, message: String, attempt: u32) {
+        if let Some(manager) = self.manager.upgrade() {
+            manager.update(self.id, |job| {
+                job.retrying = Some(DownloadJobRetry { message, attempt });
+            });
+        }
+    }
+}
+
+impl DownloadProgress for JobProgress {
+    fn on_progress(&self, bytes_received: u64, total_bytes: Option<u64>, _attempt: u32) {
+        if let Some(manager) = self.manager.upgrade() {
+            manager.update(self.id, |job| {
+                job.bytes_received = bytes_received;
+                job.total_bytes = total_bytes;
+                job.retrying = None;
+            });
+        }
+    }
+
+    fn on_retryable_error(&self, message: String, attempt: u32) {
+        self.retrying(message, attempt);
+    }
+}
+
+impl ExtractProgress for JobProgress {
+    fn on_progress(&self, progress: f64) {
+        if let Some(manager) = self.manager.upgrade() {
+            manager.update(self.id, |job| {
+                let total_bytes = job.total_bytes.unwrap_or_default();
+                job.bytes_received = (total_bytes as f64 * progress) as u64;
+                job.retrying = None;
+            });
+        }
+    }
+
+    fn on_retryable_error(&self, message: String, attempt: u32) {
+        self.retrying(message, attempt);
+    }
+}
@@ -0,0 +1,126 @@
+//! Notifies the host app of what the server is doing, from one place, e.g. to post a
+//! notification when a download finishes or refresh a list of regions when one's added.
+
+use crate::map_tiles::RegionRecord;
+use crate::{Error, Result};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Debug, uniffi::Enum)]
+pub enum ServerEvent {
+    /// The server is accepting requests at `bound_addr`, e.g. `127.0.0.1:9123`
+    Started { bound_addr: String },
+    /// The server at `bound_addr` has stopped, whether it failed or its `start` call was
+    /// cancelled
+    Stopped { bound_addr: String },
+    /// An archive is being served, e.g. after a download or extract, or replaced a previous
+    /// build of itself
+    SourceAdded { region: Arc<RegionRecord> },
+    /// An extract was removed, and is no longer served
+    SourceRemoved { file_name: String },
+    DownloadStarted {
+        source_url: String,
+        destination_filename: String,
+    },
+    /// Also sent when there was nothing to download, because the archive hadn't changed
+    DownloadFinished {
+        source_url: String,
+        destination_filename: String,
+    },
+    /// Including when the download was cancelled, even by dropping it
+    DownloadFailed {
+        source_url: String,
+        destination_filename: String,
+        error: String,
+    },
+    /// An extract finished, and is now served
+    ExtractionCompleted { region: Arc<RegionRecord> },
+}
+
+#[uniffi::export(with_foreign)]
+pub trait ServerEventListener: Send + Sync {
+    /// Called on whichever thread the event happened on, so shouldn't block
+    fn on_event(&self, event: ServerEvent);
+}
+
+#[derive(Default)]
+pub(crate) struct ServerEvents {
+    listener: Mutex<Option<Arc<dyn ServerEventListener>>>,
+}
+
+impl ServerEvents {
+    pub(crate) fn set_listener(&self, listener: Option<Arc<dyn ServerEventListener>>) {
+        *self.listener.lock().expect("poisoned lock") = listener;
+    }
+
+    pub(crate) fn emit(&self, event: ServerEvent) {
+        // Not holding the lock while calling out, in case the listener replaces itself
+        let listener = self.listener.lock().expect("poisoned lock").clone();
+        if let Some(listener) = listener {
+            listener.on_event(event);
+        }
+    }
+
+    /// Runs `download`, reporting when it starts and how it ends
+    pub(crate) async fn track_download<T>(
+        &self,
+        source_url: &str,
+        destination_filename: &str,
+        download: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let mut tracked = TrackedDownload::start(self, source_url, destination_filename);
+        let result = download.await;
+        tracked.end(result.as_ref().map(|_| ()).map_err(Error::to_string));
+        result
+    }
+}
+
+/// Reports a download as cancelled if it's dropped before it ends, e.g. when cancelled with
+/// [`OperationHandle::cancel`](crate::OperationHandle::cancel), so it's always reported as ending
+struct TrackedDownload<'a> {
+    events: &'a ServerEvents,
+    source_url: &'a str,
+    destination_filename: &'a str,
+    is_ended: bool,
+}
+
+impl<'a> TrackedDownload<'a> {
+    fn start(events: &'a ServerEvents, source_url: &'a str, destination_filename: &'a str) -> Self {
+        events.emit(ServerEvent::DownloadStarted {
+            source_url: source_url.to_string(),
+            destination_filename: destination_filename.to_string(),
+        });
+        Self {
+            events,
+            source_url,
+            destination_filename,
+            is_ended: false,
+        }
+    }
+
+    /// Reports the download as finished, or failed with the error message
+    fn end(&mut self, result: std::result::Result<(), String>) {
+        self.is_ended = true;
+        let source_url = self.source_url.to_string();
+        let destination_filename = self.destination_filename.to_string();
+        self.events.emit(match result {
+            Ok(()) => ServerEvent::DownloadFinished {
+                source_url,
+                destination_filename,
+            },
+            Err(error) => ServerEvent::DownloadFailed {
+                source_url,
+                destination_filename,
+                error,
+            },
+        });
+    }
+}
+
+impl Drop for TrackedDownload<'_> {
+    fn drop(&mut self) {
+        if !self.is_ended {
+            self.end(Err(Error::Cancelled.to_string()));
+        }
+    }
+}
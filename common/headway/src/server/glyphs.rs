@@ -0,0 +1,86 @@
+//! Serves glyph ranges (SDF font data) for label rendering.
+//!
+//! See [`crate::glyphs`] for where glyphs come from. If none of the fonts in the requested stack
+//! are available there, we fall back to a small set of glyphs bundled into the library.
+
+use crate::server::conditional::conditional_response;
+use crate::server::AppState;
+use axum::extract::{Path as UrlPath, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use bytes::Bytes;
+use std::ffi::OsStr;
+use std::path::Path;
+
+/// Glyph ranges compiled into the library, so labels render before any fonts are downloaded.
+const BUNDLED_GLYPHS: &[(&str, &str, &[u8])] = &[(
+    "Roboto Medium",
+    "0-255",
+    include_bytes!("../../tileserver_styles/fonts/Roboto%20Medium/0-255.pbf"),
+)];
+
+pub(crate) async fn get_font(
+    State(state): State<AppState>,
+    UrlPath((font_stack, range_with_ext)): UrlPath<(String, String)>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let Some(range) = range_with_ext.strip_suffix(".pbf") else {
+        log::warn!("Missing .pbf extension: {range_with_ext}");
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    let Some((start, end)) = parse_range(range) else {
+        log::warn!("Invalid glyph range: {range}");
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    // A style's text-font is a fallback stack, e.g. "Noto Sans Bold,Roboto Medium". Serve the
+    // first font in the stack which we have.
+    for font_name in font_stack.split(',').map(str::trim) {
+        if !is_valid_font_name(font_name) {
+            log::warn!("Invalid font name: {font_name:?}");
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+        match state.glyph_store.glyphs(font_name, start, end).await {
+            Ok(Some(glyphs)) => return glyphs_response(&headers, glyphs),
+            Ok(None) => {}
+            Err(e) => {
+                log::error!("Error loading glyphs {font_name}/{range}, error: {e}");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+        if let Some((_, _, glyphs)) =
+            BUNDLED_GLYPHS
+                .iter()
+                .find(|(bundled_font, bundled_range, _)| {
+                    *bundled_font == font_name && *bundled_range == range
+                })
+        {
+            return glyphs_response(&headers, *glyphs);
+        }
+    }
+
+    log::debug!("No glyphs found for {font_stack}/{range}");
+    StatusCode::NOT_FOUND.into_response()
+}
+
+fn glyphs_response(headers: &HeaderMap, glyphs: impl Into<Bytes>) -> Response {
+    conditional_response(headers, "application/x-protobuf", glyphs, None)
+}
+
+/// Ranges look like "0-255", "256-511", etc.
+fn parse_range(range: &str) -> Option<(u32, u32)> {
+    let (start, end) = range.split_once('-')?;
+    let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+    // Every range MapLibre requests spans exactly 256 codepoints
+    if end < start || end - start > 255 {
+        return None;
+    }
+    Some((start, end))
+}
+
+/// Font names become path components, so mustn't be able to escape `fonts_dir`
+fn is_valid_font_name(font_name: &str) -> bool {
+    !font_name.is_empty()
+        && !font_name.contains(['/', '\\'])
+        && Path::new(font_name).file_name() == Some(OsStr::new(font_name))
+}
@@ -0,0 +1,133 @@
+//! Keeps a misbehaving client, or a webview requesting every tile at once, from monopolizing the
+//! runtime the server shares with extractions.
+//!
+//! Tile requests beyond the concurrency limit wait for an earlier one to finish, so clients are
+//! slowed down rather than failed. Requests beyond a client's rate limit are rejected with
+//! `429 Too Many Requests`.
+
+use crate::{Error, Result};
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tower::limit::ConcurrencyLimitLayer;
+
+/// Past this many clients, those which have been idle long enough to refill are forgotten
+const MAX_TRACKED_CLIENTS: usize = 1024;
+
+/// Limits on the requests the server handles, none of which apply by default
+#[derive(Clone, Debug, Default, uniffi::Record)]
+pub struct RequestLimits {
+    /// The most tile requests handled at once, with any more waiting their turn, or `None` for no
+    /// limit
+    pub max_concurrent_tile_requests: Option<u32>,
+    /// The most requests each client may make per second, or `None` for no limit. Clients may
+    /// burst up to a second's worth of requests at once.
+    ///
+    /// Clients are identified by IP address, so every client of a unix socket, or every app on
+    /// the device connecting via `127.0.0.1`, shares a single limit.
+    pub max_requests_per_second_per_client: Option<u32>,
+}
+
+impl RequestLimits {
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.max_concurrent_tile_requests == Some(0) {
+            return Err(Error::InvalidInput(
+                "max_concurrent_tile_requests must be at least 1".to_string(),
+            ));
+        }
+        if self.max_requests_per_second_per_client == Some(0) {
+            return Err(Error::InvalidInput(
+                "max_requests_per_second_per_client must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn tile_concurrency_layer(&self) -> Option<ConcurrencyLimitLayer> {
+        let max = self.max_concurrent_tile_requests?;
+        Some(ConcurrencyLimitLayer::new(max as usize))
+    }
+
+    pub(crate) fn rate_limiter(&self) -> Option<RateLimiter> {
+        let per_second = self.max_requests_per_second_per_client?;
+        Some(RateLimiter {
+            per_second: f64::from(per_second),
+            buckets: Mutex::new(HashMap::new()),
+        })
+    }
+}
+
+/// The address of a connected client, as provided by the server's listener
+pub(crate) trait PeerAddr: Clone + Send + Sync + 'static {
+    /// Clients without an IP address, i.e. those connected via unix socket, share a limit
+    fn client_ip(&self) -> Option<IpAddr>;
+}
+
+impl PeerAddr for SocketAddr {
+    fn client_ip(&self) -> Option<IpAddr> {
+        Some(self.ip())
+    }
+}
+
+#[cfg(unix)]
+impl PeerAddr for tokio::net::unix::SocketAddr {
+    fn client_ip(&self) -> Option<IpAddr> {
+        None
+    }
+}
+
+/// A token bucket per client, holding up to a second's worth of requests
+pub(crate) struct RateLimiter {
+    per_second: f64,
+    buckets: Mutex<HashMap<Option<IpAddr>, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl RateLimiter {
+    /// Takes a token from the client's bucket, returning false if it's empty
+    fn try_acquire(&self, client_ip: Option<IpAddr>) -> bool {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().expect("poisoned lock");
+        if buckets.len() >= MAX_TRACKED_CLIENTS {
+            buckets.retain(|_, bucket| {
+                let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+                bucket.tokens + elapsed * self.per_second < self.per_second
+            });
+        }
+        let bucket = buckets.entry(client_ip).or_insert(Bucket {
+            tokens: self.per_second,
+            updated_at: now,
+        });
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.per_second).min(self.per_second);
+        bucket.updated_at = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+}
+
+pub(crate) async fn rate_limit<A: PeerAddr>(
+    State(rate_limiter): State<Arc<RateLimiter>>,
+    ConnectInfo(peer_addr): ConnectInfo<A>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let client_ip = peer_addr.client_ip();
+    if !rate_limiter.try_acquire(client_ip) {
+        log::warn!("Rate limited request from {client_ip:?}: {}", request.uri());
+        return (StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, "1")]).into_response();
+    }
+    next.run(request).await
+}
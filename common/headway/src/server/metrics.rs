@@ -0,0 +1,168 @@
+//! Request statistics, served in the Prometheus text format at `/metrics`.
+//!
+//! Statistics are kept for the lifetime of the [`crate::HeadwayServer`], across restarts.
+
+use crate::server::{tileserver, AppState};
+use axum::body::Body;
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Upper bounds of the request latency histogram buckets
+const LATENCY_BUCKETS_SECONDS: [f64; 10] =
+    [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+
+#[derive(Default)]
+pub(crate) struct Metrics {
+    /// Keyed by route, e.g. `/tileserver/fonts/{fontstack}/{range_with_ext}`
+    routes: Mutex<BTreeMap<String, RouteMetrics>>,
+}
+
+#[derive(Default)]
+struct RouteMetrics {
+    statuses: BTreeMap<u16, u64>,
+    /// Number of requests taking at most the corresponding `LATENCY_BUCKETS_SECONDS`
+    latency_buckets: [u64; LATENCY_BUCKETS_SECONDS.len()],
+    latency_sum_seconds: f64,
+    count: u64,
+}
+
+impl Metrics {
+    fn record(&self, route: &str, status: StatusCode, latency_seconds: f64) {
+        let mut routes = self.routes.lock().expect("poisoned lock");
+        let route_metrics = routes.entry(route.to_string()).or_default();
+        *route_metrics.statuses.entry(status.as_u16()).or_default() += 1;
+        for (bucket, upper_bound) in route_metrics
+            .latency_buckets
+            .iter_mut()
+            .zip(LATENCY_BUCKETS_SECONDS)
+        {
+            if latency_seconds <= upper_bound {
+                *bucket += 1;
+            }
+        }
+        route_metrics.latency_sum_seconds += latency_seconds;
+        route_metrics.count += 1;
+    }
+
+    fn render(&self) -> String {
+        let routes = self.routes.lock().expect("poisoned lock");
+        // Writing to a String can't fail
+        let mut out = String::new();
+
+        out.push_str("# HELP headway_requests_total Requests handled, by route and status code\n");
+        out.push_str("# TYPE headway_requests_total counter\n");
+        for (route, route_metrics) in routes.iter() {
+            let route = escape_label(route);
+            for (status, count) in &route_metrics.statuses {
+                writeln!(
+                    out,
+                    "headway_requests_total{{route=\"{route}\",status=\"{status}\"}} {count}"
+                )
+                .unwrap();
+            }
+        }
+
+        out.push_str("# HELP headway_request_duration_seconds Time taken to handle requests\n");
+        out.push_str("# TYPE headway_request_duration_seconds histogram\n");
+        for (route, route_metrics) in routes.iter() {
+            let route = escape_label(route);
+            for (upper_bound, count) in LATENCY_BUCKETS_SECONDS
+                .iter()
+                .zip(&route_metrics.latency_buckets)
+            {
+                writeln!(
+                    out,
+                    "headway_request_duration_seconds_bucket{{route=\"{route}\",le=\"{upper_bound}\"}} {count}"
+                )
+                .unwrap();
+            }
+            let RouteMetrics {
+                latency_sum_seconds,
+                count,
+                ..
+            } = route_metrics;
+            writeln!(
+                out,
+                "headway_request_duration_seconds_bucket{{route=\"{route}\",le=\"+Inf\"}} {count}"
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "headway_request_duration_seconds_sum{{route=\"{route}\"}} {latency_sum_seconds}"
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "headway_request_duration_seconds_count{{route=\"{route}\"}} {count}"
+            )
+            .unwrap();
+        }
+
+        // A tile request answered with 304 Not Modified was served from the client's cache
+        let tile_statuses = routes
+            .get(tileserver::TILE_ROUTE)
+            .map(|route_metrics| &route_metrics.statuses);
+        let tile_count = |status: StatusCode| {
+            tile_statuses
+                .and_then(|statuses| statuses.get(&status.as_u16()))
+                .copied()
+                .unwrap_or(0)
+        };
+        out.push_str("# HELP headway_tile_cache_requests_total Tile requests by whether the client's cached copy was still current\n");
+        out.push_str("# TYPE headway_tile_cache_requests_total counter\n");
+        writeln!(
+            out,
+            "headway_tile_cache_requests_total{{result=\"hit\"}} {}",
+            tile_count(StatusCode::NOT_MODIFIED)
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "headway_tile_cache_requests_total{{result=\"miss\"}} {}",
+            tile_count(StatusCode::OK)
+        )
+        .unwrap();
+
+        out
+    }
+}
+
+/// Records the route, status, and latency of every routed request
+pub(crate) async fn record_metrics(
+    State(metrics): State<Arc<Metrics>>,
+    matched_path: Option<MatchedPath>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let started_at = Instant::now();
+    let response = next.run(request).await;
+    if let Some(matched_path) = matched_path {
+        metrics.record(
+            matched_path.as_str(),
+            response.status(),
+            started_at.elapsed().as_secs_f64(),
+        );
+    }
+    response
+}
+
+pub(crate) async fn get_metrics(State(state): State<AppState>) -> Response {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(Body::from(state.metrics.render()))
+        .unwrap()
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
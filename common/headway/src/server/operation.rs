@@ -0,0 +1,71 @@
+//! Long running operations run in the background, e.g. by
+//! [`HeadwayServer::begin_extract`](super::HeadwayServer::begin_extract), each with a handle to
+//! check on or cancel it, the same way whatever the operation.
+
+use crate::download::DownloadCancellation;
+use crate::runtime::runtime;
+use crate::{Error, Result};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Debug, PartialEq, uniffi::Enum)]
+pub enum OperationStatus {
+    Running,
+    Completed,
+    Cancelled,
+    Failed { message: String },
+}
+
+#[derive(Debug, uniffi::Object)]
+pub struct OperationHandle {
+    cancellation: Arc<DownloadCancellation>,
+    status: Mutex<OperationStatus>,
+}
+
+#[uniffi::export]
+impl OperationHandle {
+    /// Stops the operation, which then has the status [`OperationStatus::Cancelled`], and
+    /// deletes whatever it had downloaded so far. Has no effect once the operation has finished.
+    pub fn cancel(&self) {
+        self.cancellation.cancel();
+    }
+
+    pub fn status(&self) -> OperationStatus {
+        self.status.lock().expect("poisoned lock").clone()
+    }
+}
+
+impl OperationHandle {
+    /// Runs the operation returned by `start` on the library's runtime, which should stop when
+    /// the cancellation it's passed is cancelled. It's also dropped once cancelled, in case it
+    /// doesn't.
+    pub(crate) fn spawn<F, T>(
+        start: impl FnOnce(Arc<DownloadCancellation>) -> F,
+    ) -> Result<Arc<Self>>
+    where
+        F: Future<Output = Result<T>> + Send + 'static,
+    {
+        let runtime = runtime()?;
+        let cancellation = Arc::new(DownloadCancellation::new());
+        let operation = start(cancellation.clone());
+        let handle = Arc::new(Self {
+            cancellation,
+            status: Mutex::new(OperationStatus::Running),
+        });
+        let task_handle = handle.clone();
+        runtime.spawn(async move {
+            let status = match task_handle.cancellation.run(operation).await {
+                Ok(_) => OperationStatus::Completed,
+                Err(Error::Cancelled) => OperationStatus::Cancelled,
+                Err(e) => {
+                    log::warn!("Background operation failed: {e}");
+                    OperationStatus::Failed {
+                        message: e.to_string(),
+                    }
+                }
+            };
+            *task_handle.status.lock().expect("poisoned lock") = status;
+        });
+        Ok(handle)
+    }
+}
@@ -0,0 +1,531 @@
+//! GeoJSON overlays, e.g. an imported GPX track, served at `/overlays/{overlay_id}.geojson` for
+//! map clients to add as a `geojson` source on top of the basemap.
+//!
+//! Each overlay is also cut into vector tiles on the fly, served at
+//! `/overlays/{overlay_id}/{z}/{x}/{y}.pbf`, and added to every served style as the source
+//! `overlay-{overlay_id}`, with layers to draw its points, lines and polygons on top of the
+//! basemap. Those layers follow the [simplestyle] properties `stroke`, `stroke-width`,
+//! `stroke-opacity`, `fill`, `fill-opacity`, `marker-color`, `marker-symbol` (an image in the
+//! style's sprite) and `title` of each feature, where given.
+//!
+//! A recorded track is an overlay which points are appended to as they're recorded, e.g. a live
+//! breadcrumb trail. Its tiles are rebuilt incrementally and drawn with their own layers: a line
+//! along the track and a dot at its latest point. Tracks are stored a point per line, so
+//! appending doesn't rewrite them, and served as GeoJSON like any other overlay.
+//!
+//! Overlays are stored in `{profile_dir}/overlays`, so they remain after a restart.
+//!
+//! Points which would overlap are clustered when the tiles are cut, and drawn as a bubble with
+//! the number of points in it.
+//!
+//! [simplestyle]: https://github.com/mapbox/simplestyle-spec
+
+use crate::geo::LatLon;
+use crate::map_tiles::{
+    OverlayTiler, CLUSTER_PROPERTY, OVERLAY_LAYER, OVERLAY_MAX_ZOOM, TRACK_PROPERTY,
+};
+use crate::server::conditional::conditional_response;
+use crate::server::storage::ProfileStorage;
+use crate::server::AppState;
+use crate::{Error, Result};
+use axum::body::Body;
+use axum::extract::{Path as UrlPath, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncWriteExt;
+
+/// The color of overlay features without their own
+const DEFAULT_COLOR: &str = "#e5484d";
+
+/// The color of recorded tracks
+const TRACK_COLOR: &str = "#1a73e8";
+
+pub(crate) struct Overlays {
+    storage: Arc<ProfileStorage>,
+    /// Overlays which have been tiled since they last changed
+    tilers: Mutex<HashMap<String, Arc<OverlayTiler>>>,
+    /// Held while appending to a track and updating its tiler
+    track_appends: tokio::sync::Mutex<()>,
+}
+
+impl Overlays {
+    pub(crate) fn new(storage: Arc<ProfileStorage>) -> Self {
+        Self {
+            storage,
+            tilers: Mutex::default(),
+            track_appends: tokio::sync::Mutex::default(),
+        }
+    }
+
+    fn dir(&self) -> PathBuf {
+        self.storage.join("overlays")
+    }
+
+    fn path(&self, overlay_id: &str) -> Result<PathBuf> {
+        validate_overlay_id(overlay_id)?;
+        Ok(self.dir().join(format!("{overlay_id}.geojson")))
+    }
+
+    fn track_path(&self, overlay_id: &str) -> Result<PathBuf> {
+        validate_overlay_id(overlay_id)?;
+        Ok(self.dir().join(format!("{overlay_id}.track")))
+    }
+
+    /// Adds, or replaces, the overlay `overlay_id`
+    pub(crate) async fn insert(&self, overlay_id: &str, geojson: &Value) -> Result<()> {
+        let tiler = OverlayTiler::new(geojson)?;
+        let _writing = self.storage.write_access().await;
+        let path = self.path(overlay_id)?;
+        tokio::fs::create_dir_all(self.dir()).await?;
+        // Write then rename, so a concurrent request never reads a partial overlay
+        let tmp_path = path.with_extension("geojson.tmp");
+        tokio::fs::write(&tmp_path, geojson.to_string()).await?;
+        tokio::fs::rename(&tmp_path, &path).await?;
+        remove_if_exists(&self.track_path(overlay_id)?).await?;
+        self.tilers
+            .lock()
+            .expect("poisoned lock")
+            .insert(overlay_id.to_string(), Arc::new(tiler));
+        Ok(())
+    }
+
+    /// Appends `points` to the recorded track `overlay_id`, starting it if there's no such
+    /// overlay
+    pub(crate) async fn append_to_track(&self, overlay_id: &str, points: &[LatLon]) -> Result<()> {
+        let _writing = self.storage.write_access().await;
+        let track_path = self.track_path(overlay_id)?;
+        if tokio::fs::try_exists(self.path(overlay_id)?).await? {
+            return Err(Error::InvalidInput(format!(
+                "overlay {overlay_id:?} isn't a recorded track"
+            )));
+        }
+        if let Some(point) = points
+            .iter()
+            .find(|point| !(point.lat.abs() <= 90.0 && point.lon.abs() <= 180.0))
+        {
+            return Err(Error::InvalidInput(format!(
+                "invalid track point: {point:?}"
+            )));
+        }
+        let lines: String = points
+            .iter()
+            .map(|point| format!("{:.7},{:.7}\n", point.lon, point.lat))
+            .collect();
+        tokio::fs::create_dir_all(self.dir()).await?;
+        let _appending = self.track_appends.lock().await;
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&track_path)
+            .await?;
+        file.write_all(lines.as_bytes()).await?;
+        file.flush().await?;
+
+        // A track that hasn't been tiled yet is read from disk, points and all, when it is
+        let mut tilers = self.tilers.lock().expect("poisoned lock");
+        if let Some(tiler) = tilers.get_mut(overlay_id) {
+            *tiler = Arc::new(tiler.with_track_points(points));
+        }
+        Ok(())
+    }
+
+    /// The points of the recorded track `overlay_id`, or `None` if there's no such track
+    async fn track_points(&self, overlay_id: &str) -> Result<Option<Vec<LatLon>>> {
+        let contents = match tokio::fs::read_to_string(self.track_path(overlay_id)?).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        // A line cut short by a crash mid-append is skipped
+        let points = contents
+            .lines()
+            .filter_map(|line| {
+                let (lon, lat) = line.split_once(',')?;
+                Some(LatLon {
+                    lat: lat.parse().ok()?,
+                    lon: lon.parse().ok()?,
+                })
+            })
+            .collect();
+        Ok(Some(points))
+    }
+
+    /// The overlay as GeoJSON, or `None` if there's no such overlay
+    async fn geojson(&self, overlay_id: &str) -> Result<Option<Vec<u8>>> {
+        match tokio::fs::read(self.path(overlay_id)?).await {
+            Ok(geojson) => return Ok(Some(geojson)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        let Some(points) = self.track_points(overlay_id).await? else {
+            return Ok(None);
+        };
+        Ok(Some(track_geojson(&points).to_string().into_bytes()))
+    }
+
+    /// The tiler for `overlay_id`, reading it from disk if it hasn't been tiled yet, or `None` if
+    /// there's no such overlay
+    async fn tiler(&self, overlay_id: &str) -> Result<Option<Arc<OverlayTiler>>> {
+        if let Some(tiler) = self.tilers.lock().expect("poisoned lock").get(overlay_id) {
+            return Ok(Some(tiler.clone()));
+        }
+        let path = self.path(overlay_id)?;
+        let tiler = match tokio::fs::read(&path).await {
+            Ok(geojson) => {
+                let geojson: Value = serde_json::from_slice(&geojson).map_err(|e| {
+                    Error::InvalidInput(format!("invalid overlay {overlay_id:?}: {e}"))
+                })?;
+                Arc::new(OverlayTiler::new(&geojson)?)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                // Appends wait, so none are missed between reading the track and caching it
+                let _appending = self.track_appends.lock().await;
+                if let Some(tiler) = self.tilers.lock().expect("poisoned lock").get(overlay_id) {
+                    return Ok(Some(tiler.clone()));
+                }
+                match self.track_points(overlay_id).await? {
+                    Some(points) => Arc::new(OverlayTiler::track(&points)),
+                    None => return Ok(None),
+                }
+            }
+            Err(e) => return Err(e.into()),
+        };
+        self.tilers
+            .lock()
+            .expect("poisoned lock")
+            .insert(overlay_id.to_string(), tiler.clone());
+        Ok(Some(tiler))
+    }
+
+    pub(crate) async fn remove(&self, overlay_id: &str) -> Result<()> {
+        let _writing = self.storage.write_access().await;
+        let path = self.path(overlay_id)?;
+        let track_path = self.track_path(overlay_id)?;
+        self.tilers
+            .lock()
+            .expect("poisoned lock")
+            .remove(overlay_id);
+        if !(remove_if_exists(&path).await? | remove_if_exists(&track_path).await?) {
+            return Err(Error::InvalidInput(format!(
+                "no such overlay: {overlay_id:?}"
+            )));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn ids(&self) -> Vec<String> {
+        let Ok(entries) = std::fs::read_dir(self.dir()) else {
+            return vec![];
+        };
+        let mut ids: Vec<String> = entries
+            .flatten()
+            .filter_map(|entry| {
+                let file_name = entry.file_name();
+                let file_name = file_name.to_str()?;
+                let overlay_id = file_name
+                    .strip_suffix(".geojson")
+                    .or_else(|| file_name.strip_suffix(".track"))?;
+                validate_overlay_id(overlay_id).ok()?;
+                Some(overlay_id.to_string())
+            })
+            .collect();
+        ids.sort();
+        ids.dedup();
+        ids
+    }
+
+    fn is_track(&self, overlay_id: &str) -> bool {
+        self.track_path(overlay_id)
+            .is_ok_and(|track_path| track_path.exists())
+    }
+}
+
+/// Whether there was a file at `path` to remove
+async fn remove_if_exists(path: &Path) -> Result<bool> {
+    match tokio::fs::remove_file(path).await {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// A track's line, if it has at least two points, and its latest point
+fn track_geojson(points: &[LatLon]) -> Value {
+    let coordinates: Vec<Value> = points
+        .iter()
+        .map(|point| json!([point.lon, point.lat]))
+        .collect();
+    let mut features = vec![];
+    if coordinates.len() > 1 {
+        features.push(json!({
+            "type": "Feature",
+            "properties": { TRACK_PROPERTY: "line" },
+            "geometry": { "type": "LineString", "coordinates": coordinates },
+        }));
+    }
+    if let Some(latest) = coordinates.last() {
+        features.push(json!({
+            "type": "Feature",
+            "properties": { TRACK_PROPERTY: "position" },
+            "geometry": { "type": "Point", "coordinates": latest },
+        }));
+    }
+    json!({ "type": "FeatureCollection", "features": features })
+}
+
+/// Overlay ids become file names, so mustn't be able to escape the overlays dir
+pub(crate) fn validate_overlay_id(overlay_id: &str) -> Result<()> {
+    let is_valid = !overlay_id.is_empty()
+        && overlay_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !is_valid {
+        return Err(Error::InvalidInput(format!(
+            "overlay id must be non-empty and contain only ascii letters, digits, '-' or '_' - got: {overlay_id:?}"
+        )));
+    }
+    Ok(())
+}
+
+pub(crate) async fn get_overlay(
+    State(state): State<AppState>,
+    UrlPath(file_name): UrlPath<String>,
+) -> impl IntoResponse {
+    let Some(overlay_id) = file_name
+        .strip_suffix(".geojson")
+        .filter(|overlay_id| validate_overlay_id(overlay_id).is_ok())
+    else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    match state.overlays.geojson(overlay_id).await {
+        Ok(Some(geojson)) => Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(geojson))
+            .expect("valid response"),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            log::error!("Error reading overlay {overlay_id:?}, error: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub(crate) async fn get_overlay_tile(
+    State(state): State<AppState>,
+    UrlPath((overlay_id, z, x, y_with_ext)): UrlPath<(String, u8, u32, String)>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let Some(y) = y_with_ext
+        .strip_suffix(".pbf")
+        .and_then(|y| y.parse::<u32>().ok())
+    else {
+        log::warn!("Invalid overlay tile: {y_with_ext}");
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    if z > OVERLAY_MAX_ZOOM || x >= 1 << z || y >= 1 << z {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let tiler = match state.overlays.tiler(&overlay_id).await {
+        Ok(Some(tiler)) => tiler,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            log::error!("Error tiling overlay {overlay_id:?}, error: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    // Tiling is quick for overlays of the size we expect, but not free
+    let tile = tokio::task::spawn_blocking(move || tiler.tile(z, x, y)).await;
+    match tile {
+        Ok(Some(tile)) => conditional_response(&headers, "application/x-protobuf", tile, None),
+        Ok(None) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            log::error!("Error tiling overlay {overlay_id:?}, error: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Adds a vector source for each overlay to `style`, with layers drawing them on top of the
+/// style's own
+pub(crate) fn add_overlays_to_style(style: &mut Value, overlays: &Overlays, base_url: &str) {
+    let overlay_ids = overlays.ids();
+    if overlay_ids.is_empty() {
+        return;
+    }
+    // Cluster counts are labeled in the style's own font, if it has any text
+    let font = style
+        .get("layers")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .find_map(|layer| layer.pointer("/layout/text-font"))
+        .filter(|font| font.is_array())
+        .cloned();
+    for overlay_id in overlay_ids {
+        let source_id = format!("overlay-{overlay_id}");
+        if let Some(sources) = style.get_mut("sources").and_then(Value::as_object_mut) {
+            sources.insert(
+                source_id.clone(),
+                json!({
+                    "type": "vector",
+                    "tiles": [format!("{base_url}/overlays/{overlay_id}/{{z}}/{{x}}/{{y}}.pbf")],
+                    "maxzoom": OVERLAY_MAX_ZOOM,
+                }),
+            );
+        }
+        if let Some(layers) = style.get_mut("layers").and_then(Value::as_array_mut) {
+            if overlays.is_track(&overlay_id) {
+                layers.extend(track_layers(&source_id));
+            } else {
+                layers.extend(overlay_layers(&source_id, font.as_ref()));
+            }
+        }
+    }
+}
+
+fn overlay_layers(source_id: &str, font: Option<&Value>) -> Vec<Value> {
+    let property = |name: &str, default: Value| json!(["coalesce", ["get", name], default]);
+    let mut layers = vec![
+        json!({
+            "id": format!("{source_id}-fill"),
+            "type": "fill",
+            "source": source_id,
+            "source-layer": OVERLAY_LAYER,
+            "filter": ["==", "$type", "Polygon"],
+            "paint": {
+                "fill-color": property("fill", DEFAULT_COLOR.into()),
+                "fill-opacity": property("fill-opacity", 0.25.into()),
+            },
+        }),
+        json!({
+            "id": format!("{source_id}-line"),
+            "type": "line",
+            "source": source_id,
+            "source-layer": OVERLAY_LAYER,
+            "filter": ["!=", "$type", "Point"],
+            "layout": { "line-cap": "round", "line-join": "round" },
+            "paint": {
+                "line-color": property("stroke", DEFAULT_COLOR.into()),
+                "line-width": property("stroke-width", 3.into()),
+                "line-opacity": property("stroke-opacity", 1.into()),
+            },
+        }),
+        json!({
+            "id": format!("{source_id}-circle"),
+            "type": "circle",
+            "source": source_id,
+            "source-layer": OVERLAY_LAYER,
+            "filter": ["all", ["==", "$type", "Point"], ["!has", CLUSTER_PROPERTY]],
+            "paint": {
+                "circle-color": property("marker-color", DEFAULT_COLOR.into()),
+                "circle-radius": 6,
+                "circle-stroke-color": "#ffffff",
+                "circle-stroke-width": 2,
+            },
+        }),
+        json!({
+            "id": format!("{source_id}-cluster"),
+            "type": "circle",
+            "source": source_id,
+            "source-layer": OVERLAY_LAYER,
+            "filter": ["has", CLUSTER_PROPERTY],
+            "paint": {
+                "circle-color": DEFAULT_COLOR,
+                "circle-radius": ["step", ["get", "point_count"], 12, 10, 16, 100, 20, 1000, 26],
+                "circle-stroke-color": "#ffffff",
+                "circle-stroke-width": 2,
+            },
+        }),
+    ];
+    // Icons from the style's sprite, and titles if the style has a font to draw them in
+    let mut symbol_layout = json!({
+        "icon-image": ["coalesce", ["get", "marker-symbol"], ""],
+        "icon-allow-overlap": true,
+    });
+    if let Some(font) = font {
+        symbol_layout["text-field"] = json!(["coalesce", ["get", "title"], ""]);
+        symbol_layout["text-font"] = font.clone();
+        symbol_layout["text-size"] = 12.into();
+        symbol_layout["text-anchor"] = "top".into();
+        symbol_layout["text-offset"] = json!([0, 0.8]);
+        symbol_layout["text-optional"] = true.into();
+    }
+    layers.push(json!({
+        "id": format!("{source_id}-symbol"),
+        "type": "symbol",
+        "source": source_id,
+        "source-layer": OVERLAY_LAYER,
+        "filter": [
+            "all",
+            ["==", "$type", "Point"],
+            ["!has", CLUSTER_PROPERTY],
+            ["any", ["has", "marker-symbol"], ["has", "title"]],
+        ],
+        "layout": symbol_layout,
+        "paint": {
+            "text-color": "#333333",
+            "text-halo-color": "#ffffff",
+            "text-halo-width": 1,
+        },
+    }));
+    if let Some(font) = font {
+        layers.push(json!({
+            "id": format!("{source_id}-cluster-count"),
+            "type": "symbol",
+            "source": source_id,
+            "source-layer": OVERLAY_LAYER,
+            "filter": ["has", CLUSTER_PROPERTY],
+            "layout": {
+                "text-field": ["get", "point_count_abbreviated"],
+                "text-font": font,
+                "text-size": 12,
+                "text-allow-overlap": true,
+                "text-ignore-placement": true,
+            },
+            "paint": { "text-color": "#ffffff" },
+        }));
+    }
+    layers
+}
+
+fn track_layers(source_id: &str) -> [Value; 3] {
+    let line_filter = json!(["==", TRACK_PROPERTY, "line"]);
+    [
+        json!({
+            "id": format!("{source_id}-track-casing"),
+            "type": "line",
+            "source": source_id,
+            "source-layer": OVERLAY_LAYER,
+            "filter": line_filter,
+            "layout": { "line-cap": "round", "line-join": "round" },
+            "paint": { "line-color": "#ffffff", "line-width": 7 },
+        }),
+        json!({
+            "id": format!("{source_id}-track"),
+            "type": "line",
+            "source": source_id,
+            "source-layer": OVERLAY_LAYER,
+            "filter": line_filter,
+            "layout": { "line-cap": "round", "line-join": "round" },
+            "paint": { "line-color": TRACK_COLOR, "line-width": 4 },
+        }),
+        json!({
+            "id": format!("{source_id}-track-position"),
+            "type": "circle",
+            "source": source_id,
+            "source-layer": OVERLAY_LAYER,
+            "filter": ["==", TRACK_PROPERTY, "position"],
+            "paint": {
+                "circle-color": TRACK_COLOR,
+                "circle-radius": 7,
+                "circle-stroke-color": "#ffffff",
+                "circle-stroke-width": 3,
+            },
+        }),
+    ]
+}
@@ -0,0 +1,184 @@
+//! Per-region place details (opening hours, websites, phone numbers) keyed by OSM id, so
+//! tapping a POI offline shows more than its name.
+//!
+//! A dataset is a JSON object, optionally gzipped, from OSM ids to the place's tags:
+//!
+//! ```json
+//! {
+//!   "node/123": { "opening_hours": "Mo-Fr 09:00-17:00", "website": "https://…", "phone": "…" }
+//! }
+//! ```
+//!
+//! Datasets are stored in `{profile_dir}/place_details` and held in memory, so should only
+//! include the few tags worth showing.
+
+use crate::server::storage::ProfileStorage;
+use crate::{Error, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Details of a place, from whichever place details dataset includes it
+#[derive(Clone, Debug, PartialEq, uniffi::Record)]
+pub struct PlaceDetails {
+    /// e.g. `node/123`
+    pub osm_id: String,
+    pub opening_hours: Option<String>,
+    pub website: Option<String>,
+    pub phone: Option<String>,
+    /// Every tag the dataset has for the place, including the above
+    pub tags: HashMap<String, String>,
+}
+
+#[derive(Debug)]
+pub(crate) struct PlaceDetailsStore {
+    storage: Arc<ProfileStorage>,
+    datasets: RwLock<Vec<Dataset>>,
+}
+
+#[derive(Debug)]
+struct Dataset {
+    file_name: String,
+    places: HashMap<String, HashMap<String, String>>,
+}
+
+impl PlaceDetailsStore {
+    /// Loads every dataset stored in the profile dir, skipping any that can't be read
+    pub(crate) fn new(storage: Arc<ProfileStorage>) -> Self {
+        let mut datasets = vec![];
+        if let Ok(entries) = std::fs::read_dir(storage.join("place_details")) {
+            for entry in entries.flatten() {
+                let Some(file_name) = entry.file_name().to_str().map(str::to_string) else {
+                    continue;
+                };
+                if validate_file_name(&file_name).is_err() {
+                    continue;
+                }
+                match Dataset::load(&entry.path()) {
+                    Ok(places) => datasets.push(Dataset { file_name, places }),
+                    Err(e) => log::warn!("Skipping place details {file_name:?}: {e}"),
+                }
+            }
+        }
+        datasets.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+        Self {
+            storage,
+            datasets: RwLock::new(datasets),
+        }
+    }
+
+    pub(crate) fn path(&self, file_name: &str) -> Result<PathBuf> {
+        validate_file_name(file_name)?;
+        Ok(self.storage.join("place_details").join(file_name))
+    }
+
+    /// Loads the dataset at `file_name`, replacing any previously loaded from there
+    pub(crate) async fn add(&self, file_name: &str) -> Result<()> {
+        let places = Dataset::load(&self.path(file_name)?)?;
+        let mut datasets = self.datasets.write().await;
+        datasets.retain(|dataset| dataset.file_name != file_name);
+        datasets.push(Dataset {
+            file_name: file_name.to_string(),
+            places,
+        });
+        datasets.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+        Ok(())
+    }
+
+    pub(crate) async fn remove(&self, file_name: &str) -> Result<()> {
+        let _writing = self.storage.write_access().await;
+        let path = self.path(file_name)?;
+        let mut datasets = self.datasets.write().await;
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(Error::InvalidInput(format!(
+                    "no such place details: {file_name:?}"
+                )))
+            }
+            Err(e) => return Err(e.into()),
+        }
+        datasets.retain(|dataset| dataset.file_name != file_name);
+        Ok(())
+    }
+
+    pub(crate) async fn get(&self, osm_id: &str) -> Option<PlaceDetails> {
+        let datasets = self.datasets.read().await;
+        let tags = datasets
+            .iter()
+            .find_map(|dataset| dataset.places.get(osm_id))?;
+        let tag = |keys: &[&str]| keys.iter().find_map(|key| tags.get(*key).cloned());
+        Some(PlaceDetails {
+            osm_id: osm_id.to_string(),
+            opening_hours: tag(&["opening_hours"]),
+            website: tag(&["website", "contact:website", "url"]),
+            phone: tag(&["phone", "contact:phone"]),
+            tags: tags.clone(),
+        })
+    }
+
+    pub(crate) async fn file_names(&self) -> Vec<String> {
+        let datasets = self.datasets.read().await;
+        datasets
+            .iter()
+            .map(|dataset| dataset.file_name.clone())
+            .collect()
+    }
+}
+
+impl Dataset {
+    fn load(path: &Path) -> Result<HashMap<String, HashMap<String, String>>> {
+        let invalid = |reason: String| {
+            Error::InvalidInput(format!(
+                "invalid place details {}: {reason}",
+                path.display()
+            ))
+        };
+        let bytes = std::fs::read(path)?;
+        let contents = if path.extension().is_some_and(|extension| extension == "gz") {
+            let mut contents = vec![];
+            flate2::read::GzDecoder::new(bytes.as_slice()).read_to_end(&mut contents)?;
+            contents
+        } else {
+            bytes
+        };
+        let json: Value = serde_json::from_slice(&contents).map_err(|e| invalid(e.to_string()))?;
+        let places = json
+            .as_object()
+            .ok_or_else(|| invalid("expected an object keyed by OSM id".to_string()))?;
+        Ok(places
+            .iter()
+            .filter_map(|(osm_id, tags)| {
+                let tags = tags
+                    .as_object()?
+                    .iter()
+                    .filter_map(|(key, value)| Some((key.clone(), value.as_str()?.to_string())))
+                    .collect();
+                Some((osm_id.clone(), tags))
+            })
+            .collect())
+    }
+}
+
+/// File names mustn't be able to escape the place details dir
+fn validate_file_name(file_name: &str) -> Result<()> {
+    let stem = file_name
+        .strip_suffix(".json.gz")
+        .or_else(|| file_name.strip_suffix(".json"));
+    let is_valid = stem.is_some_and(|stem| {
+        !stem.is_empty()
+            && stem
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+            && !stem.starts_with('.')
+    });
+    if !is_valid {
+        return Err(Error::InvalidInput(format!(
+            "place details file name must end with .json or .json.gz - got: {file_name:?}"
+        )));
+    }
+    Ok(())
+}
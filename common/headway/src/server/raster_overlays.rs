@@ -0,0 +1,416 @@
+//! Raster overlays, e.g. weather radar or satellite imagery, proxied from a remote tile server
+//! at `/raster_overlays/{overlay_id}/{z}/{x}/{y}` and added to every served style as the
+//! source `raster-overlay-{overlay_id}`, beneath any vector overlays.
+//!
+//! Tiles are cached on disk, and served from there without asking the tile server again until
+//! they're older than the overlay's TTL. Older tiles are still served if the tile server can't
+//! be reached, so the overlay keeps working briefly offline. The cache is bounded, by
+//! [`DEFAULT_MAX_CACHE_BYTES`] unless configured otherwise, discarding the least recently
+//! fetched tiles beyond that.
+//!
+//! Overlays are stored in `{profile_dir}/raster_overlays`, and their tiles in
+//! `{profile_dir}/raster_overlay_cache`.
+
+use crate::server::conditional::conditional_response;
+use crate::server::overlays::validate_overlay_id;
+use crate::server::storage::ProfileStorage;
+use crate::server::AppState;
+use crate::{Error, Result};
+use axum::extract::{Path as UrlPath, State};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// Beyond this, the least recently fetched tiles are discarded
+pub(crate) const DEFAULT_MAX_CACHE_BYTES: u64 = 200 * 1024 * 1024;
+
+/// `hit` if the tile was fresh in the cache, `stale` if it was served from the cache because
+/// the tile server couldn't be reached, or `miss`
+const CACHE_HEADER: HeaderName = HeaderName::from_static("x-headway-cache");
+
+/// A remote raster tile source to show as an overlay
+#[derive(Clone, Debug, PartialEq, uniffi::Record)]
+pub struct RasterOverlaySource {
+    /// e.g. `https://tiles.example.com/radar/{z}/{x}/{y}.png`
+    pub url_template: String,
+    /// In pixels, 256 or 512
+    pub tile_size: u16,
+    pub min_zoom: u8,
+    pub max_zoom: u8,
+    /// How long a cached tile is served without asking the tile server for a newer one, e.g. a
+    /// few minutes for weather radar, or days for satellite imagery
+    pub ttl_s: u64,
+    /// From 0 (transparent) to 1 (opaque)
+    pub opacity: f64,
+    /// Shown by the map, as the tile server's terms usually require
+    pub attribution: Option<String>,
+}
+
+pub(crate) struct RasterOverlays {
+    storage: Arc<ProfileStorage>,
+    sources: RwLock<BTreeMap<String, RasterOverlaySource>>,
+    /// The size of every cached tile, counted when first needed
+    cache_bytes: Mutex<Option<u64>>,
+    max_cache_bytes: u64,
+}
+
+impl RasterOverlays {
+    /// Loads every overlay stored in the profile dir, skipping any that can't be read
+    pub(crate) fn new(storage: Arc<ProfileStorage>, max_cache_bytes: u64) -> Self {
+        let mut sources = BTreeMap::new();
+        if let Ok(entries) = fs::read_dir(storage.join("raster_overlays")) {
+            for entry in entries.flatten() {
+                let file_name = entry.file_name();
+                let Some(overlay_id) = file_name
+                    .to_str()
+                    .and_then(|file_name| file_name.strip_suffix(".json"))
+                    .filter(|overlay_id| validate_overlay_id(overlay_id).is_ok())
+                else {
+                    continue;
+                };
+                let source = fs::read(entry.path())
+                    .ok()
+                    .and_then(|json| serde_json::from_slice::<Value>(&json).ok())
+                    .and_then(|json| source_from_json(&json));
+                match source {
+                    Some(source) => {
+                        sources.insert(overlay_id.to_string(), source);
+                    }
+                    None => log::warn!("Skipping invalid raster overlay {overlay_id:?}"),
+                }
+            }
+        }
+        Self {
+            storage,
+            sources: RwLock::new(sources),
+            cache_bytes: Mutex::new(None),
+            max_cache_bytes,
+        }
+    }
+
+    fn dir(&self) -> PathBuf {
+        self.storage.join("raster_overlays")
+    }
+
+    fn cache_dir(&self) -> PathBuf {
+        self.storage.join("raster_overlay_cache")
+    }
+
+    /// Adds, or replaces, the overlay `overlay_id`, discarding any tiles cached for it
+    pub(crate) async fn insert(&self, overlay_id: &str, source: RasterOverlaySource) -> Result<()> {
+        validate_overlay_id(overlay_id)?;
+        validate_source(&source)?;
+        let _writing = self.storage.write_access().await;
+        fs::create_dir_all(self.dir())?;
+        // Write then rename, so the overlay is never lost to a partial write
+        let path = self.dir().join(format!("{overlay_id}.json"));
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, source_to_json(&source).to_string())?;
+        fs::rename(&tmp_path, &path)?;
+        self.sources
+            .write()
+            .expect("poisoned lock")
+            .insert(overlay_id.to_string(), source);
+        self.clear_cache(overlay_id);
+        Ok(())
+    }
+
+    pub(crate) async fn remove(&self, overlay_id: &str) -> Result<()> {
+        validate_overlay_id(overlay_id)?;
+        let _writing = self.storage.write_access().await;
+        let mut sources = self.sources.write().expect("poisoned lock");
+        if sources.remove(overlay_id).is_none() {
+            return Err(Error::InvalidInput(format!(
+                "no such raster overlay: {overlay_id:?}"
+            )));
+        }
+        fs::remove_file(self.dir().join(format!("{overlay_id}.json")))?;
+        self.clear_cache(overlay_id);
+        Ok(())
+    }
+
+    pub(crate) fn ids(&self) -> Vec<String> {
+        let sources = self.sources.read().expect("poisoned lock");
+        sources.keys().cloned().collect()
+    }
+
+    fn source(&self, overlay_id: &str) -> Option<RasterOverlaySource> {
+        let sources = self.sources.read().expect("poisoned lock");
+        sources.get(overlay_id).cloned()
+    }
+
+    fn cache_path(&self, overlay_id: &str, z: u8, x: u32, y: u32) -> PathBuf {
+        self.cache_dir()
+            .join(overlay_id)
+            .join(format!("{z}-{x}-{y}"))
+    }
+
+    fn clear_cache(&self, overlay_id: &str) {
+        let mut cache_bytes = self.cache_bytes.lock().expect("poisoned lock");
+        match fs::remove_dir_all(self.cache_dir().join(overlay_id)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => log::warn!("Unable to clear the cache of raster overlay {overlay_id:?}: {e}"),
+        }
+        // Recounted when next needed
+        *cache_bytes = None;
+    }
+
+    /// Caches `tile` as `overlay_id`'s tile at `z/x/y`, discarding the oldest tiles if the cache
+    /// is too large. Failures are only logged, since the tile can be served regardless, and
+    /// nothing is cached while the storage is being migrated.
+    fn store(&self, overlay_id: &str, z: u8, x: u32, y: u32, tile: &[u8]) {
+        let Some(_writing) = self.storage.try_write_access() else {
+            return;
+        };
+        let mut cache_bytes = self.cache_bytes.lock().expect("poisoned lock");
+        let cache_path = self.cache_path(overlay_id, z, x, y);
+        let result = cache_path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| {
+                // Write then rename, so a concurrent request never reads a partial tile
+                let tmp_path = cache_path.with_extension("tmp");
+                fs::write(&tmp_path, tile)?;
+                fs::rename(&tmp_path, &cache_path)
+            });
+        if let Err(e) = result {
+            log::warn!("Unable to cache raster overlay tile at {cache_path:?}: {e}");
+            return;
+        }
+
+        let total = match *cache_bytes {
+            Some(total) => total + tile.len() as u64,
+            None => cached_tiles(&self.cache_dir())
+                .iter()
+                .map(|(_, size, _)| size)
+                .sum(),
+        };
+        if total <= self.max_cache_bytes {
+            *cache_bytes = Some(total);
+            return;
+        }
+        // Discard down to three quarters of the limit, so this isn't repeated for every tile
+        let mut cached = cached_tiles(&self.cache_dir());
+        cached.sort();
+        let mut total: u64 = cached.iter().map(|(_, size, _)| size).sum();
+        for (_, size, path) in &cached {
+            if total <= self.max_cache_bytes / 4 * 3 {
+                break;
+            }
+            match fs::remove_file(path) {
+                Ok(()) => total -= size,
+                Err(e) => log::warn!("Unable to remove cached raster overlay tile {path:?}: {e}"),
+            }
+        }
+        *cache_bytes = Some(total);
+    }
+}
+
+/// The modification time, size and path of every cached tile
+fn cached_tiles(cache_dir: &Path) -> Vec<(SystemTime, u64, PathBuf)> {
+    let Ok(overlay_dirs) = fs::read_dir(cache_dir) else {
+        return vec![];
+    };
+    overlay_dirs
+        .flatten()
+        .filter_map(|overlay_dir| fs::read_dir(overlay_dir.path()).ok())
+        .flat_map(|entries| entries.flatten())
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            Some((metadata.modified().ok()?, metadata.len(), entry.path()))
+        })
+        .collect()
+}
+
+fn validate_source(source: &RasterOverlaySource) -> Result<()> {
+    let invalid = |reason: String| Error::InvalidInput(format!("invalid raster overlay: {reason}"));
+    let url_template = &source.url_template;
+    if !["{z}", "{x}", "{y}"]
+        .iter()
+        .all(|placeholder| url_template.contains(placeholder))
+    {
+        return Err(invalid(format!(
+            "URL template must include {{z}}, {{x}} and {{y}} - got: {url_template:?}"
+        )));
+    }
+    let url = reqwest::Url::parse(&tile_url(url_template, 0, 0, 0))
+        .map_err(|e| invalid(format!("invalid URL template {url_template:?}: {e}")))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(invalid(format!(
+            "URL template must be http or https - got: {url_template:?}"
+        )));
+    }
+    if !matches!(source.tile_size, 256 | 512) {
+        return Err(invalid(format!(
+            "tile size must be 256 or 512 - got: {}",
+            source.tile_size
+        )));
+    }
+    if source.min_zoom > source.max_zoom || source.max_zoom > 22 {
+        return Err(invalid(format!(
+            "zooms must be from 0 to 22, with min_zoom at most max_zoom - got: {}..={}",
+            source.min_zoom, source.max_zoom
+        )));
+    }
+    if !(0.0..=1.0).contains(&source.opacity) {
+        return Err(invalid(format!(
+            "opacity must be from 0 to 1 - got: {}",
+            source.opacity
+        )));
+    }
+    Ok(())
+}
+
+fn tile_url(url_template: &str, z: u8, x: u32, y: u32) -> String {
+    url_template
+        .replace("{z}", &z.to_string())
+        .replace("{x}", &x.to_string())
+        .replace("{y}", &y.to_string())
+}
+
+fn source_to_json(source: &RasterOverlaySource) -> Value {
+    json!({
+        "url_template": source.url_template,
+        "tile_size": source.tile_size,
+        "min_zoom": source.min_zoom,
+        "max_zoom": source.max_zoom,
+        "ttl_s": source.ttl_s,
+        "opacity": source.opacity,
+        "attribution": source.attribution,
+    })
+}
+
+fn source_from_json(json: &Value) -> Option<RasterOverlaySource> {
+    let source = RasterOverlaySource {
+        url_template: json.get("url_template")?.as_str()?.to_string(),
+        tile_size: u16::try_from(json.get("tile_size")?.as_u64()?).ok()?,
+        min_zoom: u8::try_from(json.get("min_zoom")?.as_u64()?).ok()?,
+        max_zoom: u8::try_from(json.get("max_zoom")?.as_u64()?).ok()?,
+        ttl_s: json.get("ttl_s")?.as_u64()?,
+        opacity: json.get("opacity")?.as_f64()?,
+        attribution: json
+            .get("attribution")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+    };
+    validate_source(&source).ok()?;
+    Some(source)
+}
+
+/// Image tiles are served with the type of their contents, since the tile server's URLs may
+/// not say
+fn image_content_type(tile: &[u8]) -> &'static str {
+    if tile.starts_with(b"\x89PNG") {
+        "image/png"
+    } else if tile.starts_with(b"\xff\xd8\xff") {
+        "image/jpeg"
+    } else if tile.starts_with(b"RIFF") && tile.get(8..12) == Some(b"WEBP") {
+        "image/webp"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+fn tile_response(headers: &HeaderMap, tile: Vec<u8>, cache: &'static str) -> Response {
+    let mut response = conditional_response(headers, image_content_type(&tile), tile, None);
+    response
+        .headers_mut()
+        .insert(CACHE_HEADER, HeaderValue::from_static(cache));
+    response
+}
+
+pub(crate) async fn get_raster_overlay_tile(
+    State(state): State<AppState>,
+    UrlPath((overlay_id, z, x, y)): UrlPath<(String, u8, u32, u32)>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let Some(source) = state.raster_overlays.source(&overlay_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if z < source.min_zoom || z > source.max_zoom || x >= 1 << z || y >= 1 << z {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let cache_path = state.raster_overlays.cache_path(&overlay_id, z, x, y);
+    let cached = fs::read(&cache_path).ok().map(|tile| {
+        let age = fs::metadata(&cache_path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .unwrap_or(Duration::MAX);
+        (tile, age)
+    });
+    if let Some((tile, age)) = &cached {
+        if *age < Duration::from_secs(source.ttl_s) {
+            return tile_response(&headers, tile.clone(), "hit");
+        }
+    }
+
+    let url = tile_url(&source.url_template, z, x, y);
+    let downloader = state.downloader.read().await.clone();
+    let is_offline = matches!(downloader.connectivity.check(), Err(Error::Offline));
+    if !is_offline {
+        match downloader.client().get(&url).send().await {
+            Ok(response) if response.status() == StatusCode::NOT_FOUND => {
+                return StatusCode::NOT_FOUND.into_response();
+            }
+            Ok(response) if response.status().is_success() => match response.bytes().await {
+                Ok(tile) => {
+                    downloader.data_budget.spend(tile.len() as u64);
+                    state.raster_overlays.store(&overlay_id, z, x, y, &tile);
+                    return tile_response(&headers, tile.to_vec(), "miss");
+                }
+                Err(e) => log::warn!("Raster overlay request to {url} failed, trying cache: {e}"),
+            },
+            Ok(response) => log::warn!(
+                "Raster overlay request to {url} failed with {}, trying cache",
+                response.status()
+            ),
+            Err(e) => log::warn!("Raster overlay request to {url} failed, trying cache: {e}"),
+        }
+    }
+
+    match cached {
+        Some((tile, _)) => tile_response(&headers, tile, "stale"),
+        None => StatusCode::BAD_GATEWAY.into_response(),
+    }
+}
+
+/// Adds a raster source and layer for each raster overlay to `style`, on top of the style's
+/// own layers
+pub(crate) fn add_raster_overlays_to_style(
+    style: &mut Value,
+    raster_overlays: &RasterOverlays,
+    base_url: &str,
+) {
+    let sources = raster_overlays.sources.read().expect("poisoned lock");
+    for (overlay_id, source) in sources.iter() {
+        let source_id = format!("raster-overlay-{overlay_id}");
+        if let Some(style_sources) = style.get_mut("sources").and_then(Value::as_object_mut) {
+            let mut style_source = json!({
+                "type": "raster",
+                "tiles": [format!("{base_url}/raster_overlays/{overlay_id}/{{z}}/{{x}}/{{y}}")],
+                "tileSize": source.tile_size,
+                "minzoom": source.min_zoom,
+                "maxzoom": source.max_zoom,
+            });
+            if let Some(attribution) = &source.attribution {
+                style_source["attribution"] = attribution.as_str().into();
+            }
+            style_sources.insert(source_id.clone(), style_source);
+        }
+        if let Some(layers) = style.get_mut("layers").and_then(Value::as_array_mut) {
+            layers.push(json!({
+                "id": source_id,
+                "type": "raster",
+                "source": source_id,
+                "paint": { "raster-opacity": source.opacity },
+            }));
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! Proxies routing requests, like directions between places, to a remote routing endpoint such
+//! as maps.earth's. Responses say which backend answered in an `X-Headway-Routing-Backend`
+//! header, so apps can use the one route whether the device is online or not.
+//!
+//! There's no offline routing engine to fall back to yet, so while the endpoint can't be reached
+//! requests fail with an explicit error saying so, rather than a generic gateway error.
+
+use crate::server::AppState;
+use crate::Error;
+use axum::body::Body;
+use axum::extract::{Path as UrlPath, RawQuery, State};
+use axum::http::{header, HeaderName, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use bytes::Bytes;
+use serde_json::json;
+
+/// `online` if the remote endpoint answered, or `none` if no backend could
+const BACKEND_HEADER: HeaderName = HeaderName::from_static("x-headway-routing-backend");
+
+pub(crate) async fn proxy_routing(
+    State(state): State<AppState>,
+    UrlPath(path): UrlPath<String>,
+    RawQuery(query): RawQuery,
+) -> impl IntoResponse {
+    let Some(endpoint) = state.routing_endpoint.read().await.clone() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let url = match query {
+        Some(query) => format!("{endpoint}/{path}?{query}"),
+        None => format!("{endpoint}/{path}"),
+    };
+
+    let downloader = state.downloader.read().await.clone();
+    if matches!(downloader.connectivity.check(), Err(Error::Offline)) {
+        return no_offline_engine();
+    }
+    match downloader.client().get(&url).send().await {
+        Ok(response) => {
+            let status = response.status();
+            match response.bytes().await {
+                Ok(body) => {
+                    downloader.data_budget.spend(body.len() as u64);
+                    json_response(status, body, "online")
+                }
+                Err(e) => {
+                    log::warn!("Routing request to {url} failed: {e}");
+                    no_offline_engine()
+                }
+            }
+        }
+        Err(e) => {
+            log::warn!("Routing request to {url} failed: {e}");
+            no_offline_engine()
+        }
+    }
+}
+
+/// The response while the endpoint can't be reached, since there's no offline engine to answer
+/// instead
+fn no_offline_engine() -> Response {
+    let body = json!({
+        "error": "no_offline_engine",
+        "message": "The routing endpoint can't be reached, and there's no offline routing engine",
+    });
+    json_response(
+        StatusCode::SERVICE_UNAVAILABLE,
+        body.to_string().into(),
+        "none",
+    )
+}
+
+fn json_response(status: StatusCode, body: Bytes, backend: &'static str) -> Response {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(BACKEND_HEADER, HeaderValue::from_static(backend))
+        .body(Body::from(body))
+        .expect("valid response")
+}
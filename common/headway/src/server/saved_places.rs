@@ -0,0 +1,236 @@
+//! The user's saved places (favorites), persisted so both apps share one implementation, and
+//! optionally served as an overlay for the map to show them.
+
+use super::HeadwayServer;
+use crate::geo::LatLon;
+use crate::{Error, Result};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+#[derive(Clone, Debug, PartialEq, uniffi::Record)]
+pub struct SavedPlace {
+    pub id: u64,
+    pub name: String,
+    pub location: LatLon,
+    pub address: Option<String>,
+    pub note: Option<String>,
+    /// e.g. `node/123`, to look the place up again
+    pub osm_id: Option<String>,
+    pub created_at: SystemTime,
+}
+
+#[derive(uniffi::Object)]
+pub struct SavedPlaces {
+    server: Arc<HeadwayServer>,
+    state_path: PathBuf,
+    overlay_id: Option<String>,
+    store: Mutex<Store>,
+}
+
+#[derive(Default)]
+struct Store {
+    places: Vec<SavedPlace>,
+    next_id: u64,
+}
+
+#[uniffi::export(async_runtime = "tokio")]
+impl SavedPlaces {
+    /// Restores the places persisted at `state_path`, e.g. `{profile_dir}/saved_places.json`.
+    ///
+    /// If `overlay_id` is given, the places are served as GeoJSON points at
+    /// `/overlays/{overlay_id}.geojson`, with `id` and `name` properties, kept up to date as
+    /// they change.
+    #[uniffi::constructor]
+    pub async fn new(
+        server: Arc<HeadwayServer>,
+        state_path: String,
+        overlay_id: Option<String>,
+    ) -> Result<Arc<Self>> {
+        let state_path = PathBuf::from(state_path);
+        let store = Store::load(&state_path)?;
+        let saved_places = Self {
+            server,
+            state_path,
+            overlay_id,
+            store: Mutex::new(store),
+        };
+        saved_places
+            .update_overlay(&saved_places.store.lock().await)
+            .await?;
+        Ok(Arc::new(saved_places))
+    }
+
+    /// Every saved place, in the order they were saved
+    pub async fn places(&self) -> Vec<SavedPlace> {
+        self.store.lock().await.places.clone()
+    }
+
+    pub async fn place(&self, id: u64) -> Option<SavedPlace> {
+        let store = self.store.lock().await;
+        store.places.iter().find(|place| place.id == id).cloned()
+    }
+
+    /// Saves a new place, returning it with its id
+    pub async fn add(
+        &self,
+        name: String,
+        location: LatLon,
+        address: Option<String>,
+        note: Option<String>,
+        osm_id: Option<String>,
+    ) -> Result<SavedPlace> {
+        validate_location(location)?;
+        let mut store = self.store.lock().await;
+        let place = SavedPlace {
+            id: store.next_id,
+            name,
+            location,
+            address,
+            note,
+            osm_id,
+            created_at: SystemTime::now(),
+        };
+        store.next_id += 1;
+        store.places.push(place.clone());
+        self.save(&store).await?;
+        Ok(place)
+    }
+
+    /// Replaces the saved place with `place.id`, e.g. to rename it
+    pub async fn update(&self, place: SavedPlace) -> Result<()> {
+        validate_location(place.location)?;
+        let mut store = self.store.lock().await;
+        *store.place_mut(place.id)? = place;
+        self.save(&store).await
+    }
+
+    pub async fn remove(&self, id: u64) -> Result<()> {
+        let mut store = self.store.lock().await;
+        store.place_mut(id)?;
+        store.places.retain(|place| place.id != id);
+        self.save(&store).await
+    }
+}
+
+impl SavedPlaces {
+    async fn save(&self, store: &Store) -> Result<()> {
+        // Write then rename, so the places are never lost to a partial write
+        let tmp_path = self.state_path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, store.to_json().to_string())?;
+        std::fs::rename(&tmp_path, &self.state_path)?;
+        self.update_overlay(store).await
+    }
+
+    async fn update_overlay(&self, store: &Store) -> Result<()> {
+        let Some(overlay_id) = &self.overlay_id else {
+            return Ok(());
+        };
+        let features: Vec<Value> = store
+            .places
+            .iter()
+            .map(|place| {
+                json!({
+                    "type": "Feature",
+                    "id": place.id,
+                    "geometry": {
+                        "type": "Point",
+                        "coordinates": [place.location.lon, place.location.lat],
+                    },
+                    "properties": { "id": place.id, "name": place.name },
+                })
+            })
+            .collect();
+        let geojson = json!({ "type": "FeatureCollection", "features": features });
+        self.server.overlays.insert(overlay_id, &geojson).await
+    }
+}
+
+fn validate_location(location: LatLon) -> Result<()> {
+    if !(location.lat.abs() <= 90.0 && location.lon.abs() <= 180.0) {
+        return Err(Error::InvalidInput(format!(
+            "invalid location {location:?}"
+        )));
+    }
+    Ok(())
+}
+
+impl Store {
+    fn place_mut(&mut self, id: u64) -> Result<&mut SavedPlace> {
+        self.places
+            .iter_mut()
+            .find(|place| place.id == id)
+            .ok_or_else(|| Error::InvalidInput(format!("no saved place with id {id}")))
+    }
+
+    fn load(path: &Path) -> Result<Self> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let json: Value = serde_json::from_str(&contents).map_err(|e| {
+            Error::InvalidInput(format!("invalid saved places {}: {e}", path.display()))
+        })?;
+        let places: Vec<SavedPlace> = json
+            .get("places")
+            .and_then(Value::as_array)
+            .map(|places| places.iter().filter_map(place_from_json).collect())
+            .unwrap_or_default();
+        let next_id = json
+            .get("next_id")
+            .and_then(Value::as_u64)
+            .unwrap_or_default()
+            .max(
+                places
+                    .iter()
+                    .map(|place| place.id + 1)
+                    .max()
+                    .unwrap_or_default(),
+            );
+        Ok(Self { places, next_id })
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "next_id": self.next_id,
+            "places": self.places.iter().map(place_to_json).collect::<Vec<_>>(),
+        })
+    }
+}
+
+fn place_to_json(place: &SavedPlace) -> Value {
+    let created_at_ms = place
+        .created_at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    json!({
+        "id": place.id,
+        "name": place.name,
+        "lat": place.location.lat,
+        "lon": place.location.lon,
+        "address": place.address,
+        "note": place.note,
+        "osm_id": place.osm_id,
+        "created_at_ms": created_at_ms,
+    })
+}
+
+fn place_from_json(json: &Value) -> Option<SavedPlace> {
+    let string = |name: &str| json.get(name).and_then(Value::as_str).map(str::to_string);
+    Some(SavedPlace {
+        id: json.get("id")?.as_u64()?,
+        name: string("name")?,
+        location: LatLon {
+            lat: json.get("lat")?.as_f64()?,
+            lon: json.get("lon")?.as_f64()?,
+        },
+        address: string("address"),
+        note: string("note"),
+        osm_id: string("osm_id"),
+        created_at: UNIX_EPOCH + Duration::from_millis(json.get("created_at_ms")?.as_u64()?),
+    })
+}
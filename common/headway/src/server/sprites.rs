@@ -0,0 +1,161 @@
+//! Serves sprite sheets: icon images along with the JSON index describing where each icon is.
+//!
+//! A style whose `sprite` is `http://{host}/tileserver/sprites/{sheet_id}/sprite` has MapLibre
+//! request `sprite.json` and `sprite.png`, or `sprite@2x.json` and `sprite@2x.png` on high
+//! density displays. These are read from `{sprites_dir}/{sheet_id}/`, falling back to the closest
+//! available pixel ratio, and then to the sheets bundled into the library.
+
+use crate::server::conditional::conditional_response;
+use crate::server::AppState;
+use axum::extract::{Path as UrlPath, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use bytes::Bytes;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Sprite sheets compiled into the library: (sheet id, pixel ratio, json, png)
+const BUNDLED_SHEETS: &[(&str, u8, &str, &[u8])] = &[(
+    "basic",
+    2,
+    include_str!("../../tileserver_styles/basic/sprite@2x.json"),
+    include_bytes!("../../tileserver_styles/basic/sprite@2x.png"),
+)];
+
+const MAX_PIXEL_RATIO: u8 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SpriteFormat {
+    Json,
+    Png,
+}
+
+impl SpriteFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Png => "png",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Png => "image/png",
+        }
+    }
+}
+
+pub(crate) async fn get_sprite(
+    State(state): State<AppState>,
+    UrlPath((sheet_id, file_name)): UrlPath<(String, String)>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    serve_sprite(&state, &sheet_id, &file_name, &headers).await
+}
+
+pub(crate) async fn serve_sprite(
+    state: &AppState,
+    sheet_id: &str,
+    file_name: &str,
+    headers: &HeaderMap,
+) -> Response {
+    let Some((pixel_ratio, format)) = parse_sprite_file_name(file_name) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if !is_valid_sheet_id(sheet_id) {
+        log::warn!("Invalid sprite sheet id: {sheet_id:?}");
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+
+    let sheet_dir = state.sprites_dir.read().await.join(sheet_id);
+    for candidate_ratio in candidate_pixel_ratios(pixel_ratio) {
+        // Only consider ratios where both files exist, so the json and png always agree
+        let json_path = sheet_dir.join(sprite_file_name(candidate_ratio, SpriteFormat::Json));
+        let png_path = sheet_dir.join(sprite_file_name(candidate_ratio, SpriteFormat::Png));
+        if !(json_path.exists() && png_path.exists()) {
+            continue;
+        }
+        let path = match format {
+            SpriteFormat::Json => json_path,
+            SpriteFormat::Png => png_path,
+        };
+        let modified = tokio::fs::metadata(&path)
+            .await
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        return match tokio::fs::read(&path).await {
+            Ok(bytes) => sprite_response(headers, format, bytes, modified),
+            Err(e) => {
+                log::error!("Error reading sprite {path:?}, error: {e}");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        };
+    }
+
+    for candidate_ratio in candidate_pixel_ratios(pixel_ratio) {
+        let bundled = BUNDLED_SHEETS
+            .iter()
+            .find(|(id, ratio, _, _)| *id == sheet_id && *ratio == candidate_ratio);
+        if let Some((_, _, json, png)) = bundled {
+            return match format {
+                SpriteFormat::Json => sprite_response(headers, format, *json, None),
+                SpriteFormat::Png => sprite_response(headers, format, *png, None),
+            };
+        }
+    }
+
+    StatusCode::NOT_FOUND.into_response()
+}
+
+fn sprite_response(
+    headers: &HeaderMap,
+    format: SpriteFormat,
+    body: impl Into<Bytes>,
+    modified: Option<SystemTime>,
+) -> Response {
+    conditional_response(headers, format.content_type(), body, modified)
+}
+
+/// Parses file names like `sprite.json` or `sprite@2x.png`
+fn parse_sprite_file_name(file_name: &str) -> Option<(u8, SpriteFormat)> {
+    let (stem, extension) = file_name.rsplit_once('.')?;
+    let format = match extension {
+        "json" => SpriteFormat::Json,
+        "png" => SpriteFormat::Png,
+        _ => return None,
+    };
+    let pixel_ratio = match stem.strip_prefix("sprite")? {
+        "" => 1,
+        suffix => {
+            let ratio: u8 = suffix.strip_prefix('@')?.strip_suffix('x')?.parse().ok()?;
+            if ratio == 0 || ratio > MAX_PIXEL_RATIO {
+                return None;
+            }
+            ratio
+        }
+    };
+    Some((pixel_ratio, format))
+}
+
+fn sprite_file_name(pixel_ratio: u8, format: SpriteFormat) -> String {
+    let extension = format.extension();
+    if pixel_ratio == 1 {
+        format!("sprite.{extension}")
+    } else {
+        format!("sprite@{pixel_ratio}x.{extension}")
+    }
+}
+
+/// The requested ratio, then lower ratios (which upscale acceptably), then higher ones
+fn candidate_pixel_ratios(pixel_ratio: u8) -> impl Iterator<Item = u8> {
+    (1..=pixel_ratio)
+        .rev()
+        .chain(pixel_ratio + 1..=MAX_PIXEL_RATIO)
+}
+
+/// Sheet ids become path components, so mustn't be able to escape `sprites_dir`
+fn is_valid_sheet_id(sheet_id: &str) -> bool {
+    !sheet_id.is_empty() && Path::new(sheet_id).file_name() == Some(OsStr::new(sheet_id))
+}
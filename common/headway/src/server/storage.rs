@@ -0,0 +1,234 @@
+//! Where a server's profile keeps its data, e.g. tiles, overlays and place details, and where
+//! the storage dir keeps what's shared by every profile, e.g. fonts, both of which
+//! [`HeadwayServer::migrate_storage`](super::HeadwayServer::migrate_storage) can move while the
+//! server is running.
+//!
+//! Everything stored in either looks its location up here when it's needed, rather than holding
+//! on to it, so all of it switches over to the new location together.
+
+use crate::map_tiles::ExtractProgress;
+use crate::{Error, ErrorContext, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use tokio::sync::{RwLock as AsyncRwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Where the storage dir keeps each profile's own dir
+pub(crate) const PROFILES_DIR: &str = "profiles";
+
+/// What's kept in the storage dir for every profile, rather than in a profile's own dir. A
+/// server without a profile keeps its data in the storage dir itself, alongside these.
+pub(crate) const SHARED_ENTRIES: [&str; 6] = [
+    "fonts",
+    "sprites",
+    "styles",
+    "glyph_cache",
+    "asset_bundles",
+    "tls",
+];
+
+#[derive(Debug)]
+pub(crate) struct ProfileStorage {
+    dirs: RwLock<Dirs>,
+    /// Held shared by each write to the profile or storage dir, and exclusively while they're
+    /// migrated, so nothing is written to the old location once it's been copied
+    migration: AsyncRwLock<()>,
+}
+
+#[derive(Debug)]
+struct Dirs {
+    storage_dir: PathBuf,
+    /// Within `storage_dir`, or `storage_dir` itself without a profile
+    profile_dir: PathBuf,
+}
+
+impl ProfileStorage {
+    pub(crate) fn new(storage_dir: PathBuf, profile_dir: PathBuf) -> Self {
+        Self {
+            dirs: RwLock::new(Dirs {
+                storage_dir,
+                profile_dir,
+            }),
+            migration: AsyncRwLock::new(()),
+        }
+    }
+
+    /// The current location of the storage dir
+    pub(crate) fn storage_dir(&self) -> PathBuf {
+        self.dirs.read().expect("poisoned lock").storage_dir.clone()
+    }
+
+    /// The current location of the profile dir
+    pub(crate) fn dir(&self) -> PathBuf {
+        self.dirs.read().expect("poisoned lock").profile_dir.clone()
+    }
+
+    /// The current location of `name` within the profile dir
+    pub(crate) fn join(&self, name: &str) -> PathBuf {
+        self.dirs
+            .read()
+            .expect("poisoned lock")
+            .profile_dir
+            .join(name)
+    }
+
+    /// The current location of `name`, one of [`SHARED_ENTRIES`], within the storage dir
+    pub(crate) fn join_shared(&self, name: &str) -> PathBuf {
+        self.dirs
+            .read()
+            .expect("poisoned lock")
+            .storage_dir
+            .join(name)
+    }
+
+    /// Held while writing to the profile or storage dir, once any migration has finished
+    pub(crate) async fn write_access(&self) -> RwLockReadGuard<'_, ()> {
+        self.migration.read().await
+    }
+
+    /// Like [`Self::write_access`], but `None` rather than waiting out a migration, for writes
+    /// which can be skipped, like caching a response
+    pub(crate) fn try_write_access(&self) -> Option<RwLockReadGuard<'_, ()>> {
+        self.migration.try_read().ok()
+    }
+
+    /// Held while migrating, once every write in progress has finished
+    pub(crate) async fn migration(&self) -> RwLockWriteGuard<'_, ()> {
+        self.migration.write().await
+    }
+
+    /// Switches over to `storage_dir` and `profile_dir`, once they've been copied there
+    pub(crate) fn relocate(&self, storage_dir: PathBuf, profile_dir: PathBuf) {
+        *self.dirs.write().expect("poisoned lock") = Dirs {
+            storage_dir,
+            profile_dir,
+        };
+    }
+}
+
+/// Copies the [`SHARED_ENTRIES`] of `storage_dir` to `new_storage_dir`, so a server started there
+/// has the same fonts, asset bundle and TLS certificate. Entries `new_storage_dir` already has,
+/// e.g. from migrating another profile, are kept rather than replaced.
+pub(crate) fn copy_shared_entries(storage_dir: &Path, new_storage_dir: &Path) -> Result<()> {
+    for name in SHARED_ENTRIES {
+        let (dir, new_dir) = (storage_dir.join(name), new_storage_dir.join(name));
+        if fs::exists(&dir)? && !fs::exists(&new_dir)? {
+            copy_dir(&dir, &new_dir, &[], None).context(format!("copying {name}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Copies everything in `dir` but its `skipped` entries to `new_dir`, which must be empty if it
+/// exists.
+///
+/// Files are first copied into a staging directory next to `new_dir`, which is renamed into
+/// place once the copy has completed, so an interrupted migration never leaves a partially
+/// populated `new_dir` behind.
+pub(crate) fn copy_dir(
+    dir: &Path,
+    new_dir: &Path,
+    skipped: &[&str],
+    progress_callback: Option<Arc<dyn ExtractProgress>>,
+) -> Result<()> {
+    if fs::exists(new_dir)? && fs::read_dir(new_dir)?.next().is_some() {
+        return Err(Error::InvalidInput(format!(
+            "migration destination isn't empty: {new_dir:?}"
+        )));
+    }
+    let Some(parent) = new_dir.parent() else {
+        return Err(Error::InvalidInput(format!(
+            "invalid migration destination: {new_dir:?}"
+        )));
+    };
+    fs::create_dir_all(parent)?;
+    if parent.canonicalize()?.starts_with(dir.canonicalize()?) {
+        return Err(Error::InvalidInput(format!(
+            "cannot migrate storage into itself: {new_dir:?}"
+        )));
+    }
+
+    let mut files = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !is_skipped(&entry.path(), skipped) {
+            collect_files(&entry.path(), &mut files)?;
+        }
+    }
+    let total_bytes: u64 = files.iter().map(|(_, len)| len).sum();
+
+    let staging_dir = new_dir.with_extension("migrating");
+    if fs::exists(&staging_dir)? {
+        // left over from a previously interrupted migration
+        fs::remove_dir_all(&staging_dir)?;
+    }
+    fs::create_dir_all(&staging_dir)?;
+
+    log::info!(
+        "Migrating {} file(s) ({total_bytes} bytes) from {dir:?} to {new_dir:?}",
+        files.len(),
+    );
+    let mut copied_bytes = 0;
+    for (path, len) in &files {
+        let relative_path = path.strip_prefix(dir).expect("collected from within dir");
+        let destination = staging_dir.join(relative_path);
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(path, &destination).context(format!("copying {path:?}"))?;
+        copied_bytes += len;
+        if let Some(progress_callback) = &progress_callback {
+            if total_bytes > 0 {
+                progress_callback.on_progress(copied_bytes as f64 / total_bytes as f64);
+            }
+        }
+    }
+    fs::rename(&staging_dir, new_dir)?;
+    if let Some(progress_callback) = &progress_callback {
+        progress_callback.on_progress(1.0);
+    }
+    Ok(())
+}
+
+/// Deletes everything in `dir` but its `skipped` entries, and `dir` itself if nothing's skipped.
+///
+/// Only intended to clean up after [`copy_dir`], once nothing is stored in `dir` any more.
+pub(crate) fn remove_dir(dir: &Path, skipped: &[&str]) -> Result<()> {
+    if skipped.is_empty() {
+        fs::remove_dir_all(dir)?;
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if is_skipped(&path, skipped) {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(&path)?;
+        } else {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+fn is_skipped(path: &Path, skipped: &[&str]) -> bool {
+    path.file_name()
+        .and_then(|file_name| file_name.to_str())
+        .is_some_and(|file_name| skipped.contains(&file_name))
+}
+
+/// Recursively collects all the files within `dir`, or just `dir` if it's a file, along with
+/// their size.
+fn collect_files(dir: &Path, files: &mut Vec<(PathBuf, u64)>) -> Result<()> {
+    let metadata = fs::metadata(dir)?;
+    if !metadata.is_dir() {
+        files.push((dir.to_path_buf(), metadata.len()));
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        collect_files(&entry?.path(), files)?;
+    }
+    Ok(())
+}
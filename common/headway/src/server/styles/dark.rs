@@ -0,0 +1,174 @@
+//! Derives the bundled dark style from the basic style, by inverting the lightness of every
+//! paint color while keeping its hue, rather than maintaining a second style by hand.
+
+use serde_json::Value;
+
+pub(super) const DARK_STYLE_ID: &str = "dark";
+const DARK_STYLE_NAME: &str = "Headway Dark";
+
+/// Inverted lightness is squeezed into this range, since pure black and white are harsh on a
+/// dark map.
+const MIN_LIGHTNESS: f64 = 0.08;
+const MAX_LIGHTNESS: f64 = 0.92;
+
+/// Muted colors read better against a dark background
+const SATURATION_SCALE: f64 = 0.8;
+
+pub(super) fn dark_style(style_json: &str) -> String {
+    let mut style: Value = serde_json::from_str(style_json).expect("bundled style is valid JSON");
+    style["id"] = DARK_STYLE_ID.into();
+    style["name"] = DARK_STYLE_NAME.into();
+    if let Some(layers) = style.get_mut("layers").and_then(Value::as_array_mut) {
+        for layer in layers {
+            let Some(paint) = layer.get_mut("paint").and_then(Value::as_object_mut) else {
+                continue;
+            };
+            for (property, value) in paint.iter_mut() {
+                if property.ends_with("-color") {
+                    darken(value);
+                }
+            }
+        }
+    }
+    style.to_string()
+}
+
+/// Darkens every color within a paint property, including those nested in zoom stops and
+/// expressions.
+fn darken(value: &mut Value) {
+    match value {
+        Value::String(color) => {
+            if let Some(hsla) = Hsla::parse(color) {
+                *color = hsla.darkened().to_css();
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(darken),
+        Value::Object(values) => values.values_mut().for_each(darken),
+        _ => {}
+    }
+}
+
+/// A color with hue in degrees, and saturation, lightness, and alpha in 0..=1
+#[derive(Debug, Clone, Copy)]
+struct Hsla {
+    h: f64,
+    s: f64,
+    l: f64,
+    a: f64,
+}
+
+impl Hsla {
+    /// Parses the CSS color formats used by styles: `#rgb`, `#rgba`, `#rrggbb`, `#rrggbbaa`,
+    /// `rgb()`, `rgba()`, `hsl()`, and `hsla()`
+    fn parse(color: &str) -> Option<Self> {
+        let color = color.trim();
+        if let Some(hex) = color.strip_prefix('#') {
+            return Self::parse_hex(hex);
+        }
+        let (function, args) = color.strip_suffix(')')?.split_once('(')?;
+        let args: Vec<&str> = args.split(',').map(str::trim).collect();
+        let alpha = match args.len() {
+            3 => 1.0,
+            4 => args[3].parse::<f64>().ok()?.clamp(0.0, 1.0),
+            _ => return None,
+        };
+        match function.trim() {
+            "rgb" | "rgba" => {
+                let channel = |arg: &str| -> Option<f64> {
+                    let value = match arg.strip_suffix('%') {
+                        Some(percent) => percent.parse::<f64>().ok()? / 100.0,
+                        None => arg.parse::<f64>().ok()? / 255.0,
+                    };
+                    Some(value.clamp(0.0, 1.0))
+                };
+                Some(Self::from_rgb(
+                    channel(args[0])?,
+                    channel(args[1])?,
+                    channel(args[2])?,
+                    alpha,
+                ))
+            }
+            "hsl" | "hsla" => {
+                let percent = |arg: &str| -> Option<f64> {
+                    Some((arg.strip_suffix('%')?.parse::<f64>().ok()? / 100.0).clamp(0.0, 1.0))
+                };
+                Some(Self {
+                    h: args[0]
+                        .trim_end_matches("deg")
+                        .parse::<f64>()
+                        .ok()?
+                        .rem_euclid(360.0),
+                    s: percent(args[1])?,
+                    l: percent(args[2])?,
+                    a: alpha,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn parse_hex(hex: &str) -> Option<Self> {
+        if !hex.is_ascii() {
+            return None;
+        }
+        let digits: Vec<u8> = match hex.len() {
+            // Short forms repeat each digit, e.g. `#abc` is `#aabbcc`
+            3 | 4 => hex
+                .chars()
+                .map(|digit| u8::from_str_radix(&format!("{digit}{digit}"), 16))
+                .collect::<std::result::Result<_, _>>()
+                .ok()?,
+            6 | 8 => (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+                .collect::<std::result::Result<_, _>>()
+                .ok()?,
+            _ => return None,
+        };
+        let channel = |i: usize| f64::from(digits[i]) / 255.0;
+        let alpha = if digits.len() == 4 { channel(3) } else { 1.0 };
+        Some(Self::from_rgb(channel(0), channel(1), channel(2), alpha))
+    }
+
+    fn from_rgb(r: f64, g: f64, b: f64, a: f64) -> Self {
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let l = (max + min) / 2.0;
+        let delta = max - min;
+        if delta == 0.0 {
+            return Self {
+                h: 0.0,
+                s: 0.0,
+                l,
+                a,
+            };
+        }
+        let s = delta / (1.0 - (2.0 * l - 1.0).abs());
+        let h = if max == r {
+            60.0 * ((g - b) / delta).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+        Self { h, s, l, a }
+    }
+
+    fn darkened(self) -> Self {
+        Self {
+            s: self.s * SATURATION_SCALE,
+            l: MIN_LIGHTNESS + (1.0 - self.l) * (MAX_LIGHTNESS - MIN_LIGHTNESS),
+            ..self
+        }
+    }
+
+    fn to_css(self) -> String {
+        format!(
+            "hsla({:.1}, {:.1}%, {:.1}%, {:.2})",
+            self.h,
+            self.s * 100.0,
+            self.l * 100.0,
+            self.a
+        )
+    }
+}
@@ -0,0 +1,221 @@
+//! Serves map styles from `{styles_dir}/{style_id}/style.json`, alongside a listing of all
+//! available styles at `/tileserver/styles.json`.
+//!
+//! A style directory may also contain a `thumbnail.png` preview for the listing. Sprites
+//! referenced relative to a style, e.g. `/tileserver/styles/{style_id}/sprite`, come from the
+//! sprite sheet with the same id as the style, except for the bundled `dark` style, which is
+//! derived from the basic style and shares its sprites.
+//!
+//! Styles reference tiles, glyphs, and sprites by absolute URL, which can't be known ahead of time
+//! when the server might bind any port. So the `/tileserver/...` and `/archives/...` URLs in a
+//! style are rewritten to start with the server's base URL as it's served.
+//!
+//! Every overlay is added to each style as it's served, see [`raster_overlays`] and [`overlays`].
+
+mod dark;
+
+use crate::server::conditional::conditional_response;
+use crate::server::{overlays, raster_overlays, sprites, AppState};
+use axum::body::Body;
+use axum::extract::{Path as UrlPath, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+use std::sync::LazyLock;
+
+const STYLE_FILE_NAME: &str = "style.json";
+const THUMBNAIL_FILE_NAME: &str = "thumbnail.png";
+
+const BASIC_STYLE_ID: &str = "basic";
+const BASIC_STYLE_JSON: &str = include_str!("../../../tileserver_styles/basic/style.json");
+static DARK_STYLE_JSON: LazyLock<String> = LazyLock::new(|| dark::dark_style(BASIC_STYLE_JSON));
+
+/// Ids of the styles compiled into the library
+const BUNDLED_STYLE_IDS: &[&str] = &[BASIC_STYLE_ID, dark::DARK_STYLE_ID];
+
+fn bundled_style(style_id: &str) -> Option<&'static str> {
+    match style_id {
+        BASIC_STYLE_ID => Some(BASIC_STYLE_JSON),
+        dark::DARK_STYLE_ID => Some(&DARK_STYLE_JSON),
+        _ => None,
+    }
+}
+
+pub(crate) async fn get_style_file(
+    State(state): State<AppState>,
+    UrlPath((style_id, file_name)): UrlPath<(String, String)>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if !is_valid_style_id(&style_id) {
+        log::warn!("Invalid style id: {style_id:?}");
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+    let style_dir = state.styles_dir.read().await.join(&style_id);
+    match file_name.as_str() {
+        STYLE_FILE_NAME => get_style(&state, &style_dir, &style_id, &headers),
+        THUMBNAIL_FILE_NAME => get_thumbnail(&style_dir, &headers),
+        _ => sprites::serve_sprite(&state, &style_id, &file_name, &headers).await,
+    }
+}
+
+fn get_style(state: &AppState, style_dir: &Path, style_id: &str, headers: &HeaderMap) -> Response {
+    let path = style_dir.join(STYLE_FILE_NAME);
+    let style_json = match fs::read_to_string(&path) {
+        Ok(style_json) => style_json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => match bundled_style(style_id) {
+            Some(style_json) => style_json.to_string(),
+            None => return StatusCode::NOT_FOUND.into_response(),
+        },
+        Err(e) => {
+            log::error!("Error reading style {path:?}, error: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let mut style = match serde_json::from_str::<Value>(&style_json) {
+        Ok(style) => style,
+        Err(e) => {
+            log::error!("Invalid style {style_id:?}, error: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    rewrite_style_urls(&mut style, &state.base_url);
+    raster_overlays::add_raster_overlays_to_style(
+        &mut style,
+        &state.raster_overlays,
+        &state.base_url,
+    );
+    overlays::add_overlays_to_style(&mut style, &state.overlays, &state.base_url);
+    // The rewritten URLs change with the base URL, so the file's modification time isn't enough
+    conditional_response(headers, "application/json", style.to_string(), None)
+}
+
+/// Points the style's tile sources, glyphs, and sprites at `base_url`
+fn rewrite_style_urls(style: &mut Value, base_url: &str) {
+    if let Some(sources) = style.get_mut("sources").and_then(Value::as_object_mut) {
+        for source in sources.values_mut() {
+            if let Some(url) = source.get_mut("url") {
+                rewrite_url(url, base_url);
+            }
+            if let Some(tiles) = source.get_mut("tiles").and_then(Value::as_array_mut) {
+                tiles.iter_mut().for_each(|url| rewrite_url(url, base_url));
+            }
+        }
+    }
+    if let Some(glyphs) = style.get_mut("glyphs") {
+        rewrite_url(glyphs, base_url);
+    }
+    match style.get_mut("sprite") {
+        // Either a single sprite URL, or a list of `{"id": .., "url": ..}`
+        Some(Value::Array(sprites)) => {
+            for sprite in sprites {
+                if let Some(url) = sprite.get_mut("url") {
+                    rewrite_url(url, base_url);
+                }
+            }
+        }
+        Some(sprite) => rewrite_url(sprite, base_url),
+        None => {}
+    }
+}
+
+/// Replaces the scheme and authority of URLs served by this server, e.g.
+/// `http://127.0.0.1:8080/tileserver/fonts/{fontstack}/{range}.pbf`, with `base_url`.
+///
+/// URLs of other servers are left alone.
+fn rewrite_url(url: &mut Value, base_url: &str) {
+    let Some(original) = url.as_str() else {
+        return;
+    };
+    let path = match original.split_once("://") {
+        Some((_scheme, rest)) => rest.find('/').map_or("", |i| &rest[i..]),
+        None => original,
+    };
+    if path.starts_with("/tileserver/") || path.starts_with("/archives/") {
+        *url = format!("{base_url}{path}").into();
+    }
+}
+
+fn get_thumbnail(style_dir: &Path, headers: &HeaderMap) -> Response {
+    let path = style_dir.join(THUMBNAIL_FILE_NAME);
+    match fs::read(&path) {
+        Ok(thumbnail) => {
+            let modified = fs::metadata(&path)
+                .and_then(|metadata| metadata.modified())
+                .ok();
+            conditional_response(headers, "image/png", thumbnail, modified)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            log::error!("Error reading thumbnail {path:?}, error: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Lists every available style along with its name, URL, and thumbnail URL (if any).
+pub(crate) async fn list_styles(State(state): State<AppState>) -> impl IntoResponse {
+    // style id -> (style json, has thumbnail), with styles on disk taking precedence
+    let mut styles: BTreeMap<String, (String, bool)> = BUNDLED_STYLE_IDS
+        .iter()
+        .filter_map(|id| Some(((*id).to_string(), (bundled_style(id)?.to_string(), false))))
+        .collect();
+    let styles_dir = state.styles_dir.read().await.clone();
+    match fs::read_dir(&styles_dir) {
+        Ok(entries) => {
+            for entry in entries.flatten() {
+                let Some(style_id) = entry.file_name().to_str().map(str::to_string) else {
+                    continue;
+                };
+                if !is_valid_style_id(&style_id) {
+                    continue;
+                }
+                let style_dir = entry.path();
+                let Ok(style_json) = fs::read_to_string(style_dir.join(STYLE_FILE_NAME)) else {
+                    continue;
+                };
+                let has_thumbnail = style_dir.join(THUMBNAIL_FILE_NAME).exists();
+                styles.insert(style_id, (style_json, has_thumbnail));
+            }
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => {
+            log::error!("Error listing styles in {styles_dir:?}, error: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    let listing: Vec<Value> = styles
+        .into_iter()
+        .map(|(style_id, (style_json, has_thumbnail))| {
+            let name = serde_json::from_str::<Value>(&style_json)
+                .ok()
+                .and_then(|style| style.get("name")?.as_str().map(str::to_string))
+                .unwrap_or_else(|| style_id.clone());
+            let style_url = format!("{}/tileserver/styles/{style_id}", state.base_url);
+            let mut entry = json!({
+                "id": style_id,
+                "name": name,
+                "url": format!("{style_url}/{STYLE_FILE_NAME}"),
+            });
+            if has_thumbnail {
+                entry["thumbnail"] = format!("{style_url}/{THUMBNAIL_FILE_NAME}").into();
+            }
+            entry
+        })
+        .collect();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(Value::from(listing).to_string()))
+        .unwrap()
+}
+
+/// Style ids become path components, so mustn't be able to escape `styles_dir`
+fn is_valid_style_id(style_id: &str) -> bool {
+    !style_id.is_empty() && Path::new(style_id).file_name() == Some(OsStr::new(style_id))
+}
@@ -0,0 +1,103 @@
+//! Listens on several addresses at once, e.g. both `127.0.0.1` and `[::1]`, since some clients
+//! (notably Android webviews) resolve `localhost` to whichever they prefer, and only try the
+//! other if connecting fails outright.
+
+use crate::{Error, Result};
+use axum::serve::Listener;
+use std::net::SocketAddr;
+use std::task::Poll;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+
+/// How long to wait before accepting again after an error, e.g. running out of file descriptors
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+pub(crate) struct TcpListeners {
+    /// Never empty
+    listeners: Vec<TcpListener>,
+    /// Which listener to poll first, rotated so a busy listener can't starve the others
+    next: usize,
+}
+
+impl TcpListeners {
+    /// Binds every address each of `bind_addrs` resolves to, e.g. both loopback addresses for
+    /// `"localhost:9123"`.
+    ///
+    /// Addresses with port 0 share whichever port the OS assigns the first of them, so clients
+    /// can use any of the addresses interchangeably.
+    pub(crate) async fn bind(bind_addrs: &[String]) -> Result<Self> {
+        let mut listeners = vec![];
+        let mut assigned_port = None;
+        for bind_addr in bind_addrs {
+            let mut last_error = None;
+            let mut bound_any = false;
+            for mut addr in tokio::net::lookup_host(bind_addr.as_str()).await? {
+                if addr.port() == 0 {
+                    if let Some(port) = assigned_port {
+                        addr.set_port(port);
+                    }
+                }
+                match TcpListener::bind(addr).await {
+                    Ok(listener) => {
+                        assigned_port.get_or_insert(listener.local_addr()?.port());
+                        listeners.push(listener);
+                        bound_any = true;
+                    }
+                    Err(e) => {
+                        // e.g. `localhost` resolving to `::1` on a device with IPv6 disabled
+                        log::warn!("Couldn't bind {addr} for {bind_addr}, error: {e}");
+                        last_error = Some(e);
+                    }
+                }
+            }
+            if !bound_any {
+                return Err(match last_error {
+                    Some(e) => e.into(),
+                    None => Error::InvalidInput(format!("no addresses for {bind_addr:?}")),
+                });
+            }
+        }
+        if listeners.is_empty() {
+            return Err(Error::InvalidInput("no addresses to bind".to_string()));
+        }
+        Ok(Self { listeners, next: 0 })
+    }
+
+    /// The addresses of every listener, in the order they were bound
+    pub(crate) fn local_addrs(&self) -> std::io::Result<Vec<SocketAddr>> {
+        self.listeners.iter().map(TcpListener::local_addr).collect()
+    }
+}
+
+impl Listener for TcpListeners {
+    type Io = TcpStream;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        loop {
+            let result = std::future::poll_fn(|cx| {
+                let count = self.listeners.len();
+                for i in 0..count {
+                    let index = (self.next + i) % count;
+                    if let Poll::Ready(result) = self.listeners[index].poll_accept(cx) {
+                        self.next = (index + 1) % count;
+                        return Poll::Ready(result);
+                    }
+                }
+                Poll::Pending
+            })
+            .await;
+            match result {
+                Ok(accepted) => return accepted,
+                Err(e) => {
+                    log::error!("Error accepting connection: {e}");
+                    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                }
+            }
+        }
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        self.listeners[0].local_addr()
+    }
+}
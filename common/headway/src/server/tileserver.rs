@@ -1,105 +1,304 @@
+use crate::map_tiles::{
+    contour_source, contour_tile_json, inspect_tile, is_valid_tile, tile_format, CONTOUR_SOURCE_ID,
+    DEFAULT_TILESET_ID,
+};
+use crate::server::conditional::{conditional_response, etag_matches};
 use crate::server::AppState;
 use axum::body::Body;
 use axum::extract::{Path, State};
-use axum::http::{header, StatusCode};
+use axum::http::{header, HeaderMap, StatusCode};
 use axum::response::{IntoResponse, Response};
+use pmtiles::TileType;
+use serde_json::json;
+
+pub(crate) const TILE_ROUTE: &str = "/tileserver/data/{source_id}/{z}/{x}/{y_with_ext}";
 
 pub(crate) async fn get_tile(
     State(state): State<AppState>,
-    Path((z, x, y_with_ext)): Path<(u8, u32, String)>,
+    Path((source_id, z, x, y_with_ext)): Path<(String, u8, u32, String)>,
+    headers: HeaderMap,
 ) -> impl IntoResponse {
-    // Strip the .pbf extension
-    let y = match y_with_ext.strip_suffix(".pbf") {
-        Some(y_str) => match y_str.parse::<u32>() {
-            Ok(y) => y,
-            Err(_) => {
+    // Split off the extension, e.g. .pbf or .png
+    let (y, requested_tile_type) = match y_with_ext.split_once('.') {
+        Some((y_str, ext)) => {
+            let Ok(y) = y_str.parse::<u32>() else {
                 log::warn!("Invalid y coordinate: {}", y_with_ext);
                 return StatusCode::BAD_REQUEST.into_response();
-            }
-        },
+            };
+            let Some(tile_type) = tile_format::tile_type_for_extension(ext) else {
+                log::warn!("Unsupported tile extension: {}", y_with_ext);
+                return StatusCode::BAD_REQUEST.into_response();
+            };
+            (y, tile_type)
+        }
         None => {
-            log::warn!("Missing .pbf extension: {}", y_with_ext);
+            log::warn!("Missing tile extension: {}", y_with_ext);
             return StatusCode::BAD_REQUEST.into_response();
         }
     };
 
-    let tile_data = {
-        // Get tile from PMTiles archive (acquire read lock)
-        let collection = state.tile_collection.read().await;
-        match collection.get_tile(z, x, y).await {
+    if !is_valid_tile(z, x, y) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    if source_id == CONTOUR_SOURCE_ID {
+        let contour_tileset = state.contour_tileset.read().await.clone();
+        if let Some(contour_tileset) = contour_tileset {
+            if requested_tile_type != TileType::Mvt {
+                return StatusCode::NOT_FOUND.into_response();
+            }
+            return get_contour_tile(&state, &contour_tileset, z, x, y, &headers).await;
+        }
+    }
+
+    let tile = {
+        // Get tile from PMTiles archive (from a snapshot, so never waiting on sources being added)
+        let collection = state.tile_collection.load();
+        match collection.get_tile(&source_id, z, x, y).await {
             Err(e) => {
-                log::error!("Error reading tile {z}/{x}/{y}, error: {e}");
+                log::error!("Error reading tile {source_id}/{z}/{x}/{y}, error: {e}");
                 return StatusCode::INTERNAL_SERVER_ERROR.into_response();
             }
             Ok(None) => {
-                return StatusCode::NOT_FOUND.into_response();
+                let cache_control = state.tile_cache_control.read().await.clone();
+                let gap_tile = state.gap_tile.read().await.clone();
+                if let Some(gap_tile) = gap_tile {
+                    if requested_tile_type == TileType::Mvt
+                        && collection.within_vector_coverage(&source_id, z, x, y)
+                    {
+                        return Response::builder()
+                            .status(StatusCode::OK)
+                            .header(header::CONTENT_TYPE, "application/x-protobuf")
+                            .header(header::CACHE_CONTROL, cache_control)
+                            .body(Body::from(gap_tile))
+                            .unwrap();
+                    }
+                }
+                if !collection.covers(&source_id, z, x, y) {
+                    return StatusCode::NOT_FOUND.into_response();
+                }
+                // Archives omit tiles without any data, e.g. in the ocean. Tell clients there's
+                // nothing to show, rather than that the tile is missing, so they don't retry it.
+                return Response::builder()
+                    .status(StatusCode::NO_CONTENT)
+                    .header(header::CACHE_CONTROL, cache_control)
+                    .body(Body::empty())
+                    .unwrap();
             }
-            Ok(Some(data)) => data,
+            Ok(Some(tile)) => tile,
         }
     };
 
-    let mut response = Response::builder().status(StatusCode::OK);
+    if tile.tile_type != requested_tile_type {
+        log::warn!(
+            "Requested {y_with_ext} but source {source_id} contains {:?} tiles",
+            tile.tile_type
+        );
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let Some(content_type) = tile_format::content_type(tile.tile_type) else {
+        log::error!("Unsupported tile type: {:?}", tile.tile_type);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+
+    let accept_encoding = headers
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok());
+    let needs_decompression = tile_format::content_encoding(tile.compression)
+        .is_some_and(|encoding| !accepts_encoding(accept_encoding, encoding));
 
-    // TODO: support non-MVT tiles
-    let content_type = "application/x-protobuf";
+    // The decompressed representation is a different sequence of bytes, so needs a distinct ETag
+    let etag = format!(
+        "\"{:016x}-{z}-{x}-{y}{}\"",
+        tile.source_hash,
+        if needs_decompression { "-identity" } else { "" }
+    );
+    let cache_control = state.tile_cache_control.read().await.clone();
+    let if_none_match = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok());
+    if if_none_match.is_some_and(|if_none_match| etag_matches(if_none_match, &etag)) {
+        return Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .header(header::ETAG, etag)
+            .header(header::CACHE_CONTROL, cache_control)
+            .body(Body::empty())
+            .unwrap();
+    }
+
+    let tile = match tile_format::content_encoding(tile.compression) {
+        Some(encoding) if needs_decompression => {
+            log::debug!("Client doesn't accept {encoding}, decompressing {source_id}/{z}/{x}/{y}");
+            match tile.decompressed() {
+                Ok(tile) => tile,
+                Err(e) => {
+                    log::error!("Error decompressing tile {source_id}/{z}/{x}/{y}, error: {e}");
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            }
+        }
+        _ => tile,
+    };
+
+    let mut response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::ETAG, etag)
+        .header(header::CACHE_CONTROL, cache_control)
+        .header(header::VARY, "Accept-Encoding");
     response = response.header(header::CONTENT_TYPE, content_type);
 
-    // TODO: support other tile_compression
-    let tile_compression = "gzip";
-    response = response.header(header::CONTENT_ENCODING, tile_compression);
+    if let Some(content_encoding) = tile_format::content_encoding(tile.compression) {
+        response = response.header(header::CONTENT_ENCODING, content_encoding);
+    }
 
-    response.body(Body::from(tile_data)).unwrap()
+    response.body(Body::from(tile.data)).unwrap()
 }
 
-// The rest of this module is a hack to stub out a proper tileserver by returning some fixed responses to
-// resource requests.
-// We should probably do something smarter and more dynamic, but this works for expediency.
-const DEFAULT_STYLE_JSON: &str = include_str!("../../tileserver_styles/basic/style.json");
-const DEFAULT_SPRITE_JSON: &str = include_str!("../../tileserver_styles/basic/sprite@2x.json");
-const DEFAULT_SPRITE_PNG: &[u8] = include_bytes!("../../tileserver_styles/basic/sprite@2x.png");
-const DEFAULT_TILE_JSON: &str = include_str!("../../tileserver_styles/basic/tile.json");
-const DEFAULT_FONT: &[u8] =
-    include_bytes!("../../tileserver_styles/fonts/Roboto%20Medium/0-255.pbf");
-
-pub(crate) async fn get_default_style() -> impl IntoResponse {
-    Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "application/json")
-        .body(Body::from(DEFAULT_STYLE_JSON))
-        .unwrap()
+/// Generates the contour tile at `z/x/y` from the terrain tileset `tileset_id`
+async fn get_contour_tile(
+    state: &AppState,
+    tileset_id: &str,
+    z: u8,
+    x: u32,
+    y: u32,
+    headers: &HeaderMap,
+) -> Response {
+    let source = {
+        let collection = state.tile_collection.load();
+        match contour_source(&collection, tileset_id, z, x, y).await {
+            Ok(Some(source)) => source,
+            Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+            Err(e) => {
+                log::error!("Error reading terrain for contours {z}/{x}/{y}, error: {e}");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+    };
+    // Decoding the terrain and tracing its contours takes a few milliseconds
+    match tokio::task::spawn_blocking(move || source.tile()).await {
+        Ok(Ok(Some(tile))) => conditional_response(headers, "application/x-protobuf", tile, None),
+        Ok(Ok(None)) => StatusCode::NO_CONTENT.into_response(),
+        Ok(Err(e)) => {
+            log::error!("Error generating contours {z}/{x}/{y}, error: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+        Err(e) => {
+            log::error!("Error generating contours {z}/{x}/{y}, error: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
 }
 
-pub(crate) async fn get_tile_json(State(_state): State<AppState>) -> impl IntoResponse {
-    Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "application/json")
-        .body(Body::from(DEFAULT_TILE_JSON))
-        .unwrap()
+/// Whether an `Accept-Encoding` header value permits responding with `encoding`.
+///
+/// Per RFC 9110, a missing header means any encoding is acceptable.
+fn accepts_encoding(accept_encoding: Option<&str>, encoding: &str) -> bool {
+    let Some(accept_encoding) = accept_encoding else {
+        return true;
+    };
+    let mut wildcard_accepted = false;
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';');
+        let coding = params.next().unwrap_or_default().trim();
+        let rejected = params.any(|param| {
+            param
+                .trim()
+                .strip_prefix("q=")
+                .and_then(|q| q.trim().parse::<f32>().ok())
+                .is_some_and(|q| q <= 0.0)
+        });
+        if coding.eq_ignore_ascii_case(encoding) {
+            return !rejected;
+        }
+        if coding == "*" {
+            wildcard_accepted = !rejected;
+        }
+    }
+    wildcard_accepted
 }
 
-pub(crate) async fn get_sprite_json(State(_state): State<AppState>) -> impl IntoResponse {
-    Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "application/json")
-        .body(Body::from(DEFAULT_SPRITE_JSON))
-        .unwrap()
+pub(crate) async fn get_tile_json(
+    State(state): State<AppState>,
+    Path(source_id_with_ext): Path<String>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let Some(source_id) = source_id_with_ext.strip_suffix(".json") else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    // Like the URLs in styles, so tiles are requested with the same scheme and address
+    let source_url = format!("{}/tileserver/data/{source_id}", state.base_url);
+    let contour_tileset = state.contour_tileset.read().await.clone();
+    let tile_json = {
+        let collection = state.tile_collection.load();
+        let tile_json = match contour_tileset {
+            Some(contour_tileset) if source_id == CONTOUR_SOURCE_ID => {
+                contour_tile_json(&collection, &contour_tileset, &source_url)
+            }
+            _ => collection.tile_json(source_id, &source_url),
+        };
+        match tile_json {
+            Some(tile_json) => tile_json,
+            None => return StatusCode::NOT_FOUND.into_response(),
+        }
+    };
+
+    conditional_response(&headers, "application/json", tile_json.to_string(), None)
 }
 
-pub(crate) async fn get_sprite_png(State(_state): State<AppState>) -> impl IntoResponse {
-    Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "image/png")
-        .body(Body::from(DEFAULT_SPRITE_PNG))
-        .unwrap()
+/// Inspects the default tileset's tile at `z/x/y`, see [`inspect_source_tile`]
+pub(crate) async fn inspect_default_tile(
+    state: State<AppState>,
+    Path((z, x, y)): Path<(u8, u32, u32)>,
+) -> impl IntoResponse {
+    inspect(&state, DEFAULT_TILESET_ID, z, x, y).await
 }
 
-pub(crate) async fn get_font(
-    State(_state): State<AppState>,
-    Path((_font_stack, _range_with_ext)): Path<(String, String)>,
+/// Describes the tile at `z/x/y` of `source_id` as JSON: which archive it came from, and for
+/// vector tiles, each layer's features and their attributes. Missing tiles are described too,
+/// with whether they're within the source's coverage, which is when they're served empty or as
+/// the gap tile.
+pub(crate) async fn inspect_source_tile(
+    state: State<AppState>,
+    Path((source_id, z, x, y)): Path<(String, u8, u32, u32)>,
 ) -> impl IntoResponse {
+    inspect(&state, &source_id, z, x, y).await
+}
+
+async fn inspect(state: &AppState, source_id: &str, z: u8, x: u32, y: u32) -> Response {
+    if !is_valid_tile(z, x, y) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let collection = state.tile_collection.load();
+    let mut inspection = json!({ "source_id": source_id, "z": z, "x": x, "y": y });
+    match collection.get_tile_with_file_name(source_id, z, x, y).await {
+        Err(e) => {
+            log::error!("Error reading tile {source_id}/{z}/{x}/{y}, error: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        Ok(None) => {
+            inspection["found"] = false.into();
+            inspection["covered"] = collection.covers(source_id, z, x, y).into();
+            inspection["within_vector_coverage"] =
+                collection.within_vector_coverage(source_id, z, x, y).into();
+        }
+        Ok(Some((file_name, tile))) => {
+            inspection["found"] = true.into();
+            inspection["file_name"] = file_name.into();
+            inspection["tile_type"] = tile_format::extension(tile.tile_type).into();
+            inspection["compression"] = tile_format::content_encoding(tile.compression).into();
+            inspection["size_bytes"] = tile.data.len().into();
+            if tile.tile_type == TileType::Mvt {
+                match inspect_tile(tile, z, x, y) {
+                    Ok(layers) => inspection["layers"] = layers.into(),
+                    Err(e) => inspection["error"] = e.to_string().into(),
+                }
+            }
+        }
+    }
     Response::builder()
         .status(StatusCode::OK)
         .header(header::CONTENT_TYPE, "application/json")
-        .body(Body::from(DEFAULT_FONT))
+        .body(Body::from(inspection.to_string()))
         .unwrap()
 }
@@ -0,0 +1,164 @@
+//! Serves over HTTPS with a self-signed certificate generated on the device, for platforms that
+//! require secure origins (e.g. for service workers) or block cleartext traffic, even to
+//! localhost.
+//!
+//! The certificate is persisted, so once the host app has arranged for it to be trusted it stays
+//! trusted across launches.
+
+use crate::server::tcp::TcpListeners;
+use crate::{Error, ErrorContext, Result};
+use axum::serve::Listener;
+use std::fs;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::task::{JoinError, JoinSet};
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use tokio_rustls::rustls::{crypto, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+const CERTIFICATE_FILE_NAME: &str = "certificate.der";
+const PRIVATE_KEY_FILE_NAME: &str = "private_key.der";
+
+/// Names the certificate is valid for
+const SUBJECT_ALT_NAMES: &[&str] = &["localhost", "127.0.0.1", "::1"];
+
+/// Don't let a client that stalls mid-handshake hold on to its connection indefinitely
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A certificate and its private key, both DER encoded
+#[derive(Clone)]
+pub(crate) struct TlsIdentity {
+    certificate: Vec<u8>,
+    private_key: Vec<u8>,
+}
+
+impl TlsIdentity {
+    /// Loads the identity persisted in `dir`, generating one if there isn't one yet.
+    pub(crate) fn load_or_generate(dir: &Path) -> Result<Self> {
+        let certificate_path = dir.join(CERTIFICATE_FILE_NAME);
+        let private_key_path = dir.join(PRIVATE_KEY_FILE_NAME);
+        if certificate_path.exists() && private_key_path.exists() {
+            return Ok(Self {
+                certificate: fs::read(&certificate_path).context("reading TLS certificate")?,
+                private_key: fs::read(&private_key_path).context("reading TLS private key")?,
+            });
+        }
+
+        log::info!("Generating self-signed TLS certificate in {dir:?}");
+        let subject_alt_names = SUBJECT_ALT_NAMES.iter().map(|name| name.to_string());
+        let generated = rcgen::generate_simple_self_signed(subject_alt_names.collect::<Vec<_>>())
+            .map_err(|e| Error::Serve(format!("generating TLS certificate: {e}")))?;
+        let identity = Self {
+            certificate: generated.cert.der().to_vec(),
+            private_key: generated.key_pair.serialize_der(),
+        };
+
+        fs::create_dir_all(dir)?;
+        // Written before the certificate, so a partially written identity is never loaded
+        fs::write(&private_key_path, &identity.private_key).context("writing TLS private key")?;
+        fs::write(&certificate_path, &identity.certificate).context("writing TLS certificate")?;
+        Ok(identity)
+    }
+
+    /// The DER encoded certificate, for the host app to trust
+    pub(crate) fn certificate(&self) -> &[u8] {
+        &self.certificate
+    }
+
+    fn acceptor(&self) -> Result<TlsAcceptor> {
+        let provider = Arc::new(crypto::ring::default_provider());
+        let mut config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .and_then(|builder| {
+                builder.with_no_client_auth().with_single_cert(
+                    vec![CertificateDer::from(self.certificate.clone())],
+                    PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(self.private_key.clone())),
+                )
+            })
+            .map_err(|e| Error::Serve(format!("configuring TLS: {e}")))?;
+        // The server only speaks HTTP/1.1
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+/// Accepts TCP connections, and completes a TLS handshake before handing them to the server.
+///
+/// Each handshake runs in its own task, so a slow client doesn't hold up accepting others.
+pub(crate) struct TlsListener {
+    listener: TcpListeners,
+    acceptor: TlsAcceptor,
+    /// Each yields its connection once the handshake completes, or `None` if it failed
+    handshakes: JoinSet<Option<(TlsStream<TcpStream>, SocketAddr)>>,
+}
+
+/// Whichever of accepting a connection and completing a handshake happened first
+enum Accepted {
+    Connection((TcpStream, SocketAddr)),
+    Handshake(std::result::Result<Option<(TlsStream<TcpStream>, SocketAddr)>, JoinError>),
+}
+
+impl TlsListener {
+    pub(crate) fn new(listener: TcpListeners, identity: &TlsIdentity) -> Result<Self> {
+        Ok(Self {
+            listener,
+            acceptor: identity.acceptor()?,
+            handshakes: JoinSet::new(),
+        })
+    }
+
+    fn start_handshake(&mut self, stream: TcpStream, addr: SocketAddr) {
+        let acceptor = self.acceptor.clone();
+        self.handshakes.spawn(async move {
+            match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => Some((stream, addr)),
+                Ok(Err(e)) => {
+                    log::debug!("TLS handshake with {addr} failed: {e}");
+                    None
+                }
+                Err(_) => {
+                    log::debug!("TLS handshake with {addr} timed out");
+                    None
+                }
+            }
+        });
+    }
+}
+
+impl Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        loop {
+            let accepted = {
+                let mut accept = std::pin::pin!(Listener::accept(&mut self.listener));
+                let handshakes = &mut self.handshakes;
+                std::future::poll_fn(|cx| {
+                    // An empty set is ready with `None`, meaning there's no handshake to wait for
+                    if let Poll::Ready(Some(result)) = handshakes.poll_join_next(cx) {
+                        return Poll::Ready(Accepted::Handshake(result));
+                    }
+                    accept.as_mut().poll(cx).map(Accepted::Connection)
+                })
+                .await
+            };
+            match accepted {
+                Accepted::Connection((stream, addr)) => self.start_handshake(stream, addr),
+                Accepted::Handshake(Ok(Some(connection))) => return connection,
+                Accepted::Handshake(Ok(None)) => {}
+                Accepted::Handshake(Err(e)) => log::error!("TLS handshake task failed: {e}"),
+            }
+        }
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        Listener::local_addr(&self.listener)
+    }
+}
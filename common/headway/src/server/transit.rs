@@ -0,0 +1,122 @@
+//! Proxies transit requests, like trip plans and stop departures, to an OpenTripPlanner-compatible
+//! endpoint such as Transitous. Recent responses are cached on disk, so itineraries the user has
+//! already looked at remain available when they drop offline, e.g. underground.
+
+use crate::checksum::Sha256Digest;
+use crate::server::AppState;
+use crate::Error;
+use axum::body::Body;
+use axum::extract::{Path as UrlPath, RawQuery, State};
+use axum::http::{header, HeaderName, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use bytes::Bytes;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How many responses to keep by default, discarding the least recently fetched beyond that
+pub(crate) const DEFAULT_MAX_CACHED_RESPONSES: u32 = 200;
+
+/// `hit` if the response was served from the cache because the endpoint couldn't be reached
+const CACHE_HEADER: HeaderName = HeaderName::from_static("x-headway-cache");
+
+pub(crate) async fn proxy_transit(
+    State(state): State<AppState>,
+    UrlPath(path): UrlPath<String>,
+    RawQuery(query): RawQuery,
+) -> impl IntoResponse {
+    let Some(endpoint) = state.transit_endpoint.read().await.clone() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let url = match query {
+        Some(query) => format!("{endpoint}/{path}?{query}"),
+        None => format!("{endpoint}/{path}"),
+    };
+    let cache_path = cache_path(&state.storage.join("transit_cache"), &url);
+
+    let downloader = state.downloader.read().await.clone();
+    let is_offline = matches!(downloader.connectivity.check(), Err(Error::Offline));
+    if !is_offline {
+        match downloader.client().get(&url).send().await {
+            Ok(response) => {
+                let status = response.status();
+                match response.bytes().await {
+                    Ok(body) => {
+                        downloader.data_budget.spend(body.len() as u64);
+                        // Nothing's cached while the storage is being migrated
+                        let writing = state.storage.try_write_access();
+                        if status.is_success() && writing.is_some() {
+                            let cache_dir = state.storage.join("transit_cache");
+                            store(
+                                &cache_dir,
+                                &cache_path(&cache_dir, &url),
+                                &body,
+                                state.transit_cache_max_responses as usize,
+                            );
+                        }
+                        return json_response(status, body, "miss");
+                    }
+                    Err(e) => log::warn!("Transit request to {url} failed, trying cache: {e}"),
+                }
+            }
+            Err(e) => log::warn!("Transit request to {url} failed, trying cache: {e}"),
+        }
+    }
+
+    match fs::read(&cache_path) {
+        Ok(body) => json_response(StatusCode::OK, body.into(), "hit"),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            StatusCode::BAD_GATEWAY.into_response()
+        }
+        Err(e) => {
+            log::error!("Error reading cached transit response {cache_path:?}, error: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+fn json_response(status: StatusCode, body: Bytes, cache: &'static str) -> Response {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(CACHE_HEADER, HeaderValue::from_static(cache))
+        .body(Body::from(body))
+        .expect("valid response")
+}
+
+/// Where the response for `url` is cached, named for a hash that's stable across releases, so
+/// the cache outlives app updates
+fn cache_path(cache_dir: &Path, url: &str) -> PathBuf {
+    cache_dir.join(format!("{}.json", Sha256Digest::of_bytes(url.as_bytes())))
+}
+
+/// Caches `body` at `cache_path`, discarding the oldest responses if there are too many.
+/// Failures are only logged, since the response can be served regardless.
+fn store(cache_dir: &Path, cache_path: &Path, body: &[u8], max_responses: usize) {
+    let result = fs::create_dir_all(cache_dir).and_then(|()| {
+        // Write then rename, so a concurrent request never reads a partial response
+        let tmp_path = cache_path.with_extension("tmp");
+        fs::write(&tmp_path, body)?;
+        fs::rename(&tmp_path, cache_path)
+    });
+    if let Err(e) = result {
+        log::warn!("Unable to cache transit response at {cache_path:?}: {e}");
+        return;
+    }
+
+    let Ok(entries) = fs::read_dir(cache_dir) else {
+        return;
+    };
+    let mut cached: Vec<_> = entries
+        .flatten()
+        .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+        .collect();
+    if cached.len() <= max_responses {
+        return;
+    }
+    cached.sort();
+    for (_, path) in &cached[..cached.len() - max_responses] {
+        if let Err(e) = fs::remove_file(path) {
+            log::warn!("Unable to remove cached transit response {path:?}: {e}");
+        }
+    }
+}
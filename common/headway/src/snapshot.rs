@@ -0,0 +1,69 @@
+//! State that's read far more often than it changes, e.g. the tile collection, which every tile
+//! request reads.
+//!
+//! Readers take the current snapshot without waiting on writers, which change a copy on the
+//! side, e.g. while opening a new archive, and then swap it in. Only the swap itself is locked.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, RwLock};
+use tokio::sync::{Mutex, MutexGuard};
+
+#[derive(Debug)]
+pub(crate) struct Snapshot<T> {
+    current: RwLock<Arc<T>>,
+    /// Held by each writer in turn, so none of their changes are lost
+    writer: Mutex<()>,
+}
+
+impl<T: Clone> Snapshot<T> {
+    pub(crate) fn new(value: T) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(value)),
+            writer: Mutex::new(()),
+        }
+    }
+
+    /// The current snapshot, which later changes don't affect
+    pub(crate) fn load(&self) -> Arc<T> {
+        self.current.read().expect("poisoned lock").clone()
+    }
+
+    /// A copy of the current snapshot to change, once any other writer has finished. Readers
+    /// only see the changes once they're published with [`SnapshotWriter::publish`].
+    pub(crate) async fn write(&self) -> SnapshotWriter<'_, T> {
+        let guard = self.writer.lock().await;
+        SnapshotWriter {
+            snapshot: self,
+            value: T::clone(&self.load()),
+            _guard: guard,
+        }
+    }
+}
+
+pub(crate) struct SnapshotWriter<'a, T> {
+    snapshot: &'a Snapshot<T>,
+    value: T,
+    _guard: MutexGuard<'a, ()>,
+}
+
+impl<T> SnapshotWriter<'_, T> {
+    /// Swaps in the changes for readers. If it's dropped instead, e.g. on an error, they're
+    /// discarded.
+    pub(crate) fn publish(self) {
+        *self.snapshot.current.write().expect("poisoned lock") = Arc::new(self.value);
+    }
+}
+
+impl<T> Deref for SnapshotWriter<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for SnapshotWriter<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
@@ -0,0 +1,135 @@
+//! Writes routes and recorded tracks to GPX or GeoJSON files, for use in other outdoor apps.
+
+use crate::geo::LatLon;
+use crate::{Error, ErrorContext, Result};
+use quick_xml::escape::escape;
+use serde_json::json;
+use std::fmt::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct TrackPoint {
+    pub location: LatLon,
+    pub elevation_m: Option<f64>,
+    /// When the point was recorded, for recorded tracks
+    pub time: Option<SystemTime>,
+}
+
+#[derive(Clone, Copy, Debug, uniffi::Enum)]
+pub enum TrackFormat {
+    /// A GPX 1.1 file with a single `<trk>`
+    Gpx,
+    /// A GeoJSON Feature with a LineString, with elevations as a third coordinate where every
+    /// point has one, and times in a `coordTimes` property where every point has one
+    GeoJson,
+}
+
+/// Writes `points`, e.g. a computed route or a recorded track, to a new file at `path`,
+/// replacing any existing file there.
+#[uniffi::export]
+pub fn export_track(
+    name: Option<String>,
+    points: Vec<TrackPoint>,
+    format: TrackFormat,
+    path: String,
+) -> Result<()> {
+    if points.is_empty() {
+        return Err(Error::InvalidInput(
+            "can't export an empty track".to_string(),
+        ));
+    }
+    let contents = match format {
+        TrackFormat::Gpx => to_gpx(name.as_deref(), &points),
+        TrackFormat::GeoJson => to_geojson(name.as_deref(), &points),
+    };
+    // Write then rename, so other apps never see a partial file
+    let path = Path::new(&path);
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, contents).context(format!("exporting track to {path:?}"))?;
+    std::fs::rename(&tmp_path, path).context(format!("exporting track to {path:?}"))?;
+    Ok(())
+}
+
+fn to_gpx(name: Option<&str>, points: &[TrackPoint]) -> String {
+    let mut gpx = String::from(concat!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+        "\n",
+        r#"<gpx version="1.1" creator="Headway" xmlns="http://www.topografix.com/GPX/1/1">"#,
+        "\n<trk>\n"
+    ));
+    if let Some(name) = name {
+        writeln!(gpx, "<name>{}</name>", escape(name)).expect("writing to a string");
+    }
+    gpx.push_str("<trkseg>\n");
+    for point in points {
+        let LatLon { lat, lon } = point.location;
+        write!(gpx, r#"<trkpt lat="{lat}" lon="{lon}">"#).expect("writing to a string");
+        if let Some(elevation_m) = point.elevation_m {
+            write!(gpx, "<ele>{elevation_m}</ele>").expect("writing to a string");
+        }
+        if let Some(time) = point.time {
+            write!(gpx, "<time>{}</time>", rfc3339(time)).expect("writing to a string");
+        }
+        gpx.push_str("</trkpt>\n");
+    }
+    gpx.push_str("</trkseg>\n</trk>\n</gpx>\n");
+    gpx
+}
+
+fn to_geojson(name: Option<&str>, points: &[TrackPoint]) -> String {
+    let has_elevations = points.iter().all(|point| point.elevation_m.is_some());
+    let coordinates: Vec<Vec<f64>> = points
+        .iter()
+        .map(|point| {
+            let LatLon { lat, lon } = point.location;
+            match point.elevation_m.filter(|_| has_elevations) {
+                Some(elevation_m) => vec![lon, lat, elevation_m],
+                None => vec![lon, lat],
+            }
+        })
+        .collect();
+    let mut properties = json!({ "name": name });
+    let times: Option<Vec<String>> = points.iter().map(|point| point.time.map(rfc3339)).collect();
+    if let Some(times) = times {
+        properties["coordTimes"] = json!(times);
+    }
+    json!({
+        "type": "Feature",
+        "geometry": { "type": "LineString", "coordinates": coordinates },
+        "properties": properties,
+    })
+    .to_string()
+}
+
+/// e.g. `2025-06-01T12:34:56Z`, as GPX requires
+fn rfc3339(time: SystemTime) -> String {
+    let seconds = match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    };
+    let (days, seconds_of_day) = (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+
+    // Howard Hinnant's civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds_of_day / 3_600,
+        seconds_of_day % 3_600 / 60,
+        seconds_of_day % 60
+    )
+}
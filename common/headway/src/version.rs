@@ -0,0 +1,21 @@
+//! What the library was built from, for bug reports and feature checks.
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct VersionInfo {
+    /// The crate version, e.g. `0.1.0`
+    pub version: String,
+    /// The abbreviated commit the library was built from, if built from a git checkout
+    pub git_hash: Option<String>,
+    /// The version of the pmtiles library used to read and extract archives
+    pub pmtiles_version: Option<String>,
+}
+
+/// Identifies this build of the library, e.g. to include in bug reports
+#[uniffi::export]
+pub fn headway_version() -> VersionInfo {
+    VersionInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_hash: option_env!("HEADWAY_GIT_HASH").map(str::to_string),
+        pmtiles_version: option_env!("HEADWAY_PMTILES_VERSION").map(str::to_string),
+    }
+}